@@ -0,0 +1,23 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use simperby_consensus::*;
+use simperby_network::dms::MessageFilter;
+use std::sync::Arc;
+
+/// The overhead of the metrics in the message filter, which is the hottest path.
+fn filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter");
+    let metrics: [(&str, Arc<dyn ConsensusMetrics>); 2] = [
+        ("noop", Arc::new(NoopMetrics)),
+        ("atomic", Arc::new(AtomicMetrics::default())),
+    ];
+    for (name, metrics) in metrics {
+        let (filter, message, commitment) = bench_filter(metrics);
+        group.bench_function(name, |b| {
+            b.iter(|| filter.filter(&message, &commitment).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, filter);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The first 8 bytes are the seed of the validator set, and the rest is the message.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let (seed, message) = data.split_at(8);
+    let seed = u64::from_le_bytes(seed.try_into().unwrap());
+    let _ = simperby_consensus::fuzz_filter(message, seed);
+});
@@ -0,0 +1,108 @@
+use super::*;
+
+/// The version of the format of `BackupBundle`.
+pub const BACKUP_VERSION: u16 = 1;
+
+/// The consensus of a validator in the middle of a height, exported by
+/// `Consensus::export_backup()` to move the validator to another node.
+///
+/// It has the state (with the messages of this node not committed to the DMS yet)
+/// and the record of the messages signed by this node, but never the private key,
+/// which is given to the new node by its signer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupBundle {
+    pub version: u16,
+    pub height: BlockHeight,
+    pub round: ConsensusRound,
+    /// The state encoded by `StateCodec`.
+    state: String,
+    own_votes: OwnVotes,
+    /// The checksum over the other fields, to detect a damaged copy.
+    checksum: Hash256,
+}
+
+impl BackupBundle {
+    pub(crate) fn new(state: &State, raw_state: String, own_votes: OwnVotes) -> Self {
+        let mut bundle = Self {
+            version: BACKUP_VERSION,
+            height: state.height(),
+            round: state.round(),
+            state: raw_state,
+            own_votes,
+            checksum: Hash256::zero(),
+        };
+        bundle.checksum = bundle.calculate_checksum();
+        bundle
+    }
+
+    fn calculate_checksum(&self) -> Hash256 {
+        Hash256::hash(
+            serde_spb::to_string(&(
+                self.version,
+                self.height,
+                self.round,
+                &self.state,
+                &self.own_votes,
+            ))
+            .unwrap(),
+        )
+    }
+
+    /// Verifies the version and the checksum, and decodes the state.
+    pub(crate) fn open(&self) -> Result<(State, &OwnVotes), ConsensusError> {
+        let invalid = ConsensusError::InvalidBackup;
+        if self.version != BACKUP_VERSION {
+            return Err(invalid(format!("unsupported version {}", self.version)));
+        }
+        if self.checksum != self.calculate_checksum() {
+            return Err(invalid("checksum mismatch".to_owned()));
+        }
+        let state = StateCodec::decode(&self.state)
+            .map_err(|e| invalid(format!("failed to decode the state: {e}")))?;
+        if (state.height(), state.round()) != (self.height, self.round) {
+            return Err(invalid(format!(
+                "the state is of the height {} and the round {}",
+                state.height(),
+                state.round()
+            )));
+        }
+        if self.own_votes.last_header_hash != state.block_header().to_hash256() {
+            return Err(invalid("the own votes are of another height".to_owned()));
+        }
+        Ok((state, &self.own_votes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        let state = format_vectors::fixture_state();
+        let raw_state = StateCodec::default().encode(&state);
+        let own_votes = OwnVotes::new(state.block_header().to_hash256());
+        let bundle = BackupBundle::new(&state, raw_state, own_votes);
+        assert_eq!(bundle.open().unwrap().0.height(), state.height());
+
+        let serialized = serde_spb::to_string(&bundle).unwrap();
+        let bundle: BackupBundle = serde_spb::from_str(&serialized).unwrap();
+        assert!(bundle.open().is_ok());
+
+        let mut tampered = bundle.clone();
+        tampered.round += 1;
+        assert_eq!(
+            tampered.open().err(),
+            Some(ConsensusError::InvalidBackup(
+                "checksum mismatch".to_owned()
+            ))
+        );
+        let mut future = bundle;
+        future.version += 1;
+        future.checksum = future.calculate_checksum();
+        assert!(matches!(
+            future.open(),
+            Err(ConsensusError::InvalidBackup(reason)) if reason.starts_with("unsupported")
+        ));
+    }
+}
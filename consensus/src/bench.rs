@@ -0,0 +1,29 @@
+use super::*;
+
+/// Creates the filter of four validators for the height `1`, reporting to the given metrics,
+/// with a prevote of one of them that it admits every time.
+pub fn bench_filter(
+    metrics: Arc<dyn ConsensusMetrics>,
+) -> (
+    ConsensusMessageFilter,
+    ConsensusMessage,
+    MessageCommitmentProof,
+) {
+    let keys = (0..4)
+        .map(|i| generate_keypair(format!("validator{i}")).1)
+        .collect::<Vec<_>>();
+    let dms_key = "consensus".to_owned();
+    let filter = ConsensusMessageFilter::new(
+        Arc::new(parking_lot::RwLock::new(BTreeSet::new())),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        keys.iter().map(|key| key.public_key()).collect(),
+        1,
+        dms_key.clone(),
+    )
+    .with_metrics(metrics);
+    let message = ConsensusMessage::NilPreVoted(1, 0);
+    let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+    (filter, message, commitment)
+}
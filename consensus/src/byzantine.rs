@@ -0,0 +1,110 @@
+use super::*;
+
+/// A consensus node that misbehaves on demand, for the adversarial tests.
+///
+/// The messages are signed with the key of the node and committed to its DMS right away,
+/// bypassing the protection against double signing, so they are gossiped like the honest ones.
+/// The node can still be driven honestly through the wrapped `Consensus` in between.
+pub struct ByzantineConsensus<'a, S: Storage> {
+    consensus: &'a mut Consensus<S>,
+    height: BlockHeight,
+    public_key: PublicKey,
+}
+
+impl<'a, S: Storage> ByzantineConsensus<'a, S> {
+    /// Wraps the consensus of a validator.
+    pub async fn new(consensus: &'a mut Consensus<S>) -> Result<Self, ConsensusError> {
+        let state = consensus.read_state().await?;
+        let status = state.status();
+        let this_node_index = status.this_node_index.ok_or(ConsensusError::Observer)?;
+        let public_key = state.block_header().validator_set[this_node_index]
+            .0
+            .clone();
+        Ok(Self {
+            consensus,
+            height: status.height,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Prevotes for two different blocks in the same round.
+    pub async fn double_prevote(
+        &mut self,
+        round: ConsensusRound,
+        first: Hash256,
+        second: Hash256,
+    ) -> Result<(), ConsensusError> {
+        self.consensus
+            .commit_message_unchecked(&ConsensusMessage::NonNilPreVoted(self.height, round, first))
+            .await?;
+        self.consensus
+            .commit_message_unchecked(&ConsensusMessage::NonNilPreVoted(
+                self.height,
+                round,
+                second,
+            ))
+            .await
+    }
+
+    /// Proposes the block regardless of whether this node is the proposer of the round.
+    pub async fn propose(
+        &mut self,
+        round: ConsensusRound,
+        block_hash: Hash256,
+    ) -> Result<(), ConsensusError> {
+        self.consensus
+            .commit_message_unchecked(&ConsensusMessage::Proposal {
+                height: self.height,
+                round,
+                valid_round: None,
+                block_hash,
+            })
+            .await
+    }
+
+    /// Prevotes for the block even if it is not verified, by this node or by the others.
+    pub async fn prevote_unverified(
+        &mut self,
+        round: ConsensusRound,
+        block_hash: Hash256,
+    ) -> Result<(), ConsensusError> {
+        self.consensus
+            .commit_message_unchecked(&ConsensusMessage::NonNilPreVoted(
+                self.height,
+                round,
+                block_hash,
+            ))
+            .await
+    }
+
+    /// Signs again every message of the round that this node has signed before,
+    /// returning the number of them.
+    pub async fn replay_round(&mut self, round: ConsensusRound) -> Result<usize, ConsensusError> {
+        let messages = self
+            .consensus
+            .dms
+            .read()
+            .await
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)?
+            .into_iter()
+            .filter(|message| {
+                message.message.vote_key().0 == round
+                    && message
+                        .committers
+                        .iter()
+                        .any(|commitment| commitment.committer == self.public_key)
+            })
+            .map(|message| message.message)
+            .collect::<Vec<_>>();
+        for message in messages.iter() {
+            self.consensus.commit_message_unchecked(message).await?;
+        }
+        Ok(messages.len())
+    }
+}
@@ -0,0 +1,142 @@
+use super::*;
+
+/// What `ClockSource` does with a timestamp of the caller earlier than the latest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockRegression {
+    /// Takes it as it is.
+    #[default]
+    Allow,
+    /// Takes the latest one instead.
+    Clamp,
+    /// Fails with `ConsensusError::ClockRegression`.
+    Reject,
+}
+
+/// How `Consensus` normalizes the timestamps before they reach the state machine.
+///
+/// The default takes every timestamp as it is given.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClockPolicy {
+    /// What to do with a timestamp given to `Consensus::progress()` and the others
+    /// earlier than the latest one, which would move the deadlines back.
+    pub regression: ClockRegression,
+    /// How far the timestamps of the local clock (e.g., when the messages of the peers
+    /// are received in `update()`) may be from the latest one of the caller,
+    /// beyond which they are clamped; unbounded if `None`.
+    pub max_skew_ms: Option<Timestamp>,
+}
+
+/// Normalizes the timestamps as the `ClockPolicy` says, in memory only.
+///
+/// The latest timestamp is kept across the heights, but not across the restarts.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClockSource {
+    policy: ClockPolicy,
+    /// The latest timestamp of the caller that has been normalized.
+    latest: Option<Timestamp>,
+}
+
+impl ClockSource {
+    pub(crate) fn set_policy(&mut self, policy: ClockPolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the timestamp of the caller as it would be normalized, without recording it.
+    pub(crate) fn peek(&self, timestamp: Timestamp) -> Result<Timestamp, ConsensusError> {
+        let latest = match self.latest {
+            Some(latest) if timestamp < latest => latest,
+            _ => return Ok(timestamp),
+        };
+        match self.policy.regression {
+            ClockRegression::Allow => Ok(timestamp),
+            ClockRegression::Clamp => Ok(latest),
+            ClockRegression::Reject => Err(ConsensusError::ClockRegression { timestamp, latest }),
+        }
+    }
+
+    /// Normalizes the timestamp given by the caller, recording it as the latest one.
+    pub(crate) fn normalize(&mut self, timestamp: Timestamp) -> Result<Timestamp, ConsensusError> {
+        let timestamp = self.peek(timestamp)?;
+        self.latest = Some(
+            self.latest
+                .map_or(timestamp, |latest| latest.max(timestamp)),
+        );
+        Ok(timestamp)
+    }
+
+    /// Normalizes the timestamp of the local clock, which is bounded around the latest one
+    /// of the caller, and never earlier than it unless the regressions are allowed.
+    pub(crate) fn local(&self, timestamp: Timestamp) -> Timestamp {
+        let latest = match self.latest {
+            Some(latest) => latest,
+            None => return timestamp,
+        };
+        let timestamp = match self.policy.max_skew_ms {
+            Some(skew) => {
+                let skew = skew.max(0);
+                timestamp.clamp(latest.saturating_sub(skew), latest.saturating_add(skew))
+            }
+            None => timestamp,
+        };
+        match self.policy.regression {
+            ClockRegression::Allow => timestamp,
+            ClockRegression::Clamp | ClockRegression::Reject => timestamp.max(latest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regression() {
+        let mut clock = ClockSource::default();
+        assert_eq!(clock.normalize(100), Ok(100));
+        assert_eq!(clock.normalize(50), Ok(50));
+        assert_eq!(clock.local(10), 10);
+
+        clock.set_policy(ClockPolicy {
+            regression: ClockRegression::Clamp,
+            max_skew_ms: None,
+        });
+        assert_eq!(clock.normalize(50), Ok(100));
+        assert_eq!(clock.normalize(150), Ok(150));
+        assert_eq!(clock.local(10), 150);
+
+        clock.set_policy(ClockPolicy {
+            regression: ClockRegression::Reject,
+            max_skew_ms: None,
+        });
+        assert_eq!(clock.peek(200), Ok(200));
+        assert_eq!(
+            clock.normalize(149),
+            Err(ConsensusError::ClockRegression {
+                timestamp: 149,
+                latest: 150
+            })
+        );
+        assert_eq!(clock.normalize(150), Ok(150));
+    }
+
+    #[test]
+    fn skew() {
+        let mut clock = ClockSource::default();
+        clock.set_policy(ClockPolicy {
+            regression: ClockRegression::Allow,
+            max_skew_ms: Some(1000),
+        });
+        // Nothing to bound it by yet
+        assert_eq!(clock.local(1_000_000), 1_000_000);
+        clock.normalize(5000).unwrap();
+        assert_eq!(clock.local(1_000_000), 6000);
+        assert_eq!(clock.local(4500), 4500);
+        assert_eq!(clock.local(0), 4000);
+        clock.set_policy(ClockPolicy {
+            regression: ClockRegression::Clamp,
+            max_skew_ms: Some(1000),
+        });
+        assert_eq!(clock.local(4500), 5000);
+        assert_eq!(clock.local(5500), 5500);
+    }
+}
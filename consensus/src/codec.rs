@@ -0,0 +1,312 @@
+use super::*;
+use serde::de::DeserializeOwned;
+
+/// The version of the schema of `State` in the storage.
+///
+/// It must be bumped on every change of `State` (including the types in it),
+/// freezing the previous schema as `StateV{n}` to be migrated in `migrate()`.
+pub const STATE_VERSION: u32 = 6;
+
+/// The encoding of the consensus state in the storage.
+///
+/// The stored state is tagged with its codec, so it can be read whichever codec is chosen;
+/// the untagged one is from the versions before the codecs, which is always bincode.
+/// (JSON is not an option since the state has the maps with non-string keys.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StateCodec {
+    /// The most compact and the fastest.
+    #[default]
+    Bincode,
+    /// Self-describing, so the stored state can be inspected by generic tools.
+    Cbor,
+}
+
+impl StateCodec {
+    fn name(&self) -> &'static str {
+        match self {
+            StateCodec::Bincode => "bincode",
+            StateCodec::Cbor => "cbor",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [StateCodec::Bincode, StateCodec::Cbor]
+            .into_iter()
+            .find(|codec| codec.name() == name)
+    }
+
+    fn serialize<T: Serialize>(&self, state: &T) -> Vec<u8> {
+        match self {
+            StateCodec::Bincode => serde_spb::to_vec(state).unwrap(),
+            StateCodec::Cbor => {
+                let mut data = Vec::new();
+                ciborium::ser::into_writer(state, &mut data).unwrap();
+                data
+            }
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, String> {
+        match self {
+            StateCodec::Bincode => serde_spb::from_slice(data).map_err(|e| e.to_string()),
+            StateCodec::Cbor => ciborium::de::from_reader(data).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Encodes the state in the format of `{codec}:{version}:{checksum}:{data in hex}`.
+    ///
+    /// The storage holds only strings, so the data is hex-encoded.
+    pub(crate) fn encode(&self, state: &State) -> String {
+        let data = self.serialize(state);
+        format!(
+            "{}:{STATE_VERSION}:{}:{}",
+            self.name(),
+            Hash256::hash(&data),
+            hex::encode(&data)
+        )
+    }
+
+    /// Decodes the state encoded by any of the codecs, migrating it from an older version.
+    ///
+    /// Fails with `ConsensusError::UnsupportedStateVersion` if it's from a newer version,
+    /// or with `ConsensusError::CorruptState` describing the failure (as of `state.json`,
+    /// which the caller replaces with the actual file) otherwise.
+    pub(crate) fn decode(raw_state: &str) -> Result<State, ConsensusError> {
+        let corrupt = |reason| ConsensusError::CorruptState {
+            file: StateFile::Primary.name().to_owned(),
+            reason,
+        };
+        // The bare hex is of the version 0, before the checksums and the versions;
+        // the ones with the checksums but without the versions are taken as the version 1.
+        let (codec, version, checksum, data) = match raw_state.split(':').collect::<Vec<_>>()[..] {
+            [data] => ("bincode", "0", None, data),
+            [checksum, data] => ("bincode", "1", Some(checksum), data),
+            [codec, checksum, data] => (codec, "1", Some(checksum), data),
+            [codec, version, checksum, data] => (codec, version, Some(checksum), data),
+            _ => return Err(corrupt("too many fields".to_owned())),
+        };
+        let codec = StateCodec::from_name(codec)
+            .ok_or_else(|| corrupt(format!("unknown codec {codec}")))?;
+        let version = version
+            .parse::<u32>()
+            .map_err(|e| corrupt(format!("invalid version {version}: {e}")))?;
+        if version > STATE_VERSION {
+            return Err(ConsensusError::UnsupportedStateVersion(version));
+        }
+        let data = hex::decode(data).map_err(|e| corrupt(format!("invalid hex: {e}")))?;
+        if checksum.is_some_and(|checksum| Hash256::hash(&data).to_string() != checksum) {
+            return Err(corrupt("checksum mismatch".to_owned()));
+        }
+        migrate(codec, version, &data).map_err(|e| corrupt(format!("invalid state: {e}")))
+    }
+}
+
+/// Reads the state of the given version, upgrading it to the current one.
+///
+/// To add a version `n + 1`, freeze the current `State` as `StateV{n}` (with the types in it
+/// that are about to change), decode the version `n` as it, and convert it field by field
+/// (e.g., filling a new field with the default value) to the next version.
+fn migrate(codec: StateCodec, version: u32, data: &[u8]) -> Result<State, String> {
+    match version {
+        STATE_VERSION => codec.deserialize(data),
+        5 => codec.deserialize::<StateV5>(data).map(State::from),
+        4 => codec
+            .deserialize::<StateV4>(data)
+            .map(|state| State::from(StateV5::from(state))),
+        3 => codec
+            .deserialize::<StateV3>(data)
+            .map(|state| State::from(StateV5::from(StateV4::from(state)))),
+        2 => codec
+            .deserialize::<StateV2>(data)
+            .map(|state| State::from(StateV5::from(StateV4::from(StateV3::from(state))))),
+        1 => codec.deserialize::<StateV1>(data).map(|state| {
+            State::from(StateV5::from(StateV4::from(StateV3::from(StateV2::from(
+                state,
+            )))))
+        }),
+        0 => codec.deserialize::<StateV0>(data).map(|state| {
+            State::from(StateV5::from(StateV4::from(StateV3::from(StateV2::from(
+                StateV1::from(state),
+            )))))
+        }),
+        _ => Err(format!("no migration from the version {version}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a state that has processed the given number of prevotes in the future rounds.
+    fn setup(updated_events: usize) -> State {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let mut state = State::new(
+            &fi.header,
+            ConsensusParams {
+                timeout_ms: 6000,
+                timeout_increment_ms: 0,
+                repeat_round_for_first_leader: 10,
+                quorum: None,
+            },
+            0,
+            Some(keys[1].0.clone()),
+        )
+        .unwrap();
+        // A single validator can't make the others skip the rounds.
+        let prevotes = (1..=updated_events as ConsensusRound)
+            .map(|round| {
+                (
+                    ConsensusMessage::NilPreVoted(state.height(), round),
+                    keys[0].0.clone(),
+                )
+            })
+            .collect();
+        state
+            .add_consensus_messages(prevotes, 0, &|_| Some(true))
+            .unwrap();
+        state.progress(0).unwrap();
+        state
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = setup(10);
+        let expected = serde_spb::to_vec(&state).unwrap();
+        for codec in [StateCodec::Bincode, StateCodec::Cbor] {
+            let decoded = StateCodec::decode(&codec.encode(&state)).unwrap();
+            assert_eq!(serde_spb::to_vec(&decoded).unwrap(), expected);
+        }
+
+        // The states with the checksums but before the explicit versioning, taken as the version 1
+        let v1 = StateV1::from(StateV2::from(StateV3::from(StateV4::from(StateV5::from(
+            state,
+        )))));
+        let v1 = serde_spb::to_vec(&v1).unwrap();
+        let untagged = format!("{}:{}", Hash256::hash(&v1), hex::encode(&v1));
+        let decoded = StateCodec::decode(&untagged).unwrap();
+        assert_eq!(serde_spb::to_vec(&decoded).unwrap(), expected);
+        let unversioned = format!("bincode:{untagged}");
+        let decoded = StateCodec::decode(&unversioned).unwrap();
+        assert_eq!(serde_spb::to_vec(&decoded).unwrap(), expected);
+
+        let encoded = StateCodec::Cbor.encode(&setup(10));
+        assert!(StateCodec::decode(&encoded.replacen("cbor", "json", 1)).is_err());
+        assert!(StateCodec::decode(&encoded[0..encoded.len() - 2]).is_err());
+        assert!(StateCodec::decode("").is_err());
+    }
+
+    #[test]
+    fn newer_version() {
+        let encoded = StateCodec::Bincode.encode(&setup(10));
+        let newer = encoded.replacen(
+            &format!(":{STATE_VERSION}:"),
+            &format!(":{}:", STATE_VERSION + 1),
+            1,
+        );
+        assert!(matches!(
+            StateCodec::decode(&newer),
+            Err(ConsensusError::UnsupportedStateVersion(x)) if x == STATE_VERSION + 1
+        ));
+    }
+
+    /// Compares the codecs on a state with 10k updated message hashes.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture codec::tests::comparison`.
+    #[ignore]
+    #[test]
+    fn comparison() {
+        let state = setup(10_000);
+        for codec in [StateCodec::Bincode, StateCodec::Cbor] {
+            let time = std::time::Instant::now();
+            let encoded = codec.encode(&state);
+            let encoding_time = time.elapsed();
+            let time = std::time::Instant::now();
+            StateCodec::decode(&encoded).unwrap();
+            let decoding_time = time.elapsed();
+            println!(
+                "{codec:?}: {} bytes, encoded in {encoding_time:?}, decoded in {decoding_time:?}",
+                encoded.len()
+            );
+        }
+    }
+
+    /// Encodes the fixture state in the schema of the given version.
+    fn encode_fixture(codec: StateCodec, version: u32) -> String {
+        let state = crate::format_vectors::fixture_state();
+        let data = match version {
+            0 => codec.serialize(&StateV0::from(StateV1::from(StateV2::from(StateV3::from(
+                StateV4::from(StateV5::from(state)),
+            ))))),
+            1 => codec.serialize(&StateV1::from(StateV2::from(StateV3::from(StateV4::from(
+                StateV5::from(state),
+            ))))),
+            2 => codec.serialize(&StateV2::from(StateV3::from(StateV4::from(StateV5::from(
+                state,
+            ))))),
+            3 => codec.serialize(&StateV3::from(StateV4::from(StateV5::from(state)))),
+            4 => codec.serialize(&StateV4::from(StateV5::from(state))),
+            5 => codec.serialize(&StateV5::from(state)),
+            STATE_VERSION => codec.serialize(&state),
+            _ => panic!("no schema of the version {version}"),
+        };
+        // The version 0 is written in the bare hex, as it was.
+        if version == 0 {
+            return hex::encode(&data);
+        }
+        format!(
+            "{}:{version}:{}:{}",
+            codec.name(),
+            Hash256::hash(&data),
+            hex::encode(&data)
+        )
+    }
+
+    /// The states of the baseline are migrated as they were, as the fixture state has been
+    /// through all the versions.
+    #[test]
+    fn version_0() {
+        let raw_state = encode_fixture(StateCodec::Bincode, 0);
+        assert!(!raw_state.contains(':'));
+        let decoded = StateCodec::decode(&raw_state).unwrap();
+        assert_eq!(
+            serde_spb::to_vec(&decoded).unwrap(),
+            serde_spb::to_vec(&crate::format_vectors::fixture_state()).unwrap()
+        );
+    }
+
+    /// Checks that the states of every version are always readable,
+    /// from the fixtures committed under `tests/fixtures`.
+    ///
+    /// The fixtures of a new version are the ones of `encode_fixture()`,
+    /// to be committed along with the version and kept unchanged.
+    /// The one of the version 0 was written by the code before the versioning.
+    #[test]
+    fn fixtures() {
+        let directory = format!("{}/tests/fixtures", env!("CARGO_MANIFEST_DIR"));
+        for version in 0..=STATE_VERSION {
+            // The version 0 was always in bincode.
+            let codecs = if version == 0 {
+                &[StateCodec::Bincode][..]
+            } else {
+                &[StateCodec::Bincode, StateCodec::Cbor][..]
+            };
+            for &codec in codecs {
+                let path = format!("{directory}/state_v{version}.{}.txt", codec.name());
+                let raw_state = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("failed to read the fixture {path}: {e}"));
+                // The frozen schemas encode the same as ever.
+                assert_eq!(raw_state, encode_fixture(codec, version), "{path}");
+                let status = StateCodec::decode(&raw_state).unwrap().status();
+                assert_eq!(status.this_node_index, Some(1));
+                assert_eq!(status.round, 0);
+                assert_eq!(
+                    status.verified_block_hashes,
+                    vec![Hash256::hash("block0"), Hash256::hash("block1")]
+                );
+                assert_eq!(status.vetoed_block_hashes, vec![Hash256::hash("block1")]);
+                assert!(status.vetoed_rounds.is_empty());
+                assert!(status.validator_names.iter().all(String::is_empty));
+            }
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use super::*;
+use serde::de::DeserializeOwned;
+
+pub(crate) const FINALIZATION_FILE_NAME: &str = "finalization.json";
+const OWN_VOTES_FILE_NAME: &str = "own_votes.json";
+/// The scores of the peers, with the ban list, kept across the heights.
+pub(crate) const PEER_SCORES_FILE_NAME: &str = "peer_scores.json";
+const EVIDENCE_FILE_PREFIX: &str = "evidence-";
+/// The prefix of the files of the past heights, archived by `write_archive()`.
+const ARCHIVE_FILE_PREFIX: &str = "archive-";
+/// The log of the progress results in JSON lines, kept across the heights.
+pub(crate) const EVENT_LOG_FILE_NAME: &str = "events.log";
+
+/// One of the two copies of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StateFile {
+    Primary,
+    /// The copy used when the primary one is corrupted.
+    Backup,
+}
+
+impl StateFile {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            StateFile::Primary => "state.json",
+            StateFile::Backup => "state.backup.json",
+        }
+    }
+}
+
+/// The files of a past height, archived by `write_archive()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchivedFile {
+    State,
+    Finalization,
+    /// The entries of the event log of the height.
+    Events,
+    /// The messages of the height in the DMS, if they have been archived.
+    Messages,
+}
+
+impl ArchivedFile {
+    /// Returns the name of the file of the height, e.g., `archive-3-state.json`.
+    fn name(&self, height: BlockHeight) -> String {
+        let name = match self {
+            ArchivedFile::State => StateFile::Primary.name(),
+            ArchivedFile::Finalization => FINALIZATION_FILE_NAME,
+            ArchivedFile::Events => EVENT_LOG_FILE_NAME,
+            ArchivedFile::Messages => "messages.json",
+        };
+        format!("{ARCHIVE_FILE_PREFIX}{height}-{name}")
+    }
+}
+
+/// Returns the height of the archived file, or `None` if it's not an archived file.
+fn archived_height(name: &str) -> Option<BlockHeight> {
+    let (height, _) = name.strip_prefix(ARCHIVE_FILE_PREFIX)?.split_once('-')?;
+    height.parse().ok()
+}
+
+/// The messages of a past height with their commitments.
+pub(crate) type ArchivedMessages = Vec<(ConsensusMessage, Vec<MessageCommitmentProof>)>;
+
+/// The files of the consensus in a `Storage`, which owns their names and their encoding,
+/// so that the features never make up a file name of their own.
+///
+/// Every file but the state (which is encoded by `StateCodec`) is sealed by `envelope::seal()`,
+/// and every file is encrypted if the key is given.
+pub(crate) struct ConsensusStorage<S> {
+    storage: S,
+    encryption_key: Option<StorageEncryptionKey>,
+}
+
+impl<S: Storage> ConsensusStorage<S> {
+    pub(crate) fn new(storage: S, encryption_key: Option<StorageEncryptionKey>) -> Self {
+        Self {
+            storage,
+            encryption_key,
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> S {
+        self.storage
+    }
+
+    /// Reads and decrypts the file, or returns `None` if there is none.
+    async fn read(&self, name: &str) -> Result<Option<String>, ConsensusError> {
+        let raw = match self.storage.read_file(name).await {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ConsensusError::storage(e)),
+        };
+        Ok(Some(encryption::decrypt(
+            self.encryption_key.as_ref(),
+            name,
+            &raw,
+        )?))
+    }
+
+    /// Reads, unseals and deserializes the file, or returns `None` if there is none.
+    async fn read_sealed<T: DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<Option<T>, ConsensusError> {
+        match self.read(name).await? {
+            Some(raw) => Ok(Some(envelope::open(name, &raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the operation to write the content, encrypted if the key is given.
+    fn write_op(&self, name: String, content: String) -> StorageOp {
+        let content = match &self.encryption_key {
+            Some(key) => encryption::encrypt(key, &content),
+            None => content,
+        };
+        StorageOp::AddOrOverwrite { name, content }
+    }
+
+    /// Returns the operation to write the content sealed by `envelope::seal()`.
+    fn sealed_write_op(&self, name: String, content: &str) -> StorageOp {
+        self.write_op(name, envelope::seal(content))
+    }
+
+    async fn write_sealed(&mut self, name: &str, content: &str) -> Result<(), ConsensusError> {
+        let op = self.sealed_write_op(name.to_owned(), content);
+        self.apply_batch(vec![op]).await
+    }
+
+    /// Applies the operations (made by the `*_op()` methods) atomically.
+    pub(crate) async fn apply_batch(&mut self, ops: Vec<StorageOp>) -> Result<(), ConsensusError> {
+        self.storage
+            .apply_batch(ops)
+            .await
+            .map_err(ConsensusError::storage)
+    }
+
+    /// Returns the names of the files with the prefix in the ascending order.
+    async fn list_files_with_prefix(&self, prefix: &str) -> Result<Vec<String>, ConsensusError> {
+        let mut names = self
+            .storage
+            .list_files()
+            .await
+            .map_err(ConsensusError::storage)?
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    async fn remove(&mut self, name: &str) -> Result<(), ConsensusError> {
+        self.storage
+            .remove_file(name)
+            .await
+            .map_err(ConsensusError::storage)
+    }
+
+    /// Returns whether there is a state (even a corrupted one).
+    pub(crate) async fn has_state(&self) -> Result<bool, ConsensusError> {
+        let names = self
+            .storage
+            .list_files()
+            .await
+            .map_err(ConsensusError::storage)?;
+        Ok(names
+            .iter()
+            .any(|name| name == StateFile::Primary.name() || name == StateFile::Backup.name()))
+    }
+
+    /// Reads the state, failing with `ConsensusError::StateNotInitialized` if there is none.
+    pub(crate) async fn read_state(&self, file: StateFile) -> Result<State, ConsensusError> {
+        let raw_state = self
+            .read(file.name())
+            .await?
+            .ok_or(ConsensusError::StateNotInitialized)?;
+        decode_state(file.name(), &raw_state)
+    }
+
+    /// Returns the operations to write the encoded state to both of the copies.
+    pub(crate) fn state_ops(&self, raw_state: &str) -> Vec<StorageOp> {
+        [StateFile::Primary, StateFile::Backup]
+            .iter()
+            .map(|file| self.write_op(file.name().to_owned(), raw_state.to_owned()))
+            .collect()
+    }
+
+    pub(crate) async fn read_finalization(&self) -> Result<Option<Finalization>, ConsensusError> {
+        self.read_sealed(FINALIZATION_FILE_NAME).await
+    }
+
+    pub(crate) fn finalization_op(&self, finalization: &Finalization) -> StorageOp {
+        self.sealed_write_op(
+            FINALIZATION_FILE_NAME.to_owned(),
+            &serde_spb::to_string(finalization).unwrap(),
+        )
+    }
+
+    /// Returns the operation to remove the finalization (e.g., for a state not finalized).
+    pub(crate) fn remove_finalization_op(&self) -> StorageOp {
+        StorageOp::Remove {
+            name: FINALIZATION_FILE_NAME.to_owned(),
+        }
+    }
+
+    /// Reads the event log in JSON lines, or `None` if there is none.
+    pub(crate) async fn read_event_log(&self) -> Result<Option<String>, ConsensusError> {
+        match self.read(EVENT_LOG_FILE_NAME).await? {
+            Some(raw) => Ok(Some(
+                envelope::unseal(EVENT_LOG_FILE_NAME, &raw)?.to_owned(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn event_log_op(&self, event_log: &str) -> StorageOp {
+        self.sealed_write_op(EVENT_LOG_FILE_NAME.to_owned(), event_log)
+    }
+
+    pub(crate) async fn read_own_votes(&self) -> Result<Option<OwnVotes>, ConsensusError> {
+        self.read_sealed(OWN_VOTES_FILE_NAME).await
+    }
+
+    pub(crate) async fn write_own_votes(
+        &mut self,
+        own_votes: &OwnVotes,
+    ) -> Result<(), ConsensusError> {
+        let op = self.own_votes_op(own_votes);
+        self.apply_batch(vec![op]).await
+    }
+
+    pub(crate) fn own_votes_op(&self, own_votes: &OwnVotes) -> StorageOp {
+        self.sealed_write_op(
+            OWN_VOTES_FILE_NAME.to_owned(),
+            &serde_spb::to_string(own_votes).unwrap(),
+        )
+    }
+
+    pub(crate) async fn read_peer_scores(&self) -> Result<Option<PeerScores>, ConsensusError> {
+        self.read_sealed(PEER_SCORES_FILE_NAME).await
+    }
+
+    pub(crate) async fn write_peer_scores(
+        &mut self,
+        peer_scores: &PeerScores,
+    ) -> Result<(), ConsensusError> {
+        self.write_sealed(
+            PEER_SCORES_FILE_NAME,
+            &serde_spb::to_string(peer_scores).unwrap(),
+        )
+        .await
+    }
+
+    /// Reads all the evidence, in the order of the hashes.
+    pub(crate) async fn read_evidence(&self) -> Result<Vec<Evidence>, ConsensusError> {
+        let mut result = Vec::new();
+        for name in self.list_files_with_prefix(EVIDENCE_FILE_PREFIX).await? {
+            result.extend(self.read_sealed(&name).await?);
+        }
+        Ok(result)
+    }
+
+    pub(crate) async fn write_evidence(
+        &mut self,
+        evidence: &Evidence,
+    ) -> Result<(), ConsensusError> {
+        self.write_sealed(
+            &format!("{EVIDENCE_FILE_PREFIX}{}.json", evidence.to_hash256()),
+            &serde_spb::to_string(evidence).unwrap(),
+        )
+        .await
+    }
+
+    /// Removes the evidence that can't be read, returning the names of the files.
+    pub(crate) async fn remove_corrupt_evidence(&mut self) -> Result<Vec<String>, ConsensusError> {
+        let mut removed = Vec::new();
+        for name in self.list_files_with_prefix(EVIDENCE_FILE_PREFIX).await? {
+            if let Err(e) = self.read_sealed::<Evidence>(&name).await {
+                if !is_corrupt(&e) {
+                    return Err(e);
+                }
+                self.remove(&name).await?;
+                removed.push(name);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns the archived heights in the ascending order.
+    pub(crate) async fn archived_heights(&self) -> Result<Vec<BlockHeight>, ConsensusError> {
+        Ok(self
+            .list_files_with_prefix(ARCHIVE_FILE_PREFIX)
+            .await?
+            .iter()
+            .filter_map(|name| archived_height(name))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    /// Archives the files of the finalized height at once.
+    ///
+    /// The events are empty if the event log is off, and so are the messages
+    /// if they are not archived.
+    pub(crate) async fn write_archive(
+        &mut self,
+        height: BlockHeight,
+        raw_state: &str,
+        finalization: &Finalization,
+        events: &str,
+        messages: Option<&ArchivedMessages>,
+    ) -> Result<(), ConsensusError> {
+        let mut ops = vec![
+            self.write_op(ArchivedFile::State.name(height), raw_state.to_owned()),
+            self.sealed_write_op(
+                ArchivedFile::Finalization.name(height),
+                &serde_spb::to_string(finalization).unwrap(),
+            ),
+        ];
+        if !events.is_empty() {
+            ops.push(self.sealed_write_op(ArchivedFile::Events.name(height), events));
+        }
+        if let Some(messages) = messages {
+            ops.push(self.sealed_write_op(
+                ArchivedFile::Messages.name(height),
+                &serde_spb::to_string(messages).unwrap(),
+            ));
+        }
+        self.apply_batch(ops).await
+    }
+
+    pub(crate) async fn read_archived_state(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Option<State>, ConsensusError> {
+        let name = ArchivedFile::State.name(height);
+        match self.read(&name).await? {
+            Some(raw_state) => Ok(Some(decode_state(&name, &raw_state)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn read_archived_finalization(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Option<Finalization>, ConsensusError> {
+        self.read_sealed(&ArchivedFile::Finalization.name(height))
+            .await
+    }
+
+    /// Reads the entries of the event log of the past height, which are empty if there are none.
+    pub(crate) async fn read_archived_events(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Vec<EventLogEntry>, ConsensusError> {
+        let name = ArchivedFile::Events.name(height);
+        match self.read(&name).await? {
+            Some(raw) => Ok(parse_event_log(&name, envelope::unseal(&name, &raw)?)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub(crate) async fn read_archived_messages(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Option<ArchivedMessages>, ConsensusError> {
+        self.read_sealed(&ArchivedFile::Messages.name(height)).await
+    }
+
+    /// Removes all the archived files of the height.
+    pub(crate) async fn remove_archive(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<(), ConsensusError> {
+        for name in self.list_files_with_prefix(ARCHIVE_FILE_PREFIX).await? {
+            if archived_height(&name) == Some(height) {
+                self.remove(&name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the files of the current height (i.e., the state and the finalization),
+    /// keeping the ones that must survive across the heights: the record of the messages
+    /// signed by this node, the peer scores, the evidence, the archives and the event log.
+    pub(crate) async fn clear_height(&mut self) -> Result<(), ConsensusError> {
+        let names = self
+            .storage
+            .list_files()
+            .await
+            .map_err(ConsensusError::storage)?;
+        for name in names {
+            let kept = [
+                OWN_VOTES_FILE_NAME,
+                PEER_SCORES_FILE_NAME,
+                EVENT_LOG_FILE_NAME,
+            ]
+            .contains(&name.as_str())
+                || name.starts_with(EVIDENCE_FILE_PREFIX)
+                || name.starts_with(ARCHIVE_FILE_PREFIX);
+            if !kept {
+                self.remove(&name).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the state in the file, naming the file in the error.
+fn decode_state(file: &str, raw_state: &str) -> Result<State, ConsensusError> {
+    StateCodec::decode(raw_state).map_err(|e| match e {
+        ConsensusError::CorruptState { reason, .. } => ConsensusError::CorruptState {
+            file: file.to_owned(),
+            reason,
+        },
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::{create_temp_dir, MemoryStorage};
+
+    fn finalization(height: BlockHeight) -> Finalization {
+        Finalization {
+            block_hash: Hash256::hash(format!("block {height}")),
+            timestamp: height as Timestamp,
+            proof: FinalizationProof {
+                round: 0,
+                signatures: Vec::new(),
+            },
+        }
+    }
+
+    /// Lists the archives in the order of the heights (not of the names),
+    /// and removes them and the files of the current height.
+    async fn enumeration_and_deletion<S: Storage>(storage: S) {
+        let mut storage = ConsensusStorage::new(storage, None);
+        let state = format_vectors::fixture_state();
+        let raw_state = StateCodec::default().encode(&state);
+        for height in [10, 2, 1] {
+            storage
+                .write_archive(height, &raw_state, &finalization(height), "", None)
+                .await
+                .unwrap();
+        }
+        let ops = storage.state_ops(&raw_state);
+        storage.apply_batch(ops).await.unwrap();
+        storage
+            .write_own_votes(&OwnVotes::new(Hash256::hash("header")))
+            .await
+            .unwrap();
+        assert_eq!(storage.archived_heights().await.unwrap(), vec![1, 2, 10]);
+        assert_eq!(
+            storage.read_archived_finalization(2).await.unwrap(),
+            Some(finalization(2))
+        );
+        assert!(storage.read_archived_messages(2).await.unwrap().is_none());
+
+        storage.remove_archive(2).await.unwrap();
+        assert_eq!(storage.archived_heights().await.unwrap(), vec![1, 10]);
+        assert!(storage.read_archived_state(2).await.unwrap().is_none());
+        assert!(storage.read_archived_state(10).await.unwrap().is_some());
+
+        // Only the files of the current height are removed.
+        storage.clear_height().await.unwrap();
+        assert!(!storage.has_state().await.unwrap());
+        assert_eq!(storage.archived_heights().await.unwrap(), vec![1, 10]);
+        assert!(storage.read_own_votes().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn enumeration_and_deletion_on_file_system() {
+        let dir = create_temp_dir();
+        StorageImpl::create(&dir).await.unwrap();
+        enumeration_and_deletion(StorageImpl::open(&dir).await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn enumeration_and_deletion_in_memory() {
+        enumeration_and_deletion(MemoryStorage::new().await).await;
+    }
+}
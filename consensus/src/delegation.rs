@@ -0,0 +1,258 @@
+use super::*;
+
+/// The domain of the signatures on the delegations,
+/// which keeps them from being replayed in the other protocols signed by the identity key.
+const DELEGATION_DOMAIN: &str = "simperby-consensus-delegation";
+
+/// The statement of a validator authorizing a consensus key to sign its consensus messages
+/// in the heights from `from_height` to `to_height` (inclusive),
+/// so that the key in the validator set (the identity key) can be kept cold.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Delegation {
+    /// The key of the validator in the validator set.
+    pub identity: PublicKey,
+    /// The key that signs the consensus messages for the validator.
+    pub consensus_key: PublicKey,
+    pub from_height: BlockHeight,
+    pub to_height: BlockHeight,
+    /// Increases with every delegation of the validator, so that a newer one supersedes
+    /// the older ones (e.g., to rotate the consensus key or to revoke it).
+    pub sequence: u64,
+}
+
+impl ToHash256 for Delegation {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(&(DELEGATION_DOMAIN, self)).unwrap())
+    }
+}
+
+/// A delegation signed by the identity key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SignedDelegation {
+    pub delegation: Delegation,
+    pub signature: TypedSignature<Delegation>,
+}
+
+impl SignedDelegation {
+    pub fn sign(delegation: Delegation, identity_key: &PrivateKey) -> Result<Self, ConsensusError> {
+        let signature = TypedSignature::sign(&delegation, identity_key)
+            .map_err(|e| ConsensusError::Signer(e.to_string()))?;
+        Ok(Self {
+            delegation,
+            signature,
+        })
+    }
+
+    /// Verifies that the delegation is signed by its identity key.
+    pub fn verify(&self) -> Result<(), ConsensusError> {
+        if *self.signature.signer() != self.delegation.identity {
+            return Err(ConsensusError::InvalidProof(format!(
+                "delegation: the delegation of {} is signed by {}",
+                self.delegation.identity,
+                self.signature.signer()
+            )));
+        }
+        self.signature
+            .verify(&self.delegation)
+            .map_err(|e| ConsensusError::InvalidProof(format!("signature on the delegation: {e}")))
+    }
+}
+
+/// The delegations of the validators, which tell the validator that a key signs for.
+///
+/// At each height, a validator signs with exactly one key: the consensus key of the newest
+/// delegation covering the height if any, or its identity key otherwise.
+/// Only the newest delegation of each consensus key counts, so a key is revoked
+/// by delegating it again with the range ending earlier (or empty).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegations {
+    delegations: Vec<SignedDelegation>,
+}
+
+impl Delegations {
+    /// Adds the delegation, superseding the one of the same consensus key.
+    ///
+    /// It fails if the delegation is not newer than every other one of the validator,
+    /// so that the older ones can't be replayed; adding the same one again is a no-op.
+    pub fn add(&mut self, delegation: SignedDelegation) -> Result<(), ConsensusError> {
+        delegation.verify()?;
+        if self.delegations.contains(&delegation) {
+            return Ok(());
+        }
+        let new = &delegation.delegation;
+        if let Some(latest) = self.latest_sequence(&new.identity) {
+            if new.sequence <= latest {
+                return Err(ConsensusError::InvalidProof(format!(
+                    "delegation: the delegation of {} with the sequence {} is not newer than {latest}",
+                    new.identity,
+                    new.sequence
+                )));
+            }
+        }
+        if let Some(other) = self.delegations.iter().find(|x| {
+            x.delegation.consensus_key == new.consensus_key && x.delegation.identity != new.identity
+        }) {
+            return Err(ConsensusError::InvalidProof(format!(
+                "delegation: the consensus key {} is already delegated by {}",
+                new.consensus_key, other.delegation.identity
+            )));
+        }
+        self.delegations.retain(|x| {
+            x.delegation.identity != new.identity || x.delegation.consensus_key != new.consensus_key
+        });
+        self.delegations.push(delegation);
+        Ok(())
+    }
+
+    /// Returns the validator that the key signs for at the height, if it can:
+    /// the identity that has delegated to it, or the key itself if it hasn't delegated.
+    ///
+    /// Whether the result is in the validator set is up to the caller.
+    pub fn resolve(&self, key: &PublicKey, height: BlockHeight) -> Option<PublicKey> {
+        if let Some(delegation) = self
+            .delegations
+            .iter()
+            .find(|x| x.delegation.consensus_key == *key)
+        {
+            let identity = &delegation.delegation.identity;
+            return (self.signing_key(identity, height) == *key).then(|| identity.clone());
+        }
+        (self.signing_key(key, height) == *key).then(|| key.clone())
+    }
+
+    /// Returns the key that the validator signs with at the height.
+    pub fn signing_key(&self, identity: &PublicKey, height: BlockHeight) -> PublicKey {
+        self.delegations
+            .iter()
+            .map(|x| &x.delegation)
+            .filter(|x| x.identity == *identity && (x.from_height..=x.to_height).contains(&height))
+            .max_by_key(|x| x.sequence)
+            .map_or_else(|| identity.clone(), |x| x.consensus_key.clone())
+    }
+
+    /// Removes the delegations ending before the height, except the newest one of each
+    /// validator which is kept to reject the older ones.
+    pub fn prune(&mut self, height: BlockHeight) {
+        let mut latest = BTreeMap::new();
+        for x in &self.delegations {
+            let sequence = latest.entry(x.delegation.identity.clone()).or_default();
+            *sequence = x.delegation.sequence.max(*sequence);
+        }
+        self.delegations.retain(|x| {
+            x.delegation.to_height >= height
+                || latest.get(&x.delegation.identity) == Some(&x.delegation.sequence)
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SignedDelegation> {
+        self.delegations.iter()
+    }
+
+    fn latest_sequence(&self, identity: &PublicKey) -> Option<u64> {
+        self.delegations
+            .iter()
+            .filter(|x| x.delegation.identity == *identity)
+            .map(|x| x.delegation.sequence)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delegate(
+        identity: &PrivateKey,
+        consensus_key: &PublicKey,
+        heights: std::ops::RangeInclusive<BlockHeight>,
+        sequence: u64,
+    ) -> SignedDelegation {
+        SignedDelegation::sign(
+            Delegation {
+                identity: identity.public_key(),
+                consensus_key: consensus_key.clone(),
+                from_height: *heights.start(),
+                to_height: *heights.end(),
+                sequence,
+            },
+            identity,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rotation_and_revocation() {
+        let (identity, identity_key) = generate_keypair("validator");
+        let (first, _) = generate_keypair("first consensus key");
+        let (second, _) = generate_keypair("second consensus key");
+        let mut delegations = Delegations::default();
+        assert_eq!(delegations.resolve(&identity, 1), Some(identity.clone()));
+        assert_eq!(delegations.resolve(&first, 1), Some(first.clone()));
+
+        delegations
+            .add(delegate(&identity_key, &first, 1..=10, 0))
+            .unwrap();
+        assert_eq!(delegations.resolve(&first, 1), Some(identity.clone()));
+        assert_eq!(delegations.resolve(&identity, 1), None);
+        assert_eq!(delegations.resolve(&first, 11), None);
+        assert_eq!(delegations.resolve(&identity, 11), Some(identity.clone()));
+
+        // Rotated from the height 5
+        delegations
+            .add(delegate(&identity_key, &second, 5..=20, 1))
+            .unwrap();
+        assert_eq!(delegations.resolve(&first, 4), Some(identity.clone()));
+        assert_eq!(delegations.resolve(&first, 5), None);
+        assert_eq!(delegations.resolve(&second, 4), None);
+        assert_eq!(delegations.resolve(&second, 5), Some(identity.clone()));
+        assert_eq!(delegations.signing_key(&identity, 5), second);
+
+        // Revoked from the height 8, where the first one covers again
+        delegations
+            .add(delegate(&identity_key, &second, 5..=7, 2))
+            .unwrap();
+        assert_eq!(delegations.resolve(&second, 7), Some(identity.clone()));
+        assert_eq!(delegations.resolve(&second, 8), None);
+        assert_eq!(delegations.resolve(&first, 8), Some(identity.clone()));
+        assert_eq!(delegations.resolve(&identity, 11), Some(identity.clone()));
+
+        // The older ones can't be replayed, but the same one can be added again.
+        assert!(delegations
+            .add(delegate(&identity_key, &second, 5..=20, 1))
+            .is_err());
+        delegations
+            .add(delegate(&identity_key, &second, 5..=7, 2))
+            .unwrap();
+
+        delegations.prune(11);
+        assert_eq!(delegations.iter().count(), 1);
+        assert!(delegations
+            .add(delegate(&identity_key, &second, 5..=20, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let (_, identity_key) = generate_keypair("validator");
+        let (_, another_key) = generate_keypair("another validator");
+        let (consensus_key, _) = generate_keypair("consensus key");
+        let mut delegations = Delegations::default();
+
+        // Signed by another key
+        let mut delegation = delegate(&identity_key, &consensus_key, 1..=10, 0);
+        delegation.signature = delegate(&another_key, &consensus_key, 1..=10, 0).signature;
+        assert!(delegations.add(delegation).is_err());
+        // Tampered
+        let mut delegation = delegate(&identity_key, &consensus_key, 1..=10, 0);
+        delegation.delegation.to_height = 100;
+        assert!(delegations.add(delegation).is_err());
+
+        // A consensus key signs for only one validator.
+        delegations
+            .add(delegate(&identity_key, &consensus_key, 1..=10, 0))
+            .unwrap();
+        assert!(delegations
+            .add(delegate(&another_key, &consensus_key, 1..=10, 0))
+            .is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use super::*;
+
+/// How `Consensus::broadcast()` retries to send the messages of this node
+/// until enough peers have acknowledged them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastPolicy {
+    /// The number of the peers that must acknowledge a message to stop retrying it.
+    pub min_acknowledgements: usize,
+    /// The delay before the first retry, which is doubled on every retry.
+    pub initial_backoff_ms: Timestamp,
+    /// The upper bound of the delay between the retries.
+    pub max_backoff_ms: Timestamp,
+}
+
+impl Default for BroadcastPolicy {
+    fn default() -> Self {
+        Self {
+            // The peers relay the messages further by gossiping.
+            min_acknowledgements: 1,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 8_000,
+        }
+    }
+}
+
+impl BroadcastPolicy {
+    /// Returns the delay after the given number of the attempts.
+    fn backoff(&self, attempts: u32) -> Timestamp {
+        let exponent = attempts.saturating_sub(1).min(32);
+        self.initial_backoff_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff_ms)
+    }
+}
+
+/// The delivery of a message of this node to the peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryStatus {
+    pub message: ConsensusMessage,
+    /// The peers that have acknowledged the message.
+    pub acknowledged_by: BTreeSet<PublicKey>,
+    /// The number of the attempts to send the message.
+    pub attempts: u32,
+    /// When the message has been sent last, if ever.
+    pub last_attempt: Option<Timestamp>,
+    /// Whether enough peers have acknowledged the message.
+    pub delivered: bool,
+}
+
+/// The messages of this node that have been committed to the DMS in the current height,
+/// tracked until they are delivered or their rounds pass.
+///
+/// It is kept in memory, since the acknowledgements are only about the current connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Outbox {
+    deliveries: BTreeMap<Hash256, DeliveryStatus>,
+}
+
+impl Outbox {
+    pub(crate) fn track(&mut self, message: &ConsensusMessage) {
+        self.deliveries
+            .entry(message.to_hash256())
+            .or_insert_with(|| DeliveryStatus {
+                message: message.clone(),
+                acknowledged_by: BTreeSet::new(),
+                attempts: 0,
+                last_attempt: None,
+                delivered: false,
+            });
+    }
+
+    /// Drops the messages of the rounds before the given one, which are not worth retrying.
+    pub(crate) fn drop_past_rounds(&mut self, round: ConsensusRound) {
+        self.deliveries
+            .retain(|_, delivery| delivery.message.vote_key().0 >= round);
+    }
+
+    /// Returns the hashes of the undelivered messages whose backoffs have elapsed.
+    pub(crate) fn due(&self, policy: &BroadcastPolicy, timestamp: Timestamp) -> Vec<Hash256> {
+        self.deliveries
+            .iter()
+            .filter(|(_, delivery)| !delivery.delivered)
+            .filter(|(_, delivery)| match delivery.last_attempt {
+                Some(last_attempt) => {
+                    timestamp >= last_attempt.saturating_add(policy.backoff(delivery.attempts))
+                }
+                None => true,
+            })
+            .map(|(message_hash, _)| *message_hash)
+            .collect()
+    }
+
+    /// Records an attempt to send the messages, acknowledged by the given peers.
+    pub(crate) fn record_attempt(
+        &mut self,
+        message_hashes: &[Hash256],
+        acknowledged_by: &[PublicKey],
+        policy: &BroadcastPolicy,
+        timestamp: Timestamp,
+    ) {
+        for message_hash in message_hashes {
+            if let Some(delivery) = self.deliveries.get_mut(message_hash) {
+                delivery.attempts += 1;
+                delivery.last_attempt = Some(timestamp);
+                delivery
+                    .acknowledged_by
+                    .extend(acknowledged_by.iter().cloned());
+                delivery.delivered = delivery.acknowledged_by.len() >= policy.min_acknowledgements;
+            }
+        }
+    }
+
+    pub(crate) fn statuses(&self) -> Vec<DeliveryStatus> {
+        self.deliveries.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = BroadcastPolicy {
+            min_acknowledgements: 2,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        let keys = (0..2)
+            .map(|i| generate_keypair(format!("peer{i}")).0)
+            .collect::<Vec<_>>();
+        let message = ConsensusMessage::NilPreVoted(1, 3);
+        let message_hash = message.to_hash256();
+        let mut outbox = Outbox::default();
+        outbox.track(&message);
+        assert_eq!(outbox.due(&policy, 0), vec![message_hash]);
+
+        // The delays are 100, 200, 400, 800 and then 1000.
+        let mut timestamp = 0;
+        for backoff in [100, 200, 400, 800, 1000, 1000] {
+            outbox.record_attempt(&[message_hash], &[], &policy, timestamp);
+            assert!(outbox.due(&policy, timestamp + backoff - 1).is_empty());
+            assert_eq!(outbox.due(&policy, timestamp + backoff), vec![message_hash]);
+            timestamp += backoff;
+        }
+
+        outbox.record_attempt(&[message_hash], &keys[0..1], &policy, timestamp);
+        assert!(!outbox.statuses()[0].delivered);
+        outbox.record_attempt(&[message_hash], &keys, &policy, timestamp + 1000);
+        let status = &outbox.statuses()[0];
+        assert!(status.delivered);
+        assert_eq!(status.attempts, 8);
+        assert_eq!(status.acknowledged_by.len(), 2);
+        assert!(outbox.due(&policy, Timestamp::MAX).is_empty());
+
+        outbox.drop_past_rounds(3);
+        assert_eq!(outbox.statuses().len(), 1);
+        outbox.drop_past_rounds(4);
+        assert!(outbox.statuses().is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use super::*;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// The statistics of the DMS of the consensus,
+/// to tell whether the messages are arriving at all when the consensus stalls.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmsStats {
+    pub height: BlockHeight,
+    /// The number of the messages of the height in the DMS, counted by the signers.
+    pub total_messages: usize,
+    pub messages_by_kind: BTreeMap<VoteKind, usize>,
+    pub messages_by_round: BTreeMap<ConsensusRound, usize>,
+    /// When the message filter has admitted a message last since the startup, if ever.
+    pub last_admitted: Option<Timestamp>,
+    /// The number of the messages rejected by the filter since the startup, by the reason.
+    pub rejections: BTreeMap<FilterRejection, u64>,
+    /// The number of the messages rejected for `FilterRejection::VersionMismatch`
+    /// since the startup, by the protocol version of them.
+    pub version_mismatches: BTreeMap<u16, u64>,
+    /// The total size of the files in the storage of the DMS, in bytes.
+    pub storage_size: u64,
+}
+
+/// The counters of the message filter, shared with `Consensus` across the heights.
+#[derive(Debug, Default)]
+pub(crate) struct FilterCounters {
+    /// The rejected messages, indexed by `FilterRejection`.
+    rejections: [AtomicU64; FilterRejection::ALL.len()],
+    /// When a message has been admitted last, 0 if never.
+    last_admitted: AtomicI64,
+    version_mismatches: parking_lot::Mutex<BTreeMap<u16, u64>>,
+}
+
+impl FilterCounters {
+    pub(crate) fn admitted(&self, timestamp: Timestamp) {
+        self.last_admitted.fetch_max(timestamp, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rejected(&self, reason: FilterRejection) {
+        self.rejections[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn version_mismatched(&self, version: u16) {
+        *self.version_mismatches.lock().entry(version).or_default() += 1;
+    }
+
+    pub(crate) fn last_admitted(&self) -> Option<Timestamp> {
+        let timestamp = self.last_admitted.load(Ordering::Relaxed);
+        (timestamp != 0).then_some(timestamp)
+    }
+
+    pub(crate) fn rejections(&self) -> BTreeMap<FilterRejection, u64> {
+        FilterRejection::ALL
+            .iter()
+            .map(|reason| {
+                (
+                    *reason,
+                    self.rejections[*reason as usize].load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn version_mismatches(&self) -> BTreeMap<u16, u64> {
+        self.version_mismatches.lock().clone()
+    }
+}
+
+/// Counts the messages of the height by the kind and by the round.
+pub(crate) fn count_messages<'a>(
+    stats: &mut DmsStats,
+    messages: impl Iterator<Item = &'a ConsensusMessage>,
+) {
+    for message in messages.filter(|message| message.height() == stats.height) {
+        let (round, kind) = message.vote_key();
+        stats.total_messages += 1;
+        *stats.messages_by_kind.entry(kind).or_default() += 1;
+        *stats.messages_by_round.entry(round).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let block_hash = Hash256::hash("block");
+        let messages = [
+            ConsensusMessage::Proposal {
+                height: 1,
+                round: 0,
+                valid_round: None,
+                block_hash,
+            },
+            ConsensusMessage::NonNilPreVoted(1, 0, block_hash),
+            ConsensusMessage::NilPreVoted(1, 1),
+            ConsensusMessage::NilPreCommitted(1, 1),
+            // Of another height
+            ConsensusMessage::NilPreVoted(2, 0),
+        ];
+        let counters = FilterCounters::default();
+        counters.rejected(FilterRejection::OtherHeight);
+        counters.version_mismatched(2);
+        counters.admitted(10);
+        counters.admitted(5);
+        let mut stats = DmsStats {
+            height: 1,
+            total_messages: 0,
+            messages_by_kind: BTreeMap::new(),
+            messages_by_round: BTreeMap::new(),
+            last_admitted: counters.last_admitted(),
+            rejections: counters.rejections(),
+            version_mismatches: counters.version_mismatches(),
+            storage_size: 0,
+        };
+        count_messages(&mut stats, messages.iter());
+        assert_eq!(stats.total_messages, 4);
+        assert_eq!(
+            stats.messages_by_kind,
+            vec![
+                (VoteKind::Proposal, 1),
+                (VoteKind::Prevote, 2),
+                (VoteKind::Precommit, 1)
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            stats.messages_by_round,
+            vec![(0, 2), (1, 2)].into_iter().collect()
+        );
+        assert_eq!(stats.last_admitted, Some(10));
+        assert_eq!(stats.rejections[&FilterRejection::OtherHeight], 1);
+        assert_eq!(stats.rejections[&FilterRejection::TooLarge], 0);
+        assert_eq!(stats.version_mismatches, vec![(2, 1)].into_iter().collect());
+        // Printable by the CLI
+        serde_spb::to_string(&stats).unwrap();
+    }
+}
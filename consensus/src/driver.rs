@@ -0,0 +1,339 @@
+use super::*;
+
+/// What the caller of `ConsensusCore` has to do, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusEffect {
+    /// Persist the state (see `ConsensusCore::encode_state()`) before carrying out the rest.
+    PersistState,
+    /// Sign the message of this node and commit it to the DMS,
+    /// then report it back by `ConsensusCore::mark_broadcast()`.
+    Broadcast(ConsensusMessage),
+    /// Report the result to the user.
+    EmitResult(ProgressResult),
+}
+
+/// The consensus state machine of a height, without the storage, the DMS or the network.
+///
+/// Every operation is synchronous and returns the effects for the caller to carry out,
+/// so it can be driven directly (e.g., by `replay()` or a property test),
+/// while `Consensus` carries them out against the storage and the DMS.
+#[derive(Debug, Clone)]
+pub struct ConsensusCore {
+    state: State,
+}
+
+impl ConsensusCore {
+    /// Creates the consensus of the height after `block_header`,
+    /// as the validator of `this_node` or as an observer if `None`.
+    pub fn new(
+        block_header: &BlockHeader,
+        consensus_parameters: ConsensusParams,
+        round_zero_timestamp: Timestamp,
+        this_node: Option<PublicKey>,
+    ) -> Result<Self, ConsensusError> {
+        verify_validator_set(&block_header.validator_set)?;
+        let state = State::new(
+            block_header,
+            consensus_parameters,
+            round_zero_timestamp,
+            this_node,
+        )?;
+        Ok(Self { state })
+    }
+
+    fn check_not_finalized(&self) -> Result<(), ConsensusError> {
+        if self.state.check_finalized().is_some() {
+            return Err(ConsensusError::Finalized);
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> ConsensusStatus {
+        self.state.status()
+    }
+
+    /// Returns the finalization, whose proof is left empty since it is collected from the DMS.
+    pub fn check_finalized(&self) -> Option<Finalization> {
+        self.state.check_finalized()
+    }
+
+    /// Encodes the state to persist on `ConsensusEffect::PersistState`.
+    pub fn encode_state(&self, codec: &StateCodec) -> String {
+        codec.encode(&self.state)
+    }
+
+    pub fn register_verified_block_hash(
+        &mut self,
+        block_hash: Hash256,
+    ) -> Result<Vec<ConsensusEffect>, ConsensusError> {
+        self.check_not_finalized()?;
+        self.state.register_verified_block_hash(block_hash)?;
+        Ok(vec![ConsensusEffect::PersistState])
+    }
+
+    pub fn set_proposal_candidate(
+        &mut self,
+        block_hash: Hash256,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ConsensusEffect>, ConsensusError> {
+        self.check_not_finalized()?;
+        self.state.set_proposal_candidate(block_hash, timestamp)?;
+        Ok(vec![ConsensusEffect::PersistState])
+    }
+
+    /// Receives a message signed for the DMS of `dms_key`, failing if the signature is invalid.
+    ///
+    /// The proposals are considered valid if their blocks have been registered
+    /// by `register_verified_block_hash()`.
+    pub fn receive(
+        &mut self,
+        message: ConsensusMessage,
+        commitment: MessageCommitmentProof,
+        dms_key: &DmsKey,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ConsensusEffect>, ConsensusError> {
+        self.check_not_finalized()?;
+        message
+            .verify_commitment(&commitment, dms_key)
+            .map_err(|e| {
+                ConsensusError::InvalidProof(format!("signature on {}: {e}", message.to_hash256()))
+            })?;
+        let author = commitment.committer.clone();
+        self.state
+            .detect_equivocations(&[(message.clone(), commitment)], dms_key);
+        let verified = self.state.verified_block_hashes().clone();
+        self.state
+            .add_consensus_messages(vec![(message, author)], timestamp, &|block_hash| {
+                Some(verified.contains_key(block_hash))
+            })?;
+        Ok(vec![ConsensusEffect::PersistState])
+    }
+
+    /// Makes a progress at the time.
+    ///
+    /// The messages of this node are returned as `ConsensusEffect::Broadcast`
+    /// by every call until they are reported by `mark_broadcast()`.
+    pub fn progress(
+        &mut self,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ConsensusEffect>, ConsensusError> {
+        self.check_not_finalized()?;
+        let results = self.state.progress(timestamp)?;
+        let broadcasts = self.state.messages_to_broadcast().to_vec();
+        let mut effects = Vec::new();
+        if !results.is_empty() || !broadcasts.is_empty() {
+            effects.push(ConsensusEffect::PersistState);
+        }
+        effects.extend(broadcasts.into_iter().map(ConsensusEffect::Broadcast));
+        effects.extend(results.into_iter().map(ConsensusEffect::EmitResult));
+        Ok(effects)
+    }
+
+    /// Removes the message from the outbox once it has been committed to the DMS.
+    pub fn mark_broadcast(&mut self, message: &ConsensusMessage) -> Vec<ConsensusEffect> {
+        self.state.mark_message_sent(message);
+        vec![ConsensusEffect::PersistState]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    /// Every order of the arrival of the votes finalizes the proposed block
+    /// right when the precommits reach the quorum.
+    #[test]
+    fn vote_permutations() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let height = fi.header.height + 1;
+        let block_hash = Hash256::hash("block");
+        let dms_key = "consensus".to_owned();
+        let signed = |message: ConsensusMessage, i: usize| {
+            let commitment = message.commit(&dms_key, &keys[i].1).unwrap();
+            (message, commitment)
+        };
+        let proposal = signed(
+            ConsensusMessage::Proposal {
+                height,
+                round: 0,
+                valid_round: None,
+                block_hash,
+            },
+            0,
+        );
+        let votes = (0..3)
+            .flat_map(|i| {
+                [
+                    signed(ConsensusMessage::NonNilPreVoted(height, 0, block_hash), i),
+                    signed(
+                        ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                        i,
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let params = ConsensusParams {
+            timeout_ms: 6000,
+            timeout_increment_ms: 0,
+            repeat_round_for_first_leader: 10,
+            quorum: None,
+        };
+        for permutation in votes.iter().permutations(votes.len()) {
+            let mut core = ConsensusCore::new(&fi.header, params.clone(), 0, None).unwrap();
+            core.register_verified_block_hash(block_hash).unwrap();
+            core.progress(0).unwrap();
+            let (message, commitment) = proposal.clone();
+            core.receive(message, commitment, &dms_key, 1).unwrap();
+            core.progress(1).unwrap();
+            let mut precommits = 0;
+            for (message, commitment) in permutation {
+                if matches!(message, ConsensusMessage::NonNilPreCommitted(..)) {
+                    precommits += 1;
+                }
+                core.receive(message.clone(), commitment.clone(), &dms_key, 2)
+                    .unwrap();
+                let effects = core.progress(2).unwrap();
+                // An observer never broadcasts.
+                assert!(!effects
+                    .iter()
+                    .any(|x| matches!(x, ConsensusEffect::Broadcast(_))));
+                let finalized = effects.iter().any(|x| {
+                    matches!(x, ConsensusEffect::EmitResult(ProgressResult::Finalized(_)))
+                });
+                assert_eq!(finalized, precommits == 3);
+                if finalized {
+                    break;
+                }
+            }
+            assert_eq!(core.check_finalized().unwrap().block_hash, block_hash);
+            assert!(core.progress(3).is_err());
+        }
+    }
+
+    /// The lock and the valid value are reported on a quorum of prevotes,
+    /// and the lock moves to the later round where the block is re-proposed with `valid_round`.
+    #[test]
+    fn lock_and_valid_value() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let height = fi.header.height + 1;
+        let block_hash = Hash256::hash("block");
+        let dms_key = "consensus".to_owned();
+        let params = ConsensusParams {
+            timeout_ms: 100,
+            timeout_increment_ms: 0,
+            repeat_round_for_first_leader: 1,
+            quorum: None,
+        };
+        let receive = |core: &mut ConsensusCore, message: ConsensusMessage, i: usize, timestamp| {
+            let commitment = message.commit(&dms_key, &keys[i].1).unwrap();
+            core.receive(message, commitment, &dms_key, timestamp)
+                .unwrap();
+        };
+        // Returns the messages of this node, which it receives like the others.
+        let progress = |core: &mut ConsensusCore, timestamp| {
+            let mut sent = Vec::new();
+            loop {
+                let messages = core
+                    .progress(timestamp)
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|effect| match effect {
+                        ConsensusEffect::Broadcast(message) => Some(message),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if messages.is_empty() {
+                    return sent;
+                }
+                for message in messages {
+                    core.mark_broadcast(&message);
+                    receive(core, message.clone(), 1, timestamp);
+                    sent.push(message);
+                }
+            }
+        };
+        // This node is the proposer of the round 1.
+        let mut core = ConsensusCore::new(&fi.header, params, 0, Some(keys[1].0.clone())).unwrap();
+        core.register_verified_block_hash(block_hash).unwrap();
+        assert!(progress(&mut core, 0).is_empty());
+        let proposal = ConsensusMessage::Proposal {
+            height,
+            round: 0,
+            valid_round: None,
+            block_hash,
+        };
+        receive(&mut core, proposal, 0, 0);
+        assert_eq!(
+            progress(&mut core, 0),
+            vec![ConsensusMessage::NonNilPreVoted(height, 0, block_hash)]
+        );
+        assert_eq!(core.status().locked, None);
+        assert_eq!(core.status().valid, None);
+
+        for i in [0, 2] {
+            receive(
+                &mut core,
+                ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+                i,
+                0,
+            );
+        }
+        assert_eq!(
+            progress(&mut core, 0),
+            vec![ConsensusMessage::NonNilPreCommitted(height, 0, block_hash)]
+        );
+        assert_eq!(core.status().locked, Some((block_hash, 0)));
+        assert_eq!(core.status().valid, Some((block_hash, 0)));
+
+        // The others precommit nil, so the round ends without the lock released.
+        for i in [0, 2] {
+            receive(
+                &mut core,
+                ConsensusMessage::NilPreCommitted(height, 0),
+                i,
+                0,
+            );
+        }
+        assert!(progress(&mut core, 0).is_empty());
+        assert_eq!(core.status().round, 0);
+        receive(
+            &mut core,
+            ConsensusMessage::NilPreCommitted(height, 0),
+            3,
+            0,
+        );
+        assert_eq!(
+            progress(&mut core, 0),
+            vec![
+                ConsensusMessage::Proposal {
+                    height,
+                    round: 1,
+                    valid_round: Some(0),
+                    block_hash,
+                },
+                ConsensusMessage::NonNilPreVoted(height, 1, block_hash),
+            ]
+        );
+        let status = core.status();
+        assert_eq!(status.round, 1);
+        assert_eq!(status.locked, Some((block_hash, 0)));
+        assert_eq!(status.valid, Some((block_hash, 0)));
+
+        // The re-proposal gets a quorum of prevotes, which moves the lock.
+        for i in [0, 2] {
+            receive(
+                &mut core,
+                ConsensusMessage::NonNilPreVoted(height, 1, block_hash),
+                i,
+                0,
+            );
+        }
+        assert_eq!(
+            progress(&mut core, 0),
+            vec![ConsensusMessage::NonNilPreCommitted(height, 1, block_hash)]
+        );
+        assert_eq!(core.status().locked, Some((block_hash, 1)));
+        assert_eq!(core.status().valid, Some((block_hash, 1)));
+    }
+}
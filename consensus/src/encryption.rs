@@ -0,0 +1,166 @@
+use super::*;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+/// The tag of the format of the files encrypted by `encrypt()`.
+const ENCRYPTION_TAG: &str = "encrypted-1";
+
+/// The key to encrypt the files of the consensus storage at rest, given by the operator.
+///
+/// The state reveals how this node has voted and vetoed before the votes are public,
+/// so it might be worth hiding on a shared infrastructure.
+#[derive(Clone, PartialEq, Eq)]
+pub struct StorageEncryptionKey([u8; 32]);
+
+impl StorageEncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        Self(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+impl std::fmt::Debug for StorageEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StorageEncryptionKey(..)")
+    }
+}
+
+/// Encrypts the content of a file with a random nonce,
+/// in the format of `{tag}:{nonce in hex}:{ciphertext in hex}`.
+pub(crate) fn encrypt(key: &StorageEncryptionKey, content: &str) -> String {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, content.as_bytes())
+        .expect("encryption never fails for the content in the memory");
+    format!(
+        "{ENCRYPTION_TAG}:{}:{}",
+        hex::encode(nonce),
+        hex::encode(ciphertext)
+    )
+}
+
+/// Returns whether the file is encrypted by `encrypt()`.
+pub(crate) fn is_encrypted(raw: &str) -> bool {
+    raw.starts_with(&format!("{ENCRYPTION_TAG}:"))
+}
+
+/// Decrypts the content of the file if the key is given, or returns it as it is otherwise.
+///
+/// Fails if the file is encrypted but no key is given, or vice versa,
+/// or with `ConsensusError::WrongEncryptionKey` if it can't be decrypted.
+pub(crate) fn decrypt(
+    key: Option<&StorageEncryptionKey>,
+    file: &str,
+    raw: &str,
+) -> Result<String, ConsensusError> {
+    let key = match (key, is_encrypted(raw)) {
+        (None, false) => return Ok(raw.to_owned()),
+        (Some(key), true) => key,
+        (None, true) => {
+            return Err(ConsensusError::MissingEncryptionKey {
+                file: file.to_owned(),
+            })
+        }
+        (Some(_), false) => {
+            return Err(ConsensusError::UnencryptedStorage {
+                file: file.to_owned(),
+            })
+        }
+    };
+    let wrong_key = || ConsensusError::WrongEncryptionKey {
+        file: file.to_owned(),
+    };
+    let (nonce, ciphertext) = raw[ENCRYPTION_TAG.len() + 1..]
+        .split_once(':')
+        .ok_or_else(wrong_key)?;
+    let nonce = hex::decode(nonce).map_err(|_| wrong_key())?;
+    let ciphertext = hex::decode(ciphertext).map_err(|_| wrong_key())?;
+    if nonce.len() != 24 {
+        return Err(wrong_key());
+    }
+    let content = key
+        .cipher()
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| wrong_key())?;
+    String::from_utf8(content).map_err(|_| wrong_key())
+}
+
+/// Encrypts the plaintext files of an existing consensus storage (e.g., the one of a node
+/// that has run without the key) at once, returning their names.
+///
+/// The files already encrypted are left as they are, so it can be run again after a crash.
+/// The consensus must not be running on the storage meanwhile.
+pub async fn encrypt_storage<S: Storage>(
+    storage: &mut S,
+    key: &StorageEncryptionKey,
+) -> Result<Vec<String>, ConsensusError> {
+    let mut ops = Vec::new();
+    let mut names = Vec::new();
+    for name in storage
+        .list_files()
+        .await
+        .map_err(ConsensusError::storage)?
+    {
+        let raw = storage
+            .read_file(&name)
+            .await
+            .map_err(ConsensusError::storage)?;
+        if is_encrypted(&raw) {
+            continue;
+        }
+        ops.push(StorageOp::AddOrOverwrite {
+            name: name.clone(),
+            content: encrypt(key, &raw),
+        });
+        names.push(name);
+    }
+    storage
+        .apply_batch(ops)
+        .await
+        .map_err(ConsensusError::storage)?;
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = StorageEncryptionKey::generate();
+        let encrypted = encrypt(&key, "content");
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("content"));
+        // A nonce per file.
+        assert_ne!(encrypt(&key, "content"), encrypted);
+        assert_eq!(decrypt(Some(&key), "file", &encrypted).unwrap(), "content");
+        assert_eq!(decrypt(None, "file", "content").unwrap(), "content");
+
+        let file = "file".to_owned();
+        assert_eq!(
+            decrypt(Some(&StorageEncryptionKey::generate()), "file", &encrypted),
+            Err(ConsensusError::WrongEncryptionKey { file: file.clone() })
+        );
+        assert_eq!(
+            decrypt(None, "file", &encrypted),
+            Err(ConsensusError::MissingEncryptionKey { file: file.clone() })
+        );
+        assert_eq!(
+            decrypt(Some(&key), "file", "content"),
+            Err(ConsensusError::UnencryptedStorage { file })
+        );
+    }
+}
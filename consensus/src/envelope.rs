@@ -0,0 +1,87 @@
+use super::*;
+use serde::de::DeserializeOwned;
+
+/// The tag of the format of the files sealed by `seal()`.
+const ENVELOPE_TAG: &str = "envelope-1";
+
+/// Seals the content of a file in the format of `{tag}:{length}:{checksum}:{content}`,
+/// so that a bit flip or a truncation is detected by `unseal()`.
+///
+/// The state is not sealed since its encoding (see `StateCodec`) has its own checksum.
+pub(crate) fn seal(content: &str) -> String {
+    format!(
+        "{ENVELOPE_TAG}:{}:{}:{content}",
+        content.len(),
+        Hash256::hash(content)
+    )
+}
+
+/// Verifies and opens the content of the file sealed by `seal()`.
+///
+/// The files written before the envelopes are returned as they are.
+pub(crate) fn unseal<'a>(file: &str, raw: &'a str) -> Result<&'a str, ConsensusError> {
+    let corrupt = |reason: String| ConsensusError::CorruptState {
+        file: file.to_owned(),
+        reason,
+    };
+    let sealed = match raw.strip_prefix(ENVELOPE_TAG) {
+        Some(sealed) => sealed,
+        None => return Ok(raw),
+    };
+    let (length, checksum, content) = match sealed.splitn(4, ':').collect::<Vec<_>>()[..] {
+        ["", length, checksum, content] => (length, checksum, content),
+        _ => return Err(corrupt("truncated envelope".to_owned())),
+    };
+    let length = length
+        .parse::<usize>()
+        .map_err(|e| corrupt(format!("invalid length {length}: {e}")))?;
+    if content.len() != length {
+        return Err(corrupt(format!(
+            "length mismatch: expected {length}, but got {}",
+            content.len()
+        )));
+    }
+    if Hash256::hash(content).to_string() != checksum {
+        return Err(corrupt("checksum mismatch".to_owned()));
+    }
+    Ok(content)
+}
+
+/// Opens and deserializes the file sealed by `seal()`.
+pub(crate) fn open<T: DeserializeOwned>(file: &str, raw: &str) -> Result<T, ConsensusError> {
+    serde_spb::from_str(unseal(file, raw)?).map_err(|e| ConsensusError::CorruptState {
+        file: file.to_owned(),
+        reason: format!("invalid content: {e}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corruption() {
+        let content = serde_spb::to_string(&vec![1u64, 2, 3]).unwrap();
+        let sealed = seal(&content);
+        assert_eq!(open::<Vec<u64>>("file", &sealed).unwrap(), vec![1, 2, 3]);
+        // Written before the envelopes.
+        assert_eq!(open::<Vec<u64>>("file", &content).unwrap(), vec![1, 2, 3]);
+
+        let reason = |raw: &str| match open::<Vec<u64>>("file", raw) {
+            Err(ConsensusError::CorruptState { file, reason }) => {
+                assert_eq!(file, "file");
+                reason
+            }
+            x => panic!("unexpected {x:?}"),
+        };
+        let flipped = format!("{}}}", &sealed[..sealed.len() - 1]);
+        assert_eq!(reason(&flipped), "checksum mismatch");
+        assert!(reason(&sealed[..sealed.len() - 1]).starts_with("length mismatch"));
+        assert_eq!(
+            reason(&sealed[..ENVELOPE_TAG.len() + 2]),
+            "truncated envelope"
+        );
+        let broken = seal("[1, 2");
+        assert!(reason(&broken).starts_with("invalid content"));
+    }
+}
@@ -0,0 +1,195 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// The evidence of a misbehavior: two conflicting consensus messages signed by the same validator.
+///
+/// It is self-contained, so anyone who knows the validator set can check it
+/// with `verify_evidence()` (e.g., to include it in a slashing transaction).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evidence {
+    pub offender: PublicKey,
+    pub height: BlockHeight,
+    pub round: ConsensusRound,
+    /// The key of the DMS that the messages were committed to, which is a part of the signed data.
+    pub dms_key: DmsKey,
+    /// The message that was received first, with the commitment of the offender.
+    pub first: (ConsensusMessage, MessageCommitmentProof),
+    /// The message that conflicts with the first one, with the commitment of the offender.
+    pub second: (ConsensusMessage, MessageCommitmentProof),
+}
+
+impl ToHash256 for Evidence {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
+/// The kind of a misbehavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ViolationKind {
+    DoubleProposal,
+    DoublePrevote,
+    DoublePrecommit,
+    InvalidProposal,
+    InvalidPrevote,
+    InvalidPrecommit,
+}
+
+impl ViolationKind {
+    /// Returns the kind of signing two conflicting messages of the kind.
+    pub fn equivocation(kind: VoteKind) -> Self {
+        match kind {
+            VoteKind::Proposal => ViolationKind::DoubleProposal,
+            VoteKind::Prevote => ViolationKind::DoublePrevote,
+            VoteKind::Precommit => ViolationKind::DoublePrecommit,
+        }
+    }
+}
+
+/// A misbehavior reported by `Consensus::progress()`, structured for the slashing tools.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    pub violator: PublicKey,
+    pub round: ConsensusRound,
+    pub kind: ViolationKind,
+    /// The hashes of the two conflicting messages for an equivocation detected in the DMS
+    /// (see `Evidence`), or of the blocks involved for the ones detected by the state machine.
+    pub evidence_hashes: Vec<Hash256>,
+    /// The human-readable description, with the name of the violator if it has one.
+    pub description: String,
+}
+
+/// Verifies that the evidence consists of two conflicting messages
+/// of the same height, round and kind, both signed by the offender who is a validator.
+///
+/// This doesn't require any instance of the consensus, so it can be used by light clients.
+pub fn verify_evidence(
+    evidence: &Evidence,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<(), ConsensusError> {
+    let validators = validator_set.iter().cloned().collect::<BTreeMap<_, _>>();
+    let offender = &evidence.offender;
+    if !validators.contains_key(offender) {
+        return Err(ConsensusError::InvalidProof(format!(
+            "evidence: the offender {offender} is not a validator"
+        )));
+    }
+    for (message, commitment) in [&evidence.first, &evidence.second] {
+        if commitment.committer != *offender {
+            return Err(ConsensusError::InvalidProof(format!(
+                "evidence: {} is committed by {}, not by the offender",
+                message.to_hash256(),
+                commitment.committer
+            )));
+        }
+        message
+            .verify_commitment(commitment, &evidence.dms_key)
+            .map_err(|e| {
+                ConsensusError::InvalidProof(format!("signature on {}: {e}", message.to_hash256()))
+            })?;
+        if message.height() != evidence.height || message.vote_key().0 != evidence.round {
+            return Err(ConsensusError::InvalidProof(format!(
+                "evidence: {} is not of the height {} and the round {}",
+                message.to_hash256(),
+                evidence.height,
+                evidence.round
+            )));
+        }
+    }
+    let (first, second) = (&evidence.first.0, &evidence.second.0);
+    if first == second || first.vote_key() != second.vote_key() {
+        return Err(ConsensusError::InvalidProof(format!(
+            "evidence: {} and {} do not conflict",
+            first.to_hash256(),
+            second.to_hash256()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify() {
+        let keys = (0..2)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect::<Vec<_>>();
+        let dms_key = "consensus-test".to_owned();
+        let signed = |message: ConsensusMessage, key: &PrivateKey| {
+            let commitment = message.commit(&dms_key, key).unwrap();
+            (message, commitment)
+        };
+        let evidence = Evidence {
+            offender: keys[0].0.clone(),
+            height: 3,
+            round: 1,
+            dms_key: dms_key.clone(),
+            first: signed(
+                ConsensusMessage::NonNilPreCommitted(3, 1, Hash256::hash("block")),
+                &keys[0].1,
+            ),
+            second: signed(ConsensusMessage::NilPreCommitted(3, 1), &keys[0].1),
+        };
+        verify_evidence(&evidence, &validator_set).unwrap();
+
+        // The evidence stays verifiable after a round trip through JSON.
+        let json = serde_spb::to_string(&evidence).unwrap();
+        let decoded: Evidence = serde_spb::from_str(&json).unwrap();
+        assert_eq!(decoded, evidence);
+        verify_evidence(&decoded, &validator_set).unwrap();
+
+        // Not a validator
+        assert!(verify_evidence(&evidence, &validator_set[1..]).is_err());
+        // Not conflicting
+        let mut x = evidence.clone();
+        x.second = x.first.clone();
+        assert!(verify_evidence(&x, &validator_set).is_err());
+        let mut x = evidence.clone();
+        x.second = signed(ConsensusMessage::NilPreVoted(3, 1), &keys[0].1);
+        assert!(verify_evidence(&x, &validator_set).is_err());
+        // Wrong round
+        let mut x = evidence.clone();
+        x.round = 2;
+        assert!(verify_evidence(&x, &validator_set).is_err());
+        // Wrong height
+        let mut x = evidence.clone();
+        x.height = 4;
+        assert!(verify_evidence(&x, &validator_set).is_err());
+        // Signed by another validator
+        let mut x = evidence.clone();
+        x.second = signed(ConsensusMessage::NilPreCommitted(3, 1), &keys[1].1);
+        assert!(verify_evidence(&x, &validator_set).is_err());
+        // Forged signature
+        let mut x = evidence;
+        x.second.0 = ConsensusMessage::NonNilPreCommitted(3, 1, Hash256::hash("another block"));
+        assert!(verify_evidence(&x, &validator_set).is_err());
+    }
+
+    #[test]
+    fn violation_serde() {
+        let violation = Violation {
+            violator: generate_keypair("validator").0,
+            round: 2,
+            kind: ViolationKind::equivocation(VoteKind::Prevote),
+            evidence_hashes: vec![Hash256::hash("first"), Hash256::hash("second")],
+            description: "equivocation".to_owned(),
+        };
+        assert_eq!(violation.kind, ViolationKind::DoublePrevote);
+        let json = serde_spb::to_string(&violation).unwrap();
+        assert_eq!(serde_spb::from_str::<Violation>(&json).unwrap(), violation);
+
+        // In the event log
+        let result = ProgressResult::ViolationReported(violation, 10);
+        let json = serde_spb::to_string(&result).unwrap();
+        assert_eq!(
+            serde_spb::from_str::<ProgressResult>(&json).unwrap(),
+            result
+        );
+    }
+}
@@ -0,0 +1,912 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// The number of the signature verification results to remember.
+const VERIFIED_COMMITMENT_CACHE_SIZE: usize = 1024;
+
+/// The number of the distinct messages admitted per signer, round and kind:
+/// the first one and a conflicting one, which is enough to prove the equivocation.
+pub const MAX_MESSAGES_PER_VOTE: usize = 2;
+
+/// The default maximum size of an encoded consensus message,
+/// which is far more than any valid one needs (about 100 bytes).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024;
+
+/// The default number of the rounds ahead of the current one whose messages are admitted.
+///
+/// The messages of the further rounds are rejected but not lost,
+/// since the peers keep gossiping them until the round comes within the window.
+pub const DEFAULT_MAX_ROUND_LOOKAHEAD: ConsensusRound = 100;
+
+/// The maximum number of the messages held back until their blocks are verified.
+pub const MAX_QUARANTINED_MESSAGES: usize = 256;
+
+/// The maximum number of the messages of the next height held until this node moves on to it.
+pub const MAX_EARLY_MESSAGES: usize = 256;
+
+/// The hashes of the admitted messages by the signer, the round and the kind.
+pub(crate) type AdmittedMessages = BTreeMap<(PublicKey, ConsensusRound, VoteKind), Vec<Hash256>>;
+
+/// The messages on the blocks not verified yet, held back by the filter
+/// to be admitted once the blocks are verified, the oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct Quarantine {
+    messages: VecDeque<(Hash256, ConsensusMessage, MessageCommitmentProof)>,
+}
+
+impl Quarantine {
+    /// Holds the message, evicting the oldest one if full.
+    fn insert(
+        &mut self,
+        block_hash: Hash256,
+        message: ConsensusMessage,
+        commitment: MessageCommitmentProof,
+    ) {
+        if self
+            .messages
+            .iter()
+            .any(|(_, x, y)| *x == message && y.committer == commitment.committer)
+        {
+            return;
+        }
+        if self.messages.len() >= MAX_QUARANTINED_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((block_hash, message, commitment));
+    }
+
+    /// Removes the messages on the block, returning them in the order of the arrival.
+    pub(crate) fn take(
+        &mut self,
+        block_hash: &Hash256,
+    ) -> Vec<(ConsensusMessage, MessageCommitmentProof)> {
+        let (taken, kept) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|(x, _, _)| x == block_hash);
+        self.messages = kept;
+        taken
+            .into_iter()
+            .map(|(_, message, commitment)| (message, commitment))
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// The messages of the next height that have arrived while this node is still on the current one
+/// (e.g., from the peers that have finalized it first), held by the filter
+/// to be admitted once this node moves on, the oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct EarlyMessages {
+    messages: VecDeque<(ConsensusMessage, MessageCommitmentProof)>,
+}
+
+impl EarlyMessages {
+    /// Holds the message, evicting the oldest one if full.
+    fn insert(&mut self, message: ConsensusMessage, commitment: MessageCommitmentProof) {
+        if self
+            .messages
+            .iter()
+            .any(|(x, y)| *x == message && y.committer == commitment.committer)
+        {
+            return;
+        }
+        if self.messages.len() >= MAX_EARLY_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message, commitment));
+    }
+
+    /// Removes every message, returning them in the order of the arrival.
+    pub(crate) fn take(&mut self) -> Vec<(ConsensusMessage, MessageCommitmentProof)> {
+        std::mem::take(&mut self.messages).into()
+    }
+}
+
+/// A DMS message filter that admits only the consensus messages
+/// which can be processed by the current consensus state.
+pub struct ConsensusMessageFilter {
+    /// The set of the block hashes that have been verified.
+    ///
+    /// It is shared with `Consensus`, which keeps it updated.
+    verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
+    /// The messages admitted so far, to cap the ones of each signer.
+    ///
+    /// It is shared with `Consensus`, which fills it with the messages already in the DMS.
+    admitted_messages: Arc<parking_lot::RwLock<AdmittedMessages>>,
+    /// The messages on the blocks not verified yet.
+    ///
+    /// It is shared with `Consensus`, which admits them once their blocks are verified.
+    quarantine: Arc<parking_lot::RwLock<Quarantine>>,
+    /// The messages of the next height, held since they have arrived early.
+    ///
+    /// It is shared with `Consensus`, which admits them once it moves on to the height.
+    early_messages: Arc<parking_lot::RwLock<EarlyMessages>>,
+    /// The round that the consensus is in.
+    ///
+    /// It is shared with `Consensus`, which keeps it updated.
+    current_round: Arc<parking_lot::RwLock<ConsensusRound>>,
+    /// The number of the rounds ahead of the current one whose messages are admitted.
+    max_round_lookahead: ConsensusRound,
+    /// The validator set of the height.
+    validators: BTreeSet<PublicKey>,
+    /// The consensus keys delegated by the validators.
+    delegations: Delegations,
+    /// The height that the consensus is performing on.
+    height: BlockHeight,
+    /// The key of the DMS that this filter is attached to.
+    dms_key: DmsKey,
+    /// The maximum size of an encoded message.
+    max_message_size: usize,
+    /// Recently verified commitments, to avoid verifying the same signature repeatedly.
+    verified_commitments: parking_lot::Mutex<LruSet>,
+    metrics: Arc<dyn ConsensusMetrics>,
+    /// It is shared with `Consensus`, which reads it for `dms_stats()`.
+    counters: Arc<FilterCounters>,
+}
+
+impl ConsensusMessageFilter {
+    pub(crate) fn new(
+        verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
+        admitted_messages: Arc<parking_lot::RwLock<AdmittedMessages>>,
+        quarantine: Arc<parking_lot::RwLock<Quarantine>>,
+        current_round: Arc<parking_lot::RwLock<ConsensusRound>>,
+        validators: BTreeSet<PublicKey>,
+        height: BlockHeight,
+        dms_key: DmsKey,
+    ) -> Self {
+        Self {
+            verified_block_hashes,
+            admitted_messages,
+            quarantine,
+            early_messages: Default::default(),
+            current_round,
+            max_round_lookahead: DEFAULT_MAX_ROUND_LOOKAHEAD,
+            validators,
+            delegations: Delegations::default(),
+            height,
+            dms_key,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            verified_commitments: parking_lot::Mutex::new(LruSet::new(
+                VERIFIED_COMMITMENT_CACHE_SIZE,
+            )),
+            metrics: Arc::new(NoopMetrics),
+            counters: Default::default(),
+        }
+    }
+
+    /// Reports the admissions and the rejections to the metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn ConsensusMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Counts the admissions and the rejections to the given counters.
+    pub(crate) fn with_counters(mut self, counters: Arc<FilterCounters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Holds the messages of the next height in the given buffer (a private one by default).
+    pub(crate) fn with_early_messages(
+        mut self,
+        early_messages: Arc<parking_lot::RwLock<EarlyMessages>>,
+    ) -> Self {
+        self.early_messages = early_messages;
+        self
+    }
+
+    /// Sets the maximum size of an encoded message (`DEFAULT_MAX_MESSAGE_SIZE` by default).
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets the number of the rounds ahead of the current one whose messages are admitted
+    /// (`DEFAULT_MAX_ROUND_LOOKAHEAD` by default).
+    pub fn with_max_round_lookahead(mut self, max_round_lookahead: ConsensusRound) -> Self {
+        self.max_round_lookahead = max_round_lookahead;
+        self
+    }
+
+    /// Admits the messages signed by the keys delegated by the validators
+    /// (none by default), in place of the validators themselves.
+    pub fn with_delegations(mut self, delegations: Delegations) -> Self {
+        self.delegations = delegations;
+        self
+    }
+
+    fn verify_signature(
+        &self,
+        message: &ConsensusMessage,
+        commitment: &MessageCommitmentProof,
+    ) -> Result<(), String> {
+        let key = Hash256::hash(
+            serde_spb::to_vec(&(message.to_hash256(), commitment))
+                .map_err(|e| format!("can't encode the commitment: {e}"))?,
+        );
+        if self.verified_commitments.lock().touch(&key) {
+            return Ok(());
+        }
+        message
+            .verify_commitment(commitment, &self.dms_key)
+            .map_err(|e| {
+                format!(
+                    "invalid signature by {} on {}: {e}",
+                    commitment.committer,
+                    message.to_hash256()
+                )
+            })?;
+        self.verified_commitments.lock().insert(key);
+        Ok(())
+    }
+}
+
+/// A bounded set which evicts the least recently used item.
+struct LruSet {
+    capacity: usize,
+    items: VecDeque<Hash256>,
+}
+
+impl LruSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Marks the item as recently used, returning whether it exists.
+    fn touch(&mut self, item: &Hash256) -> bool {
+        if let Some(position) = self.items.iter().position(|x| x == item) {
+            let item = self.items.remove(position).unwrap();
+            self.items.push_back(item);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn insert(&mut self, item: Hash256) {
+        if self.touch(&item) {
+            return;
+        }
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+}
+
+impl ConsensusMessageFilter {
+    fn check_raw(&self, message: &[u8]) -> Result<(), (FilterRejection, String)> {
+        if message.len() > self.max_message_size {
+            return Err((
+                FilterRejection::TooLarge,
+                format!(
+                    "the message is {} bytes, more than {}",
+                    message.len(),
+                    self.max_message_size
+                ),
+            ));
+        }
+        let version =
+            ConsensusMessage::wire_version(message).map_err(|e| (FilterRejection::Malformed, e))?;
+        if version != CONSENSUS_PROTOCOL_VERSION {
+            self.counters.version_mismatched(version);
+            return Err((FilterRejection::VersionMismatch, version_mismatch(version)));
+        }
+        ConsensusMessage::from_compact(message).map_err(|e| (FilterRejection::Malformed, e))?;
+        Ok(())
+    }
+
+    fn check(
+        &self,
+        message: &ConsensusMessage,
+        commitment: &MessageCommitmentProof,
+    ) -> Result<(), (FilterRejection, String)> {
+        if !self
+            .delegations
+            .resolve(&commitment.committer, self.height)
+            .is_some_and(|identity| self.validators.contains(&identity))
+        {
+            return Err((
+                FilterRejection::NotAValidator,
+                format!(
+                    "the signer {} is neither a validator nor a key delegated by one",
+                    commitment.committer
+                ),
+            ));
+        }
+        if message.height() == self.height + 1 {
+            self.verify_signature(message, commitment)
+                .map_err(|e| (FilterRejection::InvalidSignature, e))?;
+            self.early_messages
+                .write()
+                .insert(message.clone(), commitment.clone());
+            return Err((
+                FilterRejection::OtherHeight,
+                format!(
+                    "the message is for the next height {}, held until this node moves on",
+                    message.height()
+                ),
+            ));
+        }
+        if message.height() != self.height {
+            return Err((
+                FilterRejection::OtherHeight,
+                format!(
+                    "the message is for the height {}, not {}",
+                    message.height(),
+                    self.height
+                ),
+            ));
+        }
+        message
+            .check_rounds()
+            .map_err(|e| (FilterRejection::RoundOutOfRange, e.to_string()))?;
+        let (round, kind) = message.vote_key();
+        let current_round = *self.current_round.read();
+        if round > current_round.saturating_add(self.max_round_lookahead) {
+            return Err((
+                FilterRejection::FarFutureRound,
+                format!("the message is for the round {round}, too far ahead of {current_round}"),
+            ));
+        }
+        self.verify_signature(message, commitment)
+            .map_err(|e| (FilterRejection::InvalidSignature, e))?;
+        match message {
+            ConsensusMessage::Proposal { block_hash, .. }
+            | ConsensusMessage::NonNilPreVoted(_, _, block_hash)
+            | ConsensusMessage::NonNilPreCommitted(_, _, block_hash) => {
+                if !self.verified_block_hashes.read().contains(block_hash) {
+                    self.quarantine.write().insert(
+                        *block_hash,
+                        message.clone(),
+                        commitment.clone(),
+                    );
+                    return Err((
+                        FilterRejection::UnverifiedBlock,
+                        format!("the block {block_hash} is not verified yet, held until it is"),
+                    ));
+                }
+            }
+            ConsensusMessage::NilPreVoted(..) | ConsensusMessage::NilPreCommitted(..) => (),
+        }
+        let message_hash = message.to_hash256();
+        let mut admitted_messages = self.admitted_messages.write();
+        let admitted = admitted_messages
+            .entry((commitment.committer.clone(), round, kind))
+            .or_default();
+        if !admitted.contains(&message_hash) {
+            if admitted.len() >= MAX_MESSAGES_PER_VOTE {
+                return Err((
+                    FilterRejection::TooManyMessages,
+                    format!(
+                        "{} has already signed {} messages of {kind:?} in round {round}",
+                        commitment.committer,
+                        admitted.len()
+                    ),
+                ));
+            }
+            admitted.push(message_hash);
+        }
+        Ok(())
+    }
+}
+
+impl MessageFilter<ConsensusMessage> for ConsensusMessageFilter {
+    fn filter(
+        &self,
+        message: &ConsensusMessage,
+        commitment: &MessageCommitmentProof,
+    ) -> Result<(), String> {
+        match self.check(message, commitment) {
+            Ok(()) => {
+                self.metrics.message_accepted();
+                self.counters.admitted(get_timestamp());
+                Ok(())
+            }
+            Err((reason, e)) => {
+                self.metrics.message_rejected(reason);
+                self.counters.rejected(reason);
+                tracing::warn!(
+                    ?reason,
+                    committer = %commitment.committer,
+                    message_hash = %message.to_hash256(),
+                    error = %e,
+                    "rejected a consensus message"
+                );
+                Err(e)
+            }
+        }
+    }
+
+    fn filter_raw(&self, message: &[u8]) -> Result<(), String> {
+        self.check_raw(message).map_err(|(reason, e)| {
+            self.metrics.message_rejected(reason);
+            self.counters.rejected(reason);
+            tracing::warn!(
+                ?reason,
+                size = message.len(),
+                error = %e,
+                "rejected a consensus message"
+            );
+            e
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEIGHT: BlockHeight = 1;
+
+    fn setup() -> (ConsensusMessageFilter, Vec<PrivateKey>, DmsKey) {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")).1)
+            .collect::<Vec<_>>();
+        let dms_key = "consensus".to_owned();
+        let filter = ConsensusMessageFilter::new(
+            Arc::new(parking_lot::RwLock::new(BTreeSet::new())),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            keys.iter().map(|key| key.public_key()).collect(),
+            HEIGHT,
+            dms_key.clone(),
+        );
+        (filter, keys, dms_key)
+    }
+
+    fn all_messages(height: BlockHeight, block_hash: Hash256) -> Vec<ConsensusMessage> {
+        vec![
+            ConsensusMessage::Proposal {
+                height,
+                round: 0,
+                valid_round: None,
+                block_hash,
+            },
+            ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+            ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+            ConsensusMessage::NilPreVoted(height, 0),
+            ConsensusMessage::NilPreCommitted(height, 0),
+        ]
+    }
+
+    #[test]
+    fn accept_verified_block() {
+        let (filter, keys, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        filter.verified_block_hashes.write().insert(block_hash);
+        for message in all_messages(HEIGHT, block_hash) {
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            filter.filter(&message, &commitment).unwrap();
+        }
+    }
+
+    #[test]
+    fn reject_unverified_block() {
+        let (filter, keys, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        filter
+            .verified_block_hashes
+            .write()
+            .insert(Hash256::hash("another block"));
+        for message in all_messages(HEIGHT, block_hash) {
+            let commitment = message.commit(&dms_key, &keys[1]).unwrap();
+            let result = filter.filter(&message, &commitment);
+            match message {
+                ConsensusMessage::NilPreVoted(..) | ConsensusMessage::NilPreCommitted(..) => {
+                    result.unwrap()
+                }
+                _ => assert!(result.is_err()),
+            }
+        }
+        // Once registered, the same messages become acceptable.
+        filter.verified_block_hashes.write().insert(block_hash);
+        for message in all_messages(HEIGHT, block_hash) {
+            let commitment = message.commit(&dms_key, &keys[1]).unwrap();
+            filter.filter(&message, &commitment).unwrap();
+        }
+    }
+
+    #[test]
+    fn reject_non_validator() {
+        let (filter, _, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        filter.verified_block_hashes.write().insert(block_hash);
+        let (_, key) = generate_keypair("stranger");
+        for message in all_messages(HEIGHT, block_hash) {
+            let commitment = message.commit(&dms_key, &key).unwrap();
+            assert!(filter.filter(&message, &commitment).is_err());
+        }
+    }
+
+    #[test]
+    fn delegated_key() {
+        let (filter, keys, dms_key) = setup();
+        let (consensus_key, consensus_private_key) = generate_keypair("consensus key");
+        let (revoked_key, revoked_private_key) = generate_keypair("revoked key");
+        let mut delegations = Delegations::default();
+        for (key, heights, sequence) in [
+            (&revoked_key, HEIGHT..=HEIGHT, 0),
+            (&consensus_key, HEIGHT..=HEIGHT, 1),
+            (&revoked_key, 0..=(HEIGHT - 1), 2),
+        ] {
+            let delegation = Delegation {
+                identity: keys[0].public_key(),
+                consensus_key: key.clone(),
+                from_height: *heights.start(),
+                to_height: *heights.end(),
+                sequence,
+            };
+            delegations
+                .add(SignedDelegation::sign(delegation, &keys[0]).unwrap())
+                .unwrap();
+        }
+        let filter = filter.with_delegations(delegations);
+        let message = ConsensusMessage::NilPreVoted(HEIGHT, 0);
+        let commitment = message.commit(&dms_key, &consensus_private_key).unwrap();
+        filter.filter(&message, &commitment).unwrap();
+        // The validator signs only with the delegated key in the height.
+        for key in [&keys[0], &revoked_private_key] {
+            let message = ConsensusMessage::NilPreCommitted(HEIGHT, 0);
+            let commitment = message.commit(&dms_key, key).unwrap();
+            assert!(filter.filter(&message, &commitment).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_other_height() {
+        let (filter, keys, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        filter.verified_block_hashes.write().insert(block_hash);
+        for height in [HEIGHT - 1, HEIGHT + 1] {
+            for message in all_messages(height, block_hash) {
+                let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+                assert!(filter.filter(&message, &commitment).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn early_messages() {
+        let (filter, keys, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        for message in all_messages(HEIGHT + 1, block_hash) {
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            assert!(filter.filter(&message, &commitment).is_err());
+            // Held only once
+            assert!(filter.filter(&message, &commitment).is_err());
+        }
+        // Neither the forged nor the far ones are held.
+        let message = ConsensusMessage::NilPreVoted(HEIGHT + 1, 1);
+        let mut commitment = message.commit(&dms_key, &keys[0]).unwrap();
+        commitment.committer = keys[1].public_key();
+        assert!(filter.filter(&message, &commitment).is_err());
+        let message = ConsensusMessage::NilPreVoted(HEIGHT + 2, 0);
+        let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+        assert!(filter.filter(&message, &commitment).is_err());
+        let held = filter.early_messages.write().take();
+        assert_eq!(
+            held.into_iter()
+                .map(|(message, _)| message)
+                .collect::<Vec<_>>(),
+            all_messages(HEIGHT + 1, block_hash)
+        );
+        assert!(filter.early_messages.write().take().is_empty());
+    }
+
+    #[test]
+    fn reject_forged_signature() {
+        let (filter, keys, dms_key) = setup();
+        let block_hash = Hash256::hash("block");
+        filter.verified_block_hashes.write().insert(block_hash);
+        let (_, stranger) = generate_keypair("stranger");
+        for message in all_messages(HEIGHT, block_hash) {
+            // Signed by a stranger but claims to be committed by a validator.
+            let mut commitment = message.commit(&dms_key, &stranger).unwrap();
+            commitment.committer = keys[2].public_key();
+            assert!(filter.filter(&message, &commitment).is_err());
+            // Signed by a validator but over another message.
+            let commitment = ConsensusMessage::NilPreVoted(HEIGHT, 1)
+                .commit(&dms_key, &keys[2])
+                .unwrap();
+            assert!(filter.filter(&message, &commitment).is_err());
+        }
+    }
+
+    #[test]
+    fn quarantine() {
+        let (filter, keys, dms_key) = setup();
+        let filter = filter.with_max_round_lookahead(MAX_QUARANTINED_MESSAGES as ConsensusRound);
+        let block_hash = Hash256::hash("block");
+        for message in all_messages(HEIGHT, block_hash) {
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            let _ = filter.filter(&message, &commitment);
+            // Held only once
+            let _ = filter.filter(&message, &commitment);
+        }
+        assert_eq!(filter.quarantine.read().len(), 3);
+        assert!(filter
+            .quarantine
+            .write()
+            .take(&Hash256::hash("another block"))
+            .is_empty());
+        let taken = filter.quarantine.write().take(&block_hash);
+        assert_eq!(
+            taken.into_iter().map(|(x, _)| x).collect::<Vec<_>>(),
+            all_messages(HEIGHT, block_hash)[..3]
+        );
+        assert_eq!(filter.quarantine.read().len(), 0);
+
+        // The oldest ones are evicted.
+        for round in 0..MAX_QUARANTINED_MESSAGES as ConsensusRound + 1 {
+            let message = ConsensusMessage::NonNilPreVoted(HEIGHT, round, block_hash);
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            let _ = filter.filter(&message, &commitment);
+        }
+        let taken = filter.quarantine.write().take(&block_hash);
+        assert_eq!(taken.len(), MAX_QUARANTINED_MESSAGES);
+        assert_eq!(
+            taken[0].0,
+            ConsensusMessage::NonNilPreVoted(HEIGHT, 1, block_hash)
+        );
+    }
+
+    #[test]
+    fn spam() {
+        let (filter, keys, dms_key) = setup();
+        let block_hashes = (0..10)
+            .map(|i| Hash256::hash(format!("block{i}")))
+            .collect::<Vec<_>>();
+        filter
+            .verified_block_hashes
+            .write()
+            .extend(block_hashes.iter().cloned());
+        let prevote = |block_hash| ConsensusMessage::NonNilPreVoted(HEIGHT, 0, block_hash);
+        for (i, block_hash) in block_hashes.iter().enumerate() {
+            let message = prevote(*block_hash);
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            let result = filter.filter(&message, &commitment);
+            if i < MAX_MESSAGES_PER_VOTE {
+                result.unwrap();
+            } else {
+                assert!(result.is_err());
+            }
+        }
+        // The admitted ones are still admitted, e.g., when gossiped again.
+        for block_hash in &block_hashes[..MAX_MESSAGES_PER_VOTE] {
+            let message = prevote(*block_hash);
+            let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+            filter.filter(&message, &commitment).unwrap();
+        }
+        // The cap is per signer, round and kind.
+        for (message, key) in [
+            (prevote(block_hashes[9]), &keys[1]),
+            (
+                ConsensusMessage::NonNilPreVoted(HEIGHT, 1, block_hashes[9]),
+                &keys[0],
+            ),
+            (ConsensusMessage::NilPreCommitted(HEIGHT, 0), &keys[0]),
+        ] {
+            let commitment = message.commit(&dms_key, key).unwrap();
+            filter.filter(&message, &commitment).unwrap();
+        }
+        assert_eq!(filter.admitted_messages.read().len(), 4);
+    }
+
+    #[test]
+    fn message_size() {
+        let (filter, _, _) = setup();
+        let message = ConsensusMessage::Proposal {
+            height: HEIGHT,
+            round: 0,
+            valid_round: Some(0),
+            block_hash: Hash256::hash("block"),
+        }
+        .to_compact();
+        filter.filter_raw(&message).unwrap();
+
+        // Exactly as large as the limit
+        let filter = filter.with_max_message_size(message.len());
+        filter.filter_raw(&message).unwrap();
+        let filter = filter.with_max_message_size(message.len() - 1);
+        assert!(filter.filter_raw(&message).is_err());
+
+        let filter = filter.with_max_message_size(DEFAULT_MAX_MESSAGE_SIZE);
+        let oversized = vec![0; DEFAULT_MAX_MESSAGE_SIZE + 1];
+        assert!(filter.filter_raw(&oversized).is_err());
+        let mut trailing = message.clone();
+        trailing.push(0);
+        assert!(filter.filter_raw(&trailing).is_err());
+        assert!(filter.filter_raw(&message[..message.len() - 1]).is_err());
+    }
+
+    /// Only the canonical encoding, which the message is hashed and signed over, is admitted.
+    #[test]
+    fn non_canonical() {
+        let (filter, _, _) = setup();
+        let prevote = ConsensusMessage::NonNilPreVoted(HEIGHT, 0, Hash256::hash("block"));
+        // The equivalent JSON encodings, which are never canonical on the wire
+        let json = serde_spb::to_string(&prevote).unwrap();
+        let compact_json = json.split_whitespace().collect::<String>();
+        for json in [&json, &compact_json] {
+            let decoded = serde_spb::from_str::<ConsensusMessage>(json).unwrap();
+            assert_eq!(decoded.to_hash256(), prevote.to_hash256());
+            assert!(filter.filter_raw(json.as_bytes()).is_err());
+        }
+        let proposal = ConsensusMessage::Proposal {
+            height: HEIGHT,
+            round: 0,
+            valid_round: None,
+            block_hash: Hash256::hash("block"),
+        };
+        // Whether the keys are sorted or not, they are not in the order of the fields.
+        let reordered = serde_json::json!({
+            "Proposal": {
+                "block_hash": Hash256::hash("block"),
+                "valid_round": null,
+                "round": 0,
+                "height": HEIGHT,
+            }
+        })
+        .to_string();
+        assert!(reordered.starts_with(r#"{"Proposal":{"block_hash""#));
+        let decoded = serde_spb::from_str::<ConsensusMessage>(&reordered).unwrap();
+        assert_eq!(decoded, proposal);
+        assert_eq!(decoded.to_hash256(), proposal.to_hash256());
+        assert!(filter.filter_raw(reordered.as_bytes()).is_err());
+
+        filter.filter_raw(&prevote.to_compact()).unwrap();
+        // The flag of the valid round other than `0x00` or `0x01`
+        let mut compact = proposal.to_compact();
+        compact[19] = 0xff;
+        assert!(filter.filter_raw(&compact).is_err());
+    }
+
+    #[test]
+    fn protocol_version() {
+        let (filter, _, _) = setup();
+        let counters = Arc::new(FilterCounters::default());
+        let filter = filter.with_counters(Arc::clone(&counters));
+        let prevote = ConsensusMessage::NilPreVoted(HEIGHT, 0);
+        let compact = prevote.to_compact();
+        filter.filter_raw(&compact).unwrap();
+
+        let mut newer = compact.clone();
+        newer[1..3].copy_from_slice(&(CONSENSUS_PROTOCOL_VERSION + 1).to_be_bytes());
+        for (message, version) in [
+            (newer, CONSENSUS_PROTOCOL_VERSION + 1),
+            (prevote.to_unversioned_compact(), 2),
+            (serde_spb::to_vec(&prevote).unwrap(), 1),
+        ] {
+            let error = filter.filter_raw(&message).unwrap_err();
+            assert_eq!(
+                error,
+                format!(
+                    "the message is of the protocol version {version}, \
+                    but this node is on the version {CONSENSUS_PROTOCOL_VERSION}"
+                )
+            );
+        }
+        assert_eq!(counters.rejections()[&FilterRejection::VersionMismatch], 3);
+        assert_eq!(
+            counters.version_mismatches(),
+            vec![(1, 1), (2, 1), (CONSENSUS_PROTOCOL_VERSION + 1, 1)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn far_future_round() {
+        let (filter, keys, dms_key) = setup();
+        let filter = filter.with_max_round_lookahead(10);
+        let prevote = |round| ConsensusMessage::NilPreVoted(HEIGHT, round);
+        for (round, admitted) in [(0, true), (10, true), (11, false), (500, false)] {
+            let commitment = prevote(round).commit(&dms_key, &keys[0]).unwrap();
+            assert_eq!(
+                filter.filter(&prevote(round), &commitment).is_ok(),
+                admitted
+            );
+        }
+        // The window moves along with the current round.
+        *filter.current_round.write() = 5;
+        let commitment = prevote(11).commit(&dms_key, &keys[0]).unwrap();
+        filter.filter(&prevote(11), &commitment).unwrap();
+    }
+
+    #[test]
+    fn round_out_of_range() {
+        let (filter, keys, dms_key) = setup();
+        let filter = filter.with_max_round_lookahead(ConsensusRound::MAX);
+        let message = ConsensusMessage::NilPreCommitted(HEIGHT, ConsensusRound::MAX);
+        let commitment = message.commit(&dms_key, &keys[0]).unwrap();
+        // Admitted only where vetomint can take the round as it is
+        assert_eq!(
+            filter
+                .check(&message, &commitment)
+                .map_err(|(reason, _)| reason),
+            if usize::BITS >= ConsensusRound::BITS {
+                Ok(())
+            } else {
+                Err(FilterRejection::RoundOutOfRange)
+            }
+        );
+    }
+
+    #[test]
+    fn metrics() {
+        let (filter, keys, dms_key) = setup();
+        let metrics = Arc::new(AtomicMetrics::default());
+        let filter = filter.with_metrics(Arc::clone(&metrics) as Arc<dyn ConsensusMetrics>);
+        let block_hash = Hash256::hash("block");
+        let (_, stranger) = generate_keypair("stranger");
+        for (message, key) in [
+            (ConsensusMessage::NilPreVoted(HEIGHT, 0), &keys[0]),
+            (ConsensusMessage::NilPreVoted(HEIGHT, 0), &stranger),
+            (ConsensusMessage::NilPreVoted(HEIGHT + 1, 0), &keys[0]),
+            (
+                ConsensusMessage::NonNilPreVoted(HEIGHT, 0, block_hash),
+                &keys[0],
+            ),
+        ] {
+            let commitment = message.commit(&dms_key, key).unwrap();
+            let _ = filter.filter(&message, &commitment);
+        }
+        assert_eq!(
+            metrics
+                .messages_accepted
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        for (reason, count) in [
+            (FilterRejection::NotAValidator, 1),
+            (FilterRejection::OtherHeight, 1),
+            (FilterRejection::InvalidSignature, 0),
+            (FilterRejection::UnverifiedBlock, 1),
+            (FilterRejection::TooManyMessages, 0),
+            (FilterRejection::TooLarge, 0),
+            (FilterRejection::Malformed, 0),
+            (FilterRejection::FarFutureRound, 0),
+            (FilterRejection::VersionMismatch, 0),
+            (FilterRejection::RoundOutOfRange, 0),
+        ] {
+            assert_eq!(metrics.messages_rejected(reason), count);
+        }
+    }
+
+    #[test]
+    fn cached_verification() {
+        let (filter, keys, dms_key) = setup();
+        let message = ConsensusMessage::NilPreVoted(HEIGHT, 0);
+        let commitment = message.commit(&dms_key, &keys[3]).unwrap();
+        for _ in 0..2 {
+            filter.filter(&message, &commitment).unwrap();
+        }
+        assert_eq!(filter.verified_commitments.lock().items.len(), 1);
+        // A failed verification is never cached.
+        let mut forged = commitment;
+        forged.committer = keys[0].public_key();
+        assert!(filter.filter(&message, &forged).is_err());
+        assert_eq!(filter.verified_commitments.lock().items.len(), 1);
+    }
+
+    #[test]
+    fn lru_set() {
+        let mut set = LruSet::new(2);
+        let (a, b, c) = (Hash256::hash("a"), Hash256::hash("b"), Hash256::hash("c"));
+        set.insert(a);
+        set.insert(b);
+        assert!(set.touch(&a));
+        set.insert(c);
+        assert!(set.touch(&a));
+        assert!(!set.touch(&b));
+        assert!(set.touch(&c));
+    }
+}
@@ -0,0 +1,195 @@
+//! The frozen formats of `ConsensusMessage` and `State`, which the nodes running
+//! different versions must agree on.
+//!
+//! A failure here means that the format has changed (e.g., by reordering or renaming a field),
+//! which breaks the compatibility; if it's intended, regenerate the vectors consciously
+//! (and bump `STATE_VERSION` or `CONSENSUS_PROTOCOL_VERSION` as well).
+use super::*;
+
+/// The block hash in the vectors, which is `Hash256::hash("block")`.
+const BLOCK_HASH: &str = "20b53acf0daefc8c6ad68c861fb3b543ca541abd101abc1edfcbf6606b838ef4";
+
+/// A message with its JSON (in the storage), its compact encoding (on the wire) and its hash.
+struct MessageVector {
+    message: ConsensusMessage,
+    json: String,
+    compact: &'static str,
+    hash: &'static str,
+}
+
+fn message_vectors() -> Vec<MessageVector> {
+    let block_hash = Hash256::hash("block");
+    vec![
+        MessageVector {
+            message: ConsensusMessage::Proposal {
+                height: 1,
+                round: 2,
+                valid_round: Some(1),
+                block_hash,
+            },
+            json: format!(
+                r#"{{
+  "Proposal": {{
+    "height": 1,
+    "round": 2,
+    "valid_round": 1,
+    "block_hash": "{BLOCK_HASH}"
+  }}
+}}"#
+            ),
+            compact: "20000300000000000000010000000000000002010000000000000001\
+                20b53acf0daefc8c6ad68c861fb3b543ca541abd101abc1edfcbf6606b838ef4",
+            hash: "daf6c1a5a96bffcfaa221cb75510f9ffe550e5d4db4861096dfdc4cb42fd7bb5",
+        },
+        MessageVector {
+            message: ConsensusMessage::Proposal {
+                height: 1,
+                round: 2,
+                valid_round: None,
+                block_hash,
+            },
+            json: format!(
+                r#"{{
+  "Proposal": {{
+    "height": 1,
+    "round": 2,
+    "valid_round": null,
+    "block_hash": "{BLOCK_HASH}"
+  }}
+}}"#
+            ),
+            compact: "2000030000000000000001000000000000000200\
+                20b53acf0daefc8c6ad68c861fb3b543ca541abd101abc1edfcbf6606b838ef4",
+            hash: "b19bf7777c50fe15954383fb1592d12ec48ea615ac0a95154dbe10cdad052976",
+        },
+        MessageVector {
+            message: ConsensusMessage::NonNilPreVoted(1, 2, block_hash),
+            json: format!(
+                r#"{{
+  "NonNilPreVoted": [
+    1,
+    2,
+    "{BLOCK_HASH}"
+  ]
+}}"#
+            ),
+            compact: "21000300000000000000010000000000000002\
+                20b53acf0daefc8c6ad68c861fb3b543ca541abd101abc1edfcbf6606b838ef4",
+            hash: "05514dbc8c2c816ec52b103148332894811bbe90e91a906918096b0c586aaadb",
+        },
+        MessageVector {
+            message: ConsensusMessage::NonNilPreCommitted(1, 2, block_hash),
+            json: format!(
+                r#"{{
+  "NonNilPreCommitted": [
+    1,
+    2,
+    "{BLOCK_HASH}"
+  ]
+}}"#
+            ),
+            compact: "22000300000000000000010000000000000002\
+                20b53acf0daefc8c6ad68c861fb3b543ca541abd101abc1edfcbf6606b838ef4",
+            hash: "2d31f237f86664d651d99a163b8b3a838e768030d611aaf82dea199098ee7326",
+        },
+        MessageVector {
+            message: ConsensusMessage::NilPreVoted(1, 2),
+            json: r#"{
+  "NilPreVoted": [
+    1,
+    2
+  ]
+}"#
+            .to_owned(),
+            compact: "23000300000000000000010000000000000002",
+            hash: "98a3d8f301ef7c188f330197cefd1d4f28cd659e1499e3fc271493a5fe6c9030",
+        },
+        MessageVector {
+            message: ConsensusMessage::NilPreCommitted(1, 2),
+            json: r#"{
+  "NilPreCommitted": [
+    1,
+    2
+  ]
+}"#
+            .to_owned(),
+            compact: "24000300000000000000010000000000000002",
+            hash: "28feb8e87bd575e0683e5a102386fbe6151a0121361081f67c96997f10875725",
+        },
+    ]
+}
+
+/// Creates the representative state that the fixtures are made of, which must not be changed.
+pub(crate) fn fixture_state() -> State {
+    let (fi, keys) = test_utils::generate_fi(4);
+    let mut state = State::new(
+        &fi.header,
+        ConsensusParams {
+            timeout_ms: 6000,
+            timeout_increment_ms: 0,
+            repeat_round_for_first_leader: 10,
+            quorum: None,
+        },
+        0,
+        Some(keys[1].0.clone()),
+    )
+    .unwrap();
+    for i in 0..2 {
+        state
+            .register_verified_block_hash(Hash256::hash(format!("block{i}")))
+            .unwrap();
+    }
+    state.veto_block(Hash256::hash("block1"), 0).unwrap();
+    state.progress(0).unwrap();
+    state
+}
+
+#[test]
+fn consensus_messages() {
+    assert_eq!(Hash256::hash("block").to_string(), BLOCK_HASH);
+    for vector in message_vectors() {
+        let message = &vector.message;
+        assert_eq!(
+            serde_spb::to_string(message).unwrap(),
+            vector.json,
+            "{message:?}"
+        );
+        assert_eq!(
+            serde_spb::from_str::<ConsensusMessage>(&vector.json).unwrap(),
+            *message
+        );
+        let compact = message.to_compact();
+        assert_eq!(hex::encode(&compact), vector.compact, "{message:?}");
+        assert_eq!(ConsensusMessage::from_compact(&compact).unwrap(), *message);
+        assert_eq!(message.to_hash256().to_string(), vector.hash, "{message:?}");
+        assert_eq!(message.to_hash256(), Hash256::hash(&compact));
+    }
+}
+
+/// The state is frozen in the encodings of the storage instead of JSON,
+/// since it has the maps with non-string keys.
+///
+/// The fixtures are committed under `tests/fixtures/format_vectors` and kept unchanged.
+#[test]
+fn state() {
+    let directory = format!(
+        "{}/tests/fixtures/format_vectors",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let state = fixture_state();
+    for codec in [StateCodec::Bincode, StateCodec::Cbor] {
+        let encoded = codec.encode(&state);
+        let path = format!(
+            "{directory}/state.{}.txt",
+            format!("{codec:?}").to_lowercase()
+        );
+        let frozen = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("failed to read the fixture {path}: {e}"));
+        assert_eq!(
+            encoded, frozen,
+            "the format of the state in {codec:?} has changed"
+        );
+        let decoded = StateCodec::decode(&frozen).unwrap();
+        assert_eq!(codec.encode(&decoded), frozen);
+    }
+}
@@ -0,0 +1,154 @@
+use super::*;
+
+/// A consensus message committed by a peer, in the layout of the packets of the DMS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedMessage {
+    /// The message encoded by `DmsMessage::encode_wire()`.
+    message: Vec<u8>,
+    commitment: MessageCommitmentProof,
+}
+
+/// Parses the bytes in every way that a consensus message is received,
+/// returning the one decoded from the wire.
+///
+/// Any input results in `Ok` or `Err`; it panics only if a parsed message
+/// doesn't survive its own encoding, which is a bug.
+pub fn fuzz_parse_consensus_message(bytes: &[u8]) -> Result<ConsensusMessage, String> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        if let Ok(message) = serde_spb::from_str::<ConsensusMessage>(text) {
+            let encoded = serde_spb::to_string(&message).map_err(|e| e.to_string())?;
+            assert_eq!(
+                serde_spb::from_str::<ConsensusMessage>(&encoded).ok(),
+                Some(message)
+            );
+        }
+        if let Ok(signed) = serde_spb::from_str::<SignedMessage>(text) {
+            let _ = ConsensusMessage::from_wire(&signed.message);
+        }
+    }
+    let _ = ConsensusMessage::wire_version(bytes);
+    let message = ConsensusMessage::from_wire(bytes)?;
+    // Only the canonical encoding is accepted, which is the one of the message.
+    let legacy = serde_spb::to_vec(&message).map_err(|e| e.to_string())?;
+    assert!(message.to_compact() == bytes || legacy == bytes);
+    assert_eq!(
+        ConsensusMessage::from_compact(&message.to_compact()),
+        Ok(message.clone())
+    );
+    Ok(message)
+}
+
+/// Creates the filter of the validator set generated from the seed,
+/// for the height `1` with `Hash256::zero()` as the only verified block.
+fn fuzz_filter_setup(validator_set_seed: u64) -> (ConsensusMessageFilter, Vec<PrivateKey>) {
+    let keys = (0..validator_set_seed % 7 + 1)
+        .map(|i| generate_keypair(format!("fuzz-{validator_set_seed}-{i}")).1)
+        .collect::<Vec<_>>();
+    let filter = ConsensusMessageFilter::new(
+        Arc::new(parking_lot::RwLock::new(BTreeSet::from([Hash256::zero()]))),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        keys.iter().map(|key| key.public_key()).collect(),
+        1,
+        "consensus".to_owned(),
+    );
+    (filter, keys)
+}
+
+/// Runs the filter over the bytes as the DMS does, returning whether they are admitted.
+///
+/// The bytes are taken as a signed message in the layout of the DMS packets if they are one,
+/// or otherwise as an encoded message signed by one of the validators.
+/// Any input results in `Ok` or `Err` without a panic.
+pub fn fuzz_filter(bytes: &[u8], validator_set_seed: u64) -> Result<(), String> {
+    let (filter, keys) = fuzz_filter_setup(validator_set_seed);
+    let signed = std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| serde_spb::from_str::<SignedMessage>(text).ok());
+    match signed {
+        Some(signed) => {
+            filter.filter_raw(&signed.message)?;
+            let message = ConsensusMessage::from_wire(&signed.message)?;
+            filter.filter(&message, &signed.commitment)
+        }
+        None => {
+            filter.filter_raw(bytes)?;
+            let message = ConsensusMessage::from_wire(bytes)?;
+            let commitment = message
+                .commit(&"consensus".to_owned(), &keys[bytes.len() % keys.len()])
+                .map_err(|e| e.to_string())?;
+            filter.filter(&message, &commitment)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The inputs that broke the parsing or the filter, or came close to.
+    fn regression_inputs() -> Vec<Vec<u8>> {
+        let proposal = ConsensusMessage::Proposal {
+            height: 1,
+            round: 0,
+            valid_round: Some(u64::MAX),
+            block_hash: Hash256::zero(),
+        };
+        let mut invalid_flag = proposal.to_compact();
+        invalid_flag[19] = 0x02;
+        let forged = serde_spb::to_string(&SignedMessage {
+            message: ConsensusMessage::NilPreVoted(1, 0).to_compact(),
+            commitment: MessageCommitmentProof {
+                committer: PublicKey::zero(),
+                signature: Signature::zero(),
+            },
+        })
+        .unwrap();
+        vec![
+            Vec::new(),
+            vec![0x23],
+            vec![0x23, 0x00],
+            vec![0x20; 19],
+            invalid_flag,
+            proposal.to_compact(),
+            ConsensusMessage::NilPreCommitted(1, u64::MAX).to_compact(),
+            // The legacy encodings, truncated or of an unknown variant
+            vec![0x00, 0x00, 0x00, 0x00],
+            vec![0x04, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff],
+            vec![0x05, 0x00, 0x00, 0x00],
+            serde_spb::to_vec(&ConsensusMessage::NilPreVoted(1, 0)).unwrap(),
+            b"envelope-1::::".to_vec(),
+            format!("{}{}", "[".repeat(10000), "]".repeat(10000)).into_bytes(),
+            br#"{"NilPreVoted":[1,0]}"#.to_vec(),
+            br#"{"NilPreVoted":[0]}"#.to_vec(),
+            br#"{"Proposal":{"height":1,"round":0,"valid_round":null,"block_hash":"00"}}"#.to_vec(),
+            br#"{"message":[35,0,3],"commitment":{"committer":"","signature":""}}"#.to_vec(),
+            forged.into_bytes(),
+            vec![0xff; DEFAULT_MAX_MESSAGE_SIZE + 1],
+        ]
+    }
+
+    #[test]
+    fn regressions() {
+        for input in regression_inputs() {
+            let _ = fuzz_parse_consensus_message(&input);
+            for seed in [0, 1, 6, u64::MAX] {
+                let _ = fuzz_filter(&input, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn admitted() {
+        let message = ConsensusMessage::NonNilPreVoted(1, 0, Hash256::zero());
+        assert_eq!(
+            fuzz_parse_consensus_message(&message.to_compact()),
+            Ok(message.clone())
+        );
+        fuzz_filter(&message.to_compact(), 3).unwrap();
+        // The block is not verified.
+        let message = ConsensusMessage::NonNilPreVoted(1, 0, Hash256::hash("block"));
+        assert!(fuzz_filter(&message.to_compact(), 3).is_err());
+    }
+}
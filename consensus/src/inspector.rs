@@ -0,0 +1,115 @@
+use super::*;
+use std::future::Future;
+
+/// The number of the attempts to read a file that is corrupted, e.g., by being rewritten.
+const MAX_READ_ATTEMPTS: usize = 5;
+/// How long to wait before reading a corrupted file again.
+const READ_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A read-only view of the consensus storage, opened by `Consensus::open_read_only()`,
+/// for the debugging tools and the dashboards to inspect a node that keeps running.
+///
+/// It never writes to the storage, and needs neither the DMS nor the network;
+/// so the vote tallies are of the messages that the node has consumed,
+/// rather than the ones in the DMS.
+pub struct ConsensusInspector<S: Storage = StorageImpl> {
+    state_storage: ConsensusStorage<S>,
+}
+
+impl<S: Storage> ConsensusInspector<S> {
+    pub(crate) fn new(state_storage: ConsensusStorage<S>) -> Self {
+        Self { state_storage }
+    }
+
+    /// Reads the file again while it is corrupted, since the node might be rewriting it.
+    async fn read_with_retry<'a, T, F, Fut>(&'a self, read: F) -> Result<T, ConsensusError>
+    where
+        F: Fn(&'a ConsensusStorage<S>) -> Fut,
+        Fut: Future<Output = Result<T, ConsensusError>> + 'a,
+    {
+        let mut attempts = 0;
+        loop {
+            match read(&self.state_storage).await {
+                Err(e) if is_corrupt(&e) && attempts + 1 < MAX_READ_ATTEMPTS => {
+                    attempts += 1;
+                    tracing::debug!(error = %e, attempts, "retrying to read the consensus storage");
+                    tokio::time::sleep(READ_RETRY_INTERVAL).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads the state, falling back to the backup if the primary file is corrupted.
+    async fn read_state(&self) -> Result<State, ConsensusError> {
+        self.read_with_retry(|storage| async move {
+            match storage.read_state(StateFile::Primary).await {
+                Err(e) if is_corrupt(&e) => storage.read_state(StateFile::Backup).await,
+                result => result,
+            }
+        })
+        .await
+    }
+
+    /// Returns the status of the stored state.
+    ///
+    /// The ones kept in the memory of the node (e.g., the deliveries) are empty.
+    pub async fn status(&self) -> Result<ConsensusStatus, ConsensusError> {
+        let state = self.read_state().await?;
+        let peer_scores = self
+            .read_with_retry(|storage| storage.read_peer_scores())
+            .await?
+            .unwrap_or_default();
+        Ok(ConsensusStatus {
+            banned_peers: peer_scores.banned_peers(),
+            ..state.status()
+        })
+    }
+
+    /// Tallies the votes in the round, from the messages that the node has consumed.
+    pub async fn vote_tally(&self, round: ConsensusRound) -> Result<VoteTally, ConsensusError> {
+        let state = self.read_state().await?;
+        let validator_set = &state.block_header().validator_set;
+        let mut tally = VoteTally::new(
+            &state.consumed_messages(),
+            validator_set,
+            state.height(),
+            round,
+        );
+        tally.missing_validator_names = tally
+            .missing_validators
+            .iter()
+            .map(|validator| state.validator_info(validator).name)
+            .collect();
+        Ok(tally)
+    }
+
+    /// Reads the entries of the event log from the sequence number `from_seq`.
+    pub async fn read_event_log(
+        &self,
+        from_seq: u64,
+    ) -> Result<Vec<EventLogEntry>, ConsensusError> {
+        let event_log = self
+            .read_with_retry(|storage| storage.read_event_log())
+            .await?
+            .unwrap_or_default();
+        let mut result = parse_event_log(EVENT_LOG_FILE_NAME, &event_log)?;
+        result.retain(|entry| entry.seq >= from_seq);
+        Ok(result)
+    }
+
+    pub async fn list_evidence(&self) -> Result<Vec<Evidence>, ConsensusError> {
+        self.read_with_retry(|storage| storage.read_evidence())
+            .await
+    }
+
+    /// Returns the proof of the finalized block, or `None` if not finalized yet.
+    pub async fn get_finalization_proof(
+        &self,
+    ) -> Result<Option<FinalizationProof>, ConsensusError> {
+        let finalization = self
+            .read_with_retry(|storage| storage.read_finalization())
+            .await?;
+        Ok(finalization.map(|finalization| finalization.proof))
+    }
+}
@@ -1,31 +1,385 @@
+mod backup;
+#[cfg(feature = "bench")]
+mod bench;
+#[cfg(feature = "test-util")]
+mod byzantine;
+mod clock;
+mod codec;
+mod consensus_storage;
+mod delegation;
+mod delivery;
+mod dms_stats;
+mod driver;
+mod encryption;
+mod envelope;
+mod evidence;
+mod filter;
+#[cfg(test)]
+mod format_vectors;
+#[cfg(any(test, feature = "fuzzing"))]
+mod fuzzing;
+mod inspector;
+mod manager;
+mod metrics;
+mod own_votes;
+mod peer_score;
+mod proof;
+mod read_handle;
+mod replay;
+mod signer;
+#[cfg(feature = "test-util")]
+pub mod simulation;
+mod stall;
 mod state;
+mod tally;
 
-use eyre::eyre;
+use clock::ClockSource;
+use consensus_storage::{
+    ConsensusStorage, StateFile, EVENT_LOG_FILE_NAME, FINALIZATION_FILE_NAME, PEER_SCORES_FILE_NAME,
+};
+use delivery::Outbox;
+use dms_stats::FilterCounters;
+use filter::{AdmittedMessages, EarlyMessages, Quarantine};
+use own_votes::OwnVotes;
+use peer_score::PeerScores;
+use read_handle::Snapshot;
 use serde::{Deserialize, Serialize};
 use simperby_core::utils::get_timestamp;
 use simperby_core::*;
 use simperby_network::*;
+use stall::{StallCause, StallDetector};
 use state::*;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
 
-pub type Error = eyre::Error;
+/// The failures of the consensus.
+///
+/// The ones of the storage, the network and the signer carry the description of the cause,
+/// since they come from the other crates untyped.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusError {
+    /// The operation requires the consensus in progress, but it is already finalized.
+    #[error("the consensus is already finalized")]
+    Finalized,
+    #[error("the consensus is not finalized yet")]
+    NotFinalized,
+    /// A valid proof finalizes another block than the one that this node has finalized,
+    /// which means that the safety is broken (e.g., by more than 1/3 Byzantine voting power).
+    #[error("the block {external} is proven to be finalized, but this node has finalized {local}")]
+    ConflictingFinalization { local: Hash256, external: Hash256 },
+    #[error("the block {0} is not verified yet")]
+    BlockNotVerified(Hash256),
+    #[error("{0} is not a validator")]
+    NotAValidator(PublicKey),
+    /// The given arguments don't match the ones that the consensus has been created with.
+    #[error("mismatched with the consensus: {0}")]
+    Mismatch(String),
+    /// The stored consensus is of another height than the one of the given block header,
+    /// e.g., the storage of the previous height is given by mistake.
+    #[error("the stored consensus is of the height {stored}, but expected {expected}")]
+    HeightMismatch {
+        expected: BlockHeight,
+        stored: BlockHeight,
+    },
+    #[error("failed to access the storage: {0}")]
+    Storage(String),
+    /// Failed to access the DMS or the peers, or to serve them.
+    #[error("failed to access the network: {0}")]
+    Network(String),
+    /// The signer has failed to sign a message of this node (or timed out),
+    /// which is left to be signed again.
+    #[error("failed to sign a message of this node: {0}")]
+    Signer(String),
+    /// This node is an observer, which can't sign any consensus message.
+    #[error("an observer can't sign any consensus message")]
+    Observer,
+    /// The message conflicts with the one that this node has signed before,
+    /// so signing it would be a double signing.
+    #[error("refused to sign {message}, which conflicts with the previously signed {signed}")]
+    ConflictingVote { message: String, signed: String },
+    /// A finalization proof, an evidence, a delegation or a signature on a message is invalid.
+    #[error("invalid {0}")]
+    InvalidProof(String),
+    /// The call is not valid in the current state or with the given arguments.
+    #[error("invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("the finalization of the height {0} is not archived")]
+    NotArchived(BlockHeight),
+    #[error("unknown peer {0}")]
+    UnknownPeer(PublicKey),
+    /// The `Consensus` is gone, e.g., dropped with its read handle left.
+    #[error("the consensus is gone: {0}")]
+    Gone(String),
+    /// An invariant of the state is broken, which is a bug.
+    #[error("internal error: {0}")]
+    Internal(String),
+    /// A file of the consensus storage can't be read (for the state, even from the backup),
+    /// which might be fixed by `Consensus::repair()`.
+    #[error("the consensus storage file {file} is corrupted: {reason}")]
+    CorruptState { file: String, reason: String },
+    /// The file is encrypted, but no `StorageEncryptionKey` is given.
+    #[error("the consensus storage file {file} is encrypted, but no key is given")]
+    MissingEncryptionKey { file: String },
+    /// The file can't be decrypted with the given `StorageEncryptionKey`.
+    #[error("failed to decrypt {file}; the encryption key is wrong or the file is corrupted")]
+    WrongEncryptionKey { file: String },
+    /// A key is given, but the file is not encrypted; see `encrypt_storage()`.
+    #[error("the consensus storage file {file} is not encrypted; run `encrypt_storage()` first")]
+    UnencryptedStorage { file: String },
+    /// The stored state is written by a newer version of this module.
+    #[error("the consensus state is of a newer version {0}; please upgrade")]
+    UnsupportedStateVersion(u32),
+    /// The `BackupBundle` is damaged or of an unsupported version.
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+    /// The storage has the messages signed for the height that the `BackupBundle` doesn't,
+    /// which means that the exported node has kept running after the export.
+    #[error("the storage has newer own votes of the height {height} than the backup")]
+    StaleBackup { height: BlockHeight },
+    #[error("the validator set is empty")]
+    EmptyValidatorSet,
+    /// The quorum is less than 2/3 (which breaks the safety) or not less than 1.
+    #[error("invalid quorum {}/{}", .0.numerator, .0.denominator)]
+    InvalidQuorum(Quorum),
+    #[error("{0} appears more than once in the validator set")]
+    DuplicateValidator(PublicKey),
+    #[error("{0} has no voting power")]
+    ZeroVotingPower(PublicKey),
+    /// The total voting power doesn't fit in `VotingPower`.
+    #[error("the total voting power overflows")]
+    VotingPowerOverflow,
+    /// The round doesn't fit in the `usize` of this target, on which vetomint counts the rounds,
+    /// so it can't be processed without being truncated.
+    #[error("the round {0} is out of the range of this target")]
+    RoundOutOfRange(ConsensusRound),
+    /// The timestamp is earlier than the latest one given, which `ClockRegression::Reject`
+    /// refuses so that no deadline of the state machine moves back.
+    #[error("the timestamp {timestamp} is earlier than the latest one {latest}")]
+    ClockRegression {
+        timestamp: Timestamp,
+        latest: Timestamp,
+    },
+    /// There is no state in the storage, which is initialized by `Consensus::new()`.
+    #[error("the consensus state is not initialized; create it with `Consensus::new()`")]
+    StateNotInitialized,
+    /// The given key is not the one of the validator that this node is supposed to be.
+    #[error("expected the key of the validator {index} ({expected}), but got {actual}")]
+    KeyMismatch {
+        expected: Box<PublicKey>,
+        actual: Box<PublicKey>,
+        index: usize,
+    },
+    /// The stored consensus is of a validator, but no key is given.
+    ///
+    /// The role of the node is fixed in the stored state,
+    /// so use `Consensus::recreate()` to run it as an observer.
+    #[error("expected the key of the validator {index} ({expected}), but got none")]
+    MissingKey { expected: PublicKey, index: usize },
+    /// The key of the network configuration is not the one of the DMS,
+    /// which identifies this node in the network within the height.
+    #[error("the network key must stay {expected} within the height, but got {actual}")]
+    NetworkKeyChanged {
+        expected: Box<PublicKey>,
+        actual: Box<PublicKey>,
+    },
+}
+
+impl ConsensusError {
+    pub(crate) fn storage(error: impl std::fmt::Display) -> Self {
+        Self::Storage(format!("{error:#}"))
+    }
+
+    pub(crate) fn network(error: impl std::fmt::Display) -> Self {
+        Self::Network(format!("{error:#}"))
+    }
+}
 
-pub use state::ConsensusMessage;
-pub use vetomint::ConsensusParams;
+pub use backup::{BackupBundle, BACKUP_VERSION};
+#[cfg(feature = "bench")]
+pub use bench::bench_filter;
+#[cfg(feature = "test-util")]
+pub use byzantine::ByzantineConsensus;
+pub use clock::{ClockPolicy, ClockRegression};
+pub use codec::{StateCodec, STATE_VERSION};
+pub use delegation::{Delegation, Delegations, SignedDelegation};
+pub use delivery::{BroadcastPolicy, DeliveryStatus};
+pub use dms_stats::DmsStats;
+pub use driver::{ConsensusCore, ConsensusEffect};
+pub use encryption::{encrypt_storage, StorageEncryptionKey};
+pub use evidence::{verify_evidence, Evidence, Violation, ViolationKind};
+pub use filter::{
+    ConsensusMessageFilter, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_MAX_ROUND_LOOKAHEAD,
+    MAX_EARLY_MESSAGES, MAX_MESSAGES_PER_VOTE, MAX_QUARANTINED_MESSAGES,
+};
+#[cfg(feature = "fuzzing")]
+pub use fuzzing::{fuzz_filter, fuzz_parse_consensus_message};
+pub use inspector::ConsensusInspector;
+pub use manager::{ConsensusManager, ConsensusManagerCommand, ConsensusManagerHandles};
+pub use metrics::{AtomicMetrics, ConsensusMetrics, FilterRejection, Histogram, NoopMetrics};
+pub use peer_score::{PeerBanPolicy, PeerScore};
+pub use proof::{verify_delegated_finalization_proof, verify_finalization_proof};
+pub use read_handle::ConsensusReadHandle;
+pub use replay::replay;
+pub use signer::ConsensusSigner;
+pub use stall::StallPolicy;
+pub use state::{ConsensusMessage, VoteKind, CONSENSUS_PROTOCOL_VERSION};
+pub use tally::{total_voting_power, LivenessReport, ValidatorLiveness, VoteTally};
+pub use vetomint::{ConsensusParams, ConsensusStep, Quorum};
+
+const PROGRESS_RESULT_CHANNEL_SIZE: usize = 1024;
+const COMMAND_CHANNEL_SIZE: usize = 64;
+const RECOVERED_ERROR_CHANNEL_SIZE: usize = 64;
+/// The default limit of the processed events retained in the state.
+pub const DEFAULT_MAX_RETAINED_EVENTS: usize = 10_000;
+/// The default number of the past heights whose archives are kept.
+pub const DEFAULT_ARCHIVE_RETENTION: u64 = 10;
+/// The default time to wait for the signer to sign a message of this node.
+pub const DEFAULT_SIGNER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses the entries of the event log in JSON lines.
+fn parse_event_log(file: &str, event_log: &str) -> Result<Vec<EventLogEntry>, ConsensusError> {
+    event_log
+        .lines()
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| ConsensusError::CorruptState {
+                file: file.to_owned(),
+                reason: format!("invalid entry in the event log: {e}"),
+            })
+        })
+        .collect()
+}
+
+/// Returns whether the error is `ConsensusError::CorruptState`.
+fn is_corrupt(error: &ConsensusError) -> bool {
+    matches!(error, ConsensusError::CorruptState { .. })
+}
+
+/// Checks that the validator set can run the consensus.
+fn verify_validator_set(validator_set: &[(PublicKey, VotingPower)]) -> Result<(), ConsensusError> {
+    if validator_set.is_empty() {
+        return Err(ConsensusError::EmptyValidatorSet);
+    }
+    let mut validators = BTreeSet::new();
+    let mut total_voting_power: VotingPower = 0;
+    for (validator, voting_power) in validator_set {
+        if !validators.insert(validator) {
+            return Err(ConsensusError::DuplicateValidator(validator.clone()));
+        }
+        if *voting_power == 0 {
+            return Err(ConsensusError::ZeroVotingPower(validator.clone()));
+        }
+        // The quorum arithmetic is done in `u128`, but the total must fit in `VotingPower`
+        // for the ones reporting it (e.g., `VoteTally`).
+        total_voting_power = total_voting_power
+            .checked_add(*voting_power)
+            .ok_or(ConsensusError::VotingPowerOverflow)?;
+    }
+    Ok(())
+}
 
-const STATE_FILE_NAME: &str = "state.json";
+/// Returns whether the error is of accessing the storage or the DMS, which is possibly transient.
+fn is_recoverable(error: &ConsensusError) -> bool {
+    matches!(
+        error,
+        ConsensusError::Storage(_) | ConsensusError::Network(_)
+    )
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ProgressResult {
-    Proposed(ConsensusRound, Hash256, Timestamp),
+    /// This node has proposed the block, with the valid round and the proposer (this node).
+    Proposed(
+        ConsensusRound,
+        Hash256,
+        Option<ConsensusRound>,
+        PublicKey,
+        Timestamp,
+    ),
     NonNilPreVoted(ConsensusRound, Hash256, Timestamp),
     NonNilPreCommitted(ConsensusRound, Hash256, Timestamp),
     NilPreVoted(ConsensusRound, Timestamp),
     NilPreCommitted(ConsensusRound, Timestamp),
+    /// A prevote or a precommit of another validator has been processed.
+    ///
+    /// Reported only if `Consensus::set_report_observed_votes()` is on.
+    VoteObserved {
+        signer: PublicKey,
+        round: ConsensusRound,
+        /// `None` for nil.
+        block_hash: Option<Hash256>,
+        kind: VoteKind,
+        timestamp: Timestamp,
+    },
     Finalized(Finalization),
-    ViolationReported(PublicKey, String, Timestamp),
+    ViolationReported(Violation, Timestamp),
+    /// The consensus has crossed a threshold of the `StallPolicy`,
+    /// reported once until it gets out of the stall.
+    Stalled {
+        /// The number of the consecutive rounds ended without a quorum on a block.
+        rounds_without_progress: u64,
+        /// When a new message has been consumed from the DMS last, if any in this height.
+        last_message_timestamp: Option<Timestamp>,
+        /// The voting power of the validators that have voted in neither way:
+        /// in the current round if no message has come for too long,
+        /// and in the last ended round otherwise.
+        missing_power: VotingPower,
+        timestamp: Timestamp,
+    },
+    /// The consensus has moved on to a higher round, leaving the previous one unfinalized.
+    ///
+    /// It is reported before the results of the new round (e.g., the proposal of this node).
+    RoundAdvanced {
+        new_round: ConsensusRound,
+        reason: RoundSkipReason,
+        timestamp: Timestamp,
+    },
+}
+
+/// Why the consensus has left a round, reported by `ProgressResult::RoundAdvanced`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundSkipReason {
+    /// The precommit timeout of the round has expired.
+    Timeout,
+    /// A quorum of the validators has precommitted nil in the round.
+    NilQuorum,
+    /// This node has vetoed the round by `Consensus::veto_round()`,
+    /// whichever of the above has ended it.
+    Vetoed,
+}
+
+impl ProgressResult {
+    fn is_observed_vote(&self) -> bool {
+        matches!(self, ProgressResult::VoteObserved { .. })
+    }
+
+    /// Returns the round that the result is of, if any.
+    fn round(&self) -> Option<ConsensusRound> {
+        match self {
+            ProgressResult::Proposed(round, ..)
+            | ProgressResult::NonNilPreVoted(round, ..)
+            | ProgressResult::NonNilPreCommitted(round, ..)
+            | ProgressResult::NilPreVoted(round, _)
+            | ProgressResult::NilPreCommitted(round, _)
+            | ProgressResult::VoteObserved { round, .. } => Some(*round),
+            ProgressResult::Finalized(finalization) => Some(finalization.proof.round),
+            ProgressResult::ViolationReported(violation, _) => Some(violation.round),
+            ProgressResult::RoundAdvanced { new_round, .. } => Some(*new_round),
+            ProgressResult::Stalled { .. } => None,
+        }
+    }
+}
+
+/// An entry of the event log, which records a `ProgressResult` of this node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLogEntry {
+    /// The sequence number, increasing by one from 0 across the heights.
+    pub seq: u64,
+    pub height: BlockHeight,
+    /// The round of the result, or the current round if the result has none.
+    pub round: ConsensusRound,
+    pub result: ProgressResult,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -35,166 +389,2485 @@ pub struct Finalization {
     pub proof: FinalizationProof,
 }
 
+/// The record of a past height, archived by `finalize_and_advance()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedHeight {
+    /// The status of the consensus when the height was finalized.
+    pub status: ConsensusStatus,
+    pub finalization: Finalization,
+    /// The entries of the event log of the height.
+    pub events: Vec<EventLogEntry>,
+    /// The messages of the height with their commitments,
+    /// empty unless `set_archive_messages()` is on.
+    pub messages: Vec<(ConsensusMessage, Vec<MessageCommitmentProof>)>,
+}
+
+/// A snapshot of the consensus state, for monitoring and debugging.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusStatus {
+    /// The height of the block that this consensus is performing on.
+    pub height: BlockHeight,
+    /// The current round.
+    pub round: ConsensusRound,
+    /// The step of the current round.
+    pub step: ConsensusStep,
+    /// The time when the current step times out, if it is scheduled.
+    pub timeout: Option<Timestamp>,
+    /// The block that this node has locked on, with the round of the lock.
+    ///
+    /// It is set when this node precommits a block on a quorum of prevotes for it.
+    /// A locked node prevotes nil for any other block, unless it is re-proposed with
+    /// a quorum of prevotes in a round later than the lock; so the lock is never released
+    /// within the height, but moves to the later rounds with the next quorum of prevotes.
+    pub locked: Option<(Hash256, ConsensusRound)>,
+    /// The latest block that this node has seen with a quorum of prevotes,
+    /// with the round of the quorum.
+    ///
+    /// It is what this node proposes once it is the proposer, with the round as `valid_round`.
+    /// Unlike the lock, it is updated even after this node has precommitted in the round.
+    pub valid: Option<(Hash256, ConsensusRound)>,
+    /// The block hashes that have been verified, in the order of the registration.
+    pub verified_block_hashes: Vec<Hash256>,
+    /// The block hashes that have been vetoed by this node.
+    pub vetoed_block_hashes: Vec<Hash256>,
+    /// The rounds that have been vetoed by this node, in ascending order.
+    pub vetoed_rounds: Vec<ConsensusRound>,
+    /// The queue of the proposal candidates with their priorities, the highest first.
+    pub proposal_candidates: Vec<(Hash256, u64)>,
+    /// The index of this node in the validator set, or `None` if this node is not a validator.
+    pub this_node_index: Option<usize>,
+    /// Whether the consensus is finalized.
+    pub finalized: bool,
+    /// The number of the messages from the peers held back until their blocks are verified.
+    pub quarantined_messages: usize,
+    /// The deliveries of the messages of this node in the current and the future rounds,
+    /// tracked by `broadcast()`.
+    pub deliveries: Vec<DeliveryStatus>,
+    /// The peers banned by `fetch()`, with the time until when they are banned.
+    pub banned_peers: Vec<(PublicKey, Timestamp)>,
+    /// The names of the validators in the order of the set, empty for the ones without.
+    pub validator_names: Vec<String>,
+}
+
+/// The human-readable information of a validator, shown in the reports to the operators.
+///
+/// It is only for the display, so it affects neither the consensus nor any hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorInfo {
+    pub name: String,
+    /// How to reach the operator of the validator (e.g., an email address).
+    pub contact: String,
+}
+
+/// Tells whether a verified block hash corresponds to a block that has passed the full verification.
+pub trait BlockValidityProvider: Send + Sync + 'static {
+    /// Returns `None` if the validity of the block is not known yet.
+    fn is_valid(&self, block_hash: &Hash256) -> Option<bool>;
+}
+
+impl<F> BlockValidityProvider for F
+where
+    F: Fn(&Hash256) -> Option<bool> + Send + Sync + 'static,
+{
+    fn is_valid(&self, block_hash: &Hash256) -> Option<bool> {
+        self(block_hash)
+    }
+}
+
+/// The optional inputs of `Consensus::new()` and `Consensus::recreate()`.
+///
+/// The default is of an observer with the unencrypted storage,
+/// which takes every block registered by `register_verified_block_hash()` as valid.
+#[derive(Clone)]
+pub struct ConsensusConfig {
+    /// Signs the messages of this node, which is `None` for an observer.
+    pub this_node_signer: Option<Arc<dyn ConsensusSigner>>,
+    /// The validity of the proposed blocks.
+    pub validity_provider: Arc<dyn BlockValidityProvider>,
+    /// Encrypts the files of the storage at rest if given;
+    /// the existing plaintext ones must be encrypted by `encrypt_storage()` first.
+    pub encryption_key: Option<StorageEncryptionKey>,
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            this_node_signer: None,
+            validity_provider: Arc::new(|_: &Hash256| Some(true)),
+            encryption_key: None,
+        }
+    }
+}
+
+/// The sender of the result of a `ConsensusCommand`.
+pub type CommandResultSender = oneshot::Sender<Result<(), ConsensusError>>;
+
+/// An operation on the consensus running by `serve()`, which is otherwise inaccessible.
+///
+/// Each command carries the sender to report its result.
+#[derive(Debug)]
+pub enum ConsensusCommand {
+    RegisterVerifiedBlockHash(Hash256, CommandResultSender),
+    SetProposalCandidate(Hash256, Timestamp, CommandResultSender),
+    ClearProposalCandidate(Timestamp, CommandResultSender),
+    VetoBlock(Hash256, CommandResultSender),
+    VetoRound(ConsensusRound, Timestamp, CommandResultSender),
+    AddPeer(Peer, CommandResultSender),
+    /// Fails if the peer is not known.
+    RemovePeer(PublicKey, CommandResultSender),
+    /// Stops serving after flushing the messages of this node,
+    /// so that the consensus can be opened again from the same storage.
+    ///
+    /// It is handled between the progresses like the others, so it never interrupts one.
+    Shutdown(CommandResultSender),
+}
+
+pub type ConsensusCommandSender = mpsc::Sender<ConsensusCommand>;
+
+/// What `serve()` returns: the task, the receivers of the results and the recovered errors,
+/// and the handles to read the status and to send the commands.
+pub type ServeHandles = (
+    tokio::task::JoinHandle<Result<(), ConsensusError>>,
+    mpsc::Receiver<ProgressResult>,
+    ConsensusReadHandle,
+    ConsensusCommandSender,
+    mpsc::Receiver<ConsensusError>,
+);
+
+/// How `serve()` deals with the failures of accessing the storage or the DMS,
+/// which are possibly transient.
+///
+/// The other failures (e.g., a corrupt state) always end the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of the consecutive failures to survive; one more ends the service.
+    pub max_consecutive_failures: usize,
+    /// How long to wait after a failure before retrying, instead of the progress interval.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_consecutive_failures: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
 /// The consensus module
-pub struct Consensus {
+///
+/// It is generic over the storage of the consensus state so that it can be tested in memory.
+pub struct Consensus<S: Storage = StorageImpl> {
     /// The distributed consensus message set.
     dms: Arc<RwLock<Dms<ConsensusMessage>>>,
     /// The local storage for the consensus state.
-    state_storage: StorageImpl,
+    state_storage: ConsensusStorage<S>,
+    /// The set of the verified block hashes, shared with the message filter of the DMS.
+    verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
+    /// The messages admitted to the DMS, shared with the message filter of the DMS.
+    admitted_messages: Arc<parking_lot::RwLock<AdmittedMessages>>,
+    /// The messages on the blocks not verified yet, shared with the message filter of the DMS.
+    quarantine: Arc<parking_lot::RwLock<Quarantine>>,
+    /// The messages of the next height that have arrived early,
+    /// shared with the message filter of the DMS.
+    early_messages: Arc<parking_lot::RwLock<EarlyMessages>>,
+    /// The round of the state, shared with the message filter of the DMS.
+    current_round: Arc<parking_lot::RwLock<ConsensusRound>>,
+    /// The validity of the proposed blocks.
+    validity_provider: Arc<dyn BlockValidityProvider>,
+    /// Signs the messages of this node, which is `None` for an observer.
+    signer: Option<Arc<dyn ConsensusSigner>>,
+    /// The public key of the signer, which the messages of this node are committed by.
+    this_node_public_key: Option<PublicKey>,
+    signer_timeout: Duration,
+    /// The messages that have been decoded from the DMS, by their hashes.
+    ///
+    /// A message is usually committed by many validators,
+    /// so this saves decoding it for every commitment.
+    message_cache: BTreeMap<Hash256, ConsensusMessage>,
+    /// The maximum number of the processed events retained in the state for deduplication.
+    max_retained_events: usize,
+    /// The codec to write the state with.
+    state_codec: StateCodec,
+    /// The number of the past heights whose archives are kept.
+    archive_retention: u64,
+    /// Whether the messages in the DMS are archived with the finalized height.
+    archive_messages: bool,
+    /// Whether `progress()` reports the votes of the other validators.
+    report_observed_votes: bool,
+    /// Whether the results of `progress()` are appended to the event log.
+    event_log: bool,
+    retry_policy: RetryPolicy,
+    broadcast_policy: BroadcastPolicy,
+    /// The messages of this node committed to the DMS, to be retried by `broadcast()`.
+    outbox: Outbox,
+    peer_ban_policy: PeerBanPolicy,
+    stall_policy: StallPolicy,
+    /// Whether the consensus of the current height is stalled, not persisted.
+    stall_detector: StallDetector,
+    /// Normalizes the timestamps before they reach the state, kept across the heights.
+    clock: ClockSource,
+    /// The scores of the peers that `fetch()` has fetched from, persisted on every change.
+    peer_scores: PeerScores,
+    /// The peers that `serve()` and `fetch_and_progress()` fetch from and broadcast to,
+    /// which can be changed anytime through the shared handle.
+    known_peers: SharedKnownPeers,
+    metrics: Arc<dyn ConsensusMetrics>,
+    /// The counters of the message filter since the startup, kept across the heights.
+    filter_counters: Arc<FilterCounters>,
+    /// The maximum size of an encoded message admitted to the DMS.
+    max_message_size: usize,
+    /// The number of the rounds ahead whose messages are admitted to the DMS.
+    max_round_lookahead: ConsensusRound,
+    /// The number of the received messages whose signatures the DMS verifies together.
+    verification_batch_size: usize,
+    /// When this instance has seen the first proposal of the height, for the metrics.
+    first_proposal_timestamp: Option<Timestamp>,
+    /// The current round and since when `serve()` has seen it pending,
+    /// to request its missing votes once it is pending past its timeout.
+    pending_round: Option<(ConsensusRound, Timestamp)>,
+    /// The hash of the state that this instance has written last,
+    /// to skip writing the same state again.
+    committed_state_hash: Option<Hash256>,
+    /// Publishes the latest status to the read handles.
+    snapshot_sender: watch::Sender<Snapshot>,
+    /// Kept to create the read handles, and to keep the channel open.
+    snapshot_receiver: watch::Receiver<Snapshot>,
 }
 
-impl Consensus {
-    /// Creates a consensus instance.
+impl<S: Storage> Consensus<S> {
+    /// Creates a consensus instance, or loads the one in the storage.
+    ///
+    /// It initializes the DMS and the storage if there is no state in the storage.
+    /// An existing state is never overwritten: it fails with `ConsensusError::HeightMismatch`
+    /// if the stored one is of another height (e.g., the storage of the previous height),
+    /// with `ConsensusError::Mismatch` if the block header is different from the stored one,
+    /// or with the error of reading it if it can't be read (use `recreate()` to discard it).
     ///
-    /// It clears and re-initializes the DMS and the stroage
-    /// if the block header is different from the last one.
+    /// If the stored state is corrupted (e.g., by a crash during the write),
+    /// it is recovered from the backup.
     pub async fn new(
         dms: Arc<RwLock<Dms<ConsensusMessage>>>,
-        state_storage: StorageImpl,
+        state_storage: S,
+        block_header: BlockHeader,
+        consensus_parameters: ConsensusParams,
+        round_zero_timestamp: Timestamp,
+        config: ConsensusConfig,
+    ) -> Result<Self, ConsensusError> {
+        Self::open(
+            dms,
+            ConsensusStorage::new(state_storage, config.encryption_key),
+            block_header,
+            consensus_parameters,
+            round_zero_timestamp,
+            config.this_node_signer,
+            config.validity_provider,
+            false,
+            Delegations::default(),
+        )
+        .await
+    }
+
+    /// Creates a consensus instance, clearing the DMS and the storage
+    /// (e.g., to move on to the next height, or to discard an unreadable state).
+    ///
+    /// The record of the messages signed by this node and the evidence are kept.
+    pub async fn recreate(
+        dms: Arc<RwLock<Dms<ConsensusMessage>>>,
+        state_storage: S,
+        block_header: BlockHeader,
+        consensus_parameters: ConsensusParams,
+        round_zero_timestamp: Timestamp,
+        config: ConsensusConfig,
+    ) -> Result<Self, ConsensusError> {
+        Self::open(
+            dms,
+            ConsensusStorage::new(state_storage, config.encryption_key),
+            block_header,
+            consensus_parameters,
+            round_zero_timestamp,
+            config.this_node_signer,
+            config.validity_provider,
+            true,
+            Delegations::default(),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn open(
+        dms: Arc<RwLock<Dms<ConsensusMessage>>>,
+        state_storage: ConsensusStorage<S>,
         block_header: BlockHeader,
         consensus_parameters: ConsensusParams,
         round_zero_timestamp: Timestamp,
-        this_node_key: Option<PrivateKey>,
-    ) -> Result<Self, Error> {
-        let mut this = Self { dms, state_storage };
+        this_node_signer: Option<Arc<dyn ConsensusSigner>>,
+        validity_provider: Arc<dyn BlockValidityProvider>,
+        overwrite: bool,
+        delegations: Delegations,
+    ) -> Result<Self, ConsensusError> {
+        verify_validator_set(&block_header.validator_set)?;
+        let quorum = consensus_parameters.quorum();
+        if !quorum.is_valid() {
+            return Err(ConsensusError::InvalidQuorum(quorum));
+        }
+        let validator_set = &block_header.validator_set;
+        let (this_node_public_key, this_node_delegation) = match &this_node_signer {
+            Some(signer) => (Some(signer.public_key().await), signer.delegation().await),
+            None => (None, None),
+        };
+        // The validator that this node signs for, which is not the key of the signer
+        // if it is a consensus key delegated by the validator.
+        let this_node_identity = match &this_node_delegation {
+            Some(delegation) => {
+                let mut own_delegations = Delegations::default();
+                own_delegations.add(delegation.clone())?;
+                this_node_public_key
+                    .as_ref()
+                    .and_then(|key| own_delegations.resolve(key, block_header.height + 1))
+            }
+            None => this_node_public_key.clone(),
+        };
+        let this_node_index = this_node_identity
+            .as_ref()
+            .and_then(|key| validator_set.iter().position(|(pubkey, _)| pubkey == key));
+        // The DMS identifies this node in the network, so it must not have the key
+        // of another validator; it may have a separate key if the signer is remote.
+        if let Some(index) = this_node_index {
+            let dms_public_key = dms.read().await.public_key();
+            if dms_public_key != validator_set[index].0
+                && validator_set
+                    .iter()
+                    .any(|(pubkey, _)| *pubkey == dms_public_key)
+            {
+                return Err(ConsensusError::KeyMismatch {
+                    expected: Box::new(validator_set[index].0.clone()),
+                    actual: Box::new(dms_public_key),
+                    index,
+                });
+            }
+        }
         // Prepare new state in case of storage reset.
-        let new_state = State::new(
+        let mut new_state = State::new(
             &block_header,
             consensus_parameters,
             round_zero_timestamp,
-            this_node_key.clone().unwrap(),
+            this_node_identity,
         )?;
-        if let Ok(state) = this.read_state().await {
+        new_state.set_delegations(delegations);
+        let (snapshot_sender, snapshot_receiver) = watch::channel(Snapshot {
+            status: new_state.status(),
+            progress_results: Vec::new(),
+        });
+        let mut this = Self {
+            dms,
+            state_storage,
+            verified_block_hashes: Default::default(),
+            admitted_messages: Default::default(),
+            quarantine: Default::default(),
+            early_messages: Default::default(),
+            current_round: Default::default(),
+            validity_provider,
+            signer: this_node_signer,
+            this_node_public_key: this_node_public_key.clone(),
+            signer_timeout: DEFAULT_SIGNER_TIMEOUT,
+            message_cache: BTreeMap::new(),
+            max_retained_events: DEFAULT_MAX_RETAINED_EVENTS,
+            state_codec: StateCodec::default(),
+            archive_retention: DEFAULT_ARCHIVE_RETENTION,
+            archive_messages: false,
+            report_observed_votes: false,
+            event_log: false,
+            retry_policy: RetryPolicy::default(),
+            broadcast_policy: BroadcastPolicy::default(),
+            outbox: Outbox::default(),
+            peer_ban_policy: PeerBanPolicy::default(),
+            stall_policy: StallPolicy::default(),
+            stall_detector: StallDetector::default(),
+            clock: ClockSource::default(),
+            peer_scores: PeerScores::default(),
+            known_peers: SharedKnownPeers::default(),
+            metrics: Arc::new(NoopMetrics),
+            filter_counters: Default::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_round_lookahead: DEFAULT_MAX_ROUND_LOOKAHEAD,
+            verification_batch_size: DEFAULT_VERIFICATION_BATCH_SIZE,
+            first_proposal_timestamp: None,
+            pending_round: None,
+            committed_state_hash: None,
+            snapshot_sender,
+            snapshot_receiver,
+        };
+        if this.state_storage.has_state().await? && !overwrite {
+            let state = this.read_state().await?;
+            if state.height() != block_header.height + 1 {
+                return Err(ConsensusError::HeightMismatch {
+                    expected: block_header.height + 1,
+                    stored: state.height(),
+                });
+            }
             if block_header != *state.block_header() {
-                return Err(eyre!("different block header in the storage"));
+                return Err(ConsensusError::Mismatch(
+                    "different block header in the storage".to_owned(),
+                ));
             }
+            let stored_index = state.status().this_node_index;
+            if stored_index != this_node_index {
+                return Err(match (stored_index, this_node_public_key) {
+                    (Some(index), Some(actual)) => ConsensusError::KeyMismatch {
+                        expected: Box::new(validator_set[index].0.clone()),
+                        actual: Box::new(actual),
+                        index,
+                    },
+                    (Some(index), None) => ConsensusError::MissingKey {
+                        expected: validator_set[index].0.clone(),
+                        index,
+                    },
+                    (None, _) => ConsensusError::Mismatch(
+                        "the stored consensus is of an observer".to_owned(),
+                    ),
+                });
+            }
+            // Restore both files in case one of them was corrupted.
+            this.commit_state(&state).await?;
+            this.peer_scores = this.read_peer_scores().await?;
         } else {
-            this.dms.write().await.clear().await?;
-            this.state_storage.remove_all_files().await?;
+            // The record of the messages signed by this node, the evidence, the peer scores,
+            // the archives of the past heights and the event log survive the reset
+            // (see `ConsensusStorage::clear_height()`), but they must be readable.
+            let own_votes = this.read_own_votes(&block_header).await?;
+            this.peer_scores = this.read_peer_scores().await?;
+            this.list_evidence().await?;
+            this.state_storage.read_event_log().await?;
+            this.dms
+                .write()
+                .await
+                .clear()
+                .await
+                .map_err(ConsensusError::network)?;
+            this.state_storage.clear_height().await?;
+            this.commit_own_votes(&own_votes).await?;
+            this.commit_peer_scores().await?;
             this.commit_state(&new_state).await?;
         };
 
-        if this
+        // The DMS may have more members (e.g., the network keys of the nodes whose signers
+        // are remote), which can't sign a consensus message through the filter anyway.
+        let members = this.dms.read().await.get_config().members;
+        if let Some((missing, _)) = block_header
+            .validator_set
+            .iter()
+            .find(|(pubkey, _)| !members.contains(pubkey))
+        {
+            return Err(ConsensusError::Mismatch(format!(
+                "the validator {missing} is not a member of the DMS"
+            )));
+        }
+
+        let mut state = this.read_state().await?;
+        if let Some(delegation) = this_node_delegation {
+            state.add_delegation(delegation)?;
+            this.commit_state(&state).await?;
+        }
+        *this.verified_block_hashes.write() =
+            state.verified_block_hashes().keys().cloned().collect();
+        *this.current_round.write() = state.round();
+        // The messages already in the DMS count toward the cap of the filter.
+        let messages = this
             .dms
             .read()
             .await
-            .get_config()
-            .members
-            .iter()
-            .collect::<BTreeSet<_>>()
-            != block_header
-                .validator_set
-                .iter()
-                .map(|(pubkey, _)| pubkey)
-                .collect::<BTreeSet<_>>()
-        {
-            return Err(eyre!("validator set does not match the DMS members"));
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)?;
+        for message in messages {
+            let (round, kind) = message.message.vote_key();
+            for commitment in message.committers {
+                this.admitted_messages
+                    .write()
+                    .entry((commitment.committer, round, kind))
+                    .or_default()
+                    .push(message.message.to_hash256());
+            }
         }
+        this.attach_filter(&state).await;
         Ok(this)
     }
 
-    pub async fn get_block_header(&self) -> Result<BlockHeader, Error> {
+    /// Sets the metrics to report to (`NoopMetrics` by default),
+    /// including the ones of the message filter of the DMS.
+    pub async fn set_metrics(
+        &mut self,
+        metrics: Arc<dyn ConsensusMetrics>,
+    ) -> Result<(), ConsensusError> {
+        self.metrics = metrics;
         let state = self.read_state().await?;
-        Ok(state.block_header().clone())
+        self.attach_filter(&state).await;
+        Ok(())
     }
 
-    /// Checks whether the consensus is finalized.
-    pub async fn check_finalized(&self) -> Result<Option<Finalization>, Error> {
+    /// Sets the maximum size of an encoded message that the DMS admits from the peers
+    /// (`DEFAULT_MAX_MESSAGE_SIZE` by default), which is checked before decoding it.
+    pub async fn set_max_message_size(
+        &mut self,
+        max_message_size: usize,
+    ) -> Result<(), ConsensusError> {
+        self.max_message_size = max_message_size;
         let state = self.read_state().await?;
-        Ok(state.check_finalized())
+        self.attach_filter(&state).await;
+        Ok(())
     }
 
-    pub async fn register_verified_block_hash(&mut self, block_hash: Hash256) -> Result<(), Error> {
-        let mut state = self.read_state().await?;
-        state.register_verified_block_hash(block_hash);
-        self.commit_state(&state).await?;
+    /// Sets the number of the rounds ahead of the current one whose messages the DMS admits
+    /// from the peers (`DEFAULT_MAX_ROUND_LOOKAHEAD` by default).
+    ///
+    /// The messages within the window are kept to be processed when their rounds come.
+    pub async fn set_max_round_lookahead(
+        &mut self,
+        max_round_lookahead: ConsensusRound,
+    ) -> Result<(), ConsensusError> {
+        self.max_round_lookahead = max_round_lookahead;
+        let state = self.read_state().await?;
+        self.attach_filter(&state).await;
         Ok(())
     }
 
-    /// Makes a progress in the consensus process.
-    pub async fn progress(&mut self, timestamp: Timestamp) -> Result<Vec<ProgressResult>, Error> {
-        let mut state = self.read_state().await?;
-        let result = state.progress(timestamp);
-        self.commit_state(&state).await?;
-        Ok(result)
+    /// Sets the number of the messages received from the peers whose signatures are verified
+    /// together, in parallel, when catching up on many of them
+    /// (`DEFAULT_VERIFICATION_BATCH_SIZE` by default); `1` verifies them one by one.
+    pub async fn set_verification_batch_size(
+        &mut self,
+        verification_batch_size: usize,
+    ) -> Result<(), ConsensusError> {
+        self.verification_batch_size = verification_batch_size;
+        let state = self.read_state().await?;
+        self.attach_filter(&state).await;
+        Ok(())
     }
 
-    pub async fn set_proposal_candidate(
+    /// Sets the time to wait for the signer to sign a message of this node
+    /// (`DEFAULT_SIGNER_TIMEOUT` by default), after which it is left to be signed again.
+    pub fn set_signer_timeout(&mut self, signer_timeout: Duration) {
+        self.signer_timeout = signer_timeout;
+    }
+
+    /// Sets the maximum number of the processed events retained in the state.
+    ///
+    /// The events of the passed rounds are always pruned, so this matters only
+    /// when too many messages of the current and the future rounds are received.
+    pub fn set_max_retained_events(&mut self, max_retained_events: usize) {
+        self.max_retained_events = max_retained_events;
+    }
+
+    /// Sets the codec to write the state with from now on (`StateCodec::Bincode` by default).
+    ///
+    /// The state written with any codec can be read regardless of this.
+    pub fn set_state_codec(&mut self, state_codec: StateCodec) {
+        self.state_codec = state_codec;
+    }
+
+    /// Sets whether `progress()` reports the votes of the other validators
+    /// as `ProgressResult::VoteObserved` (off by default), e.g., for a live dashboard.
+    pub fn set_report_observed_votes(&mut self, report_observed_votes: bool) {
+        self.report_observed_votes = report_observed_votes;
+    }
+
+    /// Sets how `serve()` deals with the possibly transient failures
+    /// (`RetryPolicy::default()` by default).
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Sets how `broadcast()` retries to send the messages of this node
+    /// (`BroadcastPolicy::default()` by default).
+    pub fn set_broadcast_policy(&mut self, broadcast_policy: BroadcastPolicy) {
+        self.broadcast_policy = broadcast_policy;
+    }
+
+    /// Sets when `fetch()` bans the peers that deliver the rejected messages
+    /// (`PeerBanPolicy::default()` by default).
+    pub fn set_peer_ban_policy(&mut self, peer_ban_policy: PeerBanPolicy) {
+        self.peer_ban_policy = peer_ban_policy;
+    }
+
+    /// Sets when `progress()` reports that the consensus is stalled
+    /// (`StallPolicy::default()` by default).
+    pub fn set_stall_policy(&mut self, stall_policy: StallPolicy) {
+        self.stall_policy = stall_policy;
+    }
+
+    /// Sets how the timestamps are normalized before they reach the state
+    /// (`ClockPolicy::default()` by default, which takes them as they are given).
+    pub fn set_clock_policy(&mut self, clock_policy: ClockPolicy) {
+        self.clock.set_policy(clock_policy);
+    }
+
+    /// Sets the peers that `serve()` and `fetch_and_progress()` fetch from and broadcast to
+    /// (none by default), whose handle can be kept to change them anytime.
+    pub fn set_known_peers(&mut self, known_peers: SharedKnownPeers) {
+        self.known_peers = known_peers;
+    }
+
+    /// Returns the shared handle of the known peers.
+    pub fn known_peers(&self) -> SharedKnownPeers {
+        self.known_peers.clone()
+    }
+
+    /// Adds the peer to the known peers, replacing the one of the same public key.
+    ///
+    /// Use `ConsensusCommand::AddPeer` while serving.
+    pub fn add_peer(&self, peer: Peer) {
+        self.known_peers.add_peer(peer);
+    }
+
+    /// Removes the peer from the known peers, returning whether it has been known.
+    ///
+    /// Use `ConsensusCommand::RemovePeer` while serving.
+    pub fn remove_peer(&self, public_key: &PublicKey) -> bool {
+        self.known_peers.remove_peer(public_key)
+    }
+
+    /// Returns a handle to read the latest status without accessing this instance.
+    pub fn read_handle(&self) -> ConsensusReadHandle {
+        ConsensusReadHandle::new(self.snapshot_receiver.clone())
+    }
+
+    pub async fn get_block_header(&self) -> Result<BlockHeader, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.block_header().clone())
+    }
+
+    /// Returns the total voting power of the validator set of the height.
+    pub async fn total_voting_power(&self) -> Result<u128, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(total_voting_power(&state.block_header().validator_set))
+    }
+
+    /// Returns the quorum of the height, given by `ConsensusParams::quorum`.
+    pub async fn quorum(&self) -> Result<Quorum, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.quorum())
+    }
+
+    /// Returns the least voting power that makes a quorum in the height,
+    /// exactly as the state machine decides it (see `Quorum::is_reached()`).
+    pub async fn quorum_threshold(&self) -> Result<u128, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state
+            .quorum()
+            .threshold(total_voting_power(&state.block_header().validator_set)))
+    }
+
+    /// Returns the voting power of this node, or `None` if it's an observer.
+    pub async fn this_node_voting_power(&self) -> Result<Option<VotingPower>, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state
+            .status()
+            .this_node_index
+            .map(|index| state.block_header().validator_set[index].1))
+    }
+
+    /// Returns the information of the validator (or of the one that has delegated the key),
+    /// which is empty if not given.
+    pub async fn validator_info(
+        &self,
+        public_key: &PublicKey,
+    ) -> Result<ValidatorInfo, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.validator_info(public_key))
+    }
+
+    /// Sets the information of the validators, which is shown in the reports
+    /// (the violations, `vote_tally()`, `liveness_report()` and `status()`) along with the keys.
+    ///
+    /// It is kept over the heights by `finalize_and_advance()` for the remaining validators.
+    pub async fn set_validator_info(
         &mut self,
-        block_hash: Hash256,
-        timestamp: Timestamp,
-    ) -> Result<(), Error> {
+        validator_info: BTreeMap<PublicKey, ValidatorInfo>,
+    ) -> Result<(), ConsensusError> {
         let mut state = self.read_state().await?;
-        state.set_proposal_candidate(block_hash, timestamp)?;
-        self.commit_state(&state).await?;
-        Ok(())
+        state.set_validator_info(validator_info)?;
+        self.commit_state(&state).await
     }
 
-    pub async fn veto_block(&mut self, block_hash: Hash256) -> Result<(), Error> {
-        let mut state = self.read_state().await?;
-        state.veto_block(block_hash);
-        self.commit_state(&state).await?;
-        Ok(())
+    pub async fn get_delegations(&self) -> Result<Delegations, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.delegations().clone())
     }
 
-    pub async fn veto_round(
+    /// Adds the delegation of a validator, after which the DMS admits the messages signed by
+    /// the delegated key as the ones of the validator in the heights it covers.
+    ///
+    /// It is kept over the heights by `finalize_and_advance()` until its range ends.
+    /// The delegated key must be a member of the DMS for its messages to be received.
+    pub async fn add_delegation(
         &mut self,
-        round: ConsensusRound,
-        timestamp: Timestamp,
-    ) -> Result<(), Error> {
+        delegation: SignedDelegation,
+    ) -> Result<(), ConsensusError> {
         let mut state = self.read_state().await?;
-        state.veto_round(round, timestamp);
+        state.add_delegation(delegation)?;
         self.commit_state(&state).await?;
+        self.attach_filter(&state).await;
         Ok(())
     }
 
-    pub fn get_dms(&self) -> Arc<RwLock<Dms<ConsensusMessage>>> {
-        Arc::clone(&self.dms)
+    /// Reads the current status of the consensus without making any progress.
+    pub async fn status(&self) -> Result<ConsensusStatus, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(self.status_of(&state))
     }
 
-    pub async fn flush(&mut self) -> Result<(), Error> {
-        // TODO: filter unverified messages (due to the lack of the block verification)
-        let mut state = self.read_state().await?;
-        let messages = state.drain_messages_to_broadcast();
-        for message in messages {
-            self.dms.write().await.commit_message(&message).await?;
+    /// Reads the statistics of the DMS, e.g., to see whether the messages are arriving.
+    ///
+    /// It reads every file of the DMS storage to measure the size.
+    pub async fn dms_stats(&self) -> Result<DmsStats, ConsensusError> {
+        let state = self.read_state().await?;
+        let mut stats = DmsStats {
+            height: state.height(),
+            total_messages: 0,
+            messages_by_kind: BTreeMap::new(),
+            messages_by_round: BTreeMap::new(),
+            last_admitted: self.filter_counters.last_admitted(),
+            rejections: self.filter_counters.rejections(),
+            version_mismatches: self.filter_counters.version_mismatches(),
+            storage_size: 0,
+        };
+        let dms = self.dms.read().await;
+        let messages = dms.read_messages().await.map_err(ConsensusError::network)?;
+        // The same vote of the validators is a single message with their commitments.
+        let messages = messages
+            .iter()
+            .flat_map(|message| message.committers.iter().map(move |_| &message.message));
+        dms_stats::count_messages(&mut stats, messages);
+        let storage = dms.get_storage();
+        let storage = storage.read().await;
+        for name in storage
+            .list_files()
+            .await
+            .map_err(ConsensusError::network)?
+        {
+            let content = storage
+                .read_file(&name)
+                .await
+                .map_err(ConsensusError::network)?;
+            stats.storage_size += content.len() as u64;
         }
-        Ok(())
+        Ok(stats)
     }
 
-    pub async fn update(&mut self) -> Result<(), Error> {
-        let mut state = self.read_state().await?;
-        let messages = self.dms.read().await.read_messages().await?;
-        let mut result = Vec::new();
-        for message in messages {
-            for commitment in message.committers {
-                result.push((
-                    message.message.clone(),
-                    commitment.committer,
-                    commitment.signature,
-                ));
-            }
+    /// Checks whether the consensus is finalized.
+    pub async fn check_finalized(&self) -> Result<Option<Finalization>, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.check_finalized())
+    }
+
+    /// Returns the proposer of the round.
+    pub async fn proposer(&self, round: ConsensusRound) -> Result<PublicKey, ConsensusError> {
+        let state = self.read_state().await?;
+        let index = state.proposer_index(round)?;
+        Ok(state.block_header().validator_set[index].0.clone())
+    }
+
+    /// Returns whether this node is the proposer of the round, which is never for an observer.
+    pub async fn is_this_node_proposer(
+        &self,
+        round: ConsensusRound,
+    ) -> Result<bool, ConsensusError> {
+        let state = self.read_state().await?;
+        Ok(state.status().this_node_index == Some(state.proposer_index(round)?))
+    }
+
+    /// Returns the proposers of the next `rounds` rounds, starting from the current one.
+    pub async fn proposer_schedule(
+        &self,
+        rounds: u64,
+    ) -> Result<Vec<(ConsensusRound, PublicKey)>, ConsensusError> {
+        let state = self.read_state().await?;
+        let current_round = state.status().round;
+        let validator_set = &state.block_header().validator_set;
+        (current_round..current_round.saturating_add(rounds))
+            .map(|round| Ok((round, validator_set[state.proposer_index(round)?].0.clone())))
+            .collect()
+    }
+
+    /// Tallies the votes of the round from the messages in the DMS, which works for any round
+    /// of this height (including the past ones and the ones after the finalization).
+    pub async fn vote_tally(&self, round: ConsensusRound) -> Result<VoteTally, ConsensusError> {
+        let state = self.read_state().await?;
+        let messages = self
+            .dms
+            .read()
+            .await
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)?;
+        let validator_set = &state.block_header().validator_set;
+        let mut tally = VoteTally::new(&messages, validator_set, state.height(), round);
+        tally.missing_validator_names = tally
+            .missing_validators
+            .iter()
+            .map(|validator| state.validator_info(validator).name)
+            .collect();
+        Ok(tally)
+    }
+
+    /// Reports how each validator has participated in the last `window_rounds` rounds
+    /// up to the current one, from the messages in the DMS (so an observer can make it as well).
+    pub async fn liveness_report(
+        &self,
+        window_rounds: u64,
+    ) -> Result<LivenessReport, ConsensusError> {
+        if window_rounds == 0 {
+            return Err(ConsensusError::InvalidOperation(
+                "the window of the liveness report must not be empty".to_owned(),
+            ));
+        }
+        let state = self.read_state().await?;
+        let messages = self
+            .dms
+            .read()
+            .await
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)?;
+        let last_round = state.status().round;
+        let mut report = LivenessReport::new(
+            &messages,
+            &state.block_header().validator_set,
+            state.height(),
+            last_round.saturating_sub(window_rounds - 1),
+            last_round,
+        );
+        for validator in report.validators.iter_mut() {
+            validator.name = state.validator_info(&validator.validator).name;
         }
-        state.add_consensus_messages(result, get_timestamp());
+        Ok(report)
+    }
+
+    pub async fn register_verified_block_hash(
+        &mut self,
+        block_hash: Hash256,
+    ) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        state.register_verified_block_hash(block_hash)?;
         self.commit_state(&state).await?;
-        Ok(())
+        self.verified_block_hashes.write().insert(block_hash);
+        // The messages on the block that have arrived earlier, to be read by the next `update()`
+        let quarantined = self.quarantine.write().take(&block_hash);
+        self.receive_held_messages(quarantined).await
     }
-}
 
-// Various private methods.
-impl Consensus {
-    async fn read_state(&self) -> Result<State, Error> {
-        let raw_state = self.state_storage.read_file(STATE_FILE_NAME).await?;
-        let state: State = serde_spb::from_slice(&hex::decode(raw_state)?)?;
-        Ok(state)
+    /// Puts the messages held by the filter into the DMS again,
+    /// skipping the ones that are still rejected.
+    async fn receive_held_messages(
+        &self,
+        messages: Vec<(ConsensusMessage, MessageCommitmentProof)>,
+    ) -> Result<(), ConsensusError> {
+        for (message, commitment) in messages {
+            if let Err(e) = self
+                .dms
+                .write()
+                .await
+                .receive_message(&message, commitment)
+                .await
+            {
+                if e.downcast_ref::<dms::RejectionError>().is_none() {
+                    return Err(ConsensusError::network(e));
+                }
+                tracing::warn!(
+                    consensus_message = ?message,
+                    error = %e,
+                    "rejected a held consensus message"
+                );
+            }
+        }
+        Ok(())
     }
 
-    async fn commit_state(&mut self, state: &State) -> Result<(), Error> {
-        // We can't use json because of a non-string map
-        let data = hex::encode(serde_spb::to_vec(state).unwrap());
-        self.state_storage
-            .add_or_overwrite_file(STATE_FILE_NAME, data)
+    /// Makes a progress in the consensus process.
+    ///
+    /// It also checks the timeouts against `timestamp`, so it must be called periodically
+    /// even if there is no new message (as `serve()` does every `progress_interval`);
+    /// that's how a round with an absent proposer ends up with nil votes.
+    /// The `timestamp` is normalized as the `ClockPolicy` says, and so are the ones reported.
+    ///
+    /// The messages of this node that failed to be committed to the DMS are retried first.
+    ///
+    /// If the block is finalized, it collects the precommits from the DMS
+    /// to complete the finalization proof, which can be read by `get_finalization_proof()`.
+    #[tracing::instrument(level = "debug", skip(self), fields(height, round))]
+    pub async fn progress(
+        &mut self,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ProgressResult>, ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.normalize(timestamp)?;
+        let span = tracing::Span::current();
+        span.record("height", state.height());
+        span.record("round", state.round());
+        self.commit_messages(&mut state).await?;
+        state.retry_pending_messages(&|block_hash| self.validity_provider.is_valid(block_hash))?;
+        let mut result = state.progress(timestamp)?;
+        if !self.report_observed_votes {
+            result.retain(|x| !x.is_observed_vote());
+        }
+        if self.first_proposal_timestamp.is_none()
+            && result
+                .iter()
+                .any(|x| matches!(x, ProgressResult::Proposed(..)))
+        {
+            self.first_proposal_timestamp = Some(timestamp);
+        }
+        let mut ops = Vec::new();
+        if let Some(finalization) = state.check_finalized() {
+            // The precommit of this node might not be in the DMS yet.
+            self.commit_messages(&mut state).await?;
+            let proof = self
+                .collect_finalization_proof(&state, &finalization)
+                .await?;
+            state.set_finalization_proof(proof)?;
+            let finalization = state
+                .check_finalized()
+                .expect("the state must be finalized");
+            ops.push(self.state_storage.finalization_op(&finalization));
+            for x in result.iter_mut() {
+                if let ProgressResult::Finalized(_) = x {
+                    *x = ProgressResult::Finalized(finalization.clone());
+                }
+            }
+            self.metrics.finalized(
+                finalization.proof.round + 1,
+                self.first_proposal_timestamp
+                    .map(|first| (finalization.timestamp - first).max(0)),
+            );
+        } else if let Some(stalled) = self.check_stall(&state, timestamp).await? {
+            result.push(stalled);
+        }
+        state.prune_updated_events(self.max_retained_events);
+        // In the same batch as the state, so that the log never claims more than it.
+        ops.extend(self.event_log_op(&state, &result).await?);
+        self.commit_state_with(&state, ops).await?;
+        let _ = self.snapshot_sender.send(Snapshot {
+            status: self.status_of(&state),
+            progress_results: result.clone(),
+        });
+        Ok(result)
+    }
+
+    /// Returns what `progress()` would do now without any effect:
+    /// the results and the messages that this node would broadcast.
+    ///
+    /// Nothing is signed or stored, since it runs on a copy of the state.
+    /// Note that the proof of `ProgressResult::Finalized` is left empty.
+    pub async fn progress_dry_run(
+        &self,
+        timestamp: Timestamp,
+    ) -> Result<(Vec<ProgressResult>, Vec<ConsensusMessage>), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.peek(timestamp)?;
+        state.retry_pending_messages(&|block_hash| self.validity_provider.is_valid(block_hash))?;
+        let mut result = state.progress(timestamp)?;
+        if !self.report_observed_votes {
+            result.retain(|x| !x.is_observed_vote());
+        }
+        Ok((result, state.messages_to_broadcast().to_vec()))
+    }
+
+    /// Finalizes the consensus with the proof from the peers, for a node that has missed
+    /// the votes (e.g., offline while the others finalized it, and then the DMS pruned).
+    ///
+    /// The proof is verified against the validator set, the quorum and the delegations of
+    /// the height. The block doesn't have to be verified by this node, which is up to the caller.
+    /// If this node has already finalized the same block, its own finalization is returned;
+    /// if another block, it fails with `ConsensusError::ConflictingFinalization`.
+    pub async fn apply_external_finalization(
+        &mut self,
+        block_hash: Hash256,
+        proof: FinalizationProof,
+        timestamp: Timestamp,
+    ) -> Result<ProgressResult, ConsensusError> {
+        let mut state = self.read_state().await?;
+        verify_delegated_finalization_proof(
+            &block_hash,
+            &proof,
+            &state.block_header().validator_set,
+            state.quorum(),
+            state.delegations(),
+            state.height(),
+        )?;
+        if let Some(finalization) = state.check_finalized() {
+            if finalization.block_hash != block_hash {
+                tracing::error!(
+                    local = %finalization.block_hash,
+                    external = %block_hash,
+                    "a conflicting block is proven to be finalized"
+                );
+                return Err(ConsensusError::ConflictingFinalization {
+                    local: finalization.block_hash,
+                    external: block_hash,
+                });
+            }
+            return Ok(ProgressResult::Finalized(finalization));
+        }
+        let finalization = Finalization {
+            block_hash,
+            timestamp: self.clock.normalize(timestamp)?,
+            proof,
+        };
+        state.set_external_finalization(finalization.clone())?;
+        let result = vec![ProgressResult::Finalized(finalization.clone())];
+        let mut ops = vec![self.state_storage.finalization_op(&finalization)];
+        ops.extend(self.event_log_op(&state, &result).await?);
+        self.commit_state_with(&state, ops).await?;
+        let _ = self.snapshot_sender.send(Snapshot {
+            status: self.status_of(&state),
+            progress_results: result,
+        });
+        tracing::info!(%block_hash, "finalized by the external proof");
+        Ok(ProgressResult::Finalized(finalization))
+    }
+
+    /// Returns the evidence of the misbehaviors detected so far,
+    /// which can be verified by `verify_evidence()`.
+    pub async fn list_evidence(&self) -> Result<Vec<Evidence>, ConsensusError> {
+        self.state_storage.read_evidence().await
+    }
+
+    /// Returns the proof of the finalized block, or `None` if not finalized yet.
+    pub async fn get_finalization_proof(
+        &self,
+    ) -> Result<Option<FinalizationProof>, ConsensusError> {
+        let finalization = self.state_storage.read_finalization().await?;
+        Ok(finalization.map(|finalization| finalization.proof))
+    }
+
+    /// Returns the finalization of the past height archived by `finalize_and_advance()`,
+    /// or `None` if there is no such archive.
+    pub async fn get_archived_finalization(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Option<Finalization>, ConsensusError> {
+        self.state_storage.read_archived_finalization(height).await
+    }
+
+    /// Moves on to the next height after the finalization, returning the consensus for it.
+    ///
+    /// The finalized state, the finalization, the entries of the event log of the height
+    /// and optionally the messages are archived in the storage (read by
+    /// `read_archived_height()`), and the DMS is cleared for the new height.
+    /// The archives older than the retention are purged by `prune_archives()`.
+    /// `next_header` must be the header of the finalized block.
+    ///
+    /// The DMS is kept, so its members must include the next validator set;
+    /// otherwise create a new DMS and call `recreate()` with it.
+    /// The delegations that cover the next height or later are carried over,
+    /// and so are the messages of the next height that the filter has held
+    /// (see `MAX_EARLY_MESSAGES`), which are read by the next `update()`.
+    pub async fn finalize_and_advance(
+        mut self,
+        next_header: BlockHeader,
+        consensus_parameters: ConsensusParams,
+        round_zero_timestamp: Timestamp,
+        this_node_signer: Option<Arc<dyn ConsensusSigner>>,
+    ) -> Result<Self, ConsensusError> {
+        let state = self.read_state().await?;
+        let finalization = state
+            .check_finalized()
+            .ok_or(ConsensusError::NotFinalized)?;
+        if next_header.to_hash256() != finalization.block_hash {
+            return Err(ConsensusError::Mismatch(format!(
+                "the next header is not of the finalized block {}",
+                finalization.block_hash
+            )));
+        }
+        let height = state.height();
+        let events = self
+            .read_event_log(0)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.height == height)
+            .map(|entry| serde_json::to_string(&entry).unwrap() + "\n")
+            .collect::<String>();
+        let messages = if self.archive_messages {
+            Some(
+                self.read_dms_messages()
+                    .await?
+                    .into_iter()
+                    .filter(|message| message.message.height() == height)
+                    .map(|message| (message.message, message.committers))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            None
+        };
+        self.state_storage
+            .write_archive(
+                height,
+                &self.state_codec.encode(&state),
+                &finalization,
+                &events,
+                messages.as_ref(),
+            )
+            .await?;
+
+        let mut delegations = state.delegations().clone();
+        delegations.prune(next_header.height + 1);
+        let round_zero_timestamp = self.clock.normalize(round_zero_timestamp)?;
+        let early_messages = self.early_messages.write().take();
+        let mut next = Self::open(
+            self.dms,
+            self.state_storage,
+            next_header,
+            consensus_parameters,
+            round_zero_timestamp,
+            this_node_signer,
+            self.validity_provider,
+            true,
+            delegations,
+        )
+        .await?;
+        next.max_retained_events = self.max_retained_events;
+        next.state_codec = self.state_codec;
+        next.archive_retention = self.archive_retention;
+        next.archive_messages = self.archive_messages;
+        next.report_observed_votes = self.report_observed_votes;
+        next.event_log = self.event_log;
+        next.retry_policy = self.retry_policy;
+        next.broadcast_policy = self.broadcast_policy;
+        next.peer_ban_policy = self.peer_ban_policy;
+        next.stall_policy = self.stall_policy;
+        next.clock = self.clock;
+        next.known_peers = self.known_peers;
+        next.max_message_size = self.max_message_size;
+        next.max_round_lookahead = self.max_round_lookahead;
+        next.verification_batch_size = self.verification_batch_size;
+        next.signer_timeout = self.signer_timeout;
+        next.filter_counters = self.filter_counters;
+        let validator_info = next
+            .get_block_header()
+            .await?
+            .validator_set
+            .iter()
+            .map(|(validator, _)| (validator.clone(), state.validator_info(validator)))
+            .filter(|(_, info)| *info != ValidatorInfo::default())
+            .collect();
+        next.set_validator_info(validator_info).await?;
+        next.set_metrics(self.metrics).await?;
+        next.prune_archives().await?;
+        next.receive_held_messages(early_messages).await?;
+        Ok(next)
+    }
+
+    /// Sets whether the results of `progress()` are appended to the event log
+    /// (off by default), which is kept across the heights to audit what this node did.
+    pub fn set_event_log(&mut self, event_log: bool) {
+        self.event_log = event_log;
+    }
+
+    /// Reads the entries of the event log from the sequence number `from_seq`.
+    pub async fn read_event_log(
+        &self,
+        from_seq: u64,
+    ) -> Result<Vec<EventLogEntry>, ConsensusError> {
+        let event_log = self
+            .state_storage
+            .read_event_log()
+            .await?
+            .unwrap_or_default();
+        let mut result = parse_event_log(EVENT_LOG_FILE_NAME, &event_log)?;
+        result.retain(|entry| entry.seq >= from_seq);
+        Ok(result)
+    }
+
+    /// Sets the number of the past heights whose archives are kept,
+    /// which are purged by `finalize_and_advance()` once they get older.
+    pub fn set_archive_retention(&mut self, archive_retention: u64) {
+        self.archive_retention = archive_retention;
+    }
+
+    /// Sets whether the messages of the height in the DMS are archived
+    /// by `finalize_and_advance()` (off by default).
+    pub fn set_archive_messages(&mut self, archive_messages: bool) {
+        self.archive_messages = archive_messages;
+    }
+
+    /// Returns the record of the past height archived by `finalize_and_advance()`,
+    /// or `None` if there is no such archive (e.g., it has been purged).
+    pub async fn read_archived_height(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Option<ArchivedHeight>, ConsensusError> {
+        let state = match self.state_storage.read_archived_state(height).await? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let finalization = self
+            .get_archived_finalization(height)
+            .await?
+            .ok_or(ConsensusError::NotArchived(height))?;
+        let events = self.state_storage.read_archived_events(height).await?;
+        let messages = self
+            .state_storage
+            .read_archived_messages(height)
+            .await?
+            .unwrap_or_default();
+        Ok(Some(ArchivedHeight {
+            status: state.status(),
+            finalization,
+            events,
+            messages,
+        }))
+    }
+
+    /// Purges the past heights older than the retention, returning them in ascending order.
+    pub async fn prune_archives(&mut self) -> Result<Vec<BlockHeight>, ConsensusError> {
+        let archived_heights = self.state_storage.archived_heights().await?;
+        let mut pruned = Vec::new();
+        for height in archived_heights {
+            if self.is_purgeable(height).await? {
+                self.purge_height(height).await?;
+                pruned.push(height);
+            }
+        }
+        Ok(pruned)
+    }
+
+    async fn is_purgeable(&self, height: BlockHeight) -> Result<bool, ConsensusError> {
+        let current_height = self.read_state().await?.height();
+        Ok(height + self.archive_retention < current_height)
+    }
+
+    /// Removes the archives and the remaining messages of the past height,
+    /// which must be older than the retention.
+    pub async fn purge_height(&mut self, height: BlockHeight) -> Result<(), ConsensusError> {
+        if !self.is_purgeable(height).await? {
+            return Err(ConsensusError::InvalidOperation(format!(
+                "the height {height} is not older than the retention"
+            )));
+        }
+        let messages = self
+            .dms
+            .read()
+            .await
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)?;
+        for message in messages {
+            if message.message.height() == height {
+                self.dms
+                    .write()
+                    .await
+                    .remove_message(message.message.to_hash256(), None)
+                    .await
+                    .map_err(ConsensusError::network)?;
+            }
+        }
+        self.state_storage.remove_archive(height).await
+    }
+
+    /// Repairs the corrupted files of the storage where possible, returning their names.
+    ///
+    /// - The state is restored from the intact one of the primary and the backup.
+    /// - The finalization is written again from the finalized state.
+    /// - The event log is rebuilt from the archived copies of the past heights,
+    ///   losing the entries of the current height.
+    /// - The peer scores are reset, and the corrupted evidence is removed.
+    ///
+    /// The record of the messages signed by this node can't be rebuilt safely,
+    /// so it's left as it is and reported as `ConsensusError::CorruptState`.
+    pub async fn repair(&mut self) -> Result<Vec<String>, ConsensusError> {
+        let mut repaired = Vec::new();
+        let mut state = None;
+        let mut error = None;
+        for file in [StateFile::Primary, StateFile::Backup] {
+            match self.state_storage.read_state(file).await {
+                Ok(x) => {
+                    state.get_or_insert(x);
+                }
+                Err(e) if is_corrupt(&e) => {
+                    repaired.push(file.name().to_owned());
+                    error.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // Both are corrupted.
+        let state = match (state, error) {
+            (Some(state), _) => state,
+            (None, error) => return Err(error.expect("either of them must have failed")),
+        };
+        if !repaired.is_empty() {
+            self.committed_state_hash = None;
+            self.commit_state(&state).await?;
+        }
+
+        if let Some(finalization) = state.check_finalized() {
+            if let Err(e) = self.get_finalization_proof().await {
+                if !is_corrupt(&e) {
+                    return Err(e);
+                }
+                let op = self.state_storage.finalization_op(&finalization);
+                self.state_storage.apply_batch(vec![op]).await?;
+                repaired.push(FINALIZATION_FILE_NAME.to_owned());
+            }
+        }
+
+        if let Err(e) = self.state_storage.read_event_log().await {
+            if !is_corrupt(&e) {
+                return Err(e);
+            }
+            let mut event_log = String::new();
+            for height in self.state_storage.archived_heights().await? {
+                match self.state_storage.read_archived_events(height).await {
+                    Ok(events) => event_log.extend(
+                        events
+                            .iter()
+                            .map(|entry| serde_json::to_string(entry).unwrap() + "\n"),
+                    ),
+                    Err(e) if is_corrupt(&e) => {
+                        tracing::warn!(error = %e, "skipped the archived event log")
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            let op = self.state_storage.event_log_op(&event_log);
+            self.state_storage.apply_batch(vec![op]).await?;
+            repaired.push(EVENT_LOG_FILE_NAME.to_owned());
+        }
+
+        if let Err(e) = self.read_peer_scores().await {
+            if !is_corrupt(&e) {
+                return Err(e);
+            }
+            self.peer_scores = PeerScores::default();
+            self.commit_peer_scores().await?;
+            repaired.push(PEER_SCORES_FILE_NAME.to_owned());
+        }
+        repaired.extend(self.state_storage.remove_corrupt_evidence().await?);
+
+        if !repaired.is_empty() {
+            tracing::warn!(?repaired, "repaired the corrupted files of the storage");
+        }
+        self.read_own_votes(state.block_header()).await?;
+        Ok(repaired)
+    }
+
+    /// Opens the storage of a consensus to inspect it while the node keeps running,
+    /// without the DMS or the network.
+    ///
+    /// The storage should be opened without the lock of the node
+    /// (e.g., by `StorageImpl::open_read_only()`); the inspector never writes to it.
+    pub async fn open_read_only(
+        state_storage: S,
+        encryption_key: Option<StorageEncryptionKey>,
+    ) -> Result<ConsensusInspector<S>, ConsensusError> {
+        let inspector =
+            ConsensusInspector::new(ConsensusStorage::new(state_storage, encryption_key));
+        // Fails early if there is no state.
+        inspector.status().await?;
+        Ok(inspector)
+    }
+
+    /// Exports the consensus of this node to move the validator to another node
+    /// in the middle of the height, which is imported by `import_backup()`.
+    ///
+    /// This node must not run anymore once exported; otherwise the import is refused
+    /// (see `ConsensusError::StaleBackup`) as the new node might sign a conflicting message.
+    pub async fn export_backup(&self) -> Result<BackupBundle, ConsensusError> {
+        let state = self.read_state().await?;
+        let own_votes = self.read_own_votes(state.block_header()).await?;
+        Ok(BackupBundle::new(
+            &state,
+            self.state_codec.encode(&state),
+            own_votes,
+        ))
+    }
+
+    /// Imports the consensus exported by `export_backup()` into the storage of the new node,
+    /// returning the storage to open by `new()` with the signer of the validator.
+    ///
+    /// The DMS of the new node is read from the start, since the cursor is of the old one.
+    ///
+    /// It fails with `ConsensusError::StaleBackup` if the storage has the messages signed
+    /// for the height that the backup doesn't have (e.g., it is a copy of the storage of the
+    /// exported node that has kept running), or with `ConsensusError::HeightMismatch`
+    /// if it has the state of a later height.
+    pub async fn import_backup(
+        bundle: &BackupBundle,
+        state_storage: S,
+        encryption_key: Option<StorageEncryptionKey>,
+    ) -> Result<S, ConsensusError> {
+        let (mut state, own_votes) = bundle.open()?;
+        let mut storage = ConsensusStorage::new(state_storage, encryption_key);
+        if let Some(stored) = storage.read_own_votes().await? {
+            if stored.last_header_hash == own_votes.last_header_hash
+                && stored
+                    .messages
+                    .iter()
+                    .any(|message| !own_votes.messages.contains(message))
+            {
+                return Err(ConsensusError::StaleBackup {
+                    height: bundle.height,
+                });
+            }
+        }
+        if storage.has_state().await? {
+            let stored = match storage.read_state(StateFile::Primary).await {
+                Ok(stored) => stored,
+                Err(_) => storage.read_state(StateFile::Backup).await?,
+            };
+            if stored.height() > bundle.height {
+                return Err(ConsensusError::HeightMismatch {
+                    expected: bundle.height,
+                    stored: stored.height(),
+                });
+            }
+        }
+        state.set_dms_cursor(0);
+        let mut ops = storage.state_ops(&StateCodec::default().encode(&state));
+        ops.push(storage.own_votes_op(own_votes));
+        ops.push(match state.check_finalized() {
+            Some(finalization) => storage.finalization_op(&finalization),
+            None => storage.remove_finalization_op(),
+        });
+        storage.apply_batch(ops).await?;
+        tracing::info!(
+            height = bundle.height,
+            round = bundle.round,
+            "imported the consensus backup"
+        );
+        Ok(storage.into_inner())
+    }
+
+    pub async fn set_proposal_candidate(
+        &mut self,
+        block_hash: Hash256,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.normalize(timestamp)?;
+        state.set_proposal_candidate(block_hash, timestamp)?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    /// Adds the block to the queue of the proposal candidates with the priority
+    /// (or updates its priority), which is proposed while it is the highest one not vetoed.
+    ///
+    /// `set_proposal_candidate()` and `clear_proposal_candidate()` empty the queue.
+    pub async fn push_proposal_candidate(
+        &mut self,
+        block_hash: Hash256,
+        priority: u64,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.normalize(timestamp)?;
+        state.push_proposal_candidate(block_hash, priority, timestamp)?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    /// Withdraws the proposal candidate set by `set_proposal_candidate()`
+    /// (e.g., when the block turns out to be invalid), so that this node proposes nothing.
+    pub async fn clear_proposal_candidate(
+        &mut self,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.normalize(timestamp)?;
+        state.clear_proposal_candidate(timestamp)?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    /// Vetoes the block so that this node never votes for it.
+    ///
+    /// The proposals of the block that have been already received are re-evaluated
+    /// in the next `progress()`.
+    pub async fn veto_block(&mut self, block_hash: Hash256) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        state.veto_block(block_hash, self.clock.local(get_timestamp()))?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    /// Withdraws the veto on the block, which fails if this node has already prevoted against it.
+    pub async fn unveto_block(&mut self, block_hash: Hash256) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        state.unveto_block(block_hash, self.clock.local(get_timestamp()))?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    pub async fn veto_round(
+        &mut self,
+        round: ConsensusRound,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let timestamp = self.clock.normalize(timestamp)?;
+        state.veto_round(round, timestamp)?;
+        self.commit_state(&state).await?;
+        Ok(())
+    }
+
+    pub fn get_dms(&self) -> Arc<RwLock<Dms<ConsensusMessage>>> {
+        Arc::clone(&self.dms)
+    }
+
+    /// Commits the messages of this node to its own DMS, from which the next `update()`
+    /// reads them back, so this node counts its own votes even if no peer is reachable.
+    ///
+    /// The peers get them by gossiping or by `broadcast()` afterwards.
+    pub async fn flush(&mut self) -> Result<(), ConsensusError> {
+        // TODO: filter unverified messages (due to the lack of the block verification)
+        let mut state = self.read_state().await?;
+        self.commit_messages(&mut state).await
+    }
+
+    /// Fetches the messages from the peers into the DMS, scoring the peers
+    /// by how many of their messages the DMS (with the filter) has rejected.
+    ///
+    /// The peers with fewer rejections are received from first, so that their messages
+    /// take the places in the filter (e.g., under `MAX_MESSAGES_PER_VOTE`) before the others.
+    /// A peer that has delivered too many rejected messages is banned for a while,
+    /// as the `PeerBanPolicy` says; the ban list is in `ConsensusStatus::banned_peers`.
+    pub async fn fetch(
+        &mut self,
+        fetcher: &dyn MessageFetcher<StorageImpl, ConsensusMessage>,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let mut changed = self.peer_scores.lift_expired_bans(timestamp);
+        let peers = fetcher
+            .peers(&self.dms)
+            .await
+            .map_err(ConsensusError::network)?;
+        let peers = self.peer_scores.prioritize(peers);
+        let reports = fetcher
+            .fetch_messages(&self.dms, &peers)
+            .await
+            .map_err(ConsensusError::network)?;
+        for report in reports.iter() {
+            self.peer_scores
+                .record(report, &self.peer_ban_policy, timestamp);
+            changed = true;
+        }
+        if changed {
+            self.commit_peer_scores().await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches only the prevotes and the precommits of the round that are missing in the DMS,
+    /// i.e., of the validators that have signed none of the kind, rather than everything
+    /// from everyone as `fetch()` does (e.g., to recover a vote lost in the gossip).
+    ///
+    /// Returns the validators whose votes have been requested, in the order of the set.
+    /// `serve()` does this for the current round once it is pending past its timeout.
+    pub async fn request_missing_votes(
+        &mut self,
+        fetcher: &dyn MessageFetcher<StorageImpl, ConsensusMessage>,
+        round: ConsensusRound,
+        timestamp: Timestamp,
+    ) -> Result<Vec<PublicKey>, ConsensusError> {
+        let state = self.read_state().await?;
+        let messages = self.read_dms_messages().await?;
+        let mut changed = self.peer_scores.lift_expired_bans(timestamp);
+        let peers = fetcher
+            .peers(&self.dms)
             .await
-            .map_err(|_| eyre!("failed to commit consensus state to the storage"))
+            .map_err(ConsensusError::network)?;
+        let peers = self.peer_scores.prioritize(peers);
+        let mut requested = BTreeSet::new();
+        for kind in [VoteKind::Prevote, VoteKind::Precommit] {
+            let missing = tally::missing_signers(
+                &messages,
+                &state.block_header().validator_set,
+                state.delegations(),
+                state.height(),
+                round,
+                kind,
+            );
+            if missing.is_empty() {
+                continue;
+            }
+            tracing::debug!(
+                round,
+                ?kind,
+                missing = missing.len(),
+                "requesting the missing votes"
+            );
+            let filter = PacketFilter {
+                message_prefixes: ConsensusMessage::compact_prefixes(state.height(), round, kind),
+                committers: missing
+                    .iter()
+                    .map(|validator| state.delegations().signing_key(validator, state.height()))
+                    .collect(),
+            };
+            let reports = fetcher
+                .fetch_matching_messages(&self.dms, &peers, &filter)
+                .await
+                .map_err(ConsensusError::network)?;
+            for report in reports.iter() {
+                self.peer_scores
+                    .record(report, &self.peer_ban_policy, timestamp);
+                changed = true;
+            }
+            requested.extend(missing);
+        }
+        if changed {
+            self.commit_peer_scores().await?;
+        }
+        Ok(state
+            .block_header()
+            .validator_set
+            .iter()
+            .filter(|(validator, _)| requested.contains(validator))
+            .map(|(validator, _)| validator.clone())
+            .collect())
+    }
+
+    /// Sends the messages of this node committed by `flush()` to the peers directly,
+    /// retrying the ones that not enough peers have acknowledged yet
+    /// with an exponential backoff, as the `BroadcastPolicy` says.
+    ///
+    /// Gossiping (e.g., by `serve()`) spreads the messages anyway, but this makes sure
+    /// that the votes of this node reach the peers even if some of them are unreachable.
+    /// The messages of the passed rounds are given up, and the status of the others
+    /// is in `ConsensusStatus::deliveries`.
+    ///
+    /// It is to be called regularly, e.g., after every `flush()`;
+    /// it sends nothing if no retry is due at the `timestamp`.
+    pub async fn broadcast(
+        &mut self,
+        broadcaster: &dyn MessageBroadcaster<StorageImpl, ConsensusMessage>,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let round = self.read_state().await?.round();
+        self.outbox.drop_past_rounds(round);
+        let message_hashes = self.outbox.due(&self.broadcast_policy, timestamp);
+        if message_hashes.is_empty() {
+            return Ok(());
+        }
+        let acknowledged_by = broadcaster
+            .broadcast_messages(&self.dms, &message_hashes)
+            .await
+            .map_err(ConsensusError::network)?;
+        tracing::debug!(
+            messages = message_hashes.len(),
+            acknowledgements = acknowledged_by.len(),
+            "broadcast the messages of this node"
+        );
+        self.outbox.record_attempt(
+            &message_hashes,
+            &acknowledged_by,
+            &self.broadcast_policy,
+            timestamp,
+        );
+        Ok(())
+    }
+
+    /// Reads the messages from the DMS.
+    ///
+    /// Only the messages stored since the last call are read,
+    /// tracked by the cursor of the DMS which is persisted in the state.
+    ///
+    /// The conflicting messages signed by the same validator are stored as evidence
+    /// (see `list_evidence()`) and reported by the next `progress()`.
+    ///
+    /// The messages read at once are handled in a canonical order, not in the order of
+    /// the arrival, so `progress()` is deterministic given the same messages and timestamp.
+    ///
+    /// The messages of this node that failed to be committed to the DMS are retried first.
+    #[tracing::instrument(level = "debug", skip(self), fields(height, cursor))]
+    pub async fn update(&mut self) -> Result<(), ConsensusError> {
+        self.update_limited(usize::MAX).await?;
+        Ok(())
+    }
+
+    /// Fetches the messages from the known peers by `fetch()`, and then does `update()`
+    /// with at most `max_messages` of the new ones and `progress()`,
+    /// to bound the work of a single call (e.g., after a long downtime).
+    ///
+    /// Returns the results of `progress()` and whether there are more messages to process,
+    /// in which case it should be called again soon (after yielding to the other tasks).
+    ///
+    /// The messages are taken in the order of the arrival, and each batch in a canonical order.
+    /// Splitting a round across the batches is safe, since what `progress()` decides
+    /// depends only on the thresholds reached by the processed messages,
+    /// which the later batches can only add to; so it comes to the same decisions
+    /// as processing them at once.
+    ///
+    /// The messages to broadcast are left to `flush()`, as with `progress()`.
+    #[tracing::instrument(level = "debug", skip(self), fields(height, cursor))]
+    pub async fn fetch_and_progress(
+        &mut self,
+        timestamp: Timestamp,
+        max_messages: usize,
+    ) -> Result<(Vec<ProgressResult>, bool), ConsensusError> {
+        let known_peers = self.known_peers.clone();
+        self.fetch(&known_peers, timestamp).await?;
+        let left = self.update_limited(max_messages).await?;
+        let result = self.progress(timestamp).await?;
+        Ok((result, left > 0))
+    }
+
+    /// Runs the consensus as a long-lived service.
+    ///
+    /// It serves the DMS for the message propagation and makes a progress
+    /// (`fetch()`, `update()`, `progress()`, `flush()` and `broadcast()` with the known peers)
+    /// every `progress_interval`, forwarding every `ProgressResult` through the returned receiver.
+    /// The known peers are read at every progress, so they can be changed meanwhile.
+    ///
+    /// The task finishes with `Ok(())` once the consensus is finalized
+    /// or `ConsensusCommand::Shutdown` is handled, and with an error if the DMS server dies.
+    /// It keeps running even if the receiver of the results is dropped.
+    ///
+    /// A failure of accessing the storage or the DMS is retried as the `RetryPolicy` says,
+    /// being reported through the last returned receiver (if not full) meanwhile.
+    /// Any other failure ends the task with the error.
+    ///
+    /// The status can be read through the returned `ConsensusReadHandle` meanwhile,
+    /// and the commands sent through the returned `ConsensusCommandSender`
+    /// are handled while waiting for the next progress.
+    pub async fn serve(
+        self,
+        network_config: ServerNetworkConfig,
+        progress_interval: Duration,
+    ) -> Result<ServeHandles, ConsensusError> {
+        self.serve_inner(network_config, None, progress_interval)
+            .await
+    }
+
+    /// Does `serve()` with the network configuration that can be changed while serving.
+    ///
+    /// A change takes effect at the next progress, restarting the DMS server on the new port.
+    /// The key must stay the one of the DMS, which has signed the messages of this node
+    /// in the height; otherwise the change is ignored, being reported
+    /// as `ConsensusError::NetworkKeyChanged` through the last returned receiver.
+    /// It keeps the last configuration once the sender is dropped.
+    pub async fn serve_reloadable(
+        self,
+        mut network_config: watch::Receiver<NetworkConfig>,
+        progress_interval: Duration,
+    ) -> Result<ServeHandles, ConsensusError> {
+        let config = network_config.borrow_and_update().clone();
+        self.check_network_key(&config).await?;
+        self.serve_inner(config.server, Some(network_config), progress_interval)
+            .await
+    }
+}
+
+// Various private methods.
+impl<S: Storage> Consensus<S> {
+    async fn serve_inner(
+        mut self,
+        server_config: ServerNetworkConfig,
+        mut network_config: Option<watch::Receiver<NetworkConfig>>,
+        progress_interval: Duration,
+    ) -> Result<ServeHandles, ConsensusError> {
+        if self.check_finalized().await?.is_some() {
+            return Err(ConsensusError::Finalized);
+        }
+        let read_handle = self.read_handle();
+        let (sender, receiver) = mpsc::channel(PROGRESS_RESULT_CHANNEL_SIZE);
+        let (command_sender, mut command_receiver) = mpsc::channel(COMMAND_CHANNEL_SIZE);
+        let (error_sender, error_receiver) = mpsc::channel(RECOVERED_ERROR_CHANNEL_SIZE);
+        let mut port = server_config.port;
+        let mut dms_task = tokio::spawn(Dms::serve(self.get_dms(), server_config));
+        let task = tokio::spawn(async move {
+            let mut failures = 0;
+            let result = loop {
+                let next_progress = tokio::time::sleep(if failures == 0 {
+                    progress_interval
+                } else {
+                    self.retry_policy.backoff
+                });
+                tokio::pin!(next_progress);
+                let mut shutdown = None;
+                let mut dms_result = None;
+                loop {
+                    tokio::select! {
+                        _ = &mut next_progress => break,
+                        result = &mut dms_task => {
+                            dms_result = Some(result);
+                            break;
+                        }
+                        Some(command) = command_receiver.recv() => match command {
+                            ConsensusCommand::Shutdown(result_sender) => {
+                                shutdown = Some(result_sender);
+                                break;
+                            }
+                            command => self.handle_command(command).await,
+                        }
+                    }
+                }
+                if let Some(result) = dms_result {
+                    break match result {
+                        Ok(Ok(())) => Err(ConsensusError::network(
+                            "the DMS server terminated unexpectedly",
+                        )),
+                        Ok(Err(e)) => Err(ConsensusError::network(format!(
+                            "the DMS server failed: {e}"
+                        ))),
+                        Err(e) => Err(ConsensusError::network(format!(
+                            "the DMS server panicked: {e}"
+                        ))),
+                    };
+                }
+                if let Some(result_sender) = shutdown {
+                    if let Err(e) = self.flush().await {
+                        break Err(e);
+                    }
+                    tracing::info!("stopped serving the consensus");
+                    let _ = result_sender.send(Ok(()));
+                    break Ok(());
+                }
+                if let Some(network_config) = network_config.as_mut() {
+                    if network_config.has_changed().unwrap_or(false) {
+                        let config = network_config.borrow_and_update().clone();
+                        match self.check_network_key(&config).await {
+                            Ok(()) if config.server.port != port => {
+                                tracing::info!(
+                                    from = port,
+                                    to = config.server.port,
+                                    "moved the DMS server"
+                                );
+                                port = config.server.port;
+                                dms_task.abort();
+                                dms_task = tokio::spawn(Dms::serve(self.get_dms(), config.server));
+                            }
+                            Ok(()) => {}
+                            Err(e) => {
+                                tracing::warn!(error = %e, "rejected the network configuration");
+                                let _ = error_sender.try_send(e);
+                            }
+                        }
+                    }
+                }
+                match self.serve_progress(&sender).await {
+                    Ok(true) => break Ok(()),
+                    Ok(false) => failures = 0,
+                    Err(e)
+                        if is_recoverable(&e)
+                            && failures < self.retry_policy.max_consecutive_failures =>
+                    {
+                        failures += 1;
+                        tracing::warn!(failures, error = %e, "failed to progress; retrying");
+                        let _ = error_sender.try_send(e);
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            dms_task.abort();
+            result
+        });
+        Ok((task, receiver, read_handle, command_sender, error_receiver))
+    }
+
+    /// Checks that the key of the network configuration is the one of the DMS.
+    async fn check_network_key(
+        &self,
+        network_config: &NetworkConfig,
+    ) -> Result<(), ConsensusError> {
+        let expected = self.dms.read().await.public_key();
+        let actual = network_config.private_key.public_key();
+        if actual != expected {
+            return Err(ConsensusError::NetworkKeyChanged {
+                expected: Box::new(expected),
+                actual: Box::new(actual),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sets the message filter of the DMS for the height of the state,
+    /// along with the other settings of the DMS.
+    async fn attach_filter(&self, state: &State) {
+        let block_header = state.block_header();
+        let filter = ConsensusMessageFilter::new(
+            Arc::clone(&self.verified_block_hashes),
+            Arc::clone(&self.admitted_messages),
+            Arc::clone(&self.quarantine),
+            Arc::clone(&self.current_round),
+            block_header
+                .validator_set
+                .iter()
+                .map(|(pubkey, _)| pubkey.clone())
+                .collect(),
+            block_header.height + 1,
+            self.dms.read().await.get_config().dms_key,
+        )
+        .with_metrics(Arc::clone(&self.metrics))
+        .with_counters(Arc::clone(&self.filter_counters))
+        .with_early_messages(Arc::clone(&self.early_messages))
+        .with_max_message_size(self.max_message_size)
+        .with_max_round_lookahead(self.max_round_lookahead)
+        .with_delegations(state.delegations().clone());
+        let mut dms = self.dms.write().await;
+        dms.set_filter(Arc::new(filter));
+        dms.set_verification_batch_size(self.verification_batch_size);
+    }
+
+    /// Does `update()` with at most `limit` messages, returning the number of the ones left.
+    async fn update_limited(&mut self, limit: usize) -> Result<usize, ConsensusError> {
+        let mut state = self.read_unfinalized_state().await?;
+        let span = tracing::Span::current();
+        span.record("height", state.height());
+        span.record("cursor", state.dms_cursor());
+        self.commit_messages(&mut state).await?;
+        let (commitments, cursor, left) = self
+            .dms
+            .read()
+            .await
+            .read_commitments_since_limited(state.dms_cursor(), limit)
+            .await
+            .map_err(ConsensusError::network)?;
+        let mut signed = Vec::new();
+        for (message_hash, commitment) in commitments {
+            let message = if let Some(message) = self.message_cache.get(&message_hash) {
+                message.clone()
+            } else {
+                let message = self
+                    .dms
+                    .read()
+                    .await
+                    .query_message(message_hash)
+                    .await
+                    .map_err(ConsensusError::network)?;
+                let message = match message {
+                    Some(message) => message.message,
+                    // Removed in the meantime.
+                    None => continue,
+                };
+                self.message_cache.insert(message_hash, message.clone());
+                message
+            };
+            signed.push((message, commitment));
+        }
+        self.drop_echoes(&state, &mut signed).await?;
+        self.metrics.messages_processed(signed.len());
+        self.stall_detector.consumed(signed.len());
+        for (message, commitment) in signed.iter() {
+            let (round, kind, signer, _) = state.canonical_key(message, &commitment.committer);
+            tracing::debug!(signer, ?kind, round, "consumed a consensus message");
+        }
+        if self.first_proposal_timestamp.is_none()
+            && signed
+                .iter()
+                .any(|(message, _)| matches!(message, ConsensusMessage::Proposal { .. }))
+        {
+            self.first_proposal_timestamp = Some(get_timestamp());
+        }
+        signed.sort_by_cached_key(|(message, commitment)| {
+            state.canonical_key(message, &commitment.committer)
+        });
+        let dms_key = self.dms.read().await.get_config().dms_key;
+        for evidence in state.detect_equivocations(&signed, &dms_key) {
+            self.commit_evidence(&evidence).await?;
+        }
+        let result = signed
+            .into_iter()
+            .map(|(message, commitment)| (message, commitment.committer))
+            .collect();
+        let timestamp = self.clock.local(get_timestamp());
+        state.add_consensus_messages(result, timestamp, &|block_hash| {
+            self.validity_provider.is_valid(block_hash)
+        })?;
+        state.set_dms_cursor(cursor);
+        self.commit_state(&state).await?;
+        Ok(left)
+    }
+
+    /// Drops the echoes of the messages that this node originated, i.e., the ones
+    /// that come back through the peers after having been consumed,
+    /// rather than relying on the state machine to be idempotent.
+    async fn drop_echoes(
+        &self,
+        state: &State,
+        signed: &mut Vec<(ConsensusMessage, MessageCommitmentProof)>,
+    ) -> Result<(), ConsensusError> {
+        let this_node = match &self.this_node_public_key {
+            Some(this_node) => this_node.clone(),
+            None => return Ok(()),
+        };
+        if !signed
+            .iter()
+            .any(|(_, commitment)| commitment.committer == this_node)
+        {
+            return Ok(());
+        }
+        let own_message_hashes = self
+            .read_own_votes(state.block_header())
+            .await?
+            .message_hashes();
+        signed.retain(|(message, commitment)| {
+            let echo = commitment.committer == this_node
+                && own_message_hashes.contains(&message.to_hash256())
+                && state.is_consumed(message, &this_node);
+            if echo {
+                tracing::debug!(consensus_message = ?message, "dropped an echo of this node");
+            }
+            !echo
+        });
+        Ok(())
+    }
+
+    /// Makes a progress in `serve()`, returning whether the consensus is finalized.
+    async fn serve_progress(
+        &mut self,
+        sender: &mpsc::Sender<ProgressResult>,
+    ) -> Result<bool, ConsensusError> {
+        let known_peers = self.known_peers.clone();
+        let timestamp = get_timestamp();
+        self.fetch(&known_peers, timestamp).await?;
+        self.update().await?;
+        let results = self.progress(timestamp).await?;
+        let finalized = results
+            .iter()
+            .any(|result| matches!(result, ProgressResult::Finalized(_)));
+        // Before flushing, which might fail, since they are not reported again.
+        for result in results {
+            if sender.send(result).await.is_err() {
+                tracing::warn!("the receiver of the consensus results is dropped");
+            }
+        }
+        if !finalized {
+            self.flush().await?;
+            self.broadcast(&known_peers, timestamp).await?;
+            self.request_votes_of_pending_round(&known_peers, timestamp)
+                .await?;
+        }
+        Ok(finalized)
+    }
+
+    /// Does `request_missing_votes()` for the current round once it has been pending
+    /// past its timeout, and again after every timeout while it is.
+    async fn request_votes_of_pending_round(
+        &mut self,
+        fetcher: &dyn MessageFetcher<StorageImpl, ConsensusMessage>,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        let state = self.read_state().await?;
+        let round = state.round();
+        match self.pending_round {
+            Some((pending_round, since))
+                if pending_round == round && timestamp - since >= state.round_timeout(round) => {}
+            Some((pending_round, _)) if pending_round == round => return Ok(()),
+            _ => {
+                self.pending_round = Some((round, timestamp));
+                return Ok(());
+            }
+        }
+        self.pending_round = Some((round, timestamp));
+        let requested = self
+            .request_missing_votes(fetcher, round, timestamp)
+            .await?;
+        if !requested.is_empty() {
+            tracing::info!(
+                round,
+                validators = requested.len(),
+                "requested the missing votes of the pending round"
+            );
+        }
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, command: ConsensusCommand) {
+        let (result, result_sender) = match command {
+            ConsensusCommand::RegisterVerifiedBlockHash(block_hash, result_sender) => (
+                self.register_verified_block_hash(block_hash).await,
+                result_sender,
+            ),
+            ConsensusCommand::SetProposalCandidate(block_hash, timestamp, result_sender) => (
+                self.set_proposal_candidate(block_hash, timestamp).await,
+                result_sender,
+            ),
+            ConsensusCommand::ClearProposalCandidate(timestamp, result_sender) => (
+                self.clear_proposal_candidate(timestamp).await,
+                result_sender,
+            ),
+            ConsensusCommand::VetoBlock(block_hash, result_sender) => {
+                (self.veto_block(block_hash).await, result_sender)
+            }
+            ConsensusCommand::VetoRound(round, timestamp, result_sender) => {
+                (self.veto_round(round, timestamp).await, result_sender)
+            }
+            ConsensusCommand::AddPeer(peer, result_sender) => {
+                self.add_peer(peer);
+                (Ok(()), result_sender)
+            }
+            ConsensusCommand::RemovePeer(public_key, result_sender) => (
+                if self.remove_peer(&public_key) {
+                    Ok(())
+                } else {
+                    Err(ConsensusError::UnknownPeer(public_key))
+                },
+                result_sender,
+            ),
+            ConsensusCommand::Shutdown(result_sender) => (
+                Err(ConsensusError::InvalidOperation(
+                    "the shutdown is handled by `serve()`".to_owned(),
+                )),
+                result_sender,
+            ),
+        };
+        if result_sender.send(result).is_err() {
+            tracing::warn!("the receiver of the consensus command result is dropped");
+        }
+    }
+
+    /// Commits the messages in the outbox of the state to the DMS.
+    ///
+    /// The outbox is persisted before and after the commit, so that the messages of this node
+    /// are neither lost nor committed twice even if it fails halfway.
+    ///
+    /// The messages go through the filter of the DMS like the ones from the peers,
+    /// so they are admitted (and counted) the same way on every node.
+    ///
+    /// Every message is recorded in the own votes before it is signed.
+    /// A message that conflicts with a previously signed one is dropped with an error,
+    /// and the previously signed one is committed again instead.
+    #[tracing::instrument(level = "debug", skip_all, fields(count))]
+    async fn commit_messages(&mut self, state: &mut State) -> Result<(), ConsensusError> {
+        let messages = state.messages_to_broadcast().to_vec();
+        if messages.is_empty() {
+            return Ok(());
+        }
+        tracing::Span::current().record("count", messages.len());
+        self.commit_state(state).await?;
+        let mut own_votes = self.read_own_votes(state.block_header()).await?;
+        let mut result = Ok(());
+        for message in messages {
+            if let Some(signed) = own_votes.find_conflict(&message).cloned() {
+                state.mark_message_sent(&message);
+                result = self.commit_own_message(&signed).await.and(Err(
+                    ConsensusError::ConflictingVote {
+                        message: format!("{message:?}"),
+                        signed: format!("{signed:?}"),
+                    },
+                ));
+                break;
+            }
+            if own_votes.record(&message) {
+                self.commit_own_votes(&own_votes).await?;
+            }
+            if let Err(e) = self.commit_own_message(&message).await {
+                self.metrics.broadcast_failed();
+                tracing::warn!(
+                    consensus_message = ?message,
+                    error = %e,
+                    "failed to commit a message to the DMS"
+                );
+                result = Err(e);
+                break;
+            }
+            self.metrics.message_broadcast();
+            self.outbox.track(&message);
+            state.mark_message_sent(&message);
+        }
+        self.commit_state(state).await?;
+        result
+    }
+
+    /// Signs the message with the signer and commits it to the DMS,
+    /// through the filter like the ones from the peers.
+    ///
+    /// The signer is not awaited longer than `signer_timeout`, and nothing is changed
+    /// if it fails, so the message can be signed again.
+    async fn commit_own_message(&self, message: &ConsensusMessage) -> Result<(), ConsensusError> {
+        let signer = self.signer.as_ref().ok_or(ConsensusError::Observer)?;
+        let dms_key = self.dms.read().await.get_config().dms_key;
+        let commitment = tokio::time::timeout(
+            self.signer_timeout,
+            signer::sign_message(signer.as_ref(), message, &dms_key),
+        )
+        .await
+        .map_err(|_| ConsensusError::Signer(format!("timed out after {:?}", self.signer_timeout)))
+        .and_then(|result| result)?;
+        self.dms
+            .write()
+            .await
+            .receive_message(message, commitment)
+            .await
+            .map_err(ConsensusError::network)
+    }
+
+    /// Signs the message and commits it to the DMS right away, without recording it
+    /// in the own votes, which is the hook to inject the misbehaviors in the tests.
+    #[cfg(feature = "test-util")]
+    pub(crate) async fn commit_message_unchecked(
+        &mut self,
+        message: &ConsensusMessage,
+    ) -> Result<(), ConsensusError> {
+        self.dms
+            .write()
+            .await
+            .commit_message(message)
+            .await
+            .map_err(ConsensusError::network)
+    }
+
+    /// Collects the precommits for the finalized block from the DMS.
+    async fn collect_finalization_proof(
+        &self,
+        state: &State,
+        finalization: &Finalization,
+    ) -> Result<FinalizationProof, ConsensusError> {
+        let round = finalization.proof.round;
+        let precommit =
+            ConsensusMessage::NonNilPreCommitted(state.height(), round, finalization.block_hash);
+        let validator_set = &state.block_header().validator_set;
+        let delegations = state.delegations();
+        let signatures = self
+            .dms
+            .read()
+            .await
+            .query_message(precommit.to_hash256())
+            .await
+            .map_err(ConsensusError::network)?
+            .map(|message| message.committers)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|commitment| {
+                delegations
+                    .resolve(&commitment.committer, state.height())
+                    .is_some_and(|identity| {
+                        validator_set
+                            .iter()
+                            .any(|(validator, _)| *validator == identity)
+                    })
+            })
+            .map(|commitment| TypedSignature::new(commitment.signature, commitment.committer))
+            .collect();
+        let proof = FinalizationProof { round, signatures };
+        verify_delegated_finalization_proof(
+            &finalization.block_hash,
+            &proof,
+            validator_set,
+            state.quorum(),
+            delegations,
+            state.height(),
+        )
+        .map_err(|e| {
+            ConsensusError::Internal(format!("failed to collect the finalization proof: {e}"))
+        })?;
+        Ok(proof)
+    }
+
+    async fn commit_evidence(&mut self, evidence: &Evidence) -> Result<(), ConsensusError> {
+        self.state_storage.write_evidence(evidence).await
+    }
+
+    /// Returns `ProgressResult::Stalled` if the consensus crosses a threshold of the
+    /// `StallPolicy` now, recording the rounds ended since the last call from the vote tallies.
+    async fn check_stall(
+        &mut self,
+        state: &State,
+        timestamp: Timestamp,
+    ) -> Result<Option<ProgressResult>, ConsensusError> {
+        let round = state.round();
+        let validator_set = &state.block_header().validator_set;
+        let unrecorded_rounds = self.stall_detector.unrecorded_rounds(round);
+        let mut messages = None;
+        if !unrecorded_rounds.is_empty() {
+            let read = self.read_dms_messages().await?;
+            for r in unrecorded_rounds {
+                let tally = VoteTally::new(&read, validator_set, state.height(), r);
+                self.stall_detector
+                    .record_round(r, tally.has_quorum_on_block(state.quorum()));
+            }
+            messages = Some(read);
+        }
+        let cause = match self
+            .stall_detector
+            .check(&self.stall_policy, round, timestamp)
+        {
+            Some(cause) => cause,
+            None => return Ok(None),
+        };
+        let messages = match messages {
+            Some(messages) => messages,
+            None => self.read_dms_messages().await?,
+        };
+        let tally_round = match cause {
+            StallCause::Rounds => round.saturating_sub(1),
+            StallCause::Silence => round,
+        };
+        let tally = VoteTally::new(&messages, validator_set, state.height(), tally_round);
+        let missing_power = validator_set
+            .iter()
+            .filter(|(validator, _)| tally.missing_validators.contains(validator))
+            .map(|(_, voting_power)| *voting_power)
+            .sum::<VotingPower>();
+        let rounds_without_progress = self.stall_detector.rounds_without_progress(round);
+        let last_message_timestamp = self.stall_detector.last_message_timestamp();
+        tracing::warn!(
+            ?cause,
+            rounds_without_progress,
+            ?last_message_timestamp,
+            missing_power,
+            "the consensus is stalled"
+        );
+        Ok(Some(ProgressResult::Stalled {
+            rounds_without_progress,
+            last_message_timestamp,
+            missing_power,
+            timestamp,
+        }))
+    }
+
+    async fn read_dms_messages(
+        &self,
+    ) -> Result<Vec<dms::Message<ConsensusMessage>>, ConsensusError> {
+        self.dms
+            .read()
+            .await
+            .read_messages()
+            .await
+            .map_err(ConsensusError::network)
+    }
+
+    /// Returns the operation to append the results to the event log if it is enabled,
+    /// to be committed with the state.
+    async fn event_log_op(
+        &self,
+        state: &State,
+        results: &[ProgressResult],
+    ) -> Result<Option<StorageOp>, ConsensusError> {
+        if !self.event_log || results.is_empty() {
+            return Ok(None);
+        }
+        let mut event_log = self
+            .state_storage
+            .read_event_log()
+            .await?
+            .unwrap_or_default();
+        let first_seq = match event_log.lines().last() {
+            Some(line) => {
+                let entry: EventLogEntry =
+                    serde_json::from_str(line).map_err(|e| ConsensusError::CorruptState {
+                        file: EVENT_LOG_FILE_NAME.to_owned(),
+                        reason: format!("invalid entry in the event log: {e}"),
+                    })?;
+                entry.seq + 1
+            }
+            None => 0,
+        };
+        for (seq, result) in (first_seq..).zip(results) {
+            let entry = EventLogEntry {
+                seq,
+                height: state.height(),
+                round: result.round().unwrap_or_else(|| state.round()),
+                result: result.clone(),
+            };
+            event_log.push_str(&serde_json::to_string(&entry).unwrap());
+            event_log.push('\n');
+        }
+        Ok(Some(self.state_storage.event_log_op(&event_log)))
+    }
+
+    /// Reads the messages signed by this node for the height of the given last header.
+    async fn read_own_votes(&self, last_header: &BlockHeader) -> Result<OwnVotes, ConsensusError> {
+        let last_header_hash = last_header.to_hash256();
+        match self.state_storage.read_own_votes().await? {
+            Some(own_votes) if own_votes.last_header_hash == last_header_hash => Ok(own_votes),
+            _ => Ok(OwnVotes::new(last_header_hash)),
+        }
+    }
+
+    async fn commit_own_votes(&mut self, own_votes: &OwnVotes) -> Result<(), ConsensusError> {
+        self.state_storage.write_own_votes(own_votes).await
+    }
+
+    async fn read_peer_scores(&self) -> Result<PeerScores, ConsensusError> {
+        Ok(self
+            .state_storage
+            .read_peer_scores()
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn commit_peer_scores(&mut self) -> Result<(), ConsensusError> {
+        self.state_storage
+            .write_peer_scores(&self.peer_scores)
+            .await
+    }
+
+    /// Reads the state, falling back to the backup if the primary file is corrupted.
+    async fn read_state(&self) -> Result<State, ConsensusError> {
+        match self.state_storage.read_state(StateFile::Primary).await {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                tracing::warn!(
+                    error = format!("{e:#}"),
+                    "failed to read the consensus state, trying the backup"
+                );
+                self.state_storage.read_state(StateFile::Backup).await
+            }
+        }
+    }
+
+    /// Reads the state to modify, which fails with `ConsensusError::Finalized` if finalized.
+    async fn read_unfinalized_state(&self) -> Result<State, ConsensusError> {
+        let state = self.read_state().await?;
+        if state.check_finalized().is_some() {
+            return Err(ConsensusError::Finalized);
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to the primary file and the backup,
+    /// the latter of which is read if the former is corrupted.
+    ///
+    /// Nothing is written if the state is the same as the last one written by this instance,
+    /// which is the usual case of `progress()` in the serve loop.
+    async fn commit_state(&mut self, state: &State) -> Result<(), ConsensusError> {
+        self.commit_state_with(state, Vec::new()).await
+    }
+
+    /// Commits the state with the other operations in a single batch of the storage,
+    /// so that they are never observed in a torn combination even after a crash.
+    async fn commit_state_with(
+        &mut self,
+        state: &State,
+        ops: Vec<StorageOp>,
+    ) -> Result<(), ConsensusError> {
+        *self.current_round.write() = state.round();
+        let raw_state = self.state_codec.encode(state);
+        let state_hash = Hash256::hash(&raw_state);
+        let mut batch = Vec::new();
+        if self.committed_state_hash != Some(state_hash) {
+            batch.extend(self.state_storage.state_ops(&raw_state));
+        }
+        batch.extend(ops);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        // The state is unknown if it fails.
+        self.committed_state_hash = None;
+        let time = std::time::Instant::now();
+        self.state_storage.apply_batch(batch).await?;
+        self.metrics.state_committed(time.elapsed());
+        self.committed_state_hash = Some(state_hash);
+        let progress_results = self.snapshot_receiver.borrow().progress_results.clone();
+        // It never fails since `snapshot_receiver` is kept.
+        let _ = self.snapshot_sender.send(Snapshot {
+            status: self.status_of(state),
+            progress_results,
+        });
+        Ok(())
+    }
+
+    /// Returns the status of the state, with the ones kept out of the state.
+    fn status_of(&self, state: &State) -> ConsensusStatus {
+        ConsensusStatus {
+            quarantined_messages: self.quarantine.read().len(),
+            deliveries: self.outbox.statuses(),
+            banned_peers: self.peer_scores.banned_peers(),
+            ..state.status()
+        }
     }
 }
@@ -0,0 +1,357 @@
+use super::*;
+
+/// An operation on the consensus running by `ConsensusManager::serve()`.
+///
+/// Each command carries the sender to report its result.
+#[derive(Debug)]
+pub enum ConsensusManagerCommand {
+    AddNextHeader(BlockHeader, CommandResultSender),
+    RegisterVerifiedBlockHash(BlockHeight, Hash256, CommandResultSender),
+    SetProposalCandidate(BlockHeight, Hash256, Timestamp, CommandResultSender),
+    /// Any other command, on the consensus of the current height.
+    ///
+    /// `ConsensusCommand::Shutdown` stops serving as it does for `Consensus::serve()`.
+    Consensus(ConsensusCommand),
+}
+
+/// What `ConsensusManager::serve()` returns: the task, the receivers of the results
+/// and the recovered errors, and the sender of the commands.
+pub type ConsensusManagerHandles = (
+    tokio::task::JoinHandle<Result<(), ConsensusError>>,
+    mpsc::Receiver<ProgressResult>,
+    mpsc::Sender<ConsensusManagerCommand>,
+    mpsc::Receiver<ConsensusError>,
+);
+
+/// The operations on the next height given in advance, carried out once it comes.
+#[derive(Debug, Default)]
+struct PendingOperations {
+    verified_block_hashes: Vec<Hash256>,
+    proposal_candidate: Option<(Hash256, Timestamp)>,
+}
+
+/// Runs the consensus over the heights, moving on to the next height
+/// by `Consensus::finalize_and_advance()` as soon as the current one is finalized
+/// and the header of the finalized block is known.
+///
+/// The block of the next height can be prepared while the current one is being finalized:
+/// the registrations and the proposal candidate are routed by their heights,
+/// the ones for the next height being kept until it comes.
+/// The messages of the peers that have moved on first are held by the filter
+/// (up to `MAX_EARLY_MESSAGES`) until this node does, so they are not lost either.
+pub struct ConsensusManager<S: Storage = StorageImpl> {
+    /// The consensus of the current height, which is `None` only if an advance has failed.
+    consensus: Option<Consensus<S>>,
+    /// The height that the consensus is deciding on (i.e., its block header's plus one).
+    height: BlockHeight,
+    consensus_parameters: ConsensusParams,
+    this_node_signer: Option<Arc<dyn ConsensusSigner>>,
+    /// The headers of the blocks that the current height may finalize, by their hashes.
+    next_headers: BTreeMap<Hash256, BlockHeader>,
+    /// The operations on the next height.
+    pending: PendingOperations,
+}
+
+impl<S: Storage> ConsensusManager<S> {
+    /// Manages the consensus from its current height,
+    /// opening the next ones with the given parameters and signer.
+    pub async fn new(
+        consensus: Consensus<S>,
+        consensus_parameters: ConsensusParams,
+        this_node_signer: Option<Arc<dyn ConsensusSigner>>,
+    ) -> Result<Self, ConsensusError> {
+        let height = consensus.get_block_header().await?.height + 1;
+        Ok(Self {
+            consensus: Some(consensus),
+            height,
+            consensus_parameters,
+            this_node_signer,
+            next_headers: BTreeMap::new(),
+            pending: PendingOperations::default(),
+        })
+    }
+
+    /// Returns the height that the consensus is deciding on.
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    /// Returns the consensus of the current height, or `None` if it has been lost
+    /// by a failed advance (then open it again from the storage).
+    pub fn consensus(&self) -> Option<&Consensus<S>> {
+        self.consensus.as_ref()
+    }
+
+    pub fn consensus_mut(&mut self) -> Option<&mut Consensus<S>> {
+        self.consensus.as_mut()
+    }
+
+    pub fn into_consensus(self) -> Option<Consensus<S>> {
+        self.consensus
+    }
+
+    fn current(&mut self) -> Result<&mut Consensus<S>, ConsensusError> {
+        self.consensus
+            .as_mut()
+            .ok_or_else(|| ConsensusError::Gone("lost by a failed advance".to_owned()))
+    }
+
+    /// Fails unless the height is the current or the next one,
+    /// returning whether it is the next one.
+    fn is_next_height(&self, height: BlockHeight) -> Result<bool, ConsensusError> {
+        if height == self.height {
+            Ok(false)
+        } else if height == self.height + 1 {
+            Ok(true)
+        } else {
+            Err(ConsensusError::Mismatch(format!(
+                "the height {height} is neither the current one {} nor the next",
+                self.height
+            )))
+        }
+    }
+
+    /// Adds the header of a block that the current height may finalize,
+    /// which is required to move on to the next height.
+    ///
+    /// Its validator set is the one of the next height, so it is verified here.
+    pub fn add_next_header(&mut self, header: BlockHeader) -> Result<(), ConsensusError> {
+        if header.height != self.height {
+            return Err(ConsensusError::Mismatch(format!(
+                "the header is of the height {}, not {}",
+                header.height, self.height
+            )));
+        }
+        verify_validator_set(&header.validator_set)?;
+        self.next_headers.insert(header.to_hash256(), header);
+        Ok(())
+    }
+
+    /// Registers the verified block for the height, which is either the current or the next one.
+    pub async fn register_verified_block_hash(
+        &mut self,
+        height: BlockHeight,
+        block_hash: Hash256,
+    ) -> Result<(), ConsensusError> {
+        if self.is_next_height(height)? {
+            self.pending.verified_block_hashes.push(block_hash);
+            return Ok(());
+        }
+        self.current()?
+            .register_verified_block_hash(block_hash)
+            .await
+    }
+
+    /// Sets the proposal candidate for the height, which is either the current or the next one.
+    pub async fn set_proposal_candidate(
+        &mut self,
+        height: BlockHeight,
+        block_hash: Hash256,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        if self.is_next_height(height)? {
+            self.pending.proposal_candidate = Some((block_hash, timestamp));
+            return Ok(());
+        }
+        self.current()?
+            .set_proposal_candidate(block_hash, timestamp)
+            .await
+    }
+
+    /// Makes a progress in the current height, then moves on to the next one
+    /// if it is finalized and the header of the finalized block is known.
+    ///
+    /// The results are of the height before the move, including its `Finalized`.
+    pub async fn progress(
+        &mut self,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ProgressResult>, ConsensusError> {
+        let consensus = self.current()?;
+        let results = if consensus.check_finalized().await?.is_none() {
+            consensus.progress(timestamp).await?
+        } else {
+            Vec::new()
+        };
+        self.advance(timestamp).await?;
+        Ok(results)
+    }
+
+    /// Moves on to the next height if possible.
+    ///
+    /// The round zero of the next height starts at `timestamp`.
+    async fn advance(&mut self, timestamp: Timestamp) -> Result<(), ConsensusError> {
+        let finalization = match self.current()?.check_finalized().await? {
+            Some(finalization) => finalization,
+            None => return Ok(()),
+        };
+        let next_header = match self.next_headers.remove(&finalization.block_hash) {
+            Some(next_header) => next_header,
+            None => {
+                tracing::debug!(
+                    block_hash = %finalization.block_hash,
+                    "waiting for the header of the finalized block"
+                );
+                return Ok(());
+            }
+        };
+        let consensus = self.consensus.take().expect("checked by `current()`");
+        let mut next = consensus
+            .finalize_and_advance(
+                next_header,
+                self.consensus_parameters.clone(),
+                timestamp,
+                self.this_node_signer.clone(),
+            )
+            .await?;
+        let pending = std::mem::take(&mut self.pending);
+        for block_hash in pending.verified_block_hashes {
+            next.register_verified_block_hash(block_hash).await?;
+        }
+        if let Some((block_hash, timestamp)) = pending.proposal_candidate {
+            next.set_proposal_candidate(block_hash, timestamp).await?;
+        }
+        self.consensus = Some(next);
+        self.height += 1;
+        self.next_headers.clear();
+        tracing::info!(height = self.height, "moved on to the next height");
+        Ok(())
+    }
+
+    /// Serves the consensus over the heights as `Consensus::serve()` does for one,
+    /// moving on to the next height whenever possible.
+    ///
+    /// The task finishes with `Ok(())` only once `ConsensusCommand::Shutdown` is handled.
+    /// The DMS server keeps running across the heights, since the DMS is kept.
+    pub async fn serve(
+        mut self,
+        network_config: ServerNetworkConfig,
+        progress_interval: Duration,
+    ) -> Result<ConsensusManagerHandles, ConsensusError> {
+        let (sender, receiver) = mpsc::channel(PROGRESS_RESULT_CHANNEL_SIZE);
+        let (command_sender, mut command_receiver) = mpsc::channel(COMMAND_CHANNEL_SIZE);
+        let (error_sender, error_receiver) = mpsc::channel(RECOVERED_ERROR_CHANNEL_SIZE);
+        let retry_policy = self.current()?.retry_policy.clone();
+        let mut dms_task = tokio::spawn(Dms::serve(self.current()?.get_dms(), network_config));
+        let task = tokio::spawn(async move {
+            let mut failures = 0;
+            let result = loop {
+                let next_progress = tokio::time::sleep(if failures == 0 {
+                    progress_interval
+                } else {
+                    retry_policy.backoff
+                });
+                tokio::pin!(next_progress);
+                let mut shutdown = None;
+                let mut dms_result = None;
+                loop {
+                    tokio::select! {
+                        _ = &mut next_progress => break,
+                        result = &mut dms_task => {
+                            dms_result = Some(result);
+                            break;
+                        }
+                        Some(command) = command_receiver.recv() => match command {
+                            ConsensusManagerCommand::Consensus(
+                                ConsensusCommand::Shutdown(result_sender),
+                            ) => {
+                                shutdown = Some(result_sender);
+                                break;
+                            }
+                            command => self.handle_command(command).await,
+                        }
+                    }
+                }
+                if let Some(result) = dms_result {
+                    break match result {
+                        Ok(Ok(())) => Err(ConsensusError::network(
+                            "the DMS server terminated unexpectedly",
+                        )),
+                        Ok(Err(e)) => Err(ConsensusError::network(format!(
+                            "the DMS server failed: {e}"
+                        ))),
+                        Err(e) => Err(ConsensusError::network(format!(
+                            "the DMS server panicked: {e}"
+                        ))),
+                    };
+                }
+                if let Some(result_sender) = shutdown {
+                    let result = match self.current() {
+                        Ok(consensus) => consensus.flush().await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        break Err(e);
+                    }
+                    tracing::info!("stopped serving the consensus");
+                    let _ = result_sender.send(Ok(()));
+                    break Ok(());
+                }
+                match self.serve_progress(&sender).await {
+                    Ok(()) => failures = 0,
+                    Err(e)
+                        if is_recoverable(&e)
+                            && self.consensus.is_some()
+                            && failures < retry_policy.max_consecutive_failures =>
+                    {
+                        failures += 1;
+                        tracing::warn!(failures, error = %e, "failed to progress; retrying");
+                        let _ = error_sender.try_send(e);
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            dms_task.abort();
+            result
+        });
+        Ok((task, receiver, command_sender, error_receiver))
+    }
+
+    /// Makes a progress in `serve()`, moving on to the next height if possible.
+    async fn serve_progress(
+        &mut self,
+        sender: &mpsc::Sender<ProgressResult>,
+    ) -> Result<(), ConsensusError> {
+        let consensus = self.current()?;
+        if consensus.check_finalized().await?.is_none() {
+            consensus.serve_progress(sender).await?;
+        }
+        self.advance(get_timestamp()).await?;
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, command: ConsensusManagerCommand) {
+        let (result, result_sender) = match command {
+            ConsensusManagerCommand::AddNextHeader(header, result_sender) => {
+                (self.add_next_header(header), result_sender)
+            }
+            ConsensusManagerCommand::RegisterVerifiedBlockHash(
+                height,
+                block_hash,
+                result_sender,
+            ) => (
+                self.register_verified_block_hash(height, block_hash).await,
+                result_sender,
+            ),
+            ConsensusManagerCommand::SetProposalCandidate(
+                height,
+                block_hash,
+                timestamp,
+                result_sender,
+            ) => (
+                self.set_proposal_candidate(height, block_hash, timestamp)
+                    .await,
+                result_sender,
+            ),
+            ConsensusManagerCommand::Consensus(command) => {
+                match self.current() {
+                    Ok(consensus) => consensus.handle_command(command).await,
+                    Err(e) => tracing::warn!(error = %e, "dropped a consensus command"),
+                }
+                return;
+            }
+        };
+        if result_sender.send(result).is_err() {
+            tracing::warn!("the receiver of the consensus command result is dropped");
+        }
+    }
+}
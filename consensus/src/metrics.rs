@@ -0,0 +1,254 @@
+use super::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The reason why `ConsensusMessageFilter` has rejected a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FilterRejection {
+    NotAValidator,
+    OtherHeight,
+    InvalidSignature,
+    UnverifiedBlock,
+    /// The signer has signed too many messages of the same kind in the round.
+    TooManyMessages,
+    /// The encoded message is larger than the limit.
+    TooLarge,
+    /// The encoded message can't be decoded strictly, e.g., with trailing data.
+    Malformed,
+    /// The message is for a round too far ahead of the current one.
+    FarFutureRound,
+    /// The message is of another protocol version than `CONSENSUS_PROTOCOL_VERSION`.
+    VersionMismatch,
+    /// The message has a round that doesn't fit in the rounds of this target.
+    RoundOutOfRange,
+}
+
+impl FilterRejection {
+    pub const ALL: [FilterRejection; 10] = [
+        FilterRejection::NotAValidator,
+        FilterRejection::OtherHeight,
+        FilterRejection::InvalidSignature,
+        FilterRejection::UnverifiedBlock,
+        FilterRejection::TooManyMessages,
+        FilterRejection::TooLarge,
+        FilterRejection::Malformed,
+        FilterRejection::FarFutureRound,
+        FilterRejection::VersionMismatch,
+        FilterRejection::RoundOutOfRange,
+    ];
+}
+
+/// The hooks to observe the consensus on its hot path, e.g., to export them to Prometheus.
+///
+/// Every hook does nothing by default, and they are called synchronously,
+/// so an implementation must be cheap (e.g., updating atomic counters).
+pub trait ConsensusMetrics: Send + Sync {
+    /// The consensus messages have been read from the DMS by `update()`.
+    fn messages_processed(&self, _count: usize) {}
+
+    /// The message filter of the DMS has admitted a message.
+    fn message_accepted(&self) {}
+
+    /// The message filter of the DMS has rejected a message.
+    fn message_rejected(&self, _reason: FilterRejection) {}
+
+    /// The height has been finalized in the given number of rounds,
+    /// with the time since the first proposal that this node has seen, if any.
+    fn finalized(&self, _rounds: u64, _time_since_first_proposal_ms: Option<Timestamp>) {}
+
+    /// The state has been written to the storage.
+    fn state_committed(&self, _latency: Duration) {}
+
+    /// A message of this node has been committed to the DMS to be broadcast.
+    fn message_broadcast(&self) {}
+
+    /// A message of this node has failed to be committed to the DMS.
+    fn broadcast_failed(&self) {}
+}
+
+/// The metrics that discard everything, which is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl ConsensusMetrics for NoopMetrics {}
+
+/// A histogram of the observed values with fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    /// The inclusive upper bounds of the buckets, in ascending order.
+    bounds: Vec<u64>,
+    /// The counts of each bucket, with the last one for the values above every bound.
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: Vec<u64>) -> Self {
+        Self {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    /// Returns the count of each bucket by its upper bound (`None` for the last one).
+    pub fn buckets(&self) -> Vec<(Option<u64>, u64)> {
+        self.bounds
+            .iter()
+            .map(|bound| Some(*bound))
+            .chain(std::iter::once(None))
+            .zip(self.buckets.iter())
+            .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// The metrics kept in atomic counters, to be read back by the node or the tests.
+#[derive(Debug)]
+pub struct AtomicMetrics {
+    pub messages_processed: AtomicU64,
+    pub messages_accepted: AtomicU64,
+    /// The rejected messages, indexed by `FilterRejection` (read by `messages_rejected()`).
+    pub messages_rejected: [AtomicU64; FilterRejection::ALL.len()],
+    pub messages_broadcast: AtomicU64,
+    pub broadcast_failures: AtomicU64,
+    pub rounds_per_height: Histogram,
+    pub time_to_finalization_ms: Histogram,
+    pub state_commit_latency_us: Histogram,
+}
+
+impl Default for AtomicMetrics {
+    fn default() -> Self {
+        Self {
+            messages_processed: AtomicU64::new(0),
+            messages_accepted: AtomicU64::new(0),
+            messages_rejected: Default::default(),
+            messages_broadcast: AtomicU64::new(0),
+            broadcast_failures: AtomicU64::new(0),
+            rounds_per_height: Histogram::new(vec![1, 2, 3, 5, 10, 20]),
+            time_to_finalization_ms: Histogram::new(vec![
+                100, 500, 1_000, 5_000, 10_000, 30_000, 60_000,
+            ]),
+            state_commit_latency_us: Histogram::new(vec![
+                100, 500, 1_000, 5_000, 10_000, 50_000, 100_000,
+            ]),
+        }
+    }
+}
+
+impl AtomicMetrics {
+    pub fn messages_rejected(&self, reason: FilterRejection) -> u64 {
+        self.messages_rejected[reason as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl ConsensusMetrics for AtomicMetrics {
+    fn messages_processed(&self, count: usize) {
+        self.messages_processed
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn message_accepted(&self) {
+        self.messages_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn message_rejected(&self, reason: FilterRejection) {
+        self.messages_rejected[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finalized(&self, rounds: u64, time_since_first_proposal_ms: Option<Timestamp>) {
+        self.rounds_per_height.observe(rounds);
+        if let Some(time) = time_since_first_proposal_ms {
+            self.time_to_finalization_ms.observe(time as u64);
+        }
+    }
+
+    fn state_committed(&self, latency: Duration) {
+        self.state_commit_latency_us
+            .observe(latency.as_micros() as u64);
+    }
+
+    fn message_broadcast(&self) {
+        self.messages_broadcast.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn broadcast_failed(&self) {
+        self.broadcast_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::new(vec![1, 10]);
+        for value in [0, 1, 2, 10, 11, 100] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.sum(), 124);
+        assert_eq!(
+            histogram.buckets(),
+            vec![(Some(1), 2), (Some(10), 2), (None, 2)]
+        );
+    }
+
+    #[test]
+    fn atomic() {
+        let metrics = AtomicMetrics::default();
+        metrics.messages_processed(3);
+        metrics.message_rejected(FilterRejection::OtherHeight);
+        metrics.message_rejected(FilterRejection::OtherHeight);
+        metrics.finalized(2, None);
+        assert_eq!(metrics.messages_processed.load(Ordering::Relaxed), 3);
+        assert_eq!(metrics.messages_rejected(FilterRejection::OtherHeight), 2);
+        assert_eq!(metrics.messages_rejected(FilterRejection::NotAValidator), 0);
+        assert_eq!(metrics.rounds_per_height.buckets()[1], (Some(2), 1));
+        assert_eq!(metrics.time_to_finalization_ms.count(), 0);
+    }
+
+    /// The counters shared by the threads, as the filter and the serve loop do.
+    #[test]
+    fn atomic_concurrent() {
+        let metrics = Arc::new(AtomicMetrics::default());
+        let threads = (0..4)
+            .map(|_| {
+                let metrics = Arc::clone(&metrics) as Arc<dyn ConsensusMetrics>;
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        metrics.message_accepted();
+                        metrics.message_broadcast();
+                        metrics.broadcast_failed();
+                        metrics.state_committed(Duration::from_micros(10));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(metrics.messages_accepted.load(Ordering::Relaxed), 4000);
+        assert_eq!(metrics.messages_broadcast.load(Ordering::Relaxed), 4000);
+        assert_eq!(metrics.broadcast_failures.load(Ordering::Relaxed), 4000);
+        assert_eq!(metrics.state_commit_latency_us.count(), 4000);
+        assert_eq!(metrics.state_commit_latency_us.sum(), 40_000);
+    }
+}
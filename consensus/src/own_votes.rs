@@ -0,0 +1,82 @@
+use super::*;
+
+/// The record of every consensus message that this node has signed for a height.
+///
+/// It is persisted before the messages are committed to the DMS
+/// so that the node never signs conflicting messages even across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct OwnVotes {
+    /// The hash of the last finalized block header, which identifies the height.
+    pub last_header_hash: Hash256,
+    pub messages: Vec<ConsensusMessage>,
+}
+
+impl OwnVotes {
+    pub fn new(last_header_hash: Hash256) -> Self {
+        Self {
+            last_header_hash,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Returns the previously signed message that conflicts with the given one, if any.
+    pub fn find_conflict(&self, message: &ConsensusMessage) -> Option<&ConsensusMessage> {
+        self.messages
+            .iter()
+            .find(|x| x.vote_key() == message.vote_key() && *x != message)
+    }
+
+    /// Records the message, returning `false` if it has been already recorded.
+    ///
+    /// The caller must check the conflict first.
+    pub fn record(&mut self, message: &ConsensusMessage) -> bool {
+        if self.messages.contains(message) {
+            return false;
+        }
+        self.messages.push(message.clone());
+        true
+    }
+
+    /// Returns the hashes of the recorded messages, which are the ones this node originated.
+    pub fn message_hashes(&self) -> BTreeSet<Hash256> {
+        self.messages.iter().map(|x| x.to_hash256()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflict() {
+        let mut own_votes = OwnVotes::new(Hash256::hash("header"));
+        let prevote = ConsensusMessage::NonNilPreVoted(1, 0, Hash256::hash("block"));
+        assert!(own_votes.record(&prevote));
+        assert!(!own_votes.record(&prevote));
+        assert_eq!(
+            own_votes.message_hashes(),
+            std::iter::once(prevote.to_hash256()).collect()
+        );
+        assert_eq!(own_votes.find_conflict(&prevote), None);
+        assert_eq!(
+            own_votes.find_conflict(&ConsensusMessage::NilPreVoted(1, 0)),
+            Some(&prevote)
+        );
+        assert_eq!(
+            own_votes.find_conflict(&ConsensusMessage::NonNilPreVoted(
+                1,
+                0,
+                Hash256::hash("another block")
+            )),
+            Some(&prevote)
+        );
+        assert_eq!(
+            own_votes.find_conflict(&ConsensusMessage::NilPreVoted(1, 1)),
+            None
+        );
+        assert_eq!(
+            own_votes.find_conflict(&ConsensusMessage::NilPreCommitted(1, 0)),
+            None
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use super::*;
+
+/// How `Consensus::fetch()` bans the peers that deliver the messages rejected by the DMS,
+/// which are either buggy or malicious.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerBanPolicy {
+    /// The number of the rejected messages from a peer to ban it.
+    pub max_rejections: u64,
+    /// How long a banned peer is not fetched from.
+    pub ban_duration_ms: Timestamp,
+}
+
+impl Default for PeerBanPolicy {
+    fn default() -> Self {
+        Self {
+            max_rejections: 100,
+            ban_duration_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+/// How the messages fetched from a peer have been received.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerScore {
+    pub peer: PublicKey,
+    /// The number of the admitted messages.
+    pub admitted: u64,
+    /// The number of the rejected messages since the last ban.
+    pub rejected: u64,
+    /// The reason of the last rejection.
+    pub last_rejection: Option<String>,
+    /// Until when the peer is banned, if it is.
+    pub banned_until: Option<Timestamp>,
+}
+
+impl PeerScore {
+    fn new(peer: PublicKey) -> Self {
+        Self {
+            peer,
+            admitted: 0,
+            rejected: 0,
+            last_rejection: None,
+            banned_until: None,
+        }
+    }
+}
+
+/// The scores of the peers, which are kept across the heights.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PeerScores {
+    scores: Vec<PeerScore>,
+}
+
+impl PeerScores {
+    fn get_mut(&mut self, peer: &PublicKey) -> &mut PeerScore {
+        let index = match self.scores.iter().position(|score| score.peer == *peer) {
+            Some(index) => index,
+            None => {
+                self.scores.push(PeerScore::new(peer.clone()));
+                self.scores.len() - 1
+            }
+        };
+        &mut self.scores[index]
+    }
+
+    /// Lifts the expired bans, returning whether any has been lifted.
+    pub(crate) fn lift_expired_bans(&mut self, timestamp: Timestamp) -> bool {
+        let mut lifted = false;
+        for score in self.scores.iter_mut() {
+            if score.banned_until.is_some_and(|until| until <= timestamp) {
+                tracing::info!(peer = %score.peer, "lifted the ban of a peer");
+                score.banned_until = None;
+                score.rejected = 0;
+                lifted = true;
+            }
+        }
+        lifted
+    }
+
+    /// Orders the peers to fetch from, leaving out the banned ones:
+    /// the ones with fewer rejections first, and then the ones with more admitted messages.
+    pub(crate) fn prioritize(&self, mut peers: Vec<PublicKey>) -> Vec<PublicKey> {
+        let key = |peer: &PublicKey| {
+            self.scores
+                .iter()
+                .find(|score| score.peer == *peer)
+                .map_or((0, u64::MAX), |score| {
+                    (score.rejected, u64::MAX - score.admitted)
+                })
+        };
+        peers.retain(|peer| {
+            !self
+                .scores
+                .iter()
+                .any(|score| score.peer == *peer && score.banned_until.is_some())
+        });
+        peers.sort_by_cached_key(key);
+        peers
+    }
+
+    /// Records what has been received from a peer, banning it if it has delivered
+    /// too many rejected messages.
+    pub(crate) fn record(
+        &mut self,
+        report: &FetchReport,
+        policy: &PeerBanPolicy,
+        timestamp: Timestamp,
+    ) {
+        let score = self.get_mut(&report.peer);
+        score.admitted += report.admitted as u64;
+        score.rejected += report.rejections.len() as u64;
+        if let Some(rejection) = report.rejections.last() {
+            score.last_rejection = Some(rejection.clone());
+        }
+        if score.banned_until.is_none() && score.rejected >= policy.max_rejections {
+            let until = timestamp.saturating_add(policy.ban_duration_ms);
+            tracing::warn!(
+                peer = %score.peer,
+                rejected = score.rejected,
+                last_rejection = ?score.last_rejection,
+                until,
+                "banned a peer for delivering the rejected messages"
+            );
+            score.banned_until = Some(until);
+        }
+    }
+
+    /// Returns the banned peers with the time until when they are banned.
+    pub(crate) fn banned_peers(&self) -> Vec<(PublicKey, Timestamp)> {
+        self.scores
+            .iter()
+            .filter_map(|score| Some((score.peer.clone(), score.banned_until?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban() {
+        let peers = (0..3)
+            .map(|i| generate_keypair(format!("peer{i}")).0)
+            .collect::<Vec<_>>();
+        let policy = PeerBanPolicy {
+            max_rejections: 3,
+            ban_duration_ms: 1000,
+        };
+        let report = |i: usize, admitted: usize, rejected: usize| FetchReport {
+            peer: peers[i].clone(),
+            admitted,
+            rejections: vec!["garbage".to_owned(); rejected],
+        };
+        let mut scores = PeerScores::default();
+        scores.record(&report(0, 1, 2), &policy, 0);
+        scores.record(&report(1, 1, 0), &policy, 0);
+        scores.record(&report(2, 5, 0), &policy, 0);
+        assert_eq!(
+            scores.prioritize(peers.clone()),
+            vec![peers[2].clone(), peers[1].clone(), peers[0].clone()]
+        );
+        assert!(scores.banned_peers().is_empty());
+
+        scores.record(&report(0, 0, 1), &policy, 10);
+        assert_eq!(scores.banned_peers(), vec![(peers[0].clone(), 1010)]);
+        assert_eq!(scores.prioritize(peers.clone()).len(), 2);
+        assert!(!scores.lift_expired_bans(1009));
+        assert!(scores.lift_expired_bans(1010));
+        assert!(scores.banned_peers().is_empty());
+        assert_eq!(
+            scores.prioritize(peers.clone()),
+            vec![peers[2].clone(), peers[0].clone(), peers[1].clone()]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// Verifies that the given proof finalizes the block,
+/// i.e., the precommits in it are signed by more than 2/3 of the voting power of the validator set
+/// (the default quorum).
+///
+/// Every signature must be a valid precommit on the block in the round of the proof,
+/// signed by a distinct member of the validator set.
+///
+/// This doesn't require any instance of the consensus, so it can be used by light clients.
+/// Use `verify_delegated_finalization_proof()` for another quorum,
+/// or if the validators may have delegated their keys.
+pub fn verify_finalization_proof(
+    block_hash: &Hash256,
+    proof: &FinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<(), ConsensusError> {
+    verify_delegated_finalization_proof(
+        block_hash,
+        proof,
+        validator_set,
+        Quorum::default(),
+        &Delegations::default(),
+        0,
+    )
+}
+
+/// Verifies the proof as `verify_finalization_proof()` does, but against the given quorum
+/// and counting a signature by a key delegated in the height of the block
+/// toward the validator that has delegated it.
+pub fn verify_delegated_finalization_proof(
+    block_hash: &Hash256,
+    proof: &FinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
+    quorum: Quorum,
+    delegations: &Delegations,
+    height: BlockHeight,
+) -> Result<(), ConsensusError> {
+    let target = FinalizationSignTarget {
+        block_hash: *block_hash,
+        round: proof.round,
+    };
+    let validators = validator_set.iter().cloned().collect::<BTreeMap<_, _>>();
+    let mut voted_validators = BTreeSet::new();
+    let mut voted_voting_power: u128 = 0;
+    for signature in &proof.signatures {
+        let signer = signature.signer();
+        let validator = delegations.resolve(signer, height).ok_or_else(|| {
+            ConsensusError::InvalidProof(format!("finalization proof: the signer {signer} is not the key of its validator in the height {height}"))
+        })?;
+        let power = validators.get(&validator).ok_or_else(|| {
+            ConsensusError::InvalidProof(format!(
+                "finalization proof: the signer {signer} is not a validator"
+            ))
+        })?;
+        if !voted_validators.insert(validator.clone()) {
+            return Err(ConsensusError::InvalidProof(format!(
+                "finalization proof: duplicate signatures for {validator}"
+            )));
+        }
+        signature.verify(&target).map_err(|e| {
+            ConsensusError::InvalidProof(format!(
+                "finalization proof: invalid signature by {signer} for the round {}: {e}",
+                proof.round
+            ))
+        })?;
+        voted_voting_power += *power as u128;
+    }
+    let total_voting_power = total_voting_power(validator_set);
+    if !quorum.is_reached(voted_voting_power, total_voting_power) {
+        return Err(ConsensusError::InvalidProof(format!(
+            "finalization proof: voted voting power is too low: {voted_voting_power} / {total_voting_power}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect::<Vec<_>>();
+        let block_hash = Hash256::hash("block");
+        let sign = |n: usize| FinalizationProof {
+            round: 1,
+            signatures: keys[0..n]
+                .iter()
+                .map(|(_, private_key)| {
+                    TypedSignature::sign(
+                        &FinalizationSignTarget {
+                            block_hash,
+                            round: 1,
+                        },
+                        private_key,
+                    )
+                    .unwrap()
+                })
+                .collect(),
+        };
+        verify_finalization_proof(&block_hash, &sign(4), &validator_set).unwrap();
+        verify_finalization_proof(&block_hash, &sign(3), &validator_set).unwrap();
+        assert!(verify_finalization_proof(&block_hash, &sign(2), &validator_set).is_err());
+        assert!(verify_finalization_proof(
+            &Hash256::hash("another block"),
+            &sign(4),
+            &validator_set
+        )
+        .is_err());
+
+        // With a stricter quorum
+        let quorum = Quorum {
+            numerator: 4,
+            denominator: 5,
+        };
+        let verify = |n: usize| {
+            verify_delegated_finalization_proof(
+                &block_hash,
+                &sign(n),
+                &validator_set,
+                quorum,
+                &Delegations::default(),
+                0,
+            )
+        };
+        verify(4).unwrap();
+        assert!(verify(3).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_signers() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect::<Vec<_>>();
+        let block_hash = Hash256::hash("block");
+        let sign = |private_key: &PrivateKey, round: ConsensusRound| {
+            TypedSignature::sign(&FinalizationSignTarget { block_hash, round }, private_key)
+                .unwrap()
+        };
+        let proof = |signatures| FinalizationProof {
+            round: 0,
+            signatures,
+        };
+
+        // Duplicate signers don't count twice.
+        let signatures = vec![
+            sign(&keys[0].1, 0),
+            sign(&keys[0].1, 0),
+            sign(&keys[1].1, 0),
+        ];
+        assert!(
+            verify_finalization_proof(&block_hash, &proof(signatures), &validator_set).is_err()
+        );
+
+        // A signature over another round.
+        let signatures = vec![
+            sign(&keys[0].1, 0),
+            sign(&keys[1].1, 0),
+            sign(&keys[2].1, 1),
+        ];
+        assert!(
+            verify_finalization_proof(&block_hash, &proof(signatures), &validator_set).is_err()
+        );
+
+        // A signer outside of the validator set.
+        let (_, stranger) = generate_keypair("stranger");
+        let signatures = vec![
+            sign(&keys[0].1, 0),
+            sign(&keys[1].1, 0),
+            sign(&keys[2].1, 0),
+            sign(&stranger, 0),
+        ];
+        assert!(
+            verify_finalization_proof(&block_hash, &proof(signatures), &validator_set).is_err()
+        );
+
+        let signatures = vec![
+            sign(&keys[0].1, 0),
+            sign(&keys[1].1, 0),
+            sign(&keys[2].1, 0),
+        ];
+        verify_finalization_proof(&block_hash, &proof(signatures), &validator_set).unwrap();
+    }
+
+    #[test]
+    fn delegated() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect::<Vec<_>>();
+        let (consensus_key, consensus_private_key) = generate_keypair("consensus key");
+        let mut delegations = Delegations::default();
+        delegations
+            .add(
+                SignedDelegation::sign(
+                    Delegation {
+                        identity: keys[0].0.clone(),
+                        consensus_key,
+                        from_height: 2,
+                        to_height: 2,
+                        sequence: 0,
+                    },
+                    &keys[0].1,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let block_hash = Hash256::hash("block");
+        let proof = |private_keys: Vec<&PrivateKey>| FinalizationProof {
+            round: 0,
+            signatures: private_keys
+                .into_iter()
+                .map(|private_key| {
+                    TypedSignature::sign(
+                        &FinalizationSignTarget {
+                            block_hash,
+                            round: 0,
+                        },
+                        private_key,
+                    )
+                    .unwrap()
+                })
+                .collect(),
+        };
+        let verify = |proof: &FinalizationProof, height: BlockHeight| {
+            verify_delegated_finalization_proof(
+                &block_hash,
+                proof,
+                &validator_set,
+                Quorum::default(),
+                &delegations,
+                height,
+            )
+        };
+
+        // The delegated key counts for the validator 0 only in the height 2.
+        let delegated = proof(vec![&consensus_private_key, &keys[1].1, &keys[2].1]);
+        verify(&delegated, 2).unwrap();
+        assert!(verify(&delegated, 3).is_err());
+        assert!(verify_finalization_proof(&block_hash, &delegated, &validator_set).is_err());
+        // The validator 0 can't sign with its own key while delegated.
+        let own = proof(vec![&keys[0].1, &keys[1].1, &keys[2].1]);
+        assert!(verify(&own, 2).is_err());
+        verify(&own, 3).unwrap();
+        // Nor count twice.
+        let twice = proof(vec![&consensus_private_key, &keys[0].1, &keys[1].1]);
+        assert!(verify(&twice, 2).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use super::*;
+use tokio::sync::watch;
+
+/// The latest state of the consensus published to the read handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    pub status: ConsensusStatus,
+    /// The results of the last `progress()`.
+    pub progress_results: Vec<ProgressResult>,
+}
+
+/// A cheap, cloneable handle to read the latest status of the consensus.
+///
+/// It is updated whenever the consensus commits its state,
+/// so it never waits for (or blocks) the consensus instance.
+#[derive(Debug, Clone)]
+pub struct ConsensusReadHandle {
+    receiver: watch::Receiver<Snapshot>,
+}
+
+impl ConsensusReadHandle {
+    pub(crate) fn new(receiver: watch::Receiver<Snapshot>) -> Self {
+        Self { receiver }
+    }
+
+    pub fn status(&self) -> ConsensusStatus {
+        self.receiver.borrow().status.clone()
+    }
+
+    pub fn current_round(&self) -> ConsensusRound {
+        self.receiver.borrow().status.round
+    }
+
+    pub fn verified_block_hashes(&self) -> Vec<Hash256> {
+        self.receiver.borrow().status.verified_block_hashes.clone()
+    }
+
+    pub fn is_finalized(&self) -> bool {
+        self.receiver.borrow().status.finalized
+    }
+
+    /// Returns the results of the last `progress()`.
+    pub fn last_progress_results(&self) -> Vec<ProgressResult> {
+        self.receiver.borrow().progress_results.clone()
+    }
+
+    /// Waits until the status is updated.
+    ///
+    /// It fails if the consensus instance is dropped.
+    pub async fn changed(&mut self) -> Result<(), ConsensusError> {
+        self.receiver
+            .changed()
+            .await
+            .map_err(|_| ConsensusError::Gone("dropped".to_owned()))
+    }
+}
@@ -0,0 +1,167 @@
+use super::*;
+
+/// Re-drives the consensus with the exported messages, without any side effect.
+///
+/// The messages are fed one by one in the given order, each with its signature
+/// and the time when it was received, as a non-validator so that nothing is broadcasted.
+/// Returns the results of the `progress()` right after each message, by the hash of it,
+/// which tells the exact message that caused a violation report or the finalization.
+/// The replay stops at the finalization, whose proof is left empty.
+///
+/// All the blocks in `verified_block_hashes` are considered to be valid.
+pub fn replay(
+    block_header: &BlockHeader,
+    consensus_parameters: ConsensusParams,
+    round_zero_timestamp: Timestamp,
+    verified_block_hashes: &[Hash256],
+    dms_key: &DmsKey,
+    messages: &[(ConsensusMessage, MessageCommitmentProof, Timestamp)],
+) -> Result<Vec<(Hash256, Vec<ProgressResult>)>, ConsensusError> {
+    let mut state = State::new(
+        block_header,
+        consensus_parameters,
+        round_zero_timestamp,
+        None,
+    )?;
+    for block_hash in verified_block_hashes {
+        state.register_verified_block_hash(*block_hash)?;
+    }
+    state.progress(round_zero_timestamp)?;
+    let mut result = Vec::new();
+    for (message, commitment, timestamp) in messages {
+        if state.check_finalized().is_some() {
+            break;
+        }
+        message
+            .verify_commitment(commitment, dms_key)
+            .map_err(|e| {
+                ConsensusError::InvalidProof(format!("signature on {}: {e}", message.to_hash256()))
+            })?;
+        state.detect_equivocations(&[(message.clone(), commitment.clone())], dms_key);
+        state.add_consensus_messages(
+            vec![(message.clone(), commitment.committer.clone())],
+            *timestamp,
+            &|_| Some(true),
+        )?;
+        result.push((message.to_hash256(), state.progress(*timestamp)?));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_round() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let height = fi.header.height + 1;
+        let block_hash = Hash256::hash("block");
+        let dms_key = "consensus".to_owned();
+        let signed = |message: ConsensusMessage, i: usize, timestamp: Timestamp| {
+            let commitment = message.commit(&dms_key, &keys[i].1).unwrap();
+            (message, commitment, timestamp)
+        };
+        let messages = vec![
+            signed(
+                ConsensusMessage::Proposal {
+                    height,
+                    round: 0,
+                    valid_round: None,
+                    block_hash,
+                },
+                0,
+                1,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+                0,
+                2,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+                1,
+                3,
+            ),
+            signed(ConsensusMessage::NilPreVoted(height, 0), 1, 4),
+            signed(
+                ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+                2,
+                5,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                0,
+                6,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                1,
+                7,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                2,
+                8,
+            ),
+            signed(
+                ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                3,
+                9,
+            ),
+        ];
+        let replay = || {
+            replay(
+                &fi.header,
+                ConsensusParams {
+                    timeout_ms: 6000,
+                    timeout_increment_ms: 0,
+                    repeat_round_for_first_leader: 10,
+                    quorum: None,
+                },
+                0,
+                &[block_hash],
+                &dms_key,
+                &messages,
+            )
+            .unwrap()
+        };
+        let result = replay();
+        // Deterministic
+        assert_eq!(result, replay());
+        // Stopped at the finalization.
+        assert_eq!(result.len(), 8);
+        for (i, (message_hash, progress_results)) in result.iter().enumerate() {
+            assert_eq!(*message_hash, messages[i].0.to_hash256());
+            let finalized = progress_results
+                .iter()
+                .any(|x| matches!(x, ProgressResult::Finalized(_)));
+            assert_eq!(finalized, i == 7);
+        }
+        // The double prevote is caught right at the second one.
+        let first_violation = result.iter().position(|(_, progress_results)| {
+            progress_results
+                .iter()
+                .any(|x| matches!(x, ProgressResult::ViolationReported(..)))
+        });
+        assert_eq!(first_violation, Some(3));
+
+        // A forged message, with the signature of another message of the signer
+        let mut messages = messages;
+        messages[2].1 = messages[3].1.clone();
+        assert!(super::replay(
+            &fi.header,
+            ConsensusParams {
+                timeout_ms: 6000,
+                timeout_increment_ms: 0,
+                repeat_round_for_first_leader: 10,
+                quorum: None,
+            },
+            0,
+            &[block_hash],
+            &dms_key,
+            &messages,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use super::*;
+use async_trait::async_trait;
+
+/// Signs the consensus messages of this node, so that its key doesn't have to be kept
+/// in the memory of the process (e.g., in an HSM or behind a remote signing service).
+///
+/// `PrivateKey` implements it for the key in memory.
+#[async_trait]
+pub trait ConsensusSigner: Send + Sync + 'static {
+    /// Returns the public key of the signer, which decides the validator this node votes as.
+    async fn public_key(&self) -> PublicKey;
+
+    /// Signs the payload, which is the 32-byte digest to be signed as in `Signature::sign()`.
+    ///
+    /// It may take long or fail; the message is left in the outbox of the state
+    /// and signed again on the next `progress()` or `flush()` then.
+    async fn sign(&self, payload: &[u8]) -> Result<Signature, eyre::Error>;
+
+    /// Returns the delegation that authorizes the key of the signer
+    /// if it is a consensus key delegated by a validator, rather than the key of the validator.
+    ///
+    /// It is added to the state, so the other nodes must be given it by `add_delegation()`.
+    async fn delegation(&self) -> Option<SignedDelegation> {
+        None
+    }
+}
+
+#[async_trait]
+impl ConsensusSigner for PrivateKey {
+    async fn public_key(&self) -> PublicKey {
+        PrivateKey::public_key(self)
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Signature, eyre::Error> {
+        let digest = payload
+            .try_into()
+            .map_err(|_| eyre::eyre!("the payload must be 32 bytes, but got {}", payload.len()))?;
+        Ok(Signature::sign(Hash256::from_array(digest), self)?)
+    }
+}
+
+/// Signs the message with the signer, as `ConsensusMessage::commit()` does with the key.
+pub(crate) async fn sign_message(
+    signer: &dyn ConsensusSigner,
+    message: &ConsensusMessage,
+    dms_key: &DmsKey,
+) -> Result<MessageCommitmentProof, ConsensusError> {
+    let signature = signer
+        .sign(message.signing_payload(dms_key).as_ref())
+        .await
+        .map_err(|e| ConsensusError::Signer(format!("{e:#}")))?;
+    Ok(MessageCommitmentProof {
+        committer: signer.public_key().await,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn private_key() {
+        let (public_key, private_key) = generate_keypair("validator");
+        let dms_key = "consensus".to_owned();
+        assert_eq!(ConsensusSigner::public_key(&private_key).await, public_key);
+        for message in [
+            ConsensusMessage::NilPreVoted(1, 0),
+            ConsensusMessage::NonNilPreCommitted(1, 0, Hash256::hash("block")),
+        ] {
+            let commitment = sign_message(&private_key, &message, &dms_key)
+                .await
+                .unwrap();
+            assert_eq!(commitment, message.commit(&dms_key, &private_key).unwrap());
+            message.verify_commitment(&commitment, &dms_key).unwrap();
+        }
+        assert!(ConsensusSigner::sign(&private_key, &[0; 31]).await.is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use super::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use simperby_network::dms::MockGossipNetwork;
+
+/// The default amount of the virtual time that passes in a step.
+pub const DEFAULT_TICK_MS: Timestamp = 1000;
+
+/// The environment variable to give the seed, which is usually the one of a failed run.
+pub const SEED_ENV_VAR: &str = "SIMPERBY_SIMULATION_SEED";
+
+/// Reads the seed from `SEED_ENV_VAR`, or picks a random one if not given.
+pub fn seed_from_env() -> u64 {
+    match std::env::var(SEED_ENV_VAR) {
+        Ok(seed) => seed
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid {SEED_ENV_VAR}: {seed}")),
+        Err(_) => rand::random(),
+    }
+}
+
+/// The order in which the nodes are stepped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOrder {
+    /// From the first node to the last, starting from the next one in every step.
+    RoundRobin,
+    /// Shuffled by the random number generator of the seed.
+    Randomized,
+}
+
+/// A harness that runs multiple consensus nodes in process on a `MockGossipNetwork`,
+/// checking the global invariants after every step.
+///
+/// The time given to the nodes is a virtual clock, not the system time,
+/// and the order of the nodes is determined by the seed,
+/// so a failure is reproducible with the seed that it reports.
+/// (The drops of the network are not seeded though, so keep them off to reproduce.)
+///
+/// The checked invariants are:
+/// - No two honest nodes finalize different blocks.
+/// - No honest validator signs conflicting messages.
+/// - The consensus is finalized eventually if the honest voting power reaches the quorum
+///   (checked by `run_until_finalized()`).
+pub struct Simulation<S: Storage> {
+    nodes: Vec<Consensus<S>>,
+    /// The index in the validator set of each node, `None` for an observer.
+    validator_indices: Vec<Option<usize>>,
+    validator_set: Vec<(PublicKey, VotingPower)>,
+    quorum: Quorum,
+    network: MockGossipNetwork<StorageImpl, ConsensusMessage>,
+    byzantine: BTreeSet<PublicKey>,
+    crashed: BTreeSet<usize>,
+    /// The finalized block of each node, by the index of the node.
+    finalized: BTreeMap<usize, Hash256>,
+    clock: Timestamp,
+    tick_ms: Timestamp,
+    order: StepOrder,
+    seed: u64,
+    rng: StdRng,
+    steps: usize,
+}
+
+impl<S: Storage> Simulation<S> {
+    /// Creates a simulation of the given nodes, connecting their DMSes to a new network.
+    ///
+    /// The nodes must have been created with the `validator_set`,
+    /// and the virtual clock starts at `round_zero_timestamp`.
+    pub async fn new(
+        nodes: Vec<Consensus<S>>,
+        validator_set: Vec<(PublicKey, VotingPower)>,
+        round_zero_timestamp: Timestamp,
+        order: StepOrder,
+        seed: u64,
+    ) -> Result<Self, ConsensusError> {
+        tracing::info!(seed, "starting a consensus simulation");
+        let mut network = MockGossipNetwork::new();
+        let mut validator_indices = Vec::new();
+        let mut quorum = Quorum::default();
+        for node in nodes.iter() {
+            network.add_node(node.get_dms());
+            validator_indices.push(node.status().await?.this_node_index);
+            quorum = node.quorum().await?;
+        }
+        Ok(Self {
+            nodes,
+            validator_indices,
+            validator_set,
+            quorum,
+            network,
+            byzantine: BTreeSet::new(),
+            crashed: BTreeSet::new(),
+            finalized: BTreeMap::new(),
+            clock: round_zero_timestamp,
+            tick_ms: DEFAULT_TICK_MS,
+            order,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            steps: 0,
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the current time of the virtual clock.
+    pub fn now(&self) -> Timestamp {
+        self.clock
+    }
+
+    /// Moves the virtual clock forward, without stepping the nodes.
+    pub fn advance(&mut self, duration_ms: Timestamp) {
+        self.clock += duration_ms;
+    }
+
+    /// Sets the amount of the virtual time that passes in a step.
+    pub fn set_tick(&mut self, tick_ms: Timestamp) {
+        self.tick_ms = tick_ms;
+    }
+
+    pub fn nodes(&self) -> &[Consensus<S>] {
+        &self.nodes
+    }
+
+    pub fn nodes_mut(&mut self) -> &mut [Consensus<S>] {
+        &mut self.nodes
+    }
+
+    /// Ends the simulation, returning the nodes (e.g., to move them on to the next height).
+    pub fn into_nodes(self) -> Vec<Consensus<S>> {
+        self.nodes
+    }
+
+    /// Returns the network to control the delivery (e.g., to make partitions).
+    pub fn network(&self) -> &MockGossipNetwork<StorageImpl, ConsensusMessage> {
+        &self.network
+    }
+
+    /// Excludes the validator from the invariants, for the tests of the Byzantine behaviors.
+    pub fn mark_byzantine(&mut self, validator: PublicKey) {
+        self.byzantine.insert(validator);
+    }
+
+    /// Stops stepping the node, whose DMS still stays in the network.
+    pub fn crash(&mut self, node_index: usize) {
+        self.crashed.insert(node_index);
+    }
+
+    /// Resumes stepping the crashed node, which catches up with the messages in its DMS.
+    pub fn recover(&mut self, node_index: usize) {
+        self.crashed.remove(&node_index);
+    }
+
+    /// Returns the finalized block of each node that has been finalized, by the index of the node.
+    pub fn finalized(&self) -> &BTreeMap<usize, Hash256> {
+        &self.finalized
+    }
+
+    fn is_honest(&self, node_index: usize) -> bool {
+        if self.crashed.contains(&node_index) {
+            return false;
+        }
+        match self.validator_indices[node_index] {
+            Some(i) => !self.byzantine.contains(&self.validator_set[i].0),
+            None => true,
+        }
+    }
+
+    fn violation(&self, description: String) -> ConsensusError {
+        ConsensusError::Internal(format!(
+            "invariant violated at the step {} (seed {}): {description}",
+            self.steps, self.seed
+        ))
+    }
+
+    /// Progresses and flushes every running node at the current time,
+    /// gossips the messages, and updates the nodes with them.
+    /// Then moves the clock forward by a tick and checks the invariants.
+    pub async fn step(&mut self) -> Result<(), ConsensusError> {
+        let mut order = (0..self.nodes.len()).collect::<Vec<_>>();
+        match self.order {
+            StepOrder::RoundRobin => {
+                if !order.is_empty() {
+                    order.rotate_left(self.steps % self.nodes.len());
+                }
+            }
+            StepOrder::Randomized => order.shuffle(&mut self.rng),
+        }
+        let running = order
+            .into_iter()
+            .filter(|i| !self.crashed.contains(i) && !self.finalized.contains_key(i))
+            .collect::<Vec<_>>();
+        for &i in running.iter() {
+            let node = &mut self.nodes[i];
+            node.progress(self.clock).await?;
+            if let Some(finalization) = node.check_finalized().await? {
+                self.finalized.insert(i, finalization.block_hash);
+            }
+            self.nodes[i].flush().await?;
+        }
+        self.network
+            .gossip()
+            .await
+            .map_err(ConsensusError::network)?;
+        for &i in running.iter() {
+            if !self.finalized.contains_key(&i) {
+                self.nodes[i].update().await?;
+            }
+        }
+        self.clock += self.tick_ms;
+        self.steps += 1;
+        self.check_invariants().await
+    }
+
+    /// Steps until every honest node is finalized.
+    ///
+    /// Fails if it doesn't happen in `max_steps` while the honest voting power reaches the quorum.
+    pub async fn run_until_finalized(&mut self, max_steps: usize) -> Result<(), ConsensusError> {
+        for _ in 0..max_steps {
+            if self.is_finalized() {
+                return Ok(());
+            }
+            self.step().await?;
+        }
+        if self.is_finalized() {
+            return Ok(());
+        }
+        let total_power = total_voting_power(&self.validator_set);
+        let honest_power = (0..self.nodes.len())
+            .filter(|&i| self.is_honest(i))
+            .filter_map(|i| self.validator_indices[i])
+            .map(|i| self.validator_set[i].1 as u128)
+            .sum::<u128>();
+        if self.quorum.is_reached(honest_power, total_power) {
+            return Err(self.violation(format!(
+                "not finalized in {max_steps} steps with the honest voting power \
+                 {honest_power} out of {total_power}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns whether every honest node is finalized.
+    fn is_finalized(&self) -> bool {
+        (0..self.nodes.len())
+            .filter(|&i| self.is_honest(i))
+            .all(|i| self.finalized.contains_key(&i))
+    }
+
+    async fn check_invariants(&self) -> Result<(), ConsensusError> {
+        // Agreement
+        let finalized = self
+            .finalized
+            .iter()
+            .filter(|(i, _)| self.is_honest(**i))
+            .map(|(_, block_hash)| *block_hash)
+            .collect::<BTreeSet<_>>();
+        if finalized.len() > 1 {
+            return Err(self.violation(format!("different blocks are finalized: {finalized:?}")));
+        }
+
+        // No conflicting messages signed by an honest validator
+        let mut signed = BTreeMap::<_, ConsensusMessage>::new();
+        for node in self.nodes.iter() {
+            let messages = node
+                .get_dms()
+                .read()
+                .await
+                .read_messages()
+                .await
+                .map_err(ConsensusError::network)?;
+            for message in messages {
+                for commitment in message.committers.iter() {
+                    if self.byzantine.contains(&commitment.committer) {
+                        continue;
+                    }
+                    let key = (
+                        commitment.committer.clone(),
+                        message.message.height(),
+                        message.message.vote_key(),
+                    );
+                    match signed.get(&key) {
+                        Some(previous) if *previous != message.message => {
+                            return Err(self.violation(format!(
+                                "{} signed the conflicting messages {previous:?} and {:?}",
+                                commitment.committer, message.message
+                            )));
+                        }
+                        Some(_) => {}
+                        None => {
+                            signed.insert(key, message.message.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use super::*;
+
+/// When `Consensus::progress()` reports that the consensus is stalled
+/// (e.g., because too many validators are offline) as `ProgressResult::Stalled`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallPolicy {
+    /// The number of the consecutive rounds ended without a quorum on a block to report it.
+    pub max_rounds_without_progress: u64,
+    /// How long no new message may be consumed from the DMS before it is reported.
+    pub max_silence_ms: Timestamp,
+}
+
+impl Default for StallPolicy {
+    fn default() -> Self {
+        Self {
+            max_rounds_without_progress: 5,
+            max_silence_ms: 60 * 1000,
+        }
+    }
+}
+
+/// Why the consensus is stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StallCause {
+    /// Too many rounds have ended without a quorum on a block.
+    Rounds,
+    /// No new message has been consumed for too long.
+    Silence,
+}
+
+/// Tracks whether the consensus of a height is stalled, in memory only.
+///
+/// A stall is reported once when it crosses a threshold,
+/// and again only after it is over (i.e., a quorum on a block or a new message).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StallDetector {
+    /// The number of the messages consumed from the DMS.
+    consumed_messages: u64,
+    /// `consumed_messages` as of the last `check()`.
+    checked_messages: u64,
+    /// When `check()` has seen a new message last.
+    last_message_timestamp: Option<Timestamp>,
+    /// When `check()` has been called first, to measure the silence before any message.
+    first_check_timestamp: Option<Timestamp>,
+    /// The rounds before this have been recorded by `record_round()`.
+    next_round: ConsensusRound,
+    /// The round after the last one with a quorum on a block.
+    progress_round: ConsensusRound,
+    /// Whether the current stall has been reported.
+    reported: bool,
+}
+
+impl StallDetector {
+    /// Counts the messages consumed from the DMS.
+    pub(crate) fn consumed(&mut self, count: usize) {
+        self.consumed_messages += count as u64;
+    }
+
+    /// Returns the rounds ended but not recorded yet, before the current one.
+    pub(crate) fn unrecorded_rounds(
+        &self,
+        round: ConsensusRound,
+    ) -> std::ops::Range<ConsensusRound> {
+        self.next_round..round.max(self.next_round)
+    }
+
+    /// Records whether an ended round has had a quorum on a block.
+    pub(crate) fn record_round(&mut self, round: ConsensusRound, quorum_on_block: bool) {
+        if quorum_on_block {
+            self.progress_round = self.progress_round.max(round + 1);
+        }
+        self.next_round = self.next_round.max(round + 1);
+    }
+
+    /// Returns the number of the consecutive rounds ended without a quorum on a block.
+    pub(crate) fn rounds_without_progress(&self, round: ConsensusRound) -> u64 {
+        round.saturating_sub(self.progress_round)
+    }
+
+    /// Returns when a new message has been seen last, if any.
+    pub(crate) fn last_message_timestamp(&self) -> Option<Timestamp> {
+        self.last_message_timestamp
+    }
+
+    /// Checks the stall at the time, returning the cause only if it crosses a threshold now.
+    pub(crate) fn check(
+        &mut self,
+        policy: &StallPolicy,
+        round: ConsensusRound,
+        timestamp: Timestamp,
+    ) -> Option<StallCause> {
+        let since = *self.first_check_timestamp.get_or_insert(timestamp);
+        if self.consumed_messages != self.checked_messages {
+            self.checked_messages = self.consumed_messages;
+            self.last_message_timestamp = Some(timestamp);
+        }
+        let silence = timestamp - self.last_message_timestamp.unwrap_or(since);
+        let cause = if self.rounds_without_progress(round) >= policy.max_rounds_without_progress {
+            Some(StallCause::Rounds)
+        } else if silence >= policy.max_silence_ms {
+            Some(StallCause::Silence)
+        } else {
+            None
+        };
+        match cause {
+            Some(_) if self.reported => None,
+            Some(cause) => {
+                self.reported = true;
+                Some(cause)
+            }
+            None => {
+                self.reported = false;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn once_per_crossing() {
+        let policy = StallPolicy {
+            max_rounds_without_progress: 2,
+            max_silence_ms: 100,
+        };
+        let mut detector = StallDetector::default();
+        assert_eq!(detector.check(&policy, 0, 0), None);
+        assert_eq!(detector.check(&policy, 0, 100), Some(StallCause::Silence));
+        assert_eq!(detector.check(&policy, 0, 200), None);
+
+        // A new message ends the stall.
+        detector.consumed(1);
+        assert_eq!(detector.check(&policy, 0, 250), None);
+        assert_eq!(detector.last_message_timestamp(), Some(250));
+        assert_eq!(detector.check(&policy, 0, 350), Some(StallCause::Silence));
+
+        // The messages keep coming, but no round gets a quorum on a block.
+        detector.consumed(1);
+        assert_eq!(detector.check(&policy, 1, 355), None);
+        assert_eq!(detector.unrecorded_rounds(2), 0..2);
+        detector.record_round(0, false);
+        detector.record_round(1, false);
+        assert_eq!(detector.unrecorded_rounds(2), 2..2);
+        assert_eq!(detector.rounds_without_progress(2), 2);
+        assert_eq!(detector.check(&policy, 2, 360), Some(StallCause::Rounds));
+        detector.consumed(1);
+        assert_eq!(detector.check(&policy, 2, 370), None);
+        assert_eq!(detector.check(&policy, 2, 1000), None);
+
+        // A quorum on a block ends the stall.
+        detector.record_round(2, true);
+        assert_eq!(detector.rounds_without_progress(3), 0);
+        detector.consumed(1);
+        assert_eq!(detector.check(&policy, 3, 380), None);
+        detector.record_round(3, false);
+        detector.record_round(4, false);
+        detector.consumed(1);
+        assert_eq!(detector.check(&policy, 5, 390), Some(StallCause::Rounds));
+    }
+}
@@ -1,20 +1,58 @@
+mod legacy;
+mod wire;
+
 use super::*;
-use eyre::eyre;
+pub(crate) use legacy::{StateV0, StateV1, StateV2, StateV3, StateV4, StateV5};
 use serde::{Deserialize, Serialize};
 use simperby_core::*;
 use simperby_network::*;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use vetomint::{
-    BlockIdentifier, ConsensusEvent, ConsensusParams, ConsensusResponse, HeightInfo, Vetomint,
+    BlockIdentifier, ConsensusEvent, ConsensusParams, ConsensusResponse, HeightInfo, Misbehavior,
+    Round, Vetomint,
 };
 
-pub type Error = eyre::Error;
-
 /// Consensus messages to propagate each other.
 ///
+/// Every message carries the height that it is for,
+/// so that it can't be replayed on another height.
+///
 /// Note that all message are signed by DMS itself.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum ConsensusMessage {
+    Proposal {
+        height: BlockHeight,
+        round: ConsensusRound,
+        valid_round: Option<ConsensusRound>,
+        block_hash: Hash256,
+    },
+    NonNilPreVoted(BlockHeight, ConsensusRound, Hash256),
+    NonNilPreCommitted(BlockHeight, ConsensusRound, Hash256),
+    NilPreVoted(BlockHeight, ConsensusRound),
+    NilPreCommitted(BlockHeight, ConsensusRound),
+}
+
+/// The same as `ConsensusMessage`, used to derive the deserialization.
+#[derive(Deserialize)]
+#[serde(rename = "ConsensusMessage")]
+enum ConsensusMessageFormat {
+    Proposal {
+        height: BlockHeight,
+        round: ConsensusRound,
+        valid_round: Option<ConsensusRound>,
+        block_hash: Hash256,
+    },
+    NonNilPreVoted(BlockHeight, ConsensusRound, Hash256),
+    NonNilPreCommitted(BlockHeight, ConsensusRound, Hash256),
+    NilPreVoted(BlockHeight, ConsensusRound),
+    NilPreCommitted(BlockHeight, ConsensusRound),
+}
+
+/// The format of `ConsensusMessage` before the height was added, which is no longer supported
+/// but in the states of the version 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "ConsensusMessage")]
+enum LegacyConsensusMessageFormat {
     Proposal {
         round: ConsensusRound,
         valid_round: Option<ConsensusRound>,
@@ -26,9 +64,181 @@ pub enum ConsensusMessage {
     NilPreCommitted(ConsensusRound),
 }
 
+impl From<ConsensusMessageFormat> for ConsensusMessage {
+    fn from(message: ConsensusMessageFormat) -> Self {
+        match message {
+            ConsensusMessageFormat::Proposal {
+                height,
+                round,
+                valid_round,
+                block_hash,
+            } => ConsensusMessage::Proposal {
+                height,
+                round,
+                valid_round,
+                block_hash,
+            },
+            ConsensusMessageFormat::NonNilPreVoted(height, round, block_hash) => {
+                ConsensusMessage::NonNilPreVoted(height, round, block_hash)
+            }
+            ConsensusMessageFormat::NonNilPreCommitted(height, round, block_hash) => {
+                ConsensusMessage::NonNilPreCommitted(height, round, block_hash)
+            }
+            ConsensusMessageFormat::NilPreVoted(height, round) => {
+                ConsensusMessage::NilPreVoted(height, round)
+            }
+            ConsensusMessageFormat::NilPreCommitted(height, round) => {
+                ConsensusMessage::NilPreCommitted(height, round)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ConsensusMessage {
+    /// For the self-describing formats (i.e., JSON), it reports the messages
+    /// of the legacy format (without the height) with a clear error.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        if !deserializer.is_human_readable() {
+            return ConsensusMessageFormat::deserialize(deserializer).map(Into::into);
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        #[allow(dead_code)]
+        enum Compat {
+            Current(ConsensusMessageFormat),
+            Legacy(LegacyConsensusMessageFormat),
+        }
+        match Compat::deserialize(deserializer)? {
+            Compat::Current(message) => Ok(message.into()),
+            Compat::Legacy(_) => Err(serde::de::Error::custom(
+                "the consensus message of the legacy format (without the height) is not supported",
+            )),
+        }
+    }
+}
+
+/// The kind of a consensus message, which can be signed at most once per round by a validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VoteKind {
+    Proposal,
+    Prevote,
+    Precommit,
+}
+
+impl ConsensusMessage {
+    /// Returns the height that this message is for.
+    pub fn height(&self) -> BlockHeight {
+        match self {
+            ConsensusMessage::Proposal { height, .. }
+            | ConsensusMessage::NonNilPreVoted(height, ..)
+            | ConsensusMessage::NonNilPreCommitted(height, ..)
+            | ConsensusMessage::NilPreVoted(height, _)
+            | ConsensusMessage::NilPreCommitted(height, _) => *height,
+        }
+    }
+
+    pub(crate) fn vote_key(&self) -> (ConsensusRound, VoteKind) {
+        match self {
+            ConsensusMessage::Proposal { round, .. } => (*round, VoteKind::Proposal),
+            ConsensusMessage::NonNilPreVoted(_, round, _)
+            | ConsensusMessage::NilPreVoted(_, round) => (*round, VoteKind::Prevote),
+            ConsensusMessage::NonNilPreCommitted(_, round, _)
+            | ConsensusMessage::NilPreCommitted(_, round) => (*round, VoteKind::Precommit),
+        }
+    }
+
+    /// Returns the block hash that the message is on, if any.
+    pub(crate) fn block_hash(&self) -> Option<Hash256> {
+        match self {
+            ConsensusMessage::Proposal { block_hash, .. }
+            | ConsensusMessage::NonNilPreVoted(_, _, block_hash)
+            | ConsensusMessage::NonNilPreCommitted(_, _, block_hash) => Some(*block_hash),
+            ConsensusMessage::NilPreVoted(..) | ConsensusMessage::NilPreCommitted(..) => None,
+        }
+    }
+
+    /// Fails if any round of the message (including the valid round of a proposal)
+    /// can't be converted by `to_vetomint_round()`.
+    pub(crate) fn check_rounds(&self) -> Result<(), ConsensusError> {
+        let (round, _) = self.vote_key();
+        to_vetomint_round(round)?;
+        if let ConsensusMessage::Proposal {
+            valid_round: Some(valid_round),
+            ..
+        } = self
+        {
+            to_vetomint_round(*valid_round)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the round to the one of vetomint, which is a `usize`,
+/// failing rather than truncating it on the targets narrower than 64 bits.
+///
+/// Every round going into vetomint must be converted by this.
+pub(crate) fn to_vetomint_round(round: ConsensusRound) -> Result<Round, ConsensusError> {
+    Round::try_from(round).map_err(|_| ConsensusError::RoundOutOfRange(round))
+}
+
+/// Converts the round of vetomint, which always fits in `ConsensusRound`
+/// since no target has a `usize` wider than 64 bits.
+///
+/// Every round coming out of vetomint must be converted by this.
+pub(crate) fn from_vetomint_round(round: Round) -> ConsensusRound {
+    round as ConsensusRound
+}
+
+/// The domain of the signatures on the consensus messages,
+/// which keeps them from being replayed in the other protocols signed by the same key.
+const SIGNING_DOMAIN: &str = "simperby-consensus-message";
+
+/// The version of the protocol of the consensus messages, which every message carries
+/// in its compact encoding and the nodes must agree on.
+///
+/// The version `0` is the legacy scheme which signs the message without the domain separation,
+/// the version `1` signs the legacy encoding (`serde_spb`) instead of the compact one,
+/// and the version `2` has no version field in the compact encoding.
+pub const CONSENSUS_PROTOCOL_VERSION: u16 = 3;
+
+/// Describes a message of another protocol version, naming both of the versions.
+pub(crate) fn version_mismatch(version: u16) -> String {
+    format!(
+        "the message is of the protocol version {version}, \
+        but this node is on the version {CONSENSUS_PROTOCOL_VERSION}"
+    )
+}
+
+impl ConsensusMessage {
+    /// Returns the hash to sign for the message, except for the precommits
+    /// which are signed on `FinalizationSignTarget` to form the finalization proof.
+    fn signing_target(&self, dms_key: &DmsKey) -> Hash256 {
+        let mut data = SIGNING_DOMAIN.as_bytes().to_vec();
+        data.extend_from_slice(&CONSENSUS_PROTOCOL_VERSION.to_be_bytes());
+        data.extend(self.to_compact());
+        Hash256::hash(data).aggregate(&dms_key.to_hash256())
+    }
+
+    /// Returns the hash that the signer of the message signs, which is the one on
+    /// `FinalizationSignTarget` for the precommits to form the finalization proof.
+    pub(crate) fn signing_payload(&self, dms_key: &DmsKey) -> Hash256 {
+        match self {
+            ConsensusMessage::NonNilPreCommitted(_, round, block_hash) => FinalizationSignTarget {
+                block_hash: *block_hash,
+                round: *round,
+            }
+            .to_hash256(),
+            _ => self.signing_target(dms_key),
+        }
+    }
+}
+
 impl ToHash256 for ConsensusMessage {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_spb::to_vec(self).unwrap())
+        Hash256::hash(self.to_compact())
     }
 }
 
@@ -39,75 +249,185 @@ impl DmsMessage for ConsensusMessage {
         Ok(())
     }
 
-    fn commit(
-        &self,
-        dms_key: &DmsKey,
-        private_key: &PrivateKey,
-    ) -> Result<MessageCommitmentProof, simperby_core::CryptoError>
-    where
-        Self: Sized,
-    {
-        Ok(MessageCommitmentProof {
-            signature: match self {
-                ConsensusMessage::NonNilPreCommitted(round, block_hash) => Signature::sign(
-                    FinalizationSignTarget {
-                        block_hash: *block_hash,
-                        round: *round,
-                    }
-                    .to_hash256(),
-                    private_key,
-                )?,
-                _ => Signature::sign(
-                    self.to_hash256().aggregate(&dms_key.to_hash256()),
-                    private_key,
-                )?,
-            },
-            committer: private_key.public_key(),
-        })
+    fn encode_wire(&self) -> Vec<u8> {
+        self.to_compact()
     }
 
-    fn verify_commitment(
-        &self,
-        proof: &MessageCommitmentProof,
-        dms_key: &DmsKey,
-    ) -> Result<(), simperby_core::CryptoError> {
+    fn decode_wire(data: &[u8]) -> Result<Self, dms::Error> {
+        ConsensusMessage::from_wire(data).map_err(|e| eyre::eyre!(e))
+    }
+
+    fn commitment_target(&self, dms_key: &DmsKey) -> Hash256 {
+        self.signing_payload(dms_key)
+    }
+}
+
+/// The block candidate that stands for no candidate, which is never assigned to a block.
+///
+/// Vetomint always proposes the candidate, so the proposal of this is dropped,
+/// along with the votes of this node on it (fed back from the proposal by vetomint).
+/// It is used only at the boundary with vetomint; see `ProposalRef`.
+const NIL_BLOCK_CANDIDATE: BlockIdentifier = BlockIdentifier::MAX;
+
+/// The block candidate of this node, which vetomint takes as a `BlockIdentifier`.
+///
+/// It is converted to and from `NIL_BLOCK_CANDIDATE` only where it crosses vetomint,
+/// so the sentinel is never taken as the identifier of a verified block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProposalRef {
+    /// The verified block of the identifier.
+    Candidate(usize),
+    /// No candidate, so this node proposes nothing (unless there is a valid value).
+    None,
+}
+
+impl ProposalRef {
+    fn to_vetomint(self) -> BlockIdentifier {
         match self {
-            ConsensusMessage::NonNilPreCommitted(round, block_hash) => proof.signature.verify(
-                FinalizationSignTarget {
-                    block_hash: *block_hash,
-                    round: *round,
-                }
-                .to_hash256(),
-                &proof.committer,
-            ),
-            _ => proof.signature.verify(
-                self.to_hash256().aggregate(&dms_key.to_hash256()),
-                &proof.committer,
-            ),
+            ProposalRef::Candidate(index) => index,
+            ProposalRef::None => NIL_BLOCK_CANDIDATE,
+        }
+    }
+
+    fn from_vetomint(proposal: BlockIdentifier) -> Self {
+        if proposal == NIL_BLOCK_CANDIDATE {
+            ProposalRef::None
+        } else {
+            ProposalRef::Candidate(proposal)
         }
     }
 }
 
+/// A message that can't be processed yet, with its author and the received time.
+pub(crate) type PendingMessage = (ConsensusMessage, PublicKey, Timestamp);
+
+/// The messages that can't be processed yet, indexed by the block hash that they are on,
+/// so that only the ones on the verified blocks are retried.
+///
+/// It is stored as the list of the messages, the earliest received first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<PendingMessage>", into = "Vec<PendingMessage>")]
+pub(crate) struct PendingMessages {
+    by_block_hash: HashMap<Hash256, Vec<PendingMessage>>,
+    /// The hashes of the pending messages with their authors, which dedups them.
+    keys: HashSet<(Hash256, PublicKey)>,
+}
+
+impl PendingMessages {
+    /// Adds the message unless it is already pending.
+    ///
+    /// The messages on no block (i.e., the nil votes) are always processable,
+    /// so they are never pending and ignored here.
+    fn insert(&mut self, message: PendingMessage) {
+        let block_hash = match message.0.block_hash() {
+            Some(block_hash) => block_hash,
+            None => return,
+        };
+        if self
+            .keys
+            .insert((message.0.to_hash256(), message.1.clone()))
+        {
+            self.by_block_hash
+                .entry(block_hash)
+                .or_default()
+                .push(message);
+        }
+    }
+
+    /// Removes and returns the messages on the block.
+    fn take(&mut self, block_hash: &Hash256) -> Vec<PendingMessage> {
+        let messages = self.by_block_hash.remove(block_hash).unwrap_or_default();
+        for (message, author, _) in &messages {
+            self.keys.remove(&(message.to_hash256(), author.clone()));
+        }
+        messages
+    }
+
+    fn block_hashes(&self) -> impl Iterator<Item = &Hash256> {
+        self.by_block_hash.keys()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl From<Vec<PendingMessage>> for PendingMessages {
+    fn from(messages: Vec<PendingMessage>) -> Self {
+        let mut pending_messages = PendingMessages::default();
+        for message in messages {
+            pending_messages.insert(message);
+        }
+        pending_messages
+    }
+}
+
+impl From<PendingMessages> for Vec<PendingMessage> {
+    fn from(pending_messages: PendingMessages) -> Self {
+        let mut messages = pending_messages
+            .by_block_hash
+            .into_values()
+            .flatten()
+            .collect::<Vec<_>>();
+        messages.sort_by_cached_key(|(message, author, timestamp)| {
+            (*timestamp, message.to_hash256(), author.clone())
+        });
+        messages
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     /// The vetomint state machine.
     vetomint: Vetomint,
     /// The block header that this consensus is performing on.
     block_header: BlockHeader,
-    /// An increasing counter for assigning block identifiers.
-    block_identifier_count: BlockIdentifier,
-    /// The list of the block hashes that have been verified.
+    /// The consensus keys delegated by the validators, carried over the heights.
+    delegations: Delegations,
+    /// The information of the validators, which is only for the reports.
+    validator_info: BTreeMap<PublicKey, ValidatorInfo>,
+    /// The block hashes that have been verified, indexed by their block identifiers.
+    block_hashes: Vec<Hash256>,
+    /// The block identifiers of the verified block hashes, which is the inverse of `block_hashes`.
     verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
     /// The set of hashes of the block that are valid but vetoed by the user.
     vetoed_block_hashes: BTreeSet<Hash256>,
+    /// The rounds that have been vetoed by the user.
+    vetoed_rounds: BTreeSet<ConsensusRound>,
+    /// The queue of the proposal candidates with their priorities, the highest first.
+    proposal_candidates: Vec<(Hash256, u64)>,
+    /// The proposal candidate that vetomint has been informed of.
+    proposal_candidate: Option<Hash256>,
     /// The list of the events that are to be processed.
     to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
     /// The set of messages that have been already updated to the Vetomint state machine.
+    ///
+    /// It is pruned by `prune_updated_events()` so that it doesn't grow over the rounds.
     updated_events: BTreeSet<ConsensusEvent>,
     /// Messages by this node, which are to be broadcasted.
+    ///
+    /// This works as an outbox; a message stays here until it is committed to the DMS.
     messages_to_broadcast: Vec<ConsensusMessage>,
-    /// Precommits collected so far, for each `(block, round)`.
-    precommits: BTreeMap<(Hash256, ConsensusRound), Vec<TypedSignature<FinalizationSignTarget>>>,
+    /// The rounds in which this node has prevoted.
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    /// The messages that can't be processed yet, with their authors and the received time:
+    /// the proposals whose validity is not known yet, and the messages on unverified blocks.
+    pending_messages: PendingMessages,
+    /// The cursor of the DMS, up to which the messages have been added.
+    dms_cursor: u64,
+    /// The first message received from each validator for each round and kind.
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    /// The equivocations detected so far.
+    equivocations: Vec<Evidence>,
+    /// The number of the equivocations that have been reported by `progress()`.
+    reported_equivocations: usize,
     /// If `Some`, any operation on the consensus module will fail;
     /// the user must run `new()` with the next height info.
     finalized: Option<Finalization>,
@@ -118,24 +438,40 @@ impl State {
         block_header: &BlockHeader,
         consensus_parameters: ConsensusParams,
         round_zero_timestamp: Timestamp,
-        this_node_key: PrivateKey,
-    ) -> Result<State, Error> {
+        this_node_public_key: Option<PublicKey>,
+    ) -> Result<State, ConsensusError> {
+        // The timeout never decreases over the rounds since the increment is unsigned.
+        if consensus_parameters.timeout_ms == 0 {
+            return Err(ConsensusError::InvalidOperation(
+                "the timeout must be nonzero".to_string(),
+            ));
+        }
         let height_info = generate_height_info(
             block_header,
             consensus_parameters,
             round_zero_timestamp,
-            this_node_key,
+            this_node_public_key,
         )?;
         let state = State {
             vetomint: Vetomint::new(height_info),
             block_header: block_header.clone(),
-            block_identifier_count: 0,
+            delegations: Delegations::default(),
+            validator_info: BTreeMap::new(),
+            block_hashes: Vec::new(),
             to_be_processed_events: vec![(ConsensusEvent::Start, round_zero_timestamp)],
             updated_events: BTreeSet::new(),
             verified_block_hashes: BTreeMap::new(),
             vetoed_block_hashes: BTreeSet::new(),
+            vetoed_rounds: BTreeSet::new(),
+            proposal_candidates: Vec::new(),
+            proposal_candidate: None,
             messages_to_broadcast: Vec::new(),
-            precommits: BTreeMap::new(),
+            prevoted_rounds: BTreeSet::new(),
+            pending_messages: PendingMessages::default(),
+            dms_cursor: 0,
+            signed_votes: BTreeMap::new(),
+            equivocations: Vec::new(),
+            reported_equivocations: 0,
             finalized: None,
         };
         Ok(state)
@@ -149,119 +485,770 @@ impl State {
         &self.block_header
     }
 
-    pub fn register_verified_block_hash(&mut self, block_hash: Hash256) {
-        self.assert_not_finalized();
+    pub fn delegations(&self) -> &Delegations {
+        &self.delegations
+    }
+
+    /// Adds the delegation of a validator, after which the messages signed by the delegated key
+    /// count as the ones of the validator in the heights it covers.
+    pub fn add_delegation(&mut self, delegation: SignedDelegation) -> Result<(), ConsensusError> {
+        let validator_set = &self.block_header.validator_set;
+        let Delegation {
+            identity,
+            consensus_key,
+            ..
+        } = &delegation.delegation;
+        if !validator_set.iter().any(|(x, _)| x == identity) {
+            return Err(ConsensusError::NotAValidator(identity.clone()));
+        }
+        if validator_set.iter().any(|(x, _)| x == consensus_key) {
+            return Err(ConsensusError::InvalidProof(format!(
+                "delegation: the consensus key {consensus_key} is of a validator, so it can't be delegated"
+            )));
+        }
+        self.delegations.add(delegation)
+    }
+
+    /// Returns the information of the validator that the key is of or has been delegated by,
+    /// which is empty if not given.
+    pub fn validator_info(&self, public_key: &PublicKey) -> ValidatorInfo {
+        self.validator_info
+            .get(public_key)
+            .or_else(|| {
+                self.delegations
+                    .resolve(public_key, self.height())
+                    .and_then(|identity| self.validator_info.get(&identity))
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replaces the information of the validators, all of which must be in the validator set.
+    pub fn set_validator_info(
+        &mut self,
+        validator_info: BTreeMap<PublicKey, ValidatorInfo>,
+    ) -> Result<(), ConsensusError> {
+        if let Some(key) = validator_info.keys().find(|key| {
+            !self
+                .block_header
+                .validator_set
+                .iter()
+                .any(|(x, _)| x == *key)
+        }) {
+            return Err(ConsensusError::NotAValidator(key.clone()));
+        }
+        self.validator_info = validator_info;
+        Ok(())
+    }
+
+    /// Appends the name of the violator to the description of the violation, if it has one.
+    fn describe_violation(&self, violator: &PublicKey, description: String) -> String {
+        let name = self.validator_info(violator).name;
+        if name.is_empty() {
+            description
+        } else {
+            format!("{description} (by {name})")
+        }
+    }
+
+    /// Replaces the delegations with the ones carried over from the previous height.
+    pub(crate) fn set_delegations(&mut self, delegations: Delegations) {
+        self.delegations = delegations;
+    }
+
+    /// Returns the height of the block that this consensus is performing on.
+    pub fn height(&self) -> BlockHeight {
+        self.block_header.height + 1
+    }
+
+    pub fn quorum(&self) -> Quorum {
+        self.vetomint.get_height_info().consensus_params.quorum()
+    }
+
+    /// Returns the current round.
+    pub fn round(&self) -> ConsensusRound {
+        from_vetomint_round(self.vetomint.get_round())
+    }
+
+    /// Returns the timeout of the steps in the round, as decided by vetomint.
+    pub fn round_timeout(&self, round: ConsensusRound) -> Timestamp {
+        vetomint::decide_timeout(
+            &self.vetomint.get_height_info().consensus_params,
+            // The timeout saturates long before the round does.
+            to_vetomint_round(round).unwrap_or(Round::MAX),
+        )
+    }
+
+    /// Returns the index of the proposer of the round in the validator set,
+    /// as decided by vetomint.
+    pub fn proposer_index(&self, round: ConsensusRound) -> Result<usize, ConsensusError> {
+        Ok(vetomint::decide_proposer(
+            to_vetomint_round(round)?,
+            self.vetomint.get_height_info(),
+        ))
+    }
+
+    pub fn verified_block_hashes(&self) -> &BTreeMap<Hash256, BlockIdentifier> {
+        &self.verified_block_hashes
+    }
+
+    pub fn status(&self) -> ConsensusStatus {
+        ConsensusStatus {
+            height: self.height(),
+            round: self.round(),
+            step: self.vetomint.get_step(),
+            timeout: self.vetomint.get_timeout(),
+            locked: self.vetomint.get_locked_value().and_then(|(index, round)| {
+                self.get_block_hash(index)
+                    .map(|hash| (hash, from_vetomint_round(round)))
+            }),
+            valid: self.vetomint.get_valid_value().and_then(|(index, round)| {
+                self.get_block_hash(index)
+                    .map(|hash| (hash, from_vetomint_round(round)))
+            }),
+            verified_block_hashes: self.block_hashes.clone(),
+            vetoed_block_hashes: self.vetoed_block_hashes.iter().cloned().collect(),
+            vetoed_rounds: self.vetoed_rounds.iter().cloned().collect(),
+            proposal_candidates: self.proposal_candidates.clone(),
+            this_node_index: self.vetomint.get_height_info().this_node_index,
+            finalized: self.finalized.is_some(),
+            // Filled by `Consensus`, which holds them.
+            quarantined_messages: 0,
+            deliveries: Vec::new(),
+            banned_peers: Vec::new(),
+            validator_names: self
+                .block_header
+                .validator_set
+                .iter()
+                .map(|(validator, _)| self.validator_info(validator).name)
+                .collect(),
+        }
+    }
+
+    /// Registers the block hash, assigning the next block identifier to it.
+    ///
+    /// Registering the same hash again does nothing, so the identifiers never change.
+    pub fn register_verified_block_hash(
+        &mut self,
+        block_hash: Hash256,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
         if self.verified_block_hashes.contains_key(&block_hash) {
-            return;
+            return Ok(());
+        }
+        if self.block_hashes.len() == NIL_BLOCK_CANDIDATE {
+            return Err(ConsensusError::InvalidOperation(format!(
+                "no block identifier is left for the block {block_hash}"
+            )));
         }
         self.verified_block_hashes
-            .insert(block_hash, self.block_identifier_count);
-        self.block_identifier_count += 1;
+            .insert(block_hash, self.block_hashes.len());
+        self.block_hashes.push(block_hash);
+        Ok(())
     }
 
     pub fn set_proposal_candidate(
         &mut self,
         block_hash: Hash256,
         timestamp: Timestamp,
-    ) -> Result<(), Error> {
-        self.assert_not_finalized();
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
         let block_index = self.get_block_index(&block_hash)?;
+        self.proposal_candidates.clear();
+        self.proposal_candidate = Some(block_hash);
+        self.inform_proposal_candidate(ProposalRef::Candidate(block_index), timestamp);
+        Ok(())
+    }
+
+    /// Adds the block to the queue of the proposal candidates, or updates its priority.
+    ///
+    /// The candidate of the highest priority that is not vetoed (the earliest one among the same
+    /// priority) is proposed, and vetomint is informed only when it changes.
+    pub fn push_proposal_candidate(
+        &mut self,
+        block_hash: Hash256,
+        priority: u64,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        self.get_block_index(&block_hash)?;
+        self.proposal_candidates
+            .retain(|(candidate, _)| *candidate != block_hash);
+        let position = self
+            .proposal_candidates
+            .iter()
+            .position(|(_, p)| *p < priority)
+            .unwrap_or(self.proposal_candidates.len());
+        self.proposal_candidates
+            .insert(position, (block_hash, priority));
+        self.update_proposal_candidate(timestamp)
+    }
+
+    /// Informs vetomint of the top of the candidate queue if it has changed.
+    fn update_proposal_candidate(&mut self, timestamp: Timestamp) -> Result<(), ConsensusError> {
+        if self.proposal_candidates.is_empty() {
+            return Ok(());
+        }
+        let top = self
+            .proposal_candidates
+            .iter()
+            .map(|(block_hash, _)| *block_hash)
+            .find(|block_hash| !self.vetoed_block_hashes.contains(block_hash));
+        if top == self.proposal_candidate {
+            return Ok(());
+        }
+        let proposal = match top {
+            Some(block_hash) => ProposalRef::Candidate(self.get_block_index(&block_hash)?),
+            None => ProposalRef::None,
+        };
+        self.proposal_candidate = top;
+        self.inform_proposal_candidate(proposal, timestamp);
+        Ok(())
+    }
+
+    /// Queues the update of the block candidate for vetomint,
+    /// replacing the one not processed yet since the events are processed in the reverse order.
+    fn inform_proposal_candidate(&mut self, proposal: ProposalRef, timestamp: Timestamp) {
+        self.to_be_processed_events
+            .retain(|(event, _)| !matches!(event, ConsensusEvent::BlockCandidateUpdated { .. }));
         let consensus_event = ConsensusEvent::BlockCandidateUpdated {
-            proposal: block_index,
+            proposal: proposal.to_vetomint(),
         };
         self.to_be_processed_events
             .push((consensus_event, timestamp));
+    }
+
+    /// Withdraws the proposal candidate, so that this node proposes nothing
+    /// and lets the round time out when it becomes the proposer.
+    ///
+    /// A block that has gathered the prevotes of more than 2/3 (the valid value)
+    /// is still proposed again, as the protocol requires.
+    pub fn clear_proposal_candidate(&mut self, timestamp: Timestamp) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        self.proposal_candidates.clear();
+        self.proposal_candidate = None;
+        self.inform_proposal_candidate(ProposalRef::None, timestamp);
         Ok(())
     }
 
-    pub fn veto_block(&mut self, block_hash: Hash256) {
-        self.assert_not_finalized();
+    /// Vetoes the block, re-evaluating the proposals of it that have been already received.
+    pub fn veto_block(
+        &mut self,
+        block_hash: Hash256,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
         self.vetoed_block_hashes.insert(block_hash);
+        self.reevaluate_proposals(&block_hash, timestamp);
+        self.update_proposal_candidate(timestamp)
+    }
+
+    /// Withdraws the veto on the block.
+    ///
+    /// It fails if this node has already prevoted in a round where the block was proposed,
+    /// because the veto has been already reflected in the vote.
+    pub fn unveto_block(
+        &mut self,
+        block_hash: Hash256,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        if !self.vetoed_block_hashes.contains(&block_hash) {
+            return Err(ConsensusError::InvalidOperation(format!(
+                "block {block_hash} is not vetoed"
+            )));
+        }
+        if let Ok(index) = self.get_block_index(&block_hash) {
+            for event in &self.updated_events {
+                if let ConsensusEvent::BlockProposalReceived {
+                    proposal, round, ..
+                } = event
+                {
+                    if *proposal == index
+                        && self.prevoted_rounds.contains(&from_vetomint_round(*round))
+                    {
+                        return Err(ConsensusError::InvalidOperation(format!(
+                            "already prevoted in round {round} where block {block_hash} was proposed"
+                        )));
+                    }
+                }
+            }
+        }
+        self.vetoed_block_hashes.remove(&block_hash);
+        self.reevaluate_proposals(&block_hash, timestamp);
+        self.update_proposal_candidate(timestamp)
     }
 
-    pub fn veto_round(&mut self, round: ConsensusRound, timestamp: Timestamp) {
-        self.assert_not_finalized();
+    /// Vetoes the round so that this node skips it.
+    ///
+    /// Vetoing the same round again does nothing, and a completed round can't be vetoed.
+    pub fn veto_round(
+        &mut self,
+        round: ConsensusRound,
+        timestamp: Timestamp,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        if self.vetoed_rounds.contains(&round) {
+            return Ok(());
+        }
+        let current_round = self.round();
+        if round < current_round {
+            return Err(ConsensusError::InvalidOperation(format!(
+                "round {round} is already completed (the current round is {current_round})"
+            )));
+        }
         let consensus_event = ConsensusEvent::SkipRound {
-            round: round as usize,
+            round: to_vetomint_round(round)?,
         };
+        self.vetoed_rounds.insert(round);
         self.to_be_processed_events
             .push((consensus_event, timestamp));
+        Ok(())
     }
 
+    /// Adds the messages to be processed.
+    ///
+    /// They are processed in the order of `canonical_key()` regardless of the given order,
+    /// so that the result doesn't depend on the order of the arrival.
+    ///
+    /// The messages on unverified blocks and the proposals whose validity is not known
+    /// by `validity` yet are kept pending until `retry_pending_messages()` resolves them.
     pub fn add_consensus_messages(
         &mut self,
-        messages: Vec<(ConsensusMessage, PublicKey, Signature)>,
+        mut messages: Vec<(ConsensusMessage, PublicKey)>,
         timestamp: Timestamp,
-    ) {
-        self.assert_not_finalized();
-        for (message, author, signature) in messages {
+        validity: &dyn Fn(&Hash256) -> Option<bool>,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        messages.sort_by_cached_key(|(message, author)| self.canonical_key(message, author));
+        let mut events = Vec::new();
+        for (message, author) in messages {
+            if message.height() != self.height() {
+                continue;
+            }
             if !self.is_consensus_message_acceptable(&message) {
+                self.add_pending_message(message, author, timestamp);
                 continue;
             }
-            let event = self.convert_consensus_message_to_event(
-                &message,
-                self.get_validator_index(&author)
-                    .expect("dms signer must be one of the validators"),
-            );
+            let valid = if let ConsensusMessage::Proposal { block_hash, .. } = &message {
+                if let Some(valid) = validity(block_hash) {
+                    valid
+                } else {
+                    self.add_pending_message(message, author, timestamp);
+                    continue;
+                }
+            } else {
+                true
+            };
+            // The message could have been admitted to the DMS under the different conditions,
+            // so it is skipped rather than trusted if it can't be converted.
+            let event = match self
+                .get_validator_index(&author)
+                .and_then(|signer| self.convert_consensus_message_to_event(&message, signer, valid))
+            {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!(
+                        consensus_message = ?message,
+                        %author,
+                        error = %e,
+                        "skipping an unconvertible consensus message"
+                    );
+                    continue;
+                }
+            };
             if self.updated_events.contains(&event) {
                 continue;
             }
-            self.to_be_processed_events.push((event, timestamp));
-            if let ConsensusMessage::NonNilPreCommitted(round, block_hash) = message {
-                self.precommits
-                    .entry((block_hash, round))
-                    .and_modify(|v| v.push(TypedSignature::new(signature.clone(), author.clone())))
-                    .or_insert(vec![TypedSignature::new(signature, author)]);
+            events.push((event, timestamp));
+        }
+        // The events are processed from the last one.
+        self.to_be_processed_events.extend(events.into_iter().rev());
+        Ok(())
+    }
+
+    /// Returns the key of the canonical order of the messages:
+    /// by the round, the kind (the proposal first), the index of the author and the hash.
+    pub(crate) fn canonical_key(
+        &self,
+        message: &ConsensusMessage,
+        author: &PublicKey,
+    ) -> (ConsensusRound, VoteKind, usize, Hash256) {
+        let (round, kind) = message.vote_key();
+        let author_index = self.get_validator_index(author).unwrap_or(usize::MAX);
+        (round, kind, author_index, message.to_hash256())
+    }
+
+    /// Adds the pending messages that have become processable.
+    ///
+    /// Only the messages on the verified blocks are retried (i.e., the ones on the blocks
+    /// registered since, and the proposals whose validity was not known),
+    /// so the ones on the blocks still unverified cost nothing here.
+    pub fn retry_pending_messages(
+        &mut self,
+        validity: &dyn Fn(&Hash256) -> Option<bool>,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        let mut block_hashes = self
+            .pending_messages
+            .block_hashes()
+            .filter(|block_hash| self.verified_block_hashes.contains_key(block_hash))
+            .copied()
+            .collect::<Vec<_>>();
+        // Not in the order of the `HashMap`, so that the result is deterministic.
+        block_hashes.sort();
+        for block_hash in block_hashes {
+            for (message, author, timestamp) in self.pending_messages.take(&block_hash) {
+                self.add_consensus_messages(vec![(message, author)], timestamp, validity)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn dms_cursor(&self) -> u64 {
+        self.dms_cursor
+    }
+
+    pub fn set_dms_cursor(&mut self, cursor: u64) {
+        self.dms_cursor = cursor;
+    }
+
+    /// Returns whether the message of the signer has been consumed by `detect_equivocations()`.
+    pub fn is_consumed(&self, message: &ConsensusMessage, signer: &PublicKey) -> bool {
+        let (round, kind) = message.vote_key();
+        self.signed_votes
+            .get(&(signer.clone(), round, kind))
+            .is_some_and(|(first, _)| first == message)
+    }
+
+    /// Returns the first message consumed from each validator for each round and kind,
+    /// with their commitments grouped by the message as in the DMS.
+    pub fn consumed_messages(&self) -> Vec<dms::Message<ConsensusMessage>> {
+        let mut messages = BTreeMap::<Hash256, dms::Message<ConsensusMessage>>::new();
+        for (message, commitment) in self.signed_votes.values() {
+            messages
+                .entry(message.to_hash256())
+                .or_insert_with(|| dms::Message {
+                    message: message.clone(),
+                    committers: Vec::new(),
+                })
+                .committers
+                .push(commitment.clone());
+        }
+        messages.into_values().collect()
+    }
+
+    /// Checks the signed messages against the ones received before,
+    /// recording the conflicting pairs as equivocations to be reported in the next `progress()`.
+    ///
+    /// Returns the newly detected ones.
+    pub fn detect_equivocations(
+        &mut self,
+        messages: &[(ConsensusMessage, MessageCommitmentProof)],
+        dms_key: &DmsKey,
+    ) -> Vec<Evidence> {
+        let mut result = Vec::new();
+        for (message, commitment) in messages {
+            let (round, kind) = message.vote_key();
+            let first = self
+                .signed_votes
+                .entry((commitment.committer.clone(), round, kind))
+                .or_insert_with(|| (message.clone(), commitment.clone()));
+            if first.0 == *message {
+                continue;
+            }
+            let evidence = Evidence {
+                offender: commitment.committer.clone(),
+                height: message.height(),
+                round,
+                dms_key: dms_key.clone(),
+                first: first.clone(),
+                second: (message.clone(), commitment.clone()),
+            };
+            if !self.equivocations.contains(&evidence) {
+                self.equivocations.push(evidence.clone());
+                result.push(evidence);
             }
         }
+        result
     }
 
-    pub fn progress(&mut self, timestamp: Timestamp) -> Vec<ProgressResult> {
-        self.assert_not_finalized();
+    pub fn progress(
+        &mut self,
+        timestamp: Timestamp,
+    ) -> Result<Vec<ProgressResult>, ConsensusError> {
+        self.check_not_finalized()?;
         let mut result = Vec::new();
+        for evidence in &self.equivocations[self.reported_equivocations..] {
+            let (round, kind) = evidence.first.0.vote_key();
+            let evidence_hashes = vec![
+                evidence.first.0.to_hash256(),
+                evidence.second.0.to_hash256(),
+            ];
+            let description = format!(
+                "equivocation of {kind:?} in round {round}: {} and {}",
+                evidence_hashes[0], evidence_hashes[1]
+            );
+            result.push(ProgressResult::ViolationReported(
+                Violation {
+                    violator: evidence.offender.clone(),
+                    round,
+                    kind: ViolationKind::equivocation(kind),
+                    evidence_hashes,
+                    description: self.describe_violation(&evidence.offender, description),
+                },
+                timestamp,
+            ));
+        }
+        self.reported_equivocations = self.equivocations.len();
         self.to_be_processed_events
             .push((ConsensusEvent::Timer, timestamp));
+        let is_observer = self.vetomint.get_height_info().this_node_index.is_none();
         while let Some((event, timestamp)) = self.to_be_processed_events.pop() {
+            if !self.updated_events.contains(&event) {
+                result.extend(self.observed_vote(&event, timestamp));
+            }
+            let round = self.vetomint.get_round();
             let responses = self.vetomint.progress(event.clone(), timestamp);
+            if self.vetomint.get_round() > round {
+                let reason = if self.vetoed_rounds.contains(&from_vetomint_round(round)) {
+                    RoundSkipReason::Vetoed
+                } else if matches!(event, ConsensusEvent::Timer) {
+                    RoundSkipReason::Timeout
+                } else {
+                    RoundSkipReason::NilQuorum
+                };
+                result.push(ProgressResult::RoundAdvanced {
+                    new_round: self.round(),
+                    reason,
+                    timestamp,
+                });
+            }
             self.updated_events.insert(event);
             for response in responses {
-                let (x, message) =
-                    self.process_consensus_response_to_progress_result(response, timestamp);
+                // An observer follows the consensus without broadcasting anything.
+                if is_observer
+                    && matches!(
+                        response,
+                        ConsensusResponse::BroadcastProposal { .. }
+                            | ConsensusResponse::BroadcastPrevote { .. }
+                            | ConsensusResponse::BroadcastPrecommit { .. }
+                    )
+                {
+                    continue;
+                }
+                if let ConsensusResponse::BroadcastProposal {
+                    proposal, round, ..
+                } = response
+                {
+                    if ProposalRef::from_vetomint(proposal) == ProposalRef::None {
+                        tracing::info!(round, "proposing nothing without a proposal candidate");
+                        continue;
+                    }
+                }
+                if let ConsensusResponse::BroadcastPrevote {
+                    proposal: Some(proposal),
+                    ..
+                }
+                | ConsensusResponse::BroadcastPrecommit {
+                    proposal: Some(proposal),
+                    ..
+                } = response
+                {
+                    if ProposalRef::from_vetomint(proposal) == ProposalRef::None {
+                        continue;
+                    }
+                }
+                // Vetomint finalizes the block again on every precommit after the quorum.
+                if matches!(response, ConsensusResponse::FinalizeBlock { .. })
+                    && self.finalized.is_some()
+                {
+                    continue;
+                }
+                if let ConsensusResponse::BroadcastPrevote { round, .. } = response {
+                    self.prevoted_rounds.insert(from_vetomint_round(round));
+                }
+                let (x, message) = match self
+                    .process_consensus_response_to_progress_result(response.clone(), timestamp)
+                {
+                    Ok(x) => x,
+                    Err(e) => {
+                        tracing::error!(
+                            ?response,
+                            error = %e,
+                            "failed to process the consensus response"
+                        );
+                        continue;
+                    }
+                };
                 result.push(x);
                 if let Some(message) = message {
                     self.messages_to_broadcast.push(message);
                 }
             }
         }
-        result
+        Ok(result)
     }
 
-    pub fn drain_messages_to_broadcast(&mut self) -> Vec<ConsensusMessage> {
-        self.assert_not_finalized();
-        std::mem::take(&mut self.messages_to_broadcast)
+    /// Removes the processed events that are no longer needed, keeping at most `max_retained`.
+    ///
+    /// The events of the passed rounds are removed first; they can't affect the votes
+    /// of this node any more, and the state machine keeps what it needs by itself.
+    /// If there are still too many, the ones of the furthest rounds are removed.
+    /// Everything is removed once finalized.
+    pub fn prune_updated_events(&mut self, max_retained: usize) {
+        if self.finalized.is_some() {
+            self.updated_events.clear();
+            return;
+        }
+        let current_round = self.vetomint.get_round();
+        self.updated_events
+            .retain(|event| event_round(event).is_some_and(|round| round >= current_round));
+        if self.updated_events.len() > max_retained {
+            let mut events = std::mem::take(&mut self.updated_events)
+                .into_iter()
+                .collect::<Vec<_>>();
+            events.sort_by_key(event_round);
+            events.truncate(max_retained);
+            self.updated_events = events.into_iter().collect();
+        }
+    }
+
+    /// Returns the messages that have not been committed to the DMS yet.
+    ///
+    /// Note that this is allowed for the finalized state,
+    /// because the precommit of this node could be the one that finalized the block.
+    pub fn messages_to_broadcast(&self) -> &[ConsensusMessage] {
+        &self.messages_to_broadcast
+    }
+
+    /// Removes the message from the outbox once it has been committed to the DMS.
+    pub fn mark_message_sent(&mut self, message: &ConsensusMessage) {
+        self.messages_to_broadcast.retain(|x| x != message);
+    }
+
+    pub fn set_finalization_proof(
+        &mut self,
+        proof: FinalizationProof,
+    ) -> Result<(), ConsensusError> {
+        let finalization = self
+            .finalized
+            .as_mut()
+            .ok_or(ConsensusError::NotFinalized)?;
+        if finalization.proof.round != proof.round {
+            return Err(ConsensusError::InvalidProof(format!(
+                "finalization proof: the round of the proof ({}) does not match the finalized round ({})",
+                proof.round,
+                finalization.proof.round
+            )));
+        }
+        finalization.proof = proof;
+        Ok(())
+    }
+
+    /// Finalizes the state with the finalization proven by the peers,
+    /// which must have been verified.
+    pub fn set_external_finalization(
+        &mut self,
+        finalization: Finalization,
+    ) -> Result<(), ConsensusError> {
+        self.check_not_finalized()?;
+        self.finalized = Some(finalization);
+        Ok(())
     }
 }
 
 impl State {
-    fn assert_not_finalized(&self) {
+    fn check_not_finalized(&self) -> Result<(), ConsensusError> {
         if self.finalized.is_some() {
-            panic!("mutable operations on finalized state");
+            return Err(ConsensusError::Finalized);
         }
+        Ok(())
     }
 
-    fn get_block_index(&self, block_hash: &Hash256) -> Result<usize, Error> {
+    fn get_block_index(&self, block_hash: &Hash256) -> Result<usize, ConsensusError> {
         self.verified_block_hashes
             .get(block_hash)
-            .ok_or_else(|| eyre!("block not verified yet"))
             .cloned()
+            .ok_or(ConsensusError::BlockNotVerified(*block_hash))
     }
 
-    fn get_validator_index(&self, public_key: &PublicKey) -> Result<usize, Error> {
-        self.block_header
-            .validator_set
-            .iter()
-            .position(|(x, _)| x == public_key)
-            .ok_or_else(|| eyre!("validator not found"))
+    fn get_block_hash(&self, index: BlockIdentifier) -> Option<Hash256> {
+        self.block_hashes.get(index).cloned()
+    }
+
+    /// Updates `favor` of the proposals of the block according to the current veto status.
+    ///
+    /// The pending proposals are updated in place, and the already processed ones
+    /// are fed to the state machine again so that it can reconsider them.
+    fn reevaluate_proposals(&mut self, block_hash: &Hash256, timestamp: Timestamp) {
+        let index = if let Ok(index) = self.get_block_index(block_hash) {
+            index
+        } else {
+            // No proposal of an unverified block could have been accepted.
+            return;
+        };
+        let new_favor = !self.vetoed_block_hashes.contains(block_hash);
+        for (event, _) in self.to_be_processed_events.iter_mut() {
+            if let ConsensusEvent::BlockProposalReceived {
+                proposal, favor, ..
+            } = event
+            {
+                if *proposal == index {
+                    *favor = new_favor;
+                }
+            }
+        }
+        let mut events = Vec::new();
+        for event in &self.updated_events {
+            if let ConsensusEvent::BlockProposalReceived {
+                proposal,
+                valid,
+                valid_round,
+                proposer,
+                round,
+                favor,
+            } = event
+            {
+                if *proposal != index || *favor == new_favor {
+                    continue;
+                }
+                let event = ConsensusEvent::BlockProposalReceived {
+                    proposal: *proposal,
+                    valid: *valid,
+                    valid_round: *valid_round,
+                    proposer: *proposer,
+                    round: *round,
+                    favor: new_favor,
+                };
+                if !self.to_be_processed_events.iter().any(|(x, _)| *x == event) {
+                    events.push((event, timestamp));
+                }
+            }
+        }
+        self.to_be_processed_events.extend(events);
+    }
+
+    /// Returns the index of the validator that the key signs for, resolving the delegations.
+    fn get_validator_index(&self, public_key: &PublicKey) -> Result<usize, ConsensusError> {
+        self.delegations
+            .resolve(public_key, self.height())
+            .and_then(|identity| {
+                self.block_header
+                    .validator_set
+                    .iter()
+                    .position(|(x, _)| *x == identity)
+            })
+            .ok_or_else(|| ConsensusError::NotAValidator(public_key.clone()))
+    }
+
+    fn add_pending_message(
+        &mut self,
+        message: ConsensusMessage,
+        author: PublicKey,
+        timestamp: Timestamp,
+    ) {
+        self.pending_messages.insert((message, author, timestamp));
     }
 
     /// Checks if the given message is assoicated with a verified block.
@@ -271,69 +1258,122 @@ impl State {
             ConsensusMessage::Proposal { block_hash, .. } => {
                 self.verified_block_hashes.contains_key(block_hash)
             }
-            ConsensusMessage::NonNilPreVoted(_, block_hash) => {
+            ConsensusMessage::NonNilPreVoted(_, _, block_hash) => {
                 self.verified_block_hashes.contains_key(block_hash)
             }
-            ConsensusMessage::NonNilPreCommitted(_, block_hash) => {
+            ConsensusMessage::NonNilPreCommitted(_, _, block_hash) => {
                 self.verified_block_hashes.contains_key(block_hash)
             }
             _ => true,
         }
     }
 
+    /// Returns the result reporting the event if it is a vote of another validator.
+    fn observed_vote(
+        &self,
+        event: &ConsensusEvent,
+        timestamp: Timestamp,
+    ) -> Option<ProgressResult> {
+        let (proposal, signer, round, kind) = match *event {
+            ConsensusEvent::Prevote {
+                proposal,
+                signer,
+                round,
+            } => (proposal, signer, round, VoteKind::Prevote),
+            ConsensusEvent::Precommit {
+                proposal,
+                signer,
+                round,
+            } => (proposal, signer, round, VoteKind::Precommit),
+            _ => return None,
+        };
+        if Some(signer) == self.vetomint.get_height_info().this_node_index {
+            return None;
+        }
+        let signer = self.block_header.validator_set.get(signer)?.0.clone();
+        let block_hash = match proposal {
+            Some(index) => Some(self.get_block_hash(index)?),
+            None => None,
+        };
+        Some(ProgressResult::VoteObserved {
+            signer,
+            round: from_vetomint_round(round),
+            block_hash,
+            kind,
+            timestamp,
+        })
+    }
+
     fn process_consensus_response_to_progress_result(
         &mut self,
         response: ConsensusResponse,
         timestamp: Timestamp,
-    ) -> (ProgressResult, Option<ConsensusMessage>) {
-        fn get_block_hash(state: &State, index: BlockIdentifier) -> Hash256 {
-            *state
-                .verified_block_hashes
-                .iter()
-                .find(|(_, &v)| v == index)
-                .map(|(k, _)| k)
-                .expect("the block is not in verified_block_hashes")
+    ) -> Result<(ProgressResult, Option<ConsensusMessage>), ConsensusError> {
+        fn get_block_hash(
+            state: &State,
+            index: BlockIdentifier,
+        ) -> Result<Hash256, ConsensusError> {
+            state.get_block_hash(index).ok_or_else(|| {
+                ConsensusError::Internal(format!(
+                    "the block {index} is not in verified_block_hashes"
+                ))
+            })
         }
-        match response {
+        let result = match response {
             ConsensusResponse::BroadcastProposal {
                 proposal,
                 valid_round,
                 round,
             } => {
-                let block_hash = get_block_hash(self, proposal);
+                let block_hash = get_block_hash(self, proposal)?;
+                let proposer = self
+                    .vetomint
+                    .get_height_info()
+                    .this_node_index
+                    .and_then(|index| self.block_header.validator_set.get(index))
+                    .ok_or_else(|| {
+                        ConsensusError::Internal("an observer can't propose".to_string())
+                    })?
+                    .0
+                    .clone();
+                let round = from_vetomint_round(round);
+                let valid_round = valid_round.map(from_vetomint_round);
                 (
-                    ProgressResult::Proposed(round as u64, block_hash, timestamp),
+                    ProgressResult::Proposed(round, block_hash, valid_round, proposer, timestamp),
                     Some(ConsensusMessage::Proposal {
-                        round: round as u64,
-                        valid_round: valid_round.map(|r| r as u64),
+                        height: self.height(),
+                        round,
+                        valid_round,
                         block_hash,
                     }),
                 )
             }
             ConsensusResponse::BroadcastPrevote { proposal, round } => {
+                let round = from_vetomint_round(round);
                 let (consensus_message, progress_result) = if let Some(block_index) = proposal {
-                    let block_hash = get_block_hash(self, block_index);
+                    let block_hash = get_block_hash(self, block_index)?;
                     (
-                        ConsensusMessage::NonNilPreVoted(round as u64, block_hash),
-                        ProgressResult::NonNilPreVoted(round as u64, block_hash, timestamp),
+                        ConsensusMessage::NonNilPreVoted(self.height(), round, block_hash),
+                        ProgressResult::NonNilPreVoted(round, block_hash, timestamp),
                     )
                 } else {
-                    let message = ConsensusMessage::NilPreVoted(round as u64);
-                    let result = ProgressResult::NilPreVoted(round as u64, timestamp);
+                    let message = ConsensusMessage::NilPreVoted(self.height(), round);
+                    let result = ProgressResult::NilPreVoted(round, timestamp);
                     (message, result)
                 };
                 (progress_result, Some(consensus_message))
             }
             ConsensusResponse::BroadcastPrecommit { proposal, round } => {
+                let round = from_vetomint_round(round);
                 let (consensus_message, progress_result) = if let Some(block_index) = proposal {
-                    let block_hash = get_block_hash(self, block_index);
+                    let block_hash = get_block_hash(self, block_index)?;
                     (
-                        ConsensusMessage::NonNilPreCommitted(round as u64, block_hash),
-                        ProgressResult::NonNilPreCommitted(round as u64, block_hash, timestamp),
+                        ConsensusMessage::NonNilPreCommitted(self.height(), round, block_hash),
+                        ProgressResult::NonNilPreCommitted(round, block_hash, timestamp),
                     )
                 } else {
-                    let message = ConsensusMessage::NilPreCommitted(round as u64);
-                    let result = ProgressResult::NilPreCommitted(round as u64, timestamp);
+                    let message = ConsensusMessage::NilPreCommitted(self.height(), round);
+                    let result = ProgressResult::NilPreCommitted(round, timestamp);
                     (message, result)
                 };
                 (progress_result, Some(consensus_message))
@@ -341,17 +1381,17 @@ impl State {
             ConsensusResponse::FinalizeBlock {
                 proposal, round, ..
             } => {
-                let round = round as ConsensusRound;
-                let block_hash = get_block_hash(self, proposal);
-                let signatures = self
-                    .precommits
-                    .get(&(block_hash, round))
-                    .cloned()
-                    .expect("there must be valid precommits for the finalized block");
+                let round = from_vetomint_round(round);
+                let block_hash = get_block_hash(self, proposal)?;
+                // The signatures are filled by `set_finalization_proof()`,
+                // since they are kept in the DMS, not in the state.
                 let finalization = Finalization {
                     block_hash,
                     timestamp,
-                    proof: FinalizationProof { round, signatures },
+                    proof: FinalizationProof {
+                        round,
+                        signatures: Vec::new(),
+                    },
                 };
                 self.finalized = Some(finalization.clone());
                 (ProgressResult::Finalized(finalization), None)
@@ -364,79 +1404,136 @@ impl State {
                     .block_header
                     .validator_set
                     .get(violator)
-                    .expect("the violator must be in the validator set")
+                    .ok_or_else(|| {
+                        ConsensusError::Internal(format!(
+                            "the violator {violator} is not in the validator set"
+                        ))
+                    })?
                     .0
                     .clone();
+                let description = format!("{misbehavior:?}");
+                let (round, kind, blocks) = match misbehavior {
+                    Misbehavior::DoubleProposal {
+                        round, proposals, ..
+                    } => (
+                        round,
+                        ViolationKind::DoubleProposal,
+                        vec![Some(proposals.0), Some(proposals.1)],
+                    ),
+                    Misbehavior::DoublePrevote {
+                        round, proposals, ..
+                    } => (
+                        round,
+                        ViolationKind::DoublePrevote,
+                        vec![proposals.0, proposals.1],
+                    ),
+                    Misbehavior::DoublePrecommit {
+                        round, proposals, ..
+                    } => (
+                        round,
+                        ViolationKind::DoublePrecommit,
+                        vec![proposals.0, proposals.1],
+                    ),
+                    Misbehavior::InvalidProposal {
+                        round, proposal, ..
+                    } => (round, ViolationKind::InvalidProposal, vec![Some(proposal)]),
+                    Misbehavior::InvalidPrevote {
+                        round, proposal, ..
+                    } => (round, ViolationKind::InvalidPrevote, vec![Some(proposal)]),
+                    Misbehavior::InvalidPrecommit {
+                        round, proposal, ..
+                    } => (round, ViolationKind::InvalidPrecommit, vec![Some(proposal)]),
+                };
+                // Nil and the blocks unknown to this node have no hash to report.
+                let evidence_hashes = blocks
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|block| self.get_block_hash(block))
+                    .collect();
                 (
-                    // TODO: add misbehavior handling
                     ProgressResult::ViolationReported(
-                        pubkey,
-                        format!("{misbehavior:?}"),
+                        Violation {
+                            violator: pubkey.clone(),
+                            round: from_vetomint_round(round),
+                            kind,
+                            evidence_hashes,
+                            description: self.describe_violation(&pubkey, description),
+                        },
                         timestamp,
                     ),
                     None,
                 )
             }
-        }
+        };
+        Ok(result)
     }
 
+    /// Note that `valid` is used only for proposals.
     fn convert_consensus_message_to_event(
         &self,
         consensus_message: &ConsensusMessage,
         signer: usize,
-    ) -> ConsensusEvent {
-        match consensus_message {
+        valid: bool,
+    ) -> Result<ConsensusEvent, ConsensusError> {
+        let event = match consensus_message {
             ConsensusMessage::Proposal {
                 round,
                 valid_round,
                 block_hash,
+                ..
             } => {
-                let valid_round = valid_round.map(|r| r as usize);
-                let index = self
-                    .get_block_index(block_hash)
-                    .expect("this must be already verified by the message filter");
+                let valid_round = valid_round.map(to_vetomint_round).transpose()?;
+                let index = self.get_block_index(block_hash)?;
                 ConsensusEvent::BlockProposalReceived {
                     proposal: index,
-                    // Todo, Note: For now, all proposals are regarded as valid.
-                    // See issue#201 (https://github.com/postech-dao/simperby/issues/201).
-                    valid: true,
+                    valid,
                     valid_round,
                     proposer: signer,
-                    round: *round as usize,
+                    round: to_vetomint_round(*round)?,
                     favor: !self.vetoed_block_hashes.contains(block_hash),
                 }
             }
-            ConsensusMessage::NonNilPreVoted(round, block_hash) => {
-                let index = self
-                    .get_block_index(block_hash)
-                    .expect("this must be already verified by the message filter");
+            ConsensusMessage::NonNilPreVoted(_, round, block_hash) => {
+                let index = self.get_block_index(block_hash)?;
                 ConsensusEvent::Prevote {
                     proposal: Some(index),
                     signer,
-                    round: *round as usize,
+                    round: to_vetomint_round(*round)?,
                 }
             }
-            ConsensusMessage::NonNilPreCommitted(round, block_hash) => {
-                let index = self
-                    .get_block_index(block_hash)
-                    .expect("this must be already verified by the message filter");
+            ConsensusMessage::NonNilPreCommitted(_, round, block_hash) => {
+                let index = self.get_block_index(block_hash)?;
                 ConsensusEvent::Precommit {
                     proposal: Some(index),
                     signer,
-                    round: *round as usize,
+                    round: to_vetomint_round(*round)?,
                 }
             }
-            ConsensusMessage::NilPreVoted(round) => ConsensusEvent::Prevote {
+            ConsensusMessage::NilPreVoted(_, round) => ConsensusEvent::Prevote {
                 proposal: None,
                 signer,
-                round: *round as usize,
+                round: to_vetomint_round(*round)?,
             },
-            ConsensusMessage::NilPreCommitted(round) => ConsensusEvent::Precommit {
+            ConsensusMessage::NilPreCommitted(_, round) => ConsensusEvent::Precommit {
                 proposal: None,
                 signer,
-                round: *round as usize,
+                round: to_vetomint_round(*round)?,
             },
-        }
+        };
+        Ok(event)
+    }
+}
+
+/// Returns the round that the event is for, if any.
+fn event_round(event: &ConsensusEvent) -> Option<usize> {
+    match event {
+        ConsensusEvent::BlockProposalReceived { round, .. }
+        | ConsensusEvent::SkipRound { round }
+        | ConsensusEvent::Prevote { round, .. }
+        | ConsensusEvent::Precommit { round, .. } => Some(*round),
+        ConsensusEvent::Start
+        | ConsensusEvent::BlockCandidateUpdated { .. }
+        | ConsensusEvent::Timer => None,
     }
 }
 
@@ -444,12 +1541,14 @@ fn generate_height_info(
     header: &BlockHeader,
     consensus_params: ConsensusParams,
     round_zero_timestamp: Timestamp,
-    this_node_key: PrivateKey,
-) -> Result<HeightInfo, Error> {
-    let this_node_index = header
-        .validator_set
-        .iter()
-        .position(|(pubkey, _)| *pubkey == this_node_key.public_key());
+    this_node_public_key: Option<PublicKey>,
+) -> Result<HeightInfo, ConsensusError> {
+    let this_node_index = this_node_public_key.and_then(|key| {
+        header
+            .validator_set
+            .iter()
+            .position(|(pubkey, _)| *pubkey == key)
+    });
     let info = HeightInfo {
         validators: header
             .validator_set
@@ -459,7 +1558,934 @@ fn generate_height_info(
         this_node_index,
         timestamp: round_zero_timestamp,
         consensus_params,
-        initial_block_candidate: 0 as BlockIdentifier,
+        // Not the first verified block, which is not a candidate until it is set so
+        initial_block_candidate: ProposalRef::None.to_vetomint(),
     };
     Ok(info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates the state of the validator 1, which has received a proposal of the validator 0
+    /// before the round starts so that it hasn't prevoted yet.
+    fn setup() -> (State, Vec<PrivateKey>, Hash256) {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let mut state = State::new(
+            &fi.header,
+            ConsensusParams {
+                timeout_ms: 6000,
+                timeout_increment_ms: 0,
+                repeat_round_for_first_leader: 10,
+                quorum: None,
+            },
+            0,
+            Some(keys[1].0.clone()),
+        )
+        .unwrap();
+        let block_hash = Hash256::hash("block");
+        state.register_verified_block_hash(block_hash).unwrap();
+        // Events are processed from the last one, so the proposal comes before `Start`.
+        state
+            .add_consensus_messages(
+                vec![(
+                    ConsensusMessage::Proposal {
+                        height: state.height(),
+                        round: 0,
+                        valid_round: None,
+                        block_hash,
+                    },
+                    keys[0].0.clone(),
+                )],
+                0,
+                &|_| Some(true),
+            )
+            .unwrap();
+        assert!(state.progress(0).unwrap().is_empty());
+        (
+            state,
+            keys.into_iter().map(|(_, key)| key).collect(),
+            block_hash,
+        )
+    }
+
+    #[test]
+    fn veto_received_proposal() {
+        let (mut state, _, block_hash) = setup();
+        state.veto_block(block_hash, 1).unwrap();
+        assert_eq!(
+            state.progress(1).unwrap(),
+            vec![ProgressResult::NilPreVoted(0, 1)]
+        );
+        // The veto is already reflected in the vote.
+        assert!(state.unveto_block(block_hash, 2).is_err());
+    }
+
+    #[test]
+    fn unveto_before_prevote() {
+        let (mut state, _, block_hash) = setup();
+        assert!(state.unveto_block(block_hash, 1).is_err());
+        state.veto_block(block_hash, 1).unwrap();
+        state.unveto_block(block_hash, 1).unwrap();
+        assert_eq!(
+            state.progress(1).unwrap(),
+            vec![ProgressResult::NonNilPreVoted(0, block_hash, 1)]
+        );
+        assert_eq!(
+            state.messages_to_broadcast(),
+            vec![ConsensusMessage::NonNilPreVoted(
+                state.height(),
+                0,
+                block_hash
+            )]
+        );
+    }
+
+    #[test]
+    fn outbox() {
+        let (mut state, keys, block_hash) = start();
+        let height = state.height();
+        state
+            .add_consensus_messages(proposal(height, block_hash, &keys[0]), 1, &|_| Some(true))
+            .unwrap();
+        state.progress(1).unwrap();
+        let prevote = ConsensusMessage::NonNilPreVoted(state.height(), 0, block_hash);
+        // The message stays in the outbox until it is marked as sent.
+        assert_eq!(state.messages_to_broadcast(), vec![prevote.clone()]);
+        state.mark_message_sent(&prevote);
+        assert!(state.messages_to_broadcast().is_empty());
+    }
+
+    #[test]
+    fn equivocation() {
+        let (mut state, keys, block_hash) = setup();
+        let signed = |message: ConsensusMessage, key: &PrivateKey| {
+            let commitment = MessageCommitmentProof {
+                committer: key.public_key(),
+                signature: Signature::sign(message.to_hash256(), key).unwrap(),
+            };
+            (message, commitment)
+        };
+        let height = state.height();
+        let first = signed(
+            ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+            &keys[2],
+        );
+        let second = signed(
+            ConsensusMessage::NonNilPreVoted(height, 0, Hash256::hash("another block")),
+            &keys[2],
+        );
+        let messages = vec![
+            first.clone(),
+            signed(ConsensusMessage::NilPreVoted(height, 1), &keys[2]),
+            signed(ConsensusMessage::NilPreVoted(height, 0), &keys[3]),
+            second.clone(),
+        ];
+        let evidence = Evidence {
+            offender: keys[2].public_key(),
+            height,
+            round: 0,
+            dms_key: "consensus".to_owned(),
+            first: first.clone(),
+            second: second.clone(),
+        };
+        assert_eq!(
+            state.detect_equivocations(&messages, &evidence.dms_key),
+            vec![evidence.clone()]
+        );
+        // The messages are read from the DMS repeatedly.
+        assert!(state
+            .detect_equivocations(&messages, &evidence.dms_key)
+            .is_empty());
+
+        let description = format!(
+            "equivocation of Prevote in round 0: {} and {}",
+            first.0.to_hash256(),
+            second.0.to_hash256()
+        );
+        let violation = Violation {
+            violator: keys[2].public_key(),
+            round: 0,
+            kind: ViolationKind::DoublePrevote,
+            evidence_hashes: vec![first.0.to_hash256(), second.0.to_hash256()],
+            description,
+        };
+        assert!(state
+            .progress(1)
+            .unwrap()
+            .contains(&ProgressResult::ViolationReported(violation, 1)));
+        // Reported only once.
+        assert!(!state
+            .progress(2)
+            .unwrap()
+            .iter()
+            .any(|x| matches!(x, ProgressResult::ViolationReported(..))));
+
+        // Named in the report if it has the name.
+        let info = ValidatorInfo {
+            name: "validator2".to_owned(),
+            contact: "validator2@example.com".to_owned(),
+        };
+        state
+            .set_validator_info(
+                vec![(keys[2].public_key(), info.clone())]
+                    .into_iter()
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(state.validator_info(&keys[2].public_key()), info);
+        assert_eq!(
+            state.validator_info(&keys[3].public_key()),
+            ValidatorInfo::default()
+        );
+        assert_eq!(
+            state.status().validator_names,
+            vec!["", "", "validator2", ""]
+        );
+        let conflicting = signed(
+            ConsensusMessage::NonNilPreVoted(height, 1, block_hash),
+            &keys[2],
+        );
+        state.detect_equivocations(&[conflicting], &evidence.dms_key);
+        assert!(state.progress(3).unwrap().iter().any(|x| matches!(
+            x,
+            ProgressResult::ViolationReported(violation, _)
+                if violation.round == 1 && violation.description.ends_with("(by validator2)")
+        )));
+        let (stranger, _) = generate_keypair("stranger");
+        assert!(state
+            .set_validator_info(vec![(stranger, info)].into_iter().collect())
+            .is_err());
+    }
+
+    /// Creates the state of the validator 1 which has started the round 0.
+    fn start() -> (State, Vec<PrivateKey>, Hash256) {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let mut state = State::new(
+            &fi.header,
+            ConsensusParams {
+                timeout_ms: 6000,
+                timeout_increment_ms: 0,
+                repeat_round_for_first_leader: 10,
+                quorum: None,
+            },
+            0,
+            Some(keys[1].0.clone()),
+        )
+        .unwrap();
+        let block_hash = Hash256::hash("block");
+        state.register_verified_block_hash(block_hash).unwrap();
+        assert!(state.progress(0).unwrap().is_empty());
+        (
+            state,
+            keys.into_iter().map(|(_, key)| key).collect(),
+            block_hash,
+        )
+    }
+
+    fn proposal(
+        height: BlockHeight,
+        block_hash: Hash256,
+        proposer: &PrivateKey,
+    ) -> Vec<(ConsensusMessage, PublicKey)> {
+        vec![(
+            ConsensusMessage::Proposal {
+                height,
+                round: 0,
+                valid_round: None,
+                block_hash,
+            },
+            proposer.public_key(),
+        )]
+    }
+
+    #[test]
+    fn invalid_proposal() {
+        let (mut state, keys, block_hash) = start();
+        let height = state.height();
+        state
+            .add_consensus_messages(proposal(height, block_hash, &keys[0]), 1, &|_| Some(false))
+            .unwrap();
+        assert_eq!(
+            state.progress(1).unwrap(),
+            vec![ProgressResult::NilPreVoted(0, 1)]
+        );
+    }
+
+    #[test]
+    fn observed_votes() {
+        let (mut state, keys, block_hash) = start();
+        let height = state.height();
+        let votes = vec![
+            (
+                ConsensusMessage::NonNilPreVoted(height, 0, block_hash),
+                keys[0].public_key(),
+            ),
+            (
+                ConsensusMessage::NilPreCommitted(height, 0),
+                keys[2].public_key(),
+            ),
+            // The vote of this node is not reported.
+            (
+                ConsensusMessage::NilPreVoted(height, 0),
+                keys[1].public_key(),
+            ),
+        ];
+        state
+            .add_consensus_messages(votes.clone(), 1, &|_| Some(true))
+            .unwrap();
+        // The same votes again before processing them
+        state
+            .add_consensus_messages(votes, 1, &|_| Some(true))
+            .unwrap();
+        // In the canonical order
+        assert_eq!(
+            state.progress(1).unwrap(),
+            vec![
+                ProgressResult::VoteObserved {
+                    signer: keys[0].public_key(),
+                    round: 0,
+                    block_hash: Some(block_hash),
+                    kind: VoteKind::Prevote,
+                    timestamp: 1,
+                },
+                ProgressResult::VoteObserved {
+                    signer: keys[2].public_key(),
+                    round: 0,
+                    block_hash: None,
+                    kind: VoteKind::Precommit,
+                    timestamp: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn pending_proposal() {
+        let (mut state, keys, block_hash) = start();
+        let height = state.height();
+        for _ in 0..2 {
+            state
+                .add_consensus_messages(proposal(height, block_hash, &keys[0]), 1, &|_| None)
+                .unwrap();
+        }
+        assert!(state.progress(1).unwrap().is_empty());
+        assert_eq!(state.pending_messages.len(), 1);
+
+        state.retry_pending_messages(&|_| None).unwrap();
+        assert!(state.progress(2).unwrap().is_empty());
+        assert_eq!(state.pending_messages.len(), 1);
+
+        state.retry_pending_messages(&|_| Some(true)).unwrap();
+        assert!(state.pending_messages.is_empty());
+        // It is processed as of when it was received.
+        assert_eq!(
+            state.progress(3).unwrap(),
+            vec![ProgressResult::NonNilPreVoted(0, block_hash, 1)]
+        );
+    }
+
+    #[test]
+    fn pending_votes_by_block() {
+        let (mut state, keys, _) = start();
+        let height = state.height();
+        let block_hashes = [Hash256::hash("block1"), Hash256::hash("block2")];
+        let prevotes = block_hashes
+            .iter()
+            .zip(&keys[2..])
+            .map(|(block_hash, key)| {
+                (
+                    ConsensusMessage::NonNilPreVoted(height, 0, *block_hash),
+                    key.public_key(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..2 {
+            state
+                .add_consensus_messages(prevotes.clone(), 1, &|_| Some(true))
+                .unwrap();
+        }
+        assert_eq!(state.pending_messages.len(), 2);
+
+        // Only the one on the registered block is retried.
+        state.register_verified_block_hash(block_hashes[1]).unwrap();
+        state.retry_pending_messages(&|_| Some(true)).unwrap();
+        assert_eq!(state.pending_messages.len(), 1);
+        assert_eq!(
+            state.pending_messages.block_hashes().collect::<Vec<_>>(),
+            vec![&block_hashes[0]]
+        );
+
+        // The stored form is the list of the messages.
+        let stored: Vec<PendingMessage> = state.pending_messages.clone().into();
+        assert_eq!(
+            stored,
+            vec![(prevotes[0].0.clone(), prevotes[0].1.clone(), 1)]
+        );
+        assert_eq!(PendingMessages::from(stored).len(), 1);
+    }
+
+    #[test]
+    fn other_height() {
+        let (mut state, keys, block_hash) = start();
+        let height = state.height();
+        state
+            .add_consensus_messages(proposal(height + 1, block_hash, &keys[0]), 1, &|_| {
+                Some(true)
+            })
+            .unwrap();
+        assert!(state.progress(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn serde_compat() {
+        let block_hash = Hash256::hash("block");
+        let messages = vec![
+            ConsensusMessage::Proposal {
+                height: 1,
+                round: 2,
+                valid_round: Some(1),
+                block_hash,
+            },
+            ConsensusMessage::NonNilPreVoted(1, 2, block_hash),
+            ConsensusMessage::NonNilPreCommitted(1, 2, block_hash),
+            ConsensusMessage::NilPreVoted(1, 2),
+            ConsensusMessage::NilPreCommitted(1, 2),
+        ];
+        for message in messages {
+            let json = serde_spb::to_string(&message).unwrap();
+            assert_eq!(
+                serde_spb::from_str::<ConsensusMessage>(&json).unwrap(),
+                message
+            );
+            let binary = serde_spb::to_vec(&message).unwrap();
+            assert_eq!(
+                serde_spb::from_slice::<ConsensusMessage>(&binary).unwrap(),
+                message
+            );
+        }
+
+        let legacy_messages = vec![
+            format!(
+                r#"{{"Proposal":{{"round":2,"valid_round":null,"block_hash":"{}"}}}}"#,
+                block_hash
+            ),
+            format!(r#"{{"NonNilPreVoted":[2,"{block_hash}"]}}"#),
+            format!(r#"{{"NonNilPreCommitted":[2,"{block_hash}"]}}"#),
+            r#"{"NilPreVoted":2}"#.to_owned(),
+            r#"{"NilPreCommitted":2}"#.to_owned(),
+        ];
+        for json in legacy_messages {
+            let error = serde_spb::from_str::<ConsensusMessage>(&json).unwrap_err();
+            assert!(
+                error.to_string().contains("legacy format"),
+                "{json}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn signature() {
+        let (_, key) = generate_keypair("validator");
+        let dms_key = "consensus".to_owned();
+        let message = ConsensusMessage::NilPreVoted(1, 0);
+        let commitment = message.commit(&dms_key, &key).unwrap();
+        message.verify_commitment(&commitment, &dms_key).unwrap();
+        // The signature is bound to the DMS key.
+        assert!(message
+            .verify_commitment(&commitment, &"governance".to_owned())
+            .is_err());
+
+        // The signature of the protocol version 2 is just invalid;
+        // the version mismatch is told by the encoding, before the signature is verified.
+        let mut data = SIGNING_DOMAIN.as_bytes().to_vec();
+        data.extend_from_slice(&2u64.to_be_bytes());
+        data.extend(message.to_unversioned_compact());
+        let legacy_commitment = MessageCommitmentProof {
+            committer: key.public_key(),
+            signature: Signature::sign(Hash256::hash(data).aggregate(&dms_key.to_hash256()), &key)
+                .unwrap(),
+        };
+        assert!(message
+            .verify_commitment(&legacy_commitment, &dms_key)
+            .is_err());
+        assert_eq!(
+            ConsensusMessage::wire_version(&message.to_unversioned_compact()),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn unresolvable_messages() {
+        let (mut state, keys, _) = start();
+        let height = state.height();
+        let unregistered = Hash256::hash("unregistered block");
+        let message = ConsensusMessage::NonNilPreVoted(height, 0, unregistered);
+        let (_, stranger) = generate_keypair("stranger");
+        state
+            .add_consensus_messages(
+                vec![
+                    (message.clone(), keys[0].public_key()),
+                    (
+                        ConsensusMessage::NilPreVoted(height, 0),
+                        stranger.public_key(),
+                    ),
+                ],
+                1,
+                &|_| Some(true),
+            )
+            .unwrap();
+        assert!(state.progress(1).unwrap().is_empty());
+        assert!(state
+            .convert_consensus_message_to_event(&message, 0, true)
+            .is_err());
+    }
+
+    #[test]
+    fn many_verified_blocks() {
+        let (mut state, keys, first) = start();
+        let height = state.height();
+        let block_hashes = (0..3000)
+            .map(|i| Hash256::hash(format!("block{i}")))
+            .collect::<Vec<_>>();
+        for block_hash in &block_hashes {
+            state.register_verified_block_hash(*block_hash).unwrap();
+        }
+        // Registering the same hash again changes nothing.
+        state.register_verified_block_hash(first).unwrap();
+        state.register_verified_block_hash(block_hashes[0]).unwrap();
+        assert_eq!(state.block_hashes.len(), 3001);
+        assert_eq!(state.verified_block_hashes.len(), 3001);
+        assert_eq!(state.get_block_index(&first).unwrap(), 0);
+        for (i, block_hash) in block_hashes.iter().enumerate() {
+            assert_eq!(state.get_block_index(block_hash).unwrap(), i + 1);
+            assert_eq!(state.get_block_hash(i + 1), Some(*block_hash));
+        }
+        assert_eq!(state.status().verified_block_hashes[1..], block_hashes[..]);
+
+        let last = *block_hashes.last().unwrap();
+        state
+            .add_consensus_messages(proposal(height, last, &keys[0]), 1, &|_| Some(true))
+            .unwrap();
+        assert_eq!(
+            state.progress(1).unwrap(),
+            vec![ProgressResult::NonNilPreVoted(0, last, 1)]
+        );
+    }
+
+    #[test]
+    fn register_verified_block_hash() {
+        let (mut state, keys, first) = start();
+        let height = state.height();
+        let block_hashes = (0..100)
+            .map(|i| Hash256::hash(format!("block{i}")))
+            .collect::<Vec<_>>();
+        for (i, block_hash) in block_hashes.iter().enumerate() {
+            state.register_verified_block_hash(*block_hash).unwrap();
+            state
+                .register_verified_block_hash(block_hashes[i / 2])
+                .unwrap();
+            state.register_verified_block_hash(first).unwrap();
+        }
+        assert_eq!(state.verified_block_hashes().len(), 101);
+        for (i, block_hash) in block_hashes.iter().enumerate() {
+            assert_eq!(state.verified_block_hashes()[block_hash], i + 1);
+            let (result, message) = state
+                .process_consensus_response_to_progress_result(
+                    ConsensusResponse::BroadcastPrevote {
+                        proposal: Some(i + 1),
+                        round: 0,
+                    },
+                    1,
+                )
+                .unwrap();
+            assert_eq!(result, ProgressResult::NonNilPreVoted(0, *block_hash, 1));
+            assert_eq!(
+                message,
+                Some(ConsensusMessage::NonNilPreVoted(height, 0, *block_hash))
+            );
+        }
+
+        let last = *block_hashes.last().unwrap();
+        let mut messages = proposal(height, last, &keys[0]);
+        for i in [0, 2, 3] {
+            messages.push((
+                ConsensusMessage::NonNilPreCommitted(height, 0, last),
+                keys[i].public_key(),
+            ));
+        }
+        state
+            .add_consensus_messages(messages, 1, &|_| Some(true))
+            .unwrap();
+        state.progress(1).unwrap();
+        assert_eq!(state.check_finalized().unwrap().block_hash, last);
+        // Every operation that would modify the finalized state fails, never panicking.
+        assert_eq!(
+            state.register_verified_block_hash(first),
+            Err(ConsensusError::Finalized)
+        );
+        assert_eq!(state.veto_block(last, 2), Err(ConsensusError::Finalized));
+        assert_eq!(state.progress(2), Err(ConsensusError::Finalized));
+    }
+
+    #[test]
+    fn observer() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let mut state = State::new(
+            &fi.header,
+            ConsensusParams {
+                timeout_ms: 6000,
+                timeout_increment_ms: 0,
+                repeat_round_for_first_leader: 10,
+                quorum: None,
+            },
+            0,
+            None,
+        )
+        .unwrap();
+        let height = state.height();
+        assert!(state.progress(0).unwrap().is_empty());
+        // The timeout of the proposal passes, which would make a validator prevote.
+        assert!(state.progress(10_000).unwrap().is_empty());
+        assert!(state.messages_to_broadcast().is_empty());
+
+        let block_hash = Hash256::hash("block");
+        state.register_verified_block_hash(block_hash).unwrap();
+        let mut messages = proposal(height, block_hash, &keys[0].1);
+        for (public_key, _) in &keys[..3] {
+            messages.push((
+                ConsensusMessage::NonNilPreCommitted(height, 0, block_hash),
+                public_key.clone(),
+            ));
+        }
+        state
+            .add_consensus_messages(messages, 10_000, &|_| Some(true))
+            .unwrap();
+        let mut result = state.progress(10_000).unwrap();
+        result.retain(|x| !x.is_observed_vote());
+        assert!(matches!(result[..], [ProgressResult::Finalized(_)]));
+        assert!(state.messages_to_broadcast().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let (mut state, keys, _) = start();
+        let unregistered = Hash256::hash("unregistered block");
+        let error = state.set_proposal_candidate(unregistered, 1).unwrap_err();
+        assert_eq!(error, (ConsensusError::BlockNotVerified(unregistered)));
+        let (stranger, _) = generate_keypair("stranger");
+        let error = state.get_validator_index(&stranger).unwrap_err();
+        assert_eq!(error, (ConsensusError::NotAValidator(stranger)));
+        let error = state
+            .set_finalization_proof(FinalizationProof {
+                round: 0,
+                signatures: Vec::new(),
+            })
+            .unwrap_err();
+        assert_eq!(error, (ConsensusError::NotFinalized));
+        assert!(state.get_validator_index(&keys[3].public_key()).is_ok());
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let new_state = || {
+            let mut state = State::new(
+                &fi.header,
+                ConsensusParams {
+                    timeout_ms: 6000,
+                    timeout_increment_ms: 0,
+                    repeat_round_for_first_leader: 10,
+                    quorum: None,
+                },
+                0,
+                Some(keys[1].0.clone()),
+            )
+            .unwrap();
+            state
+                .register_verified_block_hash(Hash256::hash("block"))
+                .unwrap();
+            state
+        };
+        let height = fi.header.height + 1;
+        let block_hash = Hash256::hash("block");
+        let signed = |message: ConsensusMessage, i: usize| {
+            let commitment = MessageCommitmentProof {
+                committer: keys[i].0.clone(),
+                signature: Signature::sign(message.to_hash256(), &keys[i].1).unwrap(),
+            };
+            (message, commitment)
+        };
+        let mut messages = vec![
+            signed(
+                ConsensusMessage::Proposal {
+                    height,
+                    round: 0,
+                    valid_round: None,
+                    block_hash,
+                },
+                0,
+            ),
+            signed(ConsensusMessage::NonNilPreVoted(height, 0, block_hash), 0),
+            signed(ConsensusMessage::NilPreVoted(height, 0), 2),
+            // An equivocation, whose evidence depends on which one is received first.
+            signed(ConsensusMessage::NonNilPreVoted(height, 0, block_hash), 3),
+            signed(ConsensusMessage::NilPreVoted(height, 0), 3),
+            signed(ConsensusMessage::NilPreCommitted(height, 0), 2),
+            signed(ConsensusMessage::NilPreVoted(height, 1), 0),
+        ];
+
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let mut state = new_state();
+            state.progress(0).unwrap();
+            let mut sorted = messages.clone();
+            sorted.sort_by_cached_key(|(message, commitment)| {
+                state.canonical_key(message, &commitment.committer)
+            });
+            state.detect_equivocations(&sorted, &"consensus".to_owned());
+            state
+                .add_consensus_messages(
+                    messages
+                        .iter()
+                        .map(|(message, commitment)| {
+                            (message.clone(), commitment.committer.clone())
+                        })
+                        .collect(),
+                    1,
+                    &|_| Some(true),
+                )
+                .unwrap();
+            let progress_results = state.progress(1).unwrap();
+            results.push((progress_results, serde_spb::to_vec(&state).unwrap()));
+            messages.reverse();
+            messages.rotate_left(3);
+        }
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn timeout() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let mut parameters = ConsensusParams {
+            timeout_ms: 6000,
+            timeout_increment_ms: 2000,
+            repeat_round_for_first_leader: 10,
+            quorum: None,
+        };
+        let mut state =
+            State::new(&fi.header, parameters.clone(), 0, Some(keys[1].0.clone())).unwrap();
+        let height = state.height();
+        assert!(state.progress(0).unwrap().is_empty());
+        assert_eq!(state.status().timeout, Some(6000));
+        assert!(state.progress(5999).unwrap().is_empty());
+        assert_eq!(
+            state.progress(6000).unwrap(),
+            vec![ProgressResult::NilPreVoted(0, 6000)]
+        );
+        assert_eq!(state.status().timeout, None);
+
+        let precommits = [0, 2, 3]
+            .iter()
+            .map(|&i| {
+                (
+                    ConsensusMessage::NilPreCommitted(height, 0),
+                    keys[i].0.clone(),
+                )
+            })
+            .collect();
+        state
+            .add_consensus_messages(precommits, 7000, &|_| Some(true))
+            .unwrap();
+        let mut results = state.progress(7000).unwrap();
+        results.retain(|x| !x.is_observed_vote());
+        assert_eq!(
+            results,
+            vec![ProgressResult::RoundAdvanced {
+                new_round: 1,
+                reason: RoundSkipReason::NilQuorum,
+                timestamp: 7000,
+            }]
+        );
+        // The round 1 waits longer by the increment.
+        assert_eq!(state.status().round, 1);
+        assert_eq!(state.status().timeout, Some(7000 + 8000));
+        assert!(state.progress(14_999).unwrap().is_empty());
+        assert_eq!(
+            state.progress(15_000).unwrap(),
+            vec![ProgressResult::NilPreVoted(1, 15_000)]
+        );
+
+        parameters.timeout_ms = 0;
+        assert!(State::new(&fi.header, parameters, 0, None).is_err());
+    }
+
+    #[test]
+    fn prune_updated_events() {
+        let (mut state, keys, _) = start();
+        let height = state.height();
+        let mut max_size = 0;
+        for round in 0..100 {
+            let precommits = [0, 2, 3]
+                .iter()
+                .map(|&i| {
+                    (
+                        ConsensusMessage::NilPreCommitted(height, round),
+                        keys[i].public_key(),
+                    )
+                })
+                .collect();
+            let timestamp = (round as Timestamp + 1) * 10_000;
+            state
+                .add_consensus_messages(precommits, timestamp, &|_| Some(true))
+                .unwrap();
+            state.progress(timestamp).unwrap();
+            state.prune_updated_events(16);
+            assert_eq!(state.status().round, round + 1);
+            let size = serde_spb::to_vec(&state.updated_events).unwrap().len();
+            if round < 10 {
+                max_size = max_size.max(size);
+            } else {
+                assert!(size <= max_size, "{size} bytes in round {round}");
+            }
+        }
+
+        // Future rounds are kept up to the limit, preferring the nearer ones.
+        let prevotes = (101..104)
+            .map(|round| {
+                (
+                    ConsensusMessage::NilPreVoted(height, round),
+                    keys[0].public_key(),
+                )
+            })
+            .collect();
+        state
+            .add_consensus_messages(prevotes, 1_010_000, &|_| Some(true))
+            .unwrap();
+        state.progress(1_010_000).unwrap();
+        state.prune_updated_events(2);
+        assert_eq!(
+            state
+                .updated_events
+                .iter()
+                .map(event_round)
+                .collect::<BTreeSet<_>>(),
+            vec![Some(101), Some(102)].into_iter().collect()
+        );
+    }
+
+    /// The proposer without a candidate declines to propose,
+    /// even with a verified block whose identifier vetomint starts from.
+    #[test]
+    fn proposer_without_candidate() {
+        let (fi, keys) = test_utils::generate_fi(4);
+        let params = ConsensusParams {
+            timeout_ms: 6000,
+            timeout_increment_ms: 0,
+            repeat_round_for_first_leader: 10,
+            quorum: None,
+        };
+        let mut state = State::new(&fi.header, params, 0, Some(keys[0].0.clone())).unwrap();
+        let height = state.height();
+        assert_eq!(state.proposer_index(0).unwrap(), 0);
+        state
+            .register_verified_block_hash(Hash256::hash("block"))
+            .unwrap();
+        assert!(state.progress(0).unwrap().is_empty());
+        assert!(state.messages_to_broadcast().is_empty());
+
+        // The others end the round, and this node declines again in the next one.
+        let precommits = [1, 2, 3]
+            .iter()
+            .map(|&i| {
+                (
+                    ConsensusMessage::NilPreCommitted(height, 0),
+                    keys[i].0.clone(),
+                )
+            })
+            .collect();
+        state
+            .add_consensus_messages(precommits, 7000, &|_| Some(true))
+            .unwrap();
+        let mut results = state.progress(7000).unwrap();
+        results.retain(|x| !x.is_observed_vote());
+        assert_eq!(
+            results,
+            vec![ProgressResult::RoundAdvanced {
+                new_round: 1,
+                reason: RoundSkipReason::NilQuorum,
+                timestamp: 7000,
+            }]
+        );
+        assert!(state.messages_to_broadcast().is_empty());
+    }
+
+    /// Every round is either converted exactly or rejected, never truncated.
+    #[test]
+    fn round_conversion() {
+        let wide = usize::BITS >= ConsensusRound::BITS;
+        for round in [
+            0,
+            u32::MAX as ConsensusRound,
+            u32::MAX as ConsensusRound + 1,
+            ConsensusRound::MAX - 1,
+            ConsensusRound::MAX,
+        ] {
+            match to_vetomint_round(round) {
+                Ok(converted) => assert_eq!(from_vetomint_round(converted), round),
+                Err(e) => {
+                    assert!(!wide && round > u32::MAX as ConsensusRound);
+                    assert_eq!(e, ConsensusError::RoundOutOfRange(round));
+                }
+            }
+        }
+        let proposal = ConsensusMessage::Proposal {
+            height: 1,
+            round: 0,
+            valid_round: Some(ConsensusRound::MAX),
+            block_hash: Hash256::zero(),
+        };
+        assert_eq!(proposal.check_rounds().is_ok(), wide);
+    }
+
+    #[test]
+    fn max_rounds() {
+        let (mut state, keys, _) = start();
+        let height = state.height();
+        let rounds = [ConsensusRound::MAX - 1, ConsensusRound::MAX];
+        let votes = rounds
+            .iter()
+            .map(|&round| {
+                (
+                    ConsensusMessage::NilPreVoted(height, round),
+                    keys[0].public_key(),
+                )
+            })
+            .collect();
+        state
+            .add_consensus_messages(votes, 1, &|_| Some(true))
+            .unwrap();
+        let expected = if usize::BITS >= ConsensusRound::BITS {
+            rounds
+                .iter()
+                .map(|&round| ProgressResult::VoteObserved {
+                    signer: keys[0].public_key(),
+                    round,
+                    block_hash: None,
+                    kind: VoteKind::Prevote,
+                    timestamp: 1,
+                })
+                .collect()
+        } else {
+            // Skipped rather than truncated to the rounds that fit
+            Vec::new()
+        };
+        assert_eq!(state.progress(1).unwrap(), expected);
+        assert_eq!(state.status().round, 0);
+        assert_eq!(
+            state.veto_round(ConsensusRound::MAX, 1).is_ok(),
+            usize::BITS >= ConsensusRound::BITS
+        );
+    }
+}
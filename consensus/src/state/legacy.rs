@@ -0,0 +1,570 @@
+use super::*;
+use vetomint::legacy::{VetomintV0, VetomintV1};
+
+/// The schema of `State` before the versioning, which was stored as the bare hex of bincode.
+///
+/// It had the messages without the heights, and only the precommits of the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV0 {
+    vetomint: VetomintV0,
+    block_header: BlockHeader,
+    block_identifier_count: BlockIdentifier,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<LegacyConsensusMessageFormat>,
+    precommits: BTreeMap<(Hash256, ConsensusRound), Vec<TypedSignature<FinalizationSignTarget>>>,
+    finalized: Option<Finalization>,
+}
+
+/// The schema of `State` in the version 1, which had no `vetoed_rounds`.
+///
+/// The schemas of the previous versions must not be changed,
+/// since they are to read the states stored in those versions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV1 {
+    vetomint: VetomintV1,
+    block_header: BlockHeader,
+    block_hashes: Vec<Hash256>,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<ConsensusMessage>,
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    pending_messages: Vec<(ConsensusMessage, PublicKey, Timestamp)>,
+    dms_cursor: u64,
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    equivocations: Vec<Evidence>,
+    reported_equivocations: usize,
+    finalized: Option<Finalization>,
+}
+
+/// The schema of `State` in the version 2, which had no queue of the proposal candidates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV2 {
+    vetomint: VetomintV1,
+    block_header: BlockHeader,
+    block_hashes: Vec<Hash256>,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    vetoed_rounds: BTreeSet<ConsensusRound>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<ConsensusMessage>,
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    pending_messages: Vec<(ConsensusMessage, PublicKey, Timestamp)>,
+    dms_cursor: u64,
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    equivocations: Vec<Evidence>,
+    reported_equivocations: usize,
+    finalized: Option<Finalization>,
+}
+
+/// The schema of `State` in the version 3, which had no delegations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV3 {
+    vetomint: VetomintV1,
+    block_header: BlockHeader,
+    block_hashes: Vec<Hash256>,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    vetoed_rounds: BTreeSet<ConsensusRound>,
+    proposal_candidates: Vec<(Hash256, u64)>,
+    proposal_candidate: Option<Hash256>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<ConsensusMessage>,
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    pending_messages: Vec<(ConsensusMessage, PublicKey, Timestamp)>,
+    dms_cursor: u64,
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    equivocations: Vec<Evidence>,
+    reported_equivocations: usize,
+    finalized: Option<Finalization>,
+}
+
+/// The schema of `State` in the version 4, which had no information of the validators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV4 {
+    vetomint: VetomintV1,
+    block_header: BlockHeader,
+    delegations: Delegations,
+    block_hashes: Vec<Hash256>,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    vetoed_rounds: BTreeSet<ConsensusRound>,
+    proposal_candidates: Vec<(Hash256, u64)>,
+    proposal_candidate: Option<Hash256>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<ConsensusMessage>,
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    pending_messages: Vec<(ConsensusMessage, PublicKey, Timestamp)>,
+    dms_cursor: u64,
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    equivocations: Vec<Evidence>,
+    reported_equivocations: usize,
+    finalized: Option<Finalization>,
+}
+
+/// The schema of `State` in the version 5, whose vetomint had no quorum in the parameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StateV5 {
+    vetomint: VetomintV1,
+    block_header: BlockHeader,
+    delegations: Delegations,
+    validator_info: BTreeMap<PublicKey, ValidatorInfo>,
+    block_hashes: Vec<Hash256>,
+    verified_block_hashes: BTreeMap<Hash256, BlockIdentifier>,
+    vetoed_block_hashes: BTreeSet<Hash256>,
+    vetoed_rounds: BTreeSet<ConsensusRound>,
+    proposal_candidates: Vec<(Hash256, u64)>,
+    proposal_candidate: Option<Hash256>,
+    to_be_processed_events: Vec<(ConsensusEvent, Timestamp)>,
+    updated_events: BTreeSet<ConsensusEvent>,
+    messages_to_broadcast: Vec<ConsensusMessage>,
+    prevoted_rounds: BTreeSet<ConsensusRound>,
+    pending_messages: Vec<(ConsensusMessage, PublicKey, Timestamp)>,
+    dms_cursor: u64,
+    signed_votes:
+        BTreeMap<(PublicKey, ConsensusRound, VoteKind), (ConsensusMessage, MessageCommitmentProof)>,
+    equivocations: Vec<Evidence>,
+    reported_equivocations: usize,
+    finalized: Option<Finalization>,
+}
+
+impl LegacyConsensusMessageFormat {
+    fn with_height(self, height: BlockHeight) -> ConsensusMessage {
+        match self {
+            LegacyConsensusMessageFormat::Proposal {
+                round,
+                valid_round,
+                block_hash,
+            } => ConsensusMessage::Proposal {
+                height,
+                round,
+                valid_round,
+                block_hash,
+            },
+            LegacyConsensusMessageFormat::NonNilPreVoted(round, block_hash) => {
+                ConsensusMessage::NonNilPreVoted(height, round, block_hash)
+            }
+            LegacyConsensusMessageFormat::NonNilPreCommitted(round, block_hash) => {
+                ConsensusMessage::NonNilPreCommitted(height, round, block_hash)
+            }
+            LegacyConsensusMessageFormat::NilPreVoted(round) => {
+                ConsensusMessage::NilPreVoted(height, round)
+            }
+            LegacyConsensusMessageFormat::NilPreCommitted(round) => {
+                ConsensusMessage::NilPreCommitted(height, round)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl From<ConsensusMessage> for LegacyConsensusMessageFormat {
+    fn from(message: ConsensusMessage) -> Self {
+        match message {
+            ConsensusMessage::Proposal {
+                round,
+                valid_round,
+                block_hash,
+                ..
+            } => LegacyConsensusMessageFormat::Proposal {
+                round,
+                valid_round,
+                block_hash,
+            },
+            ConsensusMessage::NonNilPreVoted(_, round, block_hash) => {
+                LegacyConsensusMessageFormat::NonNilPreVoted(round, block_hash)
+            }
+            ConsensusMessage::NonNilPreCommitted(_, round, block_hash) => {
+                LegacyConsensusMessageFormat::NonNilPreCommitted(round, block_hash)
+            }
+            ConsensusMessage::NilPreVoted(_, round) => {
+                LegacyConsensusMessageFormat::NilPreVoted(round)
+            }
+            ConsensusMessage::NilPreCommitted(_, round) => {
+                LegacyConsensusMessageFormat::NilPreCommitted(round)
+            }
+        }
+    }
+}
+
+impl From<StateV0> for StateV1 {
+    fn from(state: StateV0) -> Self {
+        let height = state.block_header.height + 1;
+        let mut vetomint = state.vetomint;
+        // The block candidate was `0` until one was given, which stands for no candidate now.
+        let candidate_given = state
+            .updated_events
+            .iter()
+            .chain(state.to_be_processed_events.iter().map(|(event, _)| event))
+            .any(|event| matches!(event, ConsensusEvent::BlockCandidateUpdated { .. }));
+        if !candidate_given {
+            vetomint.set_block_candidate(NIL_BLOCK_CANDIDATE);
+        }
+        let prevoted_rounds = vetomint
+            .prevoted_rounds()
+            .into_iter()
+            .map(from_vetomint_round)
+            .collect();
+        // The identifiers were assigned in order from `0`.
+        let mut block_hashes = state
+            .verified_block_hashes
+            .iter()
+            .map(|(block_hash, index)| (*index, *block_hash))
+            .collect::<Vec<_>>();
+        block_hashes.sort();
+        // The precommits are the only votes whose signatures were kept.
+        let mut signed_votes = BTreeMap::new();
+        for ((block_hash, round), signatures) in state.precommits {
+            for signature in signatures {
+                let signer = signature.signer().clone();
+                signed_votes
+                    .entry((signer.clone(), round, VoteKind::Precommit))
+                    .or_insert_with(|| {
+                        (
+                            ConsensusMessage::NonNilPreCommitted(height, round, block_hash),
+                            MessageCommitmentProof {
+                                signature: signature.get_raw_signature(),
+                                committer: signer,
+                            },
+                        )
+                    });
+            }
+        }
+        StateV1 {
+            vetomint: vetomint.into(),
+            block_header: state.block_header,
+            block_hashes: block_hashes
+                .into_iter()
+                .map(|(_, block_hash)| block_hash)
+                .collect(),
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state
+                .messages_to_broadcast
+                .into_iter()
+                .map(|message| message.with_height(height))
+                .collect(),
+            prevoted_rounds,
+            pending_messages: Vec::new(),
+            // The messages in the DMS are read again from the start.
+            dms_cursor: 0,
+            signed_votes,
+            equivocations: Vec::new(),
+            reported_equivocations: 0,
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 0.
+#[cfg(test)]
+impl From<StateV1> for StateV0 {
+    fn from(state: StateV1) -> Self {
+        let mut vetomint = VetomintV0::from(state.vetomint);
+        if vetomint.block_candidate() == NIL_BLOCK_CANDIDATE {
+            vetomint.set_block_candidate(0);
+        }
+        let mut precommits = BTreeMap::<_, Vec<_>>::new();
+        for (message, commitment) in state.signed_votes.into_values() {
+            if let ConsensusMessage::NonNilPreCommitted(_, round, block_hash) = message {
+                precommits
+                    .entry((block_hash, round))
+                    .or_default()
+                    .push(TypedSignature::new(
+                        commitment.signature,
+                        commitment.committer,
+                    ));
+            }
+        }
+        StateV0 {
+            vetomint,
+            block_header: state.block_header,
+            block_identifier_count: state.block_hashes.len(),
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state
+                .messages_to_broadcast
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            precommits,
+            finalized: state.finalized,
+        }
+    }
+}
+
+impl From<StateV1> for StateV2 {
+    fn from(state: StateV1) -> Self {
+        StateV2 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: BTreeSet::new(),
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+impl From<StateV2> for StateV3 {
+    fn from(state: StateV2) -> Self {
+        StateV3 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: Vec::new(),
+            proposal_candidate: None,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 1.
+#[cfg(test)]
+impl From<StateV2> for StateV1 {
+    fn from(state: StateV2) -> Self {
+        StateV1 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 2.
+#[cfg(test)]
+impl From<StateV3> for StateV2 {
+    fn from(state: StateV3) -> Self {
+        StateV2 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+impl From<StateV3> for StateV4 {
+    fn from(state: StateV3) -> Self {
+        StateV4 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            delegations: Delegations::default(),
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 3.
+#[cfg(test)]
+impl From<StateV4> for StateV3 {
+    fn from(state: StateV4) -> Self {
+        StateV3 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+impl From<StateV4> for StateV5 {
+    fn from(state: StateV4) -> Self {
+        StateV5 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            delegations: state.delegations,
+            validator_info: BTreeMap::new(),
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 4.
+#[cfg(test)]
+impl From<StateV5> for StateV4 {
+    fn from(state: StateV5) -> Self {
+        StateV4 {
+            vetomint: state.vetomint,
+            block_header: state.block_header,
+            delegations: state.delegations,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages,
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+impl From<StateV5> for State {
+    fn from(state: StateV5) -> Self {
+        State {
+            vetomint: state.vetomint.into(),
+            block_header: state.block_header,
+            delegations: state.delegations,
+            validator_info: state.validator_info,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages.into(),
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
+
+/// Downgrades the state to write the fixtures of the version 5.
+#[cfg(test)]
+impl From<State> for StateV5 {
+    fn from(state: State) -> Self {
+        StateV5 {
+            vetomint: state.vetomint.into(),
+            block_header: state.block_header,
+            delegations: state.delegations,
+            validator_info: state.validator_info,
+            block_hashes: state.block_hashes,
+            verified_block_hashes: state.verified_block_hashes,
+            vetoed_block_hashes: state.vetoed_block_hashes,
+            vetoed_rounds: state.vetoed_rounds,
+            proposal_candidates: state.proposal_candidates,
+            proposal_candidate: state.proposal_candidate,
+            to_be_processed_events: state.to_be_processed_events,
+            updated_events: state.updated_events,
+            messages_to_broadcast: state.messages_to_broadcast,
+            prevoted_rounds: state.prevoted_rounds,
+            pending_messages: state.pending_messages.into(),
+            dms_cursor: state.dms_cursor,
+            signed_votes: state.signed_votes,
+            equivocations: state.equivocations,
+            reported_equivocations: state.reported_equivocations,
+            finalized: state.finalized,
+        }
+    }
+}
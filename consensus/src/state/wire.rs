@@ -0,0 +1,351 @@
+use super::*;
+
+/// The tags of the compact encoding, by the variant.
+///
+/// They never collide with the first byte of the legacy encoding (`serde_spb`),
+/// which is the variant index from `0x00` to `0x04` in little-endian,
+/// so that both can be decoded during the transition.
+const PROPOSAL_TAG: u8 = 0x20;
+const NON_NIL_PREVOTED_TAG: u8 = 0x21;
+const NON_NIL_PRECOMMITTED_TAG: u8 = 0x22;
+const NIL_PREVOTED_TAG: u8 = 0x23;
+const NIL_PRECOMMITTED_TAG: u8 = 0x24;
+
+/// The tags of the compact encoding of the protocol version 2, which had no version field.
+const UNVERSIONED_TAGS: std::ops::RangeInclusive<u8> = 0x10..=0x14;
+
+impl ConsensusMessage {
+    /// Encodes the message in the compact fixed layout, which is the canonical wire format
+    /// and what the hash (and so the signature) of the message covers.
+    ///
+    /// The integers are in big-endian:
+    ///
+    /// | tag (1) | protocol version (2) | height (8) | round (8) | ... |
+    ///
+    /// followed by, for each tag,
+    /// - `0x20` (`Proposal`): whether there is the valid round (1, `0x00` or `0x01`),
+    ///   the valid round (8, only if there is) and the block hash (32)
+    /// - `0x21` (`NonNilPreVoted`), `0x22` (`NonNilPreCommitted`): the block hash (32)
+    /// - `0x23` (`NilPreVoted`), `0x24` (`NilPreCommitted`): nothing
+    ///
+    /// The protocol version is always `CONSENSUS_PROTOCOL_VERSION`.
+    pub fn to_compact(&self) -> Vec<u8> {
+        let (tag, round) = match self {
+            ConsensusMessage::Proposal { round, .. } => (PROPOSAL_TAG, round),
+            ConsensusMessage::NonNilPreVoted(_, round, _) => (NON_NIL_PREVOTED_TAG, round),
+            ConsensusMessage::NonNilPreCommitted(_, round, _) => (NON_NIL_PRECOMMITTED_TAG, round),
+            ConsensusMessage::NilPreVoted(_, round) => (NIL_PREVOTED_TAG, round),
+            ConsensusMessage::NilPreCommitted(_, round) => (NIL_PRECOMMITTED_TAG, round),
+        };
+        let mut data = Vec::with_capacity(60);
+        data.push(tag);
+        data.extend_from_slice(&CONSENSUS_PROTOCOL_VERSION.to_be_bytes());
+        data.extend_from_slice(&self.height().to_be_bytes());
+        data.extend_from_slice(&round.to_be_bytes());
+        match self {
+            ConsensusMessage::Proposal {
+                valid_round,
+                block_hash,
+                ..
+            } => {
+                match valid_round {
+                    Some(valid_round) => {
+                        data.push(0x01);
+                        data.extend_from_slice(&valid_round.to_be_bytes());
+                    }
+                    None => data.push(0x00),
+                }
+                data.extend_from_slice(block_hash.as_ref());
+            }
+            ConsensusMessage::NonNilPreVoted(_, _, block_hash)
+            | ConsensusMessage::NonNilPreCommitted(_, _, block_hash) => {
+                data.extend_from_slice(block_hash.as_ref());
+            }
+            ConsensusMessage::NilPreVoted(..) | ConsensusMessage::NilPreCommitted(..) => (),
+        }
+        data
+    }
+
+    /// Encodes the message in the compact layout of the protocol version 2,
+    /// which is `to_compact()` without the protocol version and with the tags from `0x10`.
+    #[cfg(test)]
+    pub(crate) fn to_unversioned_compact(&self) -> Vec<u8> {
+        let mut data = self.to_compact();
+        data.drain(1..3);
+        data[0] -= PROPOSAL_TAG - UNVERSIONED_TAGS.start();
+        data
+    }
+
+    /// Returns the prefixes of `to_compact()` that the messages of the kind in the round
+    /// start with, which select them without knowing the block hashes (e.g., for a fetch).
+    pub(crate) fn compact_prefixes(
+        height: BlockHeight,
+        round: ConsensusRound,
+        kind: VoteKind,
+    ) -> Vec<Vec<u8>> {
+        let tags: &[u8] = match kind {
+            VoteKind::Proposal => &[PROPOSAL_TAG],
+            VoteKind::Prevote => &[NON_NIL_PREVOTED_TAG, NIL_PREVOTED_TAG],
+            VoteKind::Precommit => &[NON_NIL_PRECOMMITTED_TAG, NIL_PRECOMMITTED_TAG],
+        };
+        tags.iter()
+            .map(|tag| {
+                let mut prefix = vec![*tag];
+                prefix.extend_from_slice(&CONSENSUS_PROTOCOL_VERSION.to_be_bytes());
+                prefix.extend_from_slice(&height.to_be_bytes());
+                prefix.extend_from_slice(&round.to_be_bytes());
+                prefix
+            })
+            .collect()
+    }
+
+    /// Decodes the message encoded by `to_compact()`, failing on any trailing data
+    /// or on another protocol version.
+    pub fn from_compact(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data };
+        let tag = reader.take::<1>()?[0];
+        let version = u16::from_be_bytes(reader.take()?);
+        if version != CONSENSUS_PROTOCOL_VERSION {
+            return Err(version_mismatch(version));
+        }
+        let height = u64::from_be_bytes(reader.take()?);
+        let round = u64::from_be_bytes(reader.take()?);
+        let message = match tag {
+            PROPOSAL_TAG => {
+                let valid_round = match reader.take::<1>()?[0] {
+                    0x00 => None,
+                    0x01 => Some(u64::from_be_bytes(reader.take()?)),
+                    flag => return Err(format!("invalid flag of the valid round {flag:#04x}")),
+                };
+                ConsensusMessage::Proposal {
+                    height,
+                    round,
+                    valid_round,
+                    block_hash: Hash256::from_array(reader.take()?),
+                }
+            }
+            NON_NIL_PREVOTED_TAG => {
+                ConsensusMessage::NonNilPreVoted(height, round, Hash256::from_array(reader.take()?))
+            }
+            NON_NIL_PRECOMMITTED_TAG => ConsensusMessage::NonNilPreCommitted(
+                height,
+                round,
+                Hash256::from_array(reader.take()?),
+            ),
+            NIL_PREVOTED_TAG => ConsensusMessage::NilPreVoted(height, round),
+            NIL_PRECOMMITTED_TAG => ConsensusMessage::NilPreCommitted(height, round),
+            tag => return Err(format!("unknown tag {tag:#04x}")),
+        };
+        if !reader.data.is_empty() {
+            return Err(format!("{} bytes of trailing data", reader.data.len()));
+        }
+        Ok(message)
+    }
+
+    /// Reads the protocol version of the message received from the network,
+    /// without decoding the rest.
+    ///
+    /// The ones before the version field are told by the encoding: the version 2 by the tags
+    /// from `0x10`, and the versions 0 and 1 by the legacy encoding, reported as 1 since
+    /// they differ only in the signature.
+    pub fn wire_version(data: &[u8]) -> Result<u16, String> {
+        match data.first() {
+            Some(tag) if (PROPOSAL_TAG..=NIL_PRECOMMITTED_TAG).contains(tag) => {
+                let mut reader = Reader { data: &data[1..] };
+                Ok(u16::from_be_bytes(reader.take()?))
+            }
+            Some(tag) if UNVERSIONED_TAGS.contains(tag) => Ok(2),
+            Some(0x00..=0x04) => Ok(1),
+            Some(tag) => Err(format!("unknown tag {tag:#04x}")),
+            None => Err("empty message".to_owned()),
+        }
+    }
+
+    /// Decodes the message received from the network, either in the compact encoding
+    /// or in the legacy one (`serde_spb`).
+    ///
+    /// Each message has only one encoding accepted in each, which is what it is hashed
+    /// and signed over, so that the equivalent encodings can't pass the filter as the
+    /// different messages; any other one (e.g., with the trailing data) is rejected.
+    pub(crate) fn from_wire(data: &[u8]) -> Result<Self, String> {
+        if data
+            .first()
+            .is_some_and(|tag| (PROPOSAL_TAG..=NIL_PRECOMMITTED_TAG).contains(tag))
+        {
+            return Self::from_compact(data);
+        }
+        let message = serde_spb::from_slice::<ConsensusMessage>(data)
+            .map_err(|e| format!("can't decode the message: {e}"))?;
+        // Unlike the compact one, whose layout admits only one encoding,
+        // it is checked by encoding the message again.
+        let canonical = serde_spb::to_vec(&message)
+            .map_err(|e| format!("can't encode the message again: {e}"))?;
+        if canonical != data {
+            return Err(if data.starts_with(&canonical) {
+                format!("{} bytes of trailing data", data.len() - canonical.len())
+            } else {
+                "not in the canonical encoding".to_owned()
+            });
+        }
+        Ok(message)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.data.len() < N {
+            return Err(format!(
+                "expected {N} more bytes, but only {} left",
+                self.data.len()
+            ));
+        }
+        let (taken, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(taken.try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_prefixes() {
+        let block_hash = Hash256::hash("block");
+        let matches = |message: ConsensusMessage, round, kind| {
+            ConsensusMessage::compact_prefixes(1, round, kind)
+                .iter()
+                .any(|prefix| message.to_compact().starts_with(prefix))
+        };
+        for kind in [VoteKind::Proposal, VoteKind::Prevote, VoteKind::Precommit] {
+            for round in [2, 3] {
+                for message in [
+                    ConsensusMessage::Proposal {
+                        height: 1,
+                        round: 2,
+                        valid_round: None,
+                        block_hash,
+                    },
+                    ConsensusMessage::NonNilPreVoted(1, 2, block_hash),
+                    ConsensusMessage::NilPreVoted(1, 2),
+                    ConsensusMessage::NonNilPreCommitted(1, 2, block_hash),
+                    ConsensusMessage::NilPreCommitted(1, 2),
+                    ConsensusMessage::NilPreCommitted(2, 2),
+                ] {
+                    let expected = message.height() == 1
+                        && message.vote_key().0 == round
+                        && message.vote_key().1 == kind;
+                    assert_eq!(
+                        matches(message.clone(), round, kind),
+                        expected,
+                        "{message:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Pins the compact layout, which the other implementations must follow.
+    #[test]
+    fn golden_vectors() {
+        let block_hash = Hash256::from_array([0xab; 32]);
+        let hash_hex = "ab".repeat(32);
+        for (message, expected) in [
+            (
+                ConsensusMessage::Proposal {
+                    height: 1,
+                    round: 2,
+                    valid_round: Some(1),
+                    block_hash,
+                },
+                format!("20000300000000000000010000000000000002010000000000000001{hash_hex}"),
+            ),
+            (
+                ConsensusMessage::Proposal {
+                    height: 1,
+                    round: 2,
+                    valid_round: None,
+                    block_hash,
+                },
+                format!("2000030000000000000001000000000000000200{hash_hex}"),
+            ),
+            (
+                ConsensusMessage::NonNilPreVoted(0x0102, 3, block_hash),
+                format!("21000300000000000001020000000000000003{hash_hex}"),
+            ),
+            (
+                ConsensusMessage::NonNilPreCommitted(1, 0, block_hash),
+                format!("22000300000000000000010000000000000000{hash_hex}"),
+            ),
+            (
+                ConsensusMessage::NilPreVoted(1, u64::MAX),
+                "2300030000000000000001ffffffffffffffff".to_owned(),
+            ),
+            (
+                ConsensusMessage::NilPreCommitted(1, 2),
+                "24000300000000000000010000000000000002".to_owned(),
+            ),
+        ] {
+            let compact = message.to_compact();
+            assert_eq!(hex::encode(&compact), expected, "{message:?}");
+            assert_eq!(ConsensusMessage::from_compact(&compact).unwrap(), message);
+            assert_eq!(ConsensusMessage::from_wire(&compact).unwrap(), message);
+            assert_eq!(ConsensusMessage::wire_version(&compact).unwrap(), 3);
+            // The legacy encoding is still accepted.
+            let legacy = serde_spb::to_vec(&message).unwrap();
+            assert_eq!(ConsensusMessage::from_wire(&legacy).unwrap(), message);
+            assert_eq!(ConsensusMessage::wire_version(&legacy).unwrap(), 1);
+            assert!(compact.len() < legacy.len());
+            let unversioned = message.to_unversioned_compact();
+            assert_eq!(unversioned[0], compact[0] - 0x10);
+            assert_eq!(unversioned[1..], compact[3..]);
+            assert_eq!(ConsensusMessage::wire_version(&unversioned).unwrap(), 2);
+        }
+        // The hash covers the compact encoding.
+        let message = ConsensusMessage::NilPreCommitted(1, 2);
+        assert_eq!(message.to_hash256(), Hash256::hash(message.to_compact()));
+    }
+
+    #[test]
+    fn other_version() {
+        let mut compact = ConsensusMessage::NilPreVoted(1, 2).to_compact();
+        compact[1..3].copy_from_slice(&4u16.to_be_bytes());
+        assert_eq!(ConsensusMessage::wire_version(&compact).unwrap(), 4);
+        let error = ConsensusMessage::from_compact(&compact).unwrap_err();
+        assert!(error.contains("protocol version 4"), "{error}");
+        assert!(error.contains("version 3"), "{error}");
+        assert!(ConsensusMessage::from_wire(&compact).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let compact = ConsensusMessage::NilPreVoted(1, 2).to_compact();
+        let mut trailing = compact.clone();
+        trailing.push(0);
+        assert!(ConsensusMessage::from_wire(&trailing).is_err());
+        assert!(ConsensusMessage::from_wire(&compact[..compact.len() - 1]).is_err());
+        assert!(ConsensusMessage::from_wire(&[]).is_err());
+        // An unknown tag is taken as the legacy encoding, which fails.
+        let mut unknown = compact;
+        unknown[0] = 0x25;
+        assert!(ConsensusMessage::from_wire(&unknown).is_err());
+        assert!(ConsensusMessage::from_compact(&unknown).is_err());
+        assert!(ConsensusMessage::wire_version(&unknown).is_err());
+        assert!(ConsensusMessage::wire_version(&[]).is_err());
+        assert!(ConsensusMessage::wire_version(&[NIL_PREVOTED_TAG, 0]).is_err());
+
+        let mut proposal = ConsensusMessage::Proposal {
+            height: 1,
+            round: 2,
+            valid_round: None,
+            block_hash: Hash256::zero(),
+        }
+        .to_compact();
+        proposal[19] = 0x02;
+        assert!(ConsensusMessage::from_compact(&proposal).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+use super::*;
+
+/// The votes in a round, tallied from the consensus messages in the DMS.
+///
+/// A validator who has signed conflicting votes is counted for each of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteTally {
+    pub round: ConsensusRound,
+    /// The voting power of the prevotes, by the block hash (`None` for nil).
+    pub prevotes: BTreeMap<Option<Hash256>, VotingPower>,
+    /// The voting power of the precommits, by the block hash (`None` for nil).
+    pub precommits: BTreeMap<Option<Hash256>, VotingPower>,
+    /// The validators that have neither prevoted nor precommitted, in the order of the set.
+    pub missing_validators: Vec<PublicKey>,
+    /// The names of `missing_validators`, empty for the ones without.
+    pub missing_validator_names: Vec<String>,
+    /// The voting power of the whole validator set.
+    pub total_voting_power: VotingPower,
+}
+
+impl VoteTally {
+    pub(crate) fn new(
+        messages: &[dms::Message<ConsensusMessage>],
+        validator_set: &[(PublicKey, VotingPower)],
+        height: BlockHeight,
+        round: ConsensusRound,
+    ) -> Self {
+        let validators = validator_set.iter().cloned().collect::<BTreeMap<_, _>>();
+        let mut prevotes = BTreeMap::new();
+        let mut precommits = BTreeMap::new();
+        let mut voted_validators = BTreeSet::new();
+        for message in messages {
+            if message.message.height() != height {
+                continue;
+            }
+            let (tally, block_hash) = match message.message {
+                ConsensusMessage::NonNilPreVoted(_, r, block_hash) if r == round => {
+                    (&mut prevotes, Some(block_hash))
+                }
+                ConsensusMessage::NilPreVoted(_, r) if r == round => (&mut prevotes, None),
+                ConsensusMessage::NonNilPreCommitted(_, r, block_hash) if r == round => {
+                    (&mut precommits, Some(block_hash))
+                }
+                ConsensusMessage::NilPreCommitted(_, r) if r == round => (&mut precommits, None),
+                _ => continue,
+            };
+            for commitment in &message.committers {
+                if let Some(power) = validators.get(&commitment.committer) {
+                    // Saturating, since a validator signing conflicting votes counts for each.
+                    let entry = tally.entry(block_hash).or_insert(0);
+                    *entry = power.saturating_add(*entry);
+                    voted_validators.insert(commitment.committer.clone());
+                }
+            }
+        }
+        Self {
+            round,
+            prevotes,
+            precommits,
+            missing_validators: validator_set
+                .iter()
+                .filter(|(public_key, _)| !voted_validators.contains(public_key))
+                .map(|(public_key, _)| public_key.clone())
+                .collect(),
+            // Filled by `Consensus`, which has the information of the validators.
+            missing_validator_names: Vec::new(),
+            total_voting_power: VotingPower::try_from(total_voting_power(validator_set))
+                .unwrap_or(VotingPower::MAX),
+        }
+    }
+
+    /// Returns whether the prevotes or the precommits on a block reach the quorum.
+    pub(crate) fn has_quorum_on_block(&self, quorum: Quorum) -> bool {
+        self.prevotes
+            .iter()
+            .chain(self.precommits.iter())
+            .any(|(block_hash, voting_power)| {
+                block_hash.is_some()
+                    && quorum.is_reached(*voting_power as u128, self.total_voting_power as u128)
+            })
+    }
+}
+
+/// Returns the validators that have signed no message of the kind in the round,
+/// in the order of the set, counting the ones signed by the delegated keys.
+pub(crate) fn missing_signers(
+    messages: &[dms::Message<ConsensusMessage>],
+    validator_set: &[(PublicKey, VotingPower)],
+    delegations: &Delegations,
+    height: BlockHeight,
+    round: ConsensusRound,
+    kind: VoteKind,
+) -> Vec<PublicKey> {
+    let signers = messages
+        .iter()
+        .filter(|message| {
+            message.message.height() == height && message.message.vote_key() == (round, kind)
+        })
+        .flat_map(|message| message.committers.iter())
+        .filter_map(|commitment| delegations.resolve(&commitment.committer, height))
+        .collect::<BTreeSet<_>>();
+    validator_set
+        .iter()
+        .filter(|(validator, _)| !signers.contains(validator))
+        .map(|(validator, _)| validator.clone())
+        .collect()
+}
+
+/// Returns the total voting power of the validator set, computed in `u128` not to overflow.
+pub fn total_voting_power(validator_set: &[(PublicKey, VotingPower)]) -> u128 {
+    validator_set.iter().map(|(_, power)| *power as u128).sum()
+}
+
+/// How a validator has participated in the rounds of a `LivenessReport`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorLiveness {
+    pub validator: PublicKey,
+    /// The name of the validator, empty if not given.
+    pub name: String,
+    pub voting_power: VotingPower,
+    /// The number of the rounds in which the validator has signed any consensus message.
+    pub active_rounds: u64,
+    /// The number of the rounds in which the validator has signed nothing.
+    pub silent_rounds: u64,
+}
+
+/// The participation of the validators in the recent rounds, to find the unresponsive ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LivenessReport {
+    /// The first round of the window.
+    pub first_round: ConsensusRound,
+    /// The last round of the window, which is the current round.
+    pub last_round: ConsensusRound,
+    /// The validators in the order of the set.
+    pub validators: Vec<ValidatorLiveness>,
+}
+
+impl LivenessReport {
+    pub(crate) fn new(
+        messages: &[dms::Message<ConsensusMessage>],
+        validator_set: &[(PublicKey, VotingPower)],
+        height: BlockHeight,
+        first_round: ConsensusRound,
+        last_round: ConsensusRound,
+    ) -> Self {
+        let mut active = BTreeSet::new();
+        for message in messages {
+            let round = message.message.vote_key().0;
+            if message.message.height() != height || round < first_round || round > last_round {
+                continue;
+            }
+            for commitment in &message.committers {
+                active.insert((commitment.committer.clone(), round));
+            }
+        }
+        let rounds = last_round - first_round + 1;
+        let validators = validator_set
+            .iter()
+            .map(|(validator, voting_power)| {
+                let active_rounds = (first_round..=last_round)
+                    .filter(|round| active.contains(&(validator.clone(), *round)))
+                    .count() as u64;
+                ValidatorLiveness {
+                    validator: validator.clone(),
+                    name: String::new(),
+                    voting_power: *voting_power,
+                    active_rounds,
+                    silent_rounds: rounds - active_rounds,
+                }
+            })
+            .collect();
+        Self {
+            first_round,
+            last_round,
+            validators,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum() {
+        let max = VotingPower::MAX as u128;
+        for quorum in quorums() {
+            for total in [1, 2, 3, 4, 5, 6, 7, 10, 100, max / 6, max, max * 4] {
+                let threshold = quorum.threshold(total);
+                assert!(quorum.is_reached(threshold, total), "{quorum:?} {total}");
+                assert!(
+                    !quorum.is_reached(threshold - 1, total),
+                    "{quorum:?} {total}"
+                );
+                assert!(threshold <= total);
+            }
+        }
+        let two_thirds = Quorum::TWO_THIRDS;
+        assert_eq!(two_thirds.threshold(3), 3);
+        assert_eq!(two_thirds.threshold(4), 3);
+        assert_eq!(two_thirds.threshold(6), 5);
+        assert_eq!(quorums()[1].threshold(4), 4);
+        assert_eq!(quorums()[1].threshold(5), 5);
+        assert_eq!(quorums()[1].threshold(10), 9);
+        let validator_set = (0..4)
+            .map(|i| {
+                (
+                    generate_keypair(format!("validator{i}")).0,
+                    VotingPower::MAX,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(total_voting_power(&validator_set), max * 4);
+    }
+
+    #[test]
+    fn quorum_on_block() {
+        let block_hash = Hash256::hash("block");
+        let mut tally = VoteTally {
+            round: 0,
+            prevotes: [(None, 3), (Some(block_hash), 2)].into_iter().collect(),
+            precommits: BTreeMap::new(),
+            missing_validators: Vec::new(),
+            missing_validator_names: Vec::new(),
+            total_voting_power: 4,
+        };
+        // A quorum on nil is not.
+        assert!(!tally.has_quorum_on_block(Quorum::TWO_THIRDS));
+        tally.precommits.insert(Some(block_hash), 3);
+        assert!(tally.has_quorum_on_block(Quorum::TWO_THIRDS));
+        assert!(!tally.has_quorum_on_block(quorums()[1]));
+    }
+
+    /// An arbitrary-precision natural number in the little-endian 32-bit limbs,
+    /// as the reference of the quorum arithmetic.
+    #[derive(Debug, Clone, Default)]
+    struct Natural(Vec<u32>);
+
+    impl Natural {
+        fn new(x: u128) -> Self {
+            Self((0..4).map(|i| (x >> (32 * i)) as u32).collect())
+        }
+
+        fn add(&mut self, other: &Self) {
+            let len = self.0.len().max(other.0.len()) + 1;
+            self.0.resize(len, 0);
+            let mut carry = 0;
+            for i in 0..len {
+                let x = self.0[i] as u64 + other.0.get(i).copied().unwrap_or(0) as u64 + carry;
+                self.0[i] = x as u32;
+                carry = x >> 32;
+            }
+        }
+
+        fn mul(&self, k: u32) -> Self {
+            let mut limbs = Vec::new();
+            let mut carry = 0;
+            for limb in &self.0 {
+                let x = *limb as u64 * k as u64 + carry;
+                limbs.push(x as u32);
+                carry = x >> 32;
+            }
+            limbs.push(carry as u32);
+            Self(limbs)
+        }
+
+        fn exceeds(&self, other: &Self) -> bool {
+            let limb = |x: &Self, i: usize| x.0.get(i).copied().unwrap_or(0);
+            (0..self.0.len().max(other.0.len()))
+                .rev()
+                .map(|i| (limb(self, i), limb(other, i)))
+                .find(|(x, y)| x != y)
+                .is_some_and(|(x, y)| x > y)
+        }
+    }
+
+    fn reference_is_quorum(
+        voting_power: &Natural,
+        total_voting_power: &Natural,
+        quorum: Quorum,
+    ) -> bool {
+        voting_power
+            .mul(quorum.denominator)
+            .exceeds(&total_voting_power.mul(quorum.numerator))
+    }
+
+    fn quorums() -> Vec<Quorum> {
+        [(2, 3), (4, 5), (3, 4), (99, 100), (u32::MAX - 1, u32::MAX)]
+            .into_iter()
+            .map(|(numerator, denominator)| Quorum {
+                numerator,
+                denominator,
+            })
+            .collect()
+    }
+
+    /// Compares the quorum arithmetic with the reference for the random validator sets
+    /// whose voting powers are close to `VotingPower::MAX` (or to the share of it).
+    #[test]
+    fn quorum_against_reference() {
+        let keys = (0..16)
+            .map(|i| generate_keypair(format!("validator{i}")).0)
+            .collect::<Vec<_>>();
+        // SplitMix64, to be reproducible without any dependency.
+        let mut seed = 0x5eed_u64;
+        let mut random = move || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        for _ in 0..1000 {
+            let n = random() % 16 + 1;
+            let validator_set = keys[..n as usize]
+                .iter()
+                .map(|key| {
+                    let power = match random() % 4 {
+                        0 => VotingPower::MAX - random() % 16,
+                        1 => VotingPower::MAX / n - random() % 16,
+                        2 => random().max(1),
+                        _ => random() % 16 + 1,
+                    };
+                    (key.clone(), power)
+                })
+                .collect::<Vec<_>>();
+            let voted = validator_set
+                .iter()
+                .filter(|_| random() % 2 == 0)
+                .map(|(_, power)| *power)
+                .collect::<Vec<_>>();
+
+            let mut reference_total = Natural::default();
+            for (_, power) in &validator_set {
+                reference_total.add(&Natural::new(*power as u128));
+            }
+            let mut reference_voted = Natural::default();
+            for power in &voted {
+                reference_voted.add(&Natural::new(*power as u128));
+            }
+            let total = total_voting_power(&validator_set);
+            let voted = voted.iter().map(|power| *power as u128).sum::<u128>();
+            // The same total
+            assert!(
+                !reference_total.exceeds(&Natural::new(total))
+                    && !Natural::new(total).exceeds(&reference_total)
+            );
+            for quorum in quorums() {
+                assert_eq!(
+                    quorum.is_reached(voted, total),
+                    reference_is_quorum(&reference_voted, &reference_total, quorum),
+                    "{quorum:?} {validator_set:?}"
+                );
+                let threshold = quorum.threshold(total);
+                let reached = |x| reference_is_quorum(&Natural::new(x), &reference_total, quorum);
+                assert!(reached(threshold) && !reached(threshold - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn tally() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .enumerate()
+            .map(|(i, (public_key, _))| (public_key.clone(), i as VotingPower + 1))
+            .collect::<Vec<_>>();
+        let dms_key = "consensus-test".to_owned();
+        let block_hash = Hash256::hash("block");
+        let message = |message: ConsensusMessage, signers: &[usize]| dms::Message {
+            committers: signers
+                .iter()
+                .map(|&i| message.commit(&dms_key, &keys[i].1).unwrap())
+                .collect(),
+            message,
+        };
+        let messages = vec![
+            message(ConsensusMessage::NonNilPreVoted(1, 0, block_hash), &[0, 1]),
+            message(ConsensusMessage::NilPreVoted(1, 0), &[1]),
+            message(ConsensusMessage::NonNilPreCommitted(1, 0, block_hash), &[0]),
+            // Other rounds and heights
+            message(ConsensusMessage::NilPreVoted(1, 1), &[2]),
+            message(ConsensusMessage::NilPreVoted(2, 0), &[3]),
+            message(
+                ConsensusMessage::Proposal {
+                    height: 1,
+                    round: 0,
+                    valid_round: None,
+                    block_hash,
+                },
+                &[2],
+            ),
+        ];
+
+        let tally = VoteTally::new(&messages, &validator_set, 1, 0);
+        assert_eq!(
+            tally.prevotes,
+            vec![(None, 2), (Some(block_hash), 3)].into_iter().collect()
+        );
+        assert_eq!(
+            tally.precommits,
+            vec![(Some(block_hash), 1)].into_iter().collect()
+        );
+        assert_eq!(
+            tally.missing_validators,
+            vec![keys[2].0.clone(), keys[3].0.clone()]
+        );
+        assert_eq!(tally.total_voting_power, 10);
+
+        let tally = VoteTally::new(&messages, &validator_set, 1, 1);
+        assert_eq!(tally.prevotes, vec![(None, 3)].into_iter().collect());
+        assert!(tally.precommits.is_empty());
+        assert_eq!(tally.missing_validators.len(), 3);
+    }
+
+    #[test]
+    fn liveness() {
+        let keys = (0..3)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let validator_set = keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect::<Vec<_>>();
+        let dms_key = "consensus-test".to_owned();
+        let message = |message: ConsensusMessage, signers: &[usize]| dms::Message {
+            committers: signers
+                .iter()
+                .map(|&i| message.commit(&dms_key, &keys[i].1).unwrap())
+                .collect(),
+            message,
+        };
+        let messages = vec![
+            message(
+                ConsensusMessage::Proposal {
+                    height: 1,
+                    round: 1,
+                    valid_round: None,
+                    block_hash: Hash256::hash("block"),
+                },
+                &[1],
+            ),
+            message(ConsensusMessage::NilPreVoted(1, 0), &[0]),
+            message(ConsensusMessage::NilPreCommitted(1, 0), &[0]),
+            message(ConsensusMessage::NilPreVoted(1, 1), &[0]),
+            message(ConsensusMessage::NilPreVoted(1, 2), &[0, 1]),
+            // Out of the window
+            message(ConsensusMessage::NilPreVoted(1, 3), &[2]),
+            message(ConsensusMessage::NilPreVoted(2, 1), &[2]),
+        ];
+
+        let report = LivenessReport::new(&messages, &validator_set, 1, 0, 2);
+        let activity = report
+            .validators
+            .iter()
+            .map(|v| (v.active_rounds, v.silent_rounds))
+            .collect::<Vec<_>>();
+        assert_eq!(activity, vec![(3, 0), (2, 1), (0, 3)]);
+        assert_eq!(report.validators[2].validator, keys[2].0);
+        assert_eq!(report.validators[2].voting_power, 1);
+    }
+}
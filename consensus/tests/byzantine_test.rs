@@ -0,0 +1,376 @@
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+mod common;
+use common::*;
+
+/// The last validator prevotes for two blocks in the first round,
+/// which the honest nodes report while finalizing the proposed one.
+#[tokio::test]
+async fn byzantine_double_prevote_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hashes = (0..2)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    for node in nodes.iter_mut() {
+        for block_hash in block_hashes.iter() {
+            node.register_verified_block_hash(*block_hash)
+                .await
+                .unwrap();
+        }
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hashes[0], 0)
+        .await
+        .unwrap();
+    let (honest, byzantine) = nodes.split_at_mut(3);
+    let mut byzantine = ByzantineConsensus::new(&mut byzantine[0]).await.unwrap();
+    let offender = byzantine.public_key().clone();
+
+    byzantine
+        .double_prevote(0, block_hashes[0], block_hashes[1])
+        .await
+        .unwrap();
+    exchange(honest, &network).await;
+    for node in honest.iter_mut() {
+        let results = node.progress(0).await.unwrap();
+        assert!(results.iter().any(|result| matches!(
+            result,
+            ProgressResult::ViolationReported(violation, _)
+                if violation.violator == offender && violation.kind == ViolationKind::DoublePrevote
+        )));
+        let evidence = node.list_evidence().await.unwrap();
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(evidence[0].offender, offender);
+    }
+
+    // Replaying the same messages is not another violation.
+    assert_eq!(byzantine.replay_round(0).await.unwrap(), 2);
+    exchange(honest, &network).await;
+    for node in honest.iter() {
+        assert_eq!(node.list_evidence().await.unwrap().len(), 1);
+    }
+
+    for _ in 0..4 {
+        step(honest, &network, 0).await;
+    }
+    for node in honest.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hashes[0]);
+    }
+}
+
+/// The last validator proposes out of its turn and prevotes for a block that no one has verified,
+/// which are ignored and filtered respectively.
+#[tokio::test]
+async fn byzantine_proposal_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let height = fi.header.height + 1;
+    let block_hashes = (0..3)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    // The last one is never verified.
+    for node in nodes.iter_mut() {
+        for block_hash in block_hashes[0..2].iter() {
+            node.register_verified_block_hash(*block_hash)
+                .await
+                .unwrap();
+        }
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hashes[0], 0)
+        .await
+        .unwrap();
+    let (honest, byzantine) = nodes.split_at_mut(3);
+    let mut byzantine = ByzantineConsensus::new(&mut byzantine[0]).await.unwrap();
+
+    byzantine.propose(0, block_hashes[1]).await.unwrap();
+    byzantine
+        .prevote_unverified(0, block_hashes[2])
+        .await
+        .unwrap();
+    exchange(honest, &network).await;
+    let unverified_prevote = ConsensusMessage::NonNilPreVoted(height, 0, block_hashes[2]);
+    for node in honest.iter_mut() {
+        let messages = node.get_dms().read().await.read_messages().await.unwrap();
+        assert!(messages.iter().any(|message| matches!(
+            message.message,
+            ConsensusMessage::Proposal { block_hash, .. } if block_hash == block_hashes[1]
+        )));
+        assert!(messages
+            .iter()
+            .all(|message| message.message != unverified_prevote));
+        let results = node.progress(0).await.unwrap();
+        assert!(!results.iter().any(|result| matches!(
+            result,
+            ProgressResult::NonNilPreVoted(_, block_hash, _) if *block_hash == block_hashes[1]
+        )));
+    }
+
+    for _ in 0..4 {
+        step(honest, &network, 0).await;
+    }
+    for node in honest.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hashes[0]);
+    }
+}
+
+/// The last validator signs a prevote for every block, of which only the first two are admitted
+/// by the others, enough to prove the equivocation.
+#[tokio::test]
+async fn byzantine_spam_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hashes = (0..10)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    for node in nodes.iter_mut() {
+        for block_hash in block_hashes.iter() {
+            node.register_verified_block_hash(*block_hash)
+                .await
+                .unwrap();
+        }
+    }
+    let (honest, byzantine) = nodes.split_at_mut(3);
+    let mut byzantine = ByzantineConsensus::new(&mut byzantine[0]).await.unwrap();
+    let offender = byzantine.public_key().clone();
+    for block_hash in block_hashes.iter() {
+        byzantine.prevote_unverified(0, *block_hash).await.unwrap();
+    }
+    exchange(honest, &network).await;
+
+    for node in honest.iter_mut() {
+        node.progress(0).await.unwrap();
+        let prevotes = node
+            .get_dms()
+            .read()
+            .await
+            .read_messages()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|message| {
+                matches!(message.message, ConsensusMessage::NonNilPreVoted(..))
+                    && message
+                        .committers
+                        .iter()
+                        .any(|commitment| commitment.committer == offender)
+            })
+            .count();
+        assert_eq!(prevotes, MAX_MESSAGES_PER_VOTE);
+        let evidence = node.list_evidence().await.unwrap();
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(evidence[0].offender, offender);
+    }
+}
+
+/// A node that admits no message as large as a consensus message
+/// hears nothing from the others, who still finalize without it.
+#[tokio::test]
+async fn max_message_size_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    nodes[3].set_max_message_size(8).await.unwrap();
+
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes[..3].iter() {
+        assert!(node.check_finalized().await.unwrap().is_some());
+    }
+    assert!(nodes[3].check_finalized().await.unwrap().is_none());
+    let public_key = &fi.header.validator_set[3].0;
+    let messages = nodes[3]
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap();
+    assert!(messages.iter().all(|message| message
+        .committers
+        .iter()
+        .all(|commitment| commitment.committer == *public_key)));
+}
+
+/// The prevote a few rounds ahead is kept to be counted in its round,
+/// while the one far ahead is rejected.
+#[tokio::test]
+async fn far_future_round_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    let (honest, byzantine) = nodes.split_at_mut(3);
+    let mut byzantine = ByzantineConsensus::new(&mut byzantine[0]).await.unwrap();
+    byzantine.prevote_unverified(3, block_hash).await.unwrap();
+    byzantine.prevote_unverified(500, block_hash).await.unwrap();
+    exchange(honest, &network).await;
+
+    for node in honest.iter() {
+        let tally = node.vote_tally(3).await.unwrap();
+        assert_eq!(
+            tally.prevotes,
+            vec![(Some(block_hash), 1)].into_iter().collect()
+        );
+        assert!(node.vote_tally(500).await.unwrap().prevotes.is_empty());
+    }
+}
+
+/// The proposal that arrives before its block is verified is held back
+/// and admitted once the block is registered.
+#[tokio::test]
+async fn quarantine_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    for node in nodes[1..].iter_mut() {
+        node.progress(0).await.unwrap();
+    }
+    let block_hash = Hash256::hash("block");
+    nodes[0]
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let results = nodes[0].progress(0).await.unwrap();
+    assert!(results.iter().any(|result| matches!(
+        result,
+        ProgressResult::Proposed(0, x, ..) if *x == block_hash
+    )));
+    exchange(&mut nodes, &network).await;
+    // The proposal and the prevote of the proposer on it
+    for node in nodes[1..].iter() {
+        assert_eq!(node.status().await.unwrap().quarantined_messages, 2);
+    }
+
+    for node in nodes[1..].iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        assert_eq!(node.status().await.unwrap().quarantined_messages, 0);
+        node.update().await.unwrap();
+        let results = node.progress(0).await.unwrap();
+        assert!(results.iter().any(|result| matches!(
+            result,
+            ProgressResult::NonNilPreVoted(0, x, _) if *x == block_hash
+        )));
+    }
+}
+
+/// A peer delivering only the messages of a non-validator gets banned,
+/// while the others finalize by fetching from each other.
+#[tokio::test]
+async fn peer_ban_1() {
+    setup_test();
+    let (garbage_public_key, garbage_private_key) = generate_keypair("garbage");
+    let (mut nodes, mut network, fi) = NodesBuilder::new(4)
+        .other_members(vec![garbage_public_key.clone()])
+        .build_gossiping()
+        .await;
+    let (_, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .chain(std::iter::once(garbage_public_key.clone()))
+        .collect::<Vec<_>>();
+    let garbage = Arc::new(RwLock::new(
+        create_test_dms::<ConsensusMessage>("consensus".to_owned(), members, garbage_private_key)
+            .await,
+    ));
+    for round in 0..20 {
+        garbage
+            .write()
+            .await
+            .commit_message(&ConsensusMessage::NilPreVoted(fi.header.height + 1, round))
+            .await
+            .unwrap();
+    }
+    network.add_node(garbage);
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        node.set_peer_ban_policy(PeerBanPolicy {
+            max_rejections: 10,
+            ban_duration_ms: 60_000,
+        });
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    for _ in 0..10 {
+        for node in nodes.iter_mut() {
+            if node.check_finalized().await.unwrap().is_none() {
+                node.progress(0).await.unwrap();
+                node.flush().await.unwrap();
+            }
+        }
+        for node in nodes.iter_mut() {
+            if node.check_finalized().await.unwrap().is_none() {
+                node.fetch(&network, 0).await.unwrap();
+                node.update().await.unwrap();
+            }
+        }
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+        assert_eq!(
+            node.status().await.unwrap().banned_peers,
+            vec![(garbage_public_key.clone(), 60_000)]
+        );
+    }
+
+    // The expired ban is lifted, and then renewed since the peer still delivers the same.
+    nodes[0].fetch(&network, 60_000).await.unwrap();
+    assert_eq!(
+        nodes[0].status().await.unwrap().banned_peers,
+        vec![(garbage_public_key, 120_000)]
+    );
+}
+
+/// The nodes finalize the same regardless of how many signatures they verify together.
+#[tokio::test]
+async fn verification_batch_size_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for (node, batch_size) in nodes
+        .iter_mut()
+        .zip([1, 2, DEFAULT_VERIFICATION_BATCH_SIZE, 1000])
+    {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        node.set_verification_batch_size(batch_size).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        verify_finalization_proof(&block_hash, &finalization.proof, &fi.header.validator_set)
+            .unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! The helpers shared by the consensus tests, each of which uses only some of them.
+#![allow(dead_code)]
+
+use simperby_consensus::simulation::{seed_from_env, Simulation, StepOrder};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type MockNetwork = dms::MockGossipNetwork<StorageImpl, ConsensusMessage>;
+
+/// The parameters of the most tests, whose timeout never expires unless they advance the time.
+pub fn default_params() -> ConsensusParams {
+    ConsensusParams {
+        timeout_ms: 6000,
+        timeout_increment_ms: 0,
+        repeat_round_for_first_leader: 10,
+        quorum: None,
+    }
+}
+
+/// Signs with the key of the node in memory.
+pub fn signer(key: Option<PrivateKey>) -> Option<Arc<dyn ConsensusSigner>> {
+    key.map(|key| Arc::new(key) as Arc<dyn ConsensusSigner>)
+}
+
+/// Creates the DMS of a node in memory, of the members and with the key of the node.
+pub async fn create_dms(
+    members: Vec<PublicKey>,
+    private_key: PrivateKey,
+) -> Arc<RwLock<Dms<ConsensusMessage>>> {
+    Arc::new(RwLock::new(
+        create_test_dms("consensus".to_owned(), members, private_key).await,
+    ))
+}
+
+/// Builds the consensus nodes of a test chain, with their storages and DMSes in memory.
+///
+/// The nodes of the validators come first, followed by the ones of the observers.
+pub struct NodesBuilder {
+    validators: usize,
+    keys: Option<Vec<(PublicKey, PrivateKey)>>,
+    observers: usize,
+    params: ConsensusParams,
+    other_members: Vec<PublicKey>,
+}
+
+impl NodesBuilder {
+    /// The validators of `test_utils::generate_fi()`.
+    pub fn new(validators: usize) -> Self {
+        Self {
+            validators,
+            keys: None,
+            observers: 0,
+            params: default_params(),
+            other_members: Vec::new(),
+        }
+    }
+
+    /// The validators of the given keys, which may mix the schemes.
+    pub fn with_keys(keys: Vec<(PublicKey, PrivateKey)>) -> Self {
+        Self {
+            keys: Some(keys),
+            ..Self::new(0)
+        }
+    }
+
+    /// The observers, which sync the DMSes of the validators in turn.
+    pub fn observers(mut self, observers: usize) -> Self {
+        self.observers = observers;
+        self
+    }
+
+    pub fn params(mut self, params: ConsensusParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// The other members than the validators, whose messages the DMSes admit
+    /// and leave to the consensus to reject.
+    pub fn other_members(mut self, other_members: Vec<PublicKey>) -> Self {
+        self.other_members = other_members;
+        self
+    }
+
+    /// Creates the nodes without any network, with the keys of the validators.
+    pub async fn build(
+        self,
+    ) -> (
+        Vec<(Consensus<MemoryStorage>, Option<PrivateKey>)>,
+        FinalizationInfo,
+    ) {
+        let (fi, keys) = match self.keys {
+            Some(keys) => test_utils::generate_fi_with_keys(keys),
+            None => test_utils::generate_fi(self.validators),
+        };
+        let validators = keys.len();
+        let members = keys
+            .iter()
+            .map(|(public_key, _)| public_key.clone())
+            .chain(self.other_members)
+            .collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+        for i in 0..(validators + self.observers) {
+            let storage = MemoryStorage::new().await;
+            let dms_key = keys[i % validators].1.clone();
+            let this_node_key = (i < validators).then(|| keys[i].1.clone());
+            nodes.push((
+                Consensus::new(
+                    create_dms(members.clone(), dms_key).await,
+                    storage,
+                    fi.header.clone(),
+                    self.params.clone(),
+                    0,
+                    ConsensusConfig {
+                        this_node_signer: signer(this_node_key.clone()),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap(),
+                this_node_key,
+            ));
+        }
+        (nodes, fi)
+    }
+
+    /// Creates the nodes connected by an in-process `MockGossipNetwork`.
+    pub async fn build_gossiping(
+        self,
+    ) -> (Vec<Consensus<MemoryStorage>>, MockNetwork, FinalizationInfo) {
+        let (nodes, fi) = self.build().await;
+        let mut network = MockNetwork::new();
+        let nodes = nodes
+            .into_iter()
+            .map(|(node, _)| {
+                network.add_node(node.get_dms());
+                node
+            })
+            .collect();
+        (nodes, network, fi)
+    }
+
+    /// Creates a simulation of the nodes, where every node has verified the block
+    /// and the first one proposes it.
+    pub async fn build_simulation(
+        self,
+        order: StepOrder,
+    ) -> (Simulation<MemoryStorage>, FinalizationInfo, Hash256) {
+        let (nodes, fi) = self.build().await;
+        let mut nodes = nodes.into_iter().map(|(node, _)| node).collect::<Vec<_>>();
+        let block_hash = Hash256::hash("block");
+        for node in nodes.iter_mut() {
+            node.register_verified_block_hash(block_hash).await.unwrap();
+        }
+        nodes[0]
+            .set_proposal_candidate(block_hash, 0)
+            .await
+            .unwrap();
+        let simulation = Simulation::new(
+            nodes,
+            fi.header.validator_set.clone(),
+            0,
+            order,
+            seed_from_env(),
+        )
+        .await
+        .unwrap();
+        (simulation, fi, block_hash)
+    }
+}
+
+/// Flushes the messages of the nodes, gossips them and updates the nodes not finalized yet.
+pub async fn exchange(nodes: &mut [Consensus<MemoryStorage>], network: &MockNetwork) {
+    for node in nodes.iter_mut() {
+        node.flush().await.unwrap();
+    }
+    network.gossip().await.unwrap();
+    for node in nodes.iter_mut() {
+        if node.check_finalized().await.unwrap().is_none() {
+            node.update().await.unwrap();
+        }
+    }
+}
+
+/// Progresses the nodes not finalized yet and exchanges their messages.
+pub async fn step(
+    nodes: &mut [Consensus<MemoryStorage>],
+    network: &MockNetwork,
+    timestamp: Timestamp,
+) {
+    for node in nodes.iter_mut() {
+        if node.check_finalized().await.unwrap().is_none() {
+            node.progress(timestamp).await.unwrap();
+        }
+    }
+    exchange(nodes, network).await;
+}
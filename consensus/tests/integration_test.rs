@@ -5,6 +5,9 @@ use simperby_test_suite::*;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+mod common;
+use common::*;
+
 #[tokio::test]
 async fn basic_1() {
     setup_test();
@@ -12,9 +15,7 @@ async fn basic_1() {
     let network_id = "consensus".to_string();
     let ((server_network_config, server_private_key), client_network_configs_and_keys, members, fi) =
         setup_server_client_nodes(network_id.clone(), 4).await;
-    let path = create_temp_dir();
-    StorageImpl::create(&path).await.unwrap();
-    let storage = StorageImpl::open(&path).await.unwrap();
+    let storage = MemoryStorage::new().await;
 
     let mut server_node = Consensus::new(
         Arc::new(RwLock::new(
@@ -27,21 +28,19 @@ async fn basic_1() {
         )),
         storage,
         fi.header.clone(),
-        ConsensusParams {
-            timeout_ms: 6000,
-            repeat_round_for_first_leader: 10,
-        },
+        default_params(),
         0,
-        Some(server_private_key),
+        ConsensusConfig {
+            this_node_signer: signer(Some(server_private_key)),
+            ..Default::default()
+        },
     )
     .await
     .unwrap();
 
     let mut client_nodes = Vec::new();
     for (network_config, private_key) in client_network_configs_and_keys {
-        let path = create_temp_dir();
-        StorageImpl::create(&path).await.unwrap();
-        let storage = StorageImpl::open(&path).await.unwrap();
+        let storage = MemoryStorage::new().await;
 
         client_nodes.push((
             Consensus::new(
@@ -50,12 +49,12 @@ async fn basic_1() {
                 )),
                 storage,
                 fi.header.clone(),
-                ConsensusParams {
-                    timeout_ms: 6000,
-                    repeat_round_for_first_leader: 10,
-                },
+                default_params(),
                 0,
-                Some(private_key.clone()),
+                ConsensusConfig {
+                    this_node_signer: signer(Some(private_key.clone())),
+                    ..Default::default()
+                },
             )
             .await
             .unwrap(),
@@ -90,7 +89,7 @@ async fn basic_1() {
         );
     });
 
-    async fn sync(client_nodes: &mut [(Consensus, ClientNetworkConfig)]) {
+    async fn sync(client_nodes: &mut [(Consensus<MemoryStorage>, ClientNetworkConfig)]) {
         for (node, network_config) in client_nodes.iter_mut() {
             node.flush().await.unwrap();
             dms::DistributedMessageSet::broadcast(node.get_dms(), network_config)
@@ -130,12 +129,372 @@ async fn basic_1() {
         node.progress(0).await.unwrap();
     }
     for (node, _) in client_nodes.iter_mut() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+        let proof = node.get_finalization_proof().await.unwrap().unwrap();
+        assert_eq!(proof, finalization.proof);
+        verify_finalization_proof(&block_hash, &proof, &fi.header.validator_set).unwrap();
+        let error = node.progress(0).await.unwrap_err();
+        assert_eq!(error, ConsensusError::Finalized);
+    }
+    serve_task.await.unwrap();
+}
+
+/// Same as `basic_1` but the server node is a non-validator observer running `serve()`.
+#[tokio::test]
+async fn serve_1() {
+    setup_test();
+
+    let network_id = "consensus".to_string();
+    let ((server_network_config, server_private_key), client_network_configs_and_keys, members, fi) =
+        setup_server_client_nodes(network_id.clone(), 4).await;
+    let storage = MemoryStorage::new().await;
+
+    let mut server_node = Consensus::new(
+        Arc::new(RwLock::new(
+            create_test_dms(network_id.clone(), members.clone(), server_private_key).await,
+        )),
+        storage,
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(None),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut client_nodes = Vec::new();
+    for (network_config, private_key) in client_network_configs_and_keys {
+        let storage = MemoryStorage::new().await;
+
+        client_nodes.push((
+            Consensus::new(
+                Arc::new(RwLock::new(
+                    create_test_dms(network_id.clone(), members.clone(), private_key.clone()).await,
+                )),
+                storage,
+                fi.header.clone(),
+                default_params(),
+                0,
+                ConsensusConfig {
+                    this_node_signer: signer(Some(private_key.clone())),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+            network_config,
+        ));
+    }
+
+    let block_hash = Hash256::hash("block");
+    server_node
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    for (node, _) in client_nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+
+    let (serve_task, mut results, read_handle, _, _) = server_node
+        .serve(server_network_config, std::time::Duration::from_millis(200))
+        .await
+        .unwrap();
+    sleep_ms(500).await;
+    assert_eq!(read_handle.current_round(), 0);
+    assert_eq!(read_handle.verified_block_hashes(), vec![block_hash]);
+    assert!(!read_handle.is_finalized());
+
+    client_nodes[0]
+        .0
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // PROPOSE, PREVOTE, PRECOMMIT
+    for _ in 0..3 {
+        for (node, _) in client_nodes.iter_mut() {
+            node.progress(0).await.unwrap();
+        }
+        for (node, network_config) in client_nodes.iter_mut() {
+            node.flush().await.unwrap();
+            dms::DistributedMessageSet::broadcast(node.get_dms(), network_config)
+                .await
+                .unwrap();
+        }
+        for (node, network_config) in client_nodes.iter_mut() {
+            dms::DistributedMessageSet::fetch(node.get_dms(), network_config)
+                .await
+                .unwrap();
+            node.update().await.unwrap();
+        }
+    }
+
+    let finalization = loop {
+        match results.recv().await.unwrap() {
+            ProgressResult::Finalized(finalization) => break finalization,
+            ProgressResult::Proposed(..)
+            | ProgressResult::NonNilPreVoted(..)
+            | ProgressResult::NonNilPreCommitted(..)
+            | ProgressResult::NilPreVoted(..)
+            | ProgressResult::NilPreCommitted(..) => panic!("an observer must not vote"),
+            ProgressResult::VoteObserved { .. }
+            | ProgressResult::ViolationReported(..)
+            | ProgressResult::Stalled { .. }
+            | ProgressResult::RoundAdvanced { .. } => (),
+        }
+    };
+    assert_eq!(finalization.block_hash, block_hash);
+    serve_task.await.unwrap().unwrap();
+    // The sender is dropped once the serving task is finished.
+    while results.recv().await.is_some() {}
+    // The last status stays readable.
+    assert!(read_handle.is_finalized());
+    assert!(read_handle
+        .last_progress_results()
+        .contains(&ProgressResult::Finalized(finalization)));
+}
+
+/// The proposer of the first round gets the candidate through the command while serving.
+#[tokio::test]
+async fn serve_commands_1() {
+    setup_test();
+    let (mut nodes, _) = NodesBuilder::new(4).build().await;
+    let (node, _) = nodes.remove(0);
+    let network_config = ServerNetworkConfig {
+        port: dispense_port(),
+    };
+    let (serve_task, mut results, _, commands, _) = node
+        .serve(network_config, std::time::Duration::from_millis(1000))
+        .await
+        .unwrap();
+
+    async fn send(
+        commands: &ConsensusCommandSender,
+        command: impl FnOnce(CommandResultSender) -> ConsensusCommand,
+    ) -> Result<(), ConsensusError> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        commands.send(command(sender)).await.unwrap();
+        receiver.await.unwrap()
+    }
+    let block_hashes = (0..2)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    // The candidate must be verified first.
+    let error = send(&commands, |x| {
+        ConsensusCommand::SetProposalCandidate(block_hashes[1], 0, x)
+    })
+    .await
+    .unwrap_err();
+    assert_eq!(error, ConsensusError::BlockNotVerified(block_hashes[1]));
+    for block_hash in block_hashes.iter() {
+        send(&commands, |x| {
+            ConsensusCommand::RegisterVerifiedBlockHash(*block_hash, x)
+        })
+        .await
+        .unwrap();
+    }
+    send(&commands, |x| {
+        ConsensusCommand::SetProposalCandidate(block_hashes[1], 0, x)
+    })
+    .await
+    .unwrap();
+
+    match results.recv().await.unwrap() {
+        ProgressResult::Proposed(0, block_hash, ..) => assert_eq!(block_hash, block_hashes[1]),
+        result => panic!("unexpected result: {result:?}"),
+    }
+    serve_task.abort();
+}
+
+/// Three of the four validators reach the consensus, followed by a non-validator observer
+/// which never broadcasts anything but still reports the finalization.
+#[tokio::test]
+async fn observer_1() {
+    setup_test();
+
+    let network_id = "consensus".to_string();
+    let ((server_network_config, server_private_key), client_network_configs_and_keys, members, fi) =
+        setup_server_client_nodes(network_id.clone(), 4).await;
+    let server_dms = Arc::new(RwLock::new(
+        create_test_dms::<ConsensusMessage>(
+            network_id.clone(),
+            members.clone(),
+            server_private_key,
+        )
+        .await,
+    ));
+    let server_task = tokio::spawn(Dms::serve(server_dms, server_network_config));
+
+    let mut nodes = Vec::new();
+    let clients = client_network_configs_and_keys.into_iter().enumerate();
+    for (i, (network_config, private_key)) in clients {
+        let storage = MemoryStorage::new().await;
+        // The last one is the observer.
+        let this_node_key = (i < 3).then(|| private_key.clone());
+        nodes.push((
+            Consensus::new(
+                Arc::new(RwLock::new(
+                    create_test_dms(network_id.clone(), members.clone(), private_key).await,
+                )),
+                storage,
+                fi.header.clone(),
+                default_params(),
+                0,
+                ConsensusConfig {
+                    this_node_signer: signer(this_node_key),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+            network_config,
+        ));
+    }
+
+    let block_hash = Hash256::hash("block");
+    for (node, _) in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .0
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // PROPOSE, PREVOTE, PRECOMMIT
+    for _ in 0..3 {
+        for (i, (node, _)) in nodes.iter_mut().enumerate() {
+            if i < 3 {
+                node.progress(0).await.unwrap();
+            } else {
+                // The timeout of the proposal passes, which would make a validator prevote.
+                assert!(node.progress(10_000).await.unwrap().is_empty());
+            }
+        }
+        for (node, network_config) in nodes.iter_mut() {
+            node.flush().await.unwrap();
+            dms::DistributedMessageSet::broadcast(node.get_dms(), network_config)
+                .await
+                .unwrap();
+        }
+        for (node, network_config) in nodes.iter_mut() {
+            dms::DistributedMessageSet::fetch(node.get_dms(), network_config)
+                .await
+                .unwrap();
+            node.update().await.unwrap();
+        }
+    }
+
+    let (observer, _) = &mut nodes[3];
+    let results = observer.progress(10_000).await.unwrap();
+    let finalization = match &results[..] {
+        [ProgressResult::Finalized(finalization)] => finalization.clone(),
+        _ => panic!("unexpected results: {results:?}"),
+    };
+    assert_eq!(finalization.block_hash, block_hash);
+    verify_finalization_proof(&block_hash, &finalization.proof, &fi.header.validator_set).unwrap();
+    // Nothing is signed with the key of the observer's DMS.
+    for message in observer
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap()
+    {
+        assert!(message
+            .committers
+            .iter()
+            .all(|commitment| commitment.committer != members[3]));
+    }
+    server_task.abort();
+}
+
+/// The proposer of the first round is absent, so the others prevote and precommit nil
+/// on the timeout, moving on to the next round.
+#[tokio::test]
+async fn timeout_propose_1() {
+    setup_test();
+
+    let network_id = "consensus".to_string();
+    let ((server_network_config, server_private_key), client_network_configs_and_keys, members, fi) =
+        setup_server_client_nodes(network_id.clone(), 4).await;
+    let server_dms = Arc::new(RwLock::new(
+        create_test_dms::<ConsensusMessage>(
+            network_id.clone(),
+            members.clone(),
+            server_private_key,
+        )
+        .await,
+    ));
+    let server_task = tokio::spawn(Dms::serve(server_dms, server_network_config));
+
+    let mut nodes = Vec::new();
+    // The first one, the proposer of the first round, never runs.
+    for (network_config, private_key) in client_network_configs_and_keys.into_iter().skip(1) {
+        let storage = MemoryStorage::new().await;
+        nodes.push((
+            Consensus::new(
+                Arc::new(RwLock::new(
+                    create_test_dms(network_id.clone(), members.clone(), private_key.clone()).await,
+                )),
+                storage,
+                fi.header.clone(),
+                default_params(),
+                0,
+                ConsensusConfig {
+                    this_node_signer: signer(Some(private_key)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+            network_config,
+        ));
+    }
+
+    async fn sync(nodes: &mut [(Consensus<MemoryStorage>, ClientNetworkConfig)]) {
+        for (node, network_config) in nodes.iter_mut() {
+            node.flush().await.unwrap();
+            dms::DistributedMessageSet::broadcast(node.get_dms(), network_config)
+                .await
+                .unwrap();
+        }
+        for (node, network_config) in nodes.iter_mut() {
+            dms::DistributedMessageSet::fetch(node.get_dms(), network_config)
+                .await
+                .unwrap();
+            node.update().await.unwrap();
+        }
+    }
+
+    for (node, _) in nodes.iter_mut() {
+        assert!(node.progress(0).await.unwrap().is_empty());
+        // Nothing happens without a message before the timeout.
+        assert!(node.progress(5999).await.unwrap().is_empty());
         assert_eq!(
-            node.check_finalized().await.unwrap().unwrap().block_hash,
-            block_hash
+            node.progress(6000).await.unwrap(),
+            vec![ProgressResult::NilPreVoted(0, 6000)]
         );
     }
-    serve_task.await.unwrap();
+    sync(&mut nodes).await;
+    for (node, _) in nodes.iter_mut() {
+        let results = node.progress(6000).await.unwrap();
+        assert!(matches!(
+            results[..],
+            [ProgressResult::NilPreCommitted(0, _)]
+        ));
+    }
+    sync(&mut nodes).await;
+    for (node, _) in nodes.iter_mut() {
+        node.progress(6000).await.unwrap();
+        let status = node.status().await.unwrap();
+        assert_eq!(status.round, 1);
+        assert!(!status.finalized);
+    }
+    server_task.abort();
 }
 
 /// Same as `basic_1` but all the nodes (including the 'server node') participate in consensus.
@@ -167,3 +526,435 @@ async fn double_votes_1() {}
 #[ignore]
 #[tokio::test]
 async fn timeout_prevote_1() {}
+
+/// The serving node is shut down in the middle of the height,
+/// and a new instance on the same storage finalizes it along with the others.
+#[tokio::test]
+async fn shutdown_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let round_zero_timestamp = utils::get_timestamp();
+    let new_node = |dms, storage, private_key| {
+        Consensus::new(
+            dms,
+            storage,
+            fi.header.clone(),
+            ConsensusParams {
+                timeout_ms: 60_000,
+                ..default_params()
+            },
+            round_zero_timestamp,
+            ConsensusConfig {
+                this_node_signer: signer(Some(private_key)),
+                ..Default::default()
+            },
+        )
+    };
+    let mut network = MockNetwork::new();
+    let mut dmses = Vec::new();
+    let mut nodes = Vec::new();
+    let storage = MemoryStorage::new().await;
+    for (i, (_, private_key)) in keys.iter().enumerate() {
+        let dms = create_dms(members.clone(), private_key.clone()).await;
+        network.add_node(Arc::clone(&dms));
+        dmses.push(Arc::clone(&dms));
+        let storage = if i == 3 {
+            storage.clone()
+        } else {
+            MemoryStorage::new().await
+        };
+        nodes.push(new_node(dms, storage, private_key.clone()).await.unwrap());
+    }
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, round_zero_timestamp)
+        .await
+        .unwrap();
+
+    // The results are not received at all.
+    let (serve_task, _, read_handle, commands, _) = nodes
+        .pop()
+        .unwrap()
+        .serve(
+            ServerNetworkConfig {
+                port: dispense_port(),
+            },
+            std::time::Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+    // PROPOSE and PREVOTE
+    for _ in 0..2 {
+        step(&mut nodes, &network, round_zero_timestamp).await;
+        sleep_ms(300).await;
+    }
+    assert!(!read_handle.is_finalized());
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    commands
+        .send(ConsensusCommand::Shutdown(sender))
+        .await
+        .unwrap();
+    receiver.await.unwrap().unwrap();
+    serve_task.await.unwrap().unwrap();
+
+    let node = new_node(Arc::clone(&dmses[3]), storage, keys[3].1.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        node.status().await.unwrap().verified_block_hashes,
+        vec![block_hash]
+    );
+    nodes.push(node);
+    for _ in 0..4 {
+        step(&mut nodes, &network, round_zero_timestamp).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+    }
+}
+
+/// The serving observer survives the failures of writing its state, reporting them,
+/// and still reports the finalization.
+#[tokio::test]
+async fn serve_retry_1() {
+    setup_test();
+    let (mut nodes, mut network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let (_, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    network.add_node(Arc::clone(&dms));
+    let storage = MemoryStorage::new().await;
+    let mut observer = Consensus::new(
+        dms,
+        storage.clone(),
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(None),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let block_hash = Hash256::hash("block");
+    observer
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    observer.set_retry_policy(RetryPolicy {
+        max_consecutive_failures: 2,
+        backoff: std::time::Duration::from_millis(50),
+    });
+
+    let (serve_task, mut results, _, _, mut errors) = observer
+        .serve(
+            ServerNetworkConfig {
+                port: dispense_port(),
+            },
+            std::time::Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+    storage.fail_next_writes(2);
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for _ in 0..2 {
+        let error = errors.recv().await.unwrap();
+        assert!(matches!(error, ConsensusError::Storage(_)));
+    }
+    let finalization = loop {
+        if let ProgressResult::Finalized(finalization) = results.recv().await.unwrap() {
+            break finalization;
+        }
+    };
+    assert_eq!(finalization.block_hash, block_hash);
+    serve_task.await.unwrap().unwrap();
+}
+
+/// The proposer retries to send its messages with the exponential backoff
+/// until enough peers acknowledge them.
+#[tokio::test]
+async fn broadcast_retry_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    nodes[0].set_broadcast_policy(BroadcastPolicy {
+        min_acknowledgements: 3,
+        initial_backoff_ms: 1000,
+        max_backoff_ms: 4000,
+    });
+    nodes[0].progress(0).await.unwrap();
+    nodes[0].flush().await.unwrap();
+    let attempts = |status: ConsensusStatus| {
+        assert!(!status.deliveries.is_empty());
+        status
+            .deliveries
+            .iter()
+            .map(|delivery| (delivery.attempts, delivery.acknowledged_by.len()))
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    assert_eq!(
+        attempts(nodes[0].status().await.unwrap()),
+        vec![(0, 0)].into_iter().collect()
+    );
+
+    // Every attempt fails over a fully lossy network, backing off from 1s to 2s.
+    network.set_drop_probability(1.0);
+    for (timestamp, expected) in [(0, 1), (999, 1), (1000, 2), (2999, 2)] {
+        nodes[0].broadcast(&network, timestamp).await.unwrap();
+        assert_eq!(
+            attempts(nodes[0].status().await.unwrap()),
+            vec![(expected, 0)].into_iter().collect()
+        );
+    }
+    for node in nodes[1..].iter() {
+        let messages = node.get_dms().read().await.read_messages().await.unwrap();
+        assert!(messages.is_empty());
+    }
+
+    network.set_drop_probability(0.0);
+    nodes[0].broadcast(&network, 3000).await.unwrap();
+    let status = nodes[0].status().await.unwrap();
+    assert!(status.deliveries.iter().all(|delivery| delivery.delivered));
+    assert_eq!(attempts(status.clone()), vec![(3, 3)].into_iter().collect());
+    for node in nodes[1..].iter() {
+        let messages = node.get_dms().read().await.read_messages().await.unwrap();
+        assert_eq!(messages.len(), status.deliveries.len());
+    }
+
+    // Nothing is sent once delivered.
+    network.set_drop_probability(1.0);
+    nodes[0].broadcast(&network, 100_000).await.unwrap();
+    assert_eq!(
+        attempts(nodes[0].status().await.unwrap()),
+        vec![(3, 3)].into_iter().collect()
+    );
+}
+
+/// The serving node starts sending its messages to a peer added in the middle of the round.
+#[tokio::test]
+async fn known_peers_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let round_zero_timestamp = utils::get_timestamp();
+    let mut dmses = Vec::new();
+    let mut peers = Vec::new();
+    for (public_key, private_key) in keys.iter() {
+        let dms = create_dms(members.clone(), private_key.clone()).await;
+        let port = dispense_port();
+        peers.push(Peer {
+            public_key: public_key.clone(),
+            name: format!("{public_key}"),
+            address: "127.0.0.1:1".parse().unwrap(),
+            ports: vec![(keys::port_key_dms::<ConsensusMessage>(), port)]
+                .into_iter()
+                .collect(),
+            message: "".to_owned(),
+            recently_seen_timestamp: 0,
+        });
+        dmses.push((dms, port));
+    }
+    // The peers only serve their DMSes.
+    for (dms, port) in dmses[1..3].iter() {
+        tokio::spawn(Dms::serve(
+            Arc::clone(dms),
+            ServerNetworkConfig { port: *port },
+        ));
+    }
+    let mut node = Consensus::new(
+        Arc::clone(&dmses[0].0),
+        MemoryStorage::new().await,
+        fi.header.clone(),
+        ConsensusParams {
+            timeout_ms: 60_000,
+            ..default_params()
+        },
+        round_zero_timestamp,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let block_hash = Hash256::hash("block");
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.set_proposal_candidate(block_hash, 0).await.unwrap();
+    // Keeps retrying until both of the peers receive the messages.
+    node.set_broadcast_policy(BroadcastPolicy {
+        min_acknowledgements: 2,
+        initial_backoff_ms: 100,
+        max_backoff_ms: 100,
+    });
+    node.add_peer(peers[1].clone());
+    let (serve_task, _, read_handle, commands, _) = node
+        .serve(
+            ServerNetworkConfig { port: dmses[0].1 },
+            std::time::Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+    let message_count = |dms: Arc<RwLock<Dms<ConsensusMessage>>>| async move {
+        let messages = dms.read().await.read_messages().await.unwrap();
+        messages.len()
+    };
+    while message_count(Arc::clone(&dmses[1].0)).await == 0 {
+        sleep_ms(100).await;
+    }
+    assert_eq!(message_count(Arc::clone(&dmses[2].0)).await, 0);
+    assert_eq!(read_handle.status().round, 0);
+
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    commands
+        .send(ConsensusCommand::AddPeer(peers[2].clone(), sender))
+        .await
+        .unwrap();
+    receiver.await.unwrap().unwrap();
+    while message_count(Arc::clone(&dmses[2].0)).await == 0 {
+        sleep_ms(100).await;
+    }
+    assert_eq!(read_handle.status().round, 0);
+
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    commands
+        .send(ConsensusCommand::Shutdown(sender))
+        .await
+        .unwrap();
+    receiver.await.unwrap().unwrap();
+    serve_task.await.unwrap().unwrap();
+}
+
+/// The serving node moves its DMS server to a new port without restarting,
+/// while a configuration with another key is rejected.
+#[tokio::test]
+async fn network_config_reload_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let round_zero_timestamp = utils::get_timestamp();
+    let mut dmses = Vec::new();
+    for (_, private_key) in keys.iter() {
+        dmses.push(create_dms(members.clone(), private_key.clone()).await);
+    }
+    let mut node = Consensus::new(
+        Arc::clone(&dmses[0]),
+        MemoryStorage::new().await,
+        fi.header.clone(),
+        ConsensusParams {
+            timeout_ms: 60_000,
+            ..default_params()
+        },
+        round_zero_timestamp,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let block_hash = Hash256::hash("block");
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.set_proposal_candidate(block_hash, round_zero_timestamp)
+        .await
+        .unwrap();
+    let network_config = |private_key: &PrivateKey, port| NetworkConfig {
+        private_key: private_key.clone(),
+        server: ServerNetworkConfig { port },
+    };
+    // Fetches the messages of the node from the port into the DMS, returning their number.
+    let fetch_from = |dms: Arc<RwLock<Dms<ConsensusMessage>>>, port| {
+        let peer = Peer {
+            public_key: keys[0].0.clone(),
+            name: "node".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            ports: vec![(keys::port_key_dms::<ConsensusMessage>(), port)]
+                .into_iter()
+                .collect(),
+            message: "".to_owned(),
+            recently_seen_timestamp: 0,
+        };
+        async move {
+            let _ = Dms::fetch(Arc::clone(&dms), &ClientNetworkConfig { peers: vec![peer] }).await;
+            let messages = dms.read().await.read_messages().await.unwrap();
+            messages.len()
+        }
+    };
+
+    let old_port = dispense_port();
+    let (config_sender, config_receiver) =
+        tokio::sync::watch::channel(network_config(&keys[0].1, old_port));
+    let (serve_task, _, _, commands, mut errors) = node
+        .serve_reloadable(config_receiver, std::time::Duration::from_millis(100))
+        .await
+        .unwrap();
+    while fetch_from(Arc::clone(&dmses[1]), old_port).await == 0 {
+        sleep_ms(100).await;
+    }
+
+    // Rejected, keeping the old port.
+    let new_port = dispense_port();
+    config_sender
+        .send(network_config(&keys[1].1, new_port))
+        .unwrap();
+    let error = errors.recv().await.unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::NetworkKeyChanged {
+            expected: Box::new(keys[0].0.clone()),
+            actual: Box::new(keys[1].0.clone()),
+        }
+    );
+    assert_eq!(fetch_from(Arc::clone(&dmses[2]), new_port).await, 0);
+    assert!(fetch_from(Arc::clone(&dmses[2]), old_port).await > 0);
+
+    // Accepted, moving to the new port.
+    config_sender
+        .send(network_config(&keys[0].1, new_port))
+        .unwrap();
+    while fetch_from(Arc::clone(&dmses[3]), new_port).await == 0 {
+        sleep_ms(100).await;
+    }
+    assert!(!serve_task.is_finished());
+
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    commands
+        .send(ConsensusCommand::Shutdown(sender))
+        .await
+        .unwrap();
+    receiver.await.unwrap().unwrap();
+    serve_task.await.unwrap().unwrap();
+}
@@ -0,0 +1,495 @@
+use simperby_consensus::simulation::{seed_from_env, Simulation, StepOrder};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn errors_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage, block_header| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            block_header,
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let node = new_node(storage.clone(), fi.header.clone()).await.unwrap();
+    let mut other_header = fi.header.clone();
+    other_header.timestamp += 1;
+    let error = new_node(storage.clone(), other_header).await.err().unwrap();
+    assert!(matches!(error, ConsensusError::Mismatch(_)));
+    // The storage of the previous height
+    let mut next_header = fi.header.clone();
+    next_header.height += 1;
+    let error = new_node(storage.clone(), next_header).await.err().unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::HeightMismatch {
+            expected: fi.header.height + 2,
+            stored: fi.header.height + 1,
+        }
+    );
+
+    // Both the primary and the backup are corrupted.
+    let mut corrupted = storage.clone();
+    for name in ["state.json", "state.backup.json"] {
+        corrupted
+            .add_or_overwrite_file(name, "broken".to_owned())
+            .await
+            .unwrap();
+    }
+    let error = node.status().await.unwrap_err();
+    assert!(matches!(error, ConsensusError::CorruptState { .. }));
+}
+
+#[tokio::test]
+async fn invalid_validator_set_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |validator_set: Vec<(PublicKey, VotingPower)>| {
+        let mut header = fi.header.clone();
+        header.validator_set = validator_set;
+        Consensus::new(
+            Arc::clone(&dms),
+            storage.clone(),
+            header,
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+    let validator_set = fi.header.validator_set.clone();
+
+    let error = new_node(Vec::new()).await.err().unwrap();
+    assert_eq!(error, ConsensusError::EmptyValidatorSet);
+
+    let mut duplicated = validator_set.clone();
+    duplicated.push(validator_set[1].clone());
+    let error = new_node(duplicated).await.err().unwrap();
+    assert_eq!(error, ConsensusError::DuplicateValidator(keys[1].0.clone()));
+
+    let mut powerless = validator_set.clone();
+    powerless[2].1 = 0;
+    let error = new_node(powerless).await.err().unwrap();
+    assert_eq!(error, ConsensusError::ZeroVotingPower(keys[2].0.clone()));
+
+    let others: VotingPower = validator_set[..3].iter().map(|(_, power)| power).sum();
+    for power in [VotingPower::MAX, VotingPower::MAX - others + 1] {
+        let mut overflowing = validator_set.clone();
+        overflowing[3].1 = power;
+        let error = new_node(overflowing).await.err().unwrap();
+        assert_eq!(error, ConsensusError::VotingPowerOverflow);
+    }
+
+    // A quorum less than 2/3 breaks the safety, and the one of 1 can't be reached.
+    for (numerator, denominator) in [(3, 5), (1, 1), (0, 0)] {
+        let quorum = Quorum {
+            numerator,
+            denominator,
+        };
+        let error = Consensus::new(
+            Arc::clone(&dms),
+            storage.clone(),
+            fi.header.clone(),
+            ConsensusParams {
+                quorum: Some(quorum),
+                ..default_params()
+            },
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(error, ConsensusError::InvalidQuorum(quorum));
+    }
+
+    // Nothing has been written.
+    assert!(storage.list_files().await.unwrap().is_empty());
+    // The total voting power may be up to `VotingPower::MAX`.
+    let mut largest = validator_set;
+    largest[3].1 = VotingPower::MAX - others;
+    new_node(largest).await.unwrap();
+}
+
+#[tokio::test]
+async fn no_overwrite_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let params = default_params();
+
+    let block_hash = Hash256::hash("block");
+    let mut node = Consensus::new(
+        Arc::clone(&dms),
+        storage.clone(),
+        fi.header.clone(),
+        params.clone(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    drop(node);
+
+    // Both the primary and the backup are unreadable.
+    let mut corrupted = storage.clone();
+    for name in ["state.json", "state.backup.json"] {
+        let raw_state = storage.read_file(name).await.unwrap();
+        corrupted
+            .add_or_overwrite_file(name, raw_state.replacen("bincode", "unknown", 1))
+            .await
+            .unwrap();
+    }
+    let error = Consensus::new(
+        Arc::clone(&dms),
+        storage.clone(),
+        fi.header.clone(),
+        params.clone(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .err()
+    .unwrap();
+    assert!(matches!(error, ConsensusError::CorruptState { .. }));
+    // The state is left as it is, so it can still be recovered by hand.
+    assert!(storage
+        .read_file("state.json")
+        .await
+        .unwrap()
+        .starts_with("unknown:"));
+
+    // Only an explicit recreation discards it.
+    let node = Consensus::recreate(
+        Arc::clone(&dms),
+        storage.clone(),
+        fi.header.clone(),
+        params,
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(node
+        .status()
+        .await
+        .unwrap()
+        .verified_block_hashes
+        .is_empty());
+}
+
+/// Finalizes two heights in a row, moving on with `finalize_and_advance()`.
+#[tokio::test]
+async fn finalize_and_advance_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let (mut nodes, keys): (Vec<_>, Vec<_>) = nodes.into_iter().unzip();
+    let params = default_params();
+    let mut header = fi.header.clone();
+    let mut round_zero_timestamp = 0;
+    let mut block_hashes = Vec::new();
+    for _ in 0..2 {
+        let next_header = BlockHeader {
+            author: header.validator_set[0].0.clone(),
+            previous_hash: header.to_hash256(),
+            height: header.height + 1,
+            timestamp: round_zero_timestamp,
+            ..header.clone()
+        };
+        let block_hash = next_header.to_hash256();
+        for node in nodes.iter_mut() {
+            node.register_verified_block_hash(block_hash).await.unwrap();
+        }
+        nodes[0]
+            .set_proposal_candidate(block_hash, round_zero_timestamp)
+            .await
+            .unwrap();
+        let mut simulation = Simulation::new(
+            nodes,
+            header.validator_set.clone(),
+            round_zero_timestamp,
+            StepOrder::RoundRobin,
+            seed_from_env(),
+        )
+        .await
+        .unwrap();
+        simulation.run_until_finalized(20).await.unwrap();
+        assert_eq!(simulation.finalized().len(), 4);
+        assert!(simulation.finalized().values().all(|x| *x == block_hash));
+
+        round_zero_timestamp = simulation.now();
+        nodes = Vec::new();
+        for (node, key) in simulation.into_nodes().into_iter().zip(keys.iter()) {
+            let node = node
+                .finalize_and_advance(
+                    next_header.clone(),
+                    params.clone(),
+                    round_zero_timestamp,
+                    signer(key.clone()),
+                )
+                .await
+                .unwrap();
+            nodes.push(node);
+        }
+        header = next_header;
+        block_hashes.push(block_hash);
+    }
+
+    for node in nodes.iter() {
+        let status = node.status().await.unwrap();
+        assert_eq!(status.height, fi.header.height + 3);
+        assert!(!status.finalized);
+        for (i, block_hash) in block_hashes.iter().enumerate() {
+            let finalization = node
+                .get_archived_finalization(fi.header.height + 1 + i as BlockHeight)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(finalization.block_hash, *block_hash);
+        }
+    }
+}
+
+/// Progresses the managers, then exchanges the messages of their current heights.
+async fn step_managers(
+    managers: &mut [ConsensusManager<MemoryStorage>],
+    network: &MockNetwork,
+    timestamp: Timestamp,
+) {
+    for manager in managers.iter_mut() {
+        manager.progress(timestamp).await.unwrap();
+        manager.consensus_mut().unwrap().flush().await.unwrap();
+    }
+    network.gossip().await.unwrap();
+    for manager in managers.iter_mut() {
+        let consensus = manager.consensus_mut().unwrap();
+        if consensus.check_finalized().await.unwrap().is_none() {
+            consensus.update().await.unwrap();
+        }
+    }
+}
+
+/// The next height is prepared while the current one is being finalized,
+/// and the last validator, which learns the finalized block late, catches up
+/// only with the messages of the next height that have arrived meanwhile.
+#[tokio::test]
+async fn consensus_manager_pipelining_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let params = default_params();
+    let mut network = MockNetwork::new();
+    let mut managers = Vec::new();
+    for (node, key) in nodes {
+        network.add_node(node.get_dms());
+        managers.push(
+            ConsensusManager::new(node, params.clone(), signer(key))
+                .await
+                .unwrap(),
+        );
+    }
+    let height = fi.header.height + 1;
+    let mut headers = Vec::new();
+    let mut header = fi.header.clone();
+    for _ in 0..2 {
+        header = BlockHeader {
+            author: header.validator_set[0].0.clone(),
+            previous_hash: header.to_hash256(),
+            height: header.height + 1,
+            ..header.clone()
+        };
+        headers.push(header.clone());
+    }
+    for (i, manager) in managers.iter_mut().enumerate() {
+        assert_eq!(manager.height(), height);
+        // The last one learns the finalized block late.
+        if i != 3 {
+            manager.add_next_header(headers[0].clone()).unwrap();
+        }
+        for (j, header) in headers.iter().enumerate() {
+            let header_height = height + j as BlockHeight;
+            manager
+                .register_verified_block_hash(header_height, header.to_hash256())
+                .await
+                .unwrap();
+            if i == 0 {
+                manager
+                    .set_proposal_candidate(header_height, header.to_hash256(), 0)
+                    .await
+                    .unwrap();
+            }
+        }
+        // Neither the past nor the far heights are accepted.
+        for wrong_height in [height - 1, height + 2] {
+            assert!(manager
+                .register_verified_block_hash(wrong_height, Hash256::hash("block"))
+                .await
+                .is_err());
+        }
+    }
+
+    for _ in 0..10 {
+        step_managers(&mut managers, &network, 0).await;
+    }
+    for manager in managers[..3].iter() {
+        assert_eq!(manager.height(), height + 1);
+        let finalization = manager
+            .consensus()
+            .unwrap()
+            .check_finalized()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(finalization.block_hash, headers[1].to_hash256());
+    }
+    assert_eq!(managers[3].height(), height);
+    let finalization = managers[3]
+        .consensus()
+        .unwrap()
+        .check_finalized()
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(finalization.block_hash, headers[0].to_hash256());
+
+    // The others have moved on, so it can't fetch the messages of the next height anymore.
+    network.partition(&[vec![0, 1, 2], vec![3]]);
+    managers[3].add_next_header(headers[0].clone()).unwrap();
+    for _ in 0..4 {
+        step_managers(&mut managers, &network, 0).await;
+    }
+    assert_eq!(managers[3].height(), height + 1);
+    let node = managers.pop().unwrap().into_consensus().unwrap();
+    let finalization = node.check_finalized().await.unwrap().unwrap();
+    assert_eq!(finalization.block_hash, headers[1].to_hash256());
+    verify_finalization_proof(
+        &finalization.block_hash,
+        &finalization.proof,
+        &headers[0].validator_set,
+    )
+    .unwrap();
+    assert_eq!(
+        node.get_archived_finalization(height)
+            .await
+            .unwrap()
+            .unwrap()
+            .block_hash,
+        headers[0].to_hash256()
+    );
+}
+
+/// The messages of the finalized height are ignored at the next height until they are purged.
+#[tokio::test]
+async fn stale_height_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let (mut nodes, keys): (Vec<_>, Vec<_>) = nodes.into_iter().unzip();
+    let next_header = BlockHeader {
+        author: fi.header.validator_set[0].0.clone(),
+        previous_hash: fi.header.to_hash256(),
+        height: fi.header.height + 1,
+        ..fi.header.clone()
+    };
+    let block_hash = next_header.to_hash256();
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let mut simulation = Simulation::new(
+        nodes,
+        fi.header.validator_set.clone(),
+        0,
+        StepOrder::RoundRobin,
+        seed_from_env(),
+    )
+    .await
+    .unwrap();
+    simulation.run_until_finalized(20).await.unwrap();
+    let timestamp = simulation.now();
+    let node = simulation.into_nodes().remove(1);
+    let mut node = node
+        .finalize_and_advance(
+            next_header,
+            default_params(),
+            timestamp,
+            signer(keys[1].clone()),
+        )
+        .await
+        .unwrap();
+    let stale_height = fi.header.height + 1;
+    node.progress(timestamp).await.unwrap();
+    let status = node.status().await.unwrap();
+
+    // The messages of the previous height are left in the DMS (e.g., by a late delivery).
+    let dms = node.get_dms();
+    for message in [
+        ConsensusMessage::NilPreVoted(stale_height, 0),
+        ConsensusMessage::NilPreCommitted(stale_height, 0),
+    ] {
+        dms.write().await.commit_message(&message).await.unwrap();
+    }
+    node.update().await.unwrap();
+    assert!(node.progress(timestamp).await.unwrap().is_empty());
+    assert_eq!(node.status().await.unwrap(), status);
+    assert!(node.vote_tally(0).await.unwrap().prevotes.is_empty());
+
+    // The previous height is still in the retention.
+    assert!(node.purge_height(stale_height).await.is_err());
+    node.set_archive_retention(0);
+    node.purge_height(stale_height).await.unwrap();
+    assert!(dms.read().await.read_messages().await.unwrap().is_empty());
+    assert!(node
+        .get_archived_finalization(stale_height)
+        .await
+        .unwrap()
+        .is_none());
+}
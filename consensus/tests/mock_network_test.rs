@@ -0,0 +1,901 @@
+use simperby_consensus::simulation::{seed_from_env, Simulation, StepOrder};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn mock_network_finalization_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    network.set_latency(std::time::Duration::from_millis(10));
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE, PREVOTE, PRECOMMIT and FINALIZE
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+        assert_eq!(finalization.proof.round, 0);
+        verify_finalization_proof(&block_hash, &finalization.proof, &fi.header.validator_set)
+            .unwrap();
+    }
+}
+
+/// The proposer of the first round crashes, so the others move on to the next round
+/// with the nil votes on the timeout.
+#[tokio::test]
+async fn mock_network_proposer_crash_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    // The proposer stays in the network but never runs.
+    let mut nodes = nodes.split_off(1);
+    let height = fi.header.height + 1;
+
+    for node in nodes.iter_mut() {
+        assert!(node.progress(0).await.unwrap().is_empty());
+        assert_eq!(
+            node.progress(6000).await.unwrap(),
+            vec![ProgressResult::NilPreVoted(0, 6000)]
+        );
+    }
+    exchange(&mut nodes, &network).await;
+    for node in nodes.iter_mut() {
+        let results = node.progress(6000).await.unwrap();
+        assert!(matches!(
+            results[..],
+            [ProgressResult::NilPreCommitted(0, _)]
+        ));
+    }
+    exchange(&mut nodes, &network).await;
+    for node in nodes.iter_mut() {
+        node.progress(6000).await.unwrap();
+        let status = node.status().await.unwrap();
+        assert_eq!(status.round, 1);
+        assert!(!status.finalized);
+    }
+    // Every node has received the nil votes of the others.
+    let messages = nodes[0]
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap();
+    for round_message in [
+        ConsensusMessage::NilPreVoted(height, 0),
+        ConsensusMessage::NilPreCommitted(height, 0),
+    ] {
+        let message = messages
+            .iter()
+            .find(|message| message.message == round_message)
+            .unwrap();
+        assert_eq!(message.committers.len(), 3);
+    }
+}
+
+/// The proposer is silent, so every round ends with the nil precommits,
+/// each of which is reported exactly once.
+#[tokio::test]
+async fn round_advanced_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    // The proposer stays in the network but never runs.
+    let mut nodes = nodes.split_off(1);
+    // The first progress starts the round 0.
+    for node in nodes.iter_mut() {
+        node.progress(utils::get_timestamp()).await.unwrap();
+    }
+    let mut results = vec![Vec::new(); nodes.len()];
+    for _ in 0..3 {
+        // The messages are consumed at the current time, which starts the next round.
+        let timestamp = utils::get_timestamp() + 6000;
+        // The nil prevotes on the timeout, the nil precommits and the next round
+        for _ in 0..3 {
+            for (node, results) in nodes.iter_mut().zip(results.iter_mut()) {
+                results.extend(node.progress(timestamp).await.unwrap());
+            }
+            exchange(&mut nodes, &network).await;
+        }
+    }
+    for (node, results) in nodes.iter().zip(results) {
+        let advanced = results
+            .into_iter()
+            .filter_map(|result| match result {
+                ProgressResult::RoundAdvanced {
+                    new_round, reason, ..
+                } => Some((new_round, reason)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            advanced,
+            (1..=3)
+                .map(|round| (round, RoundSkipReason::NilQuorum))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(node.status().await.unwrap().round, 3);
+    }
+}
+
+/// A caller clock jumping backwards moves no deadline back with `ClockRegression::Clamp`,
+/// and neither does the local clock behind it, by which the messages are received.
+#[tokio::test]
+async fn clock_regression_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    // The proposer stays in the network but never runs.
+    let mut nodes = nodes.split_off(1);
+    for node in nodes.iter_mut() {
+        node.set_clock_policy(ClockPolicy {
+            regression: ClockRegression::Clamp,
+            max_skew_ms: Some(1000),
+        });
+    }
+    let timestamp = utils::get_timestamp() + 60_000;
+    let mut results = Vec::new();
+    // The start, the nil prevotes on the timeout, the nil precommits and the next round
+    for _ in 0..4 {
+        for node in nodes.iter_mut() {
+            results.extend(node.progress(timestamp).await.unwrap());
+        }
+        exchange(&mut nodes, &network).await;
+    }
+    // The round 1 starts at the time of the caller, not at the one of the local clock.
+    let advanced = results
+        .iter()
+        .filter_map(|result| match result {
+            ProgressResult::RoundAdvanced { timestamp, .. } => Some(*timestamp),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(advanced, vec![timestamp; 3]);
+    for node in nodes.iter_mut() {
+        assert!(node.progress(timestamp - 30_000).await.unwrap().is_empty());
+        let status = node.status().await.unwrap();
+        assert_eq!(status.round, 1);
+        assert_eq!(status.timeout, Some(timestamp + 6000));
+    }
+
+    nodes[0].set_clock_policy(ClockPolicy {
+        regression: ClockRegression::Reject,
+        max_skew_ms: Some(1000),
+    });
+    let error = nodes[0].progress(timestamp - 1).await.unwrap_err();
+    assert_eq!(
+        error,
+        ConsensusError::ClockRegression {
+            timestamp: timestamp - 1,
+            latest: timestamp,
+        }
+    );
+    assert_eq!(
+        nodes[0].progress(timestamp + 6000).await.unwrap(),
+        vec![ProgressResult::NilPreVoted(1, timestamp + 6000)]
+    );
+}
+
+/// The validators are split in half, so neither side can make a quorum
+/// until the partition heals.
+#[tokio::test]
+async fn mock_network_partition_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    network.partition(&[vec![0, 1], vec![2, 3]]);
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        let status = node.status().await.unwrap();
+        assert_eq!(status.round, 0);
+        assert!(!status.finalized);
+        // The other side has never seen the proposal.
+        if i >= 2 {
+            assert_eq!(status.step, ConsensusStep::Propose);
+        }
+    }
+
+    network.heal();
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+        assert_eq!(finalization.proof.round, 0);
+    }
+}
+
+#[tokio::test]
+async fn external_finalization_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let keys = nodes
+        .iter()
+        .map(|(_, key)| key.clone().unwrap())
+        .collect::<Vec<_>>();
+    let mut nodes = nodes.into_iter().map(|(node, _)| node).collect::<Vec<_>>();
+    let block_hash = Hash256::hash("block");
+    // Not even verified by the validator 3, which misses the height.
+    for node in nodes.iter_mut().take(3) {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let mut simulation = Simulation::new(
+        nodes,
+        fi.header.validator_set.clone(),
+        0,
+        StepOrder::Randomized,
+        seed_from_env(),
+    )
+    .await
+    .unwrap();
+    simulation.crash(3);
+    simulation.run_until_finalized(20).await.unwrap();
+    let mut nodes = simulation.into_nodes();
+    let proof = nodes[0].get_finalization_proof().await.unwrap().unwrap();
+    assert!(nodes[3].check_finalized().await.unwrap().is_none());
+
+    let another_block_hash = Hash256::hash("another block");
+    assert!(nodes[3]
+        .apply_external_finalization(another_block_hash, proof.clone(), 10_000)
+        .await
+        .is_err());
+    assert!(nodes[3].check_finalized().await.unwrap().is_none());
+    let expected = Finalization {
+        block_hash,
+        timestamp: 10_000,
+        proof: proof.clone(),
+    };
+    assert_eq!(
+        nodes[3]
+            .apply_external_finalization(block_hash, proof.clone(), 10_000)
+            .await
+            .unwrap(),
+        ProgressResult::Finalized(expected.clone())
+    );
+    assert_eq!(
+        nodes[3].check_finalized().await.unwrap(),
+        Some(expected.clone())
+    );
+    assert_eq!(
+        nodes[3].get_finalization_proof().await.unwrap(),
+        Some(proof.clone())
+    );
+    // Idempotent
+    assert_eq!(
+        nodes[3]
+            .apply_external_finalization(block_hash, proof, 20_000)
+            .await
+            .unwrap(),
+        ProgressResult::Finalized(expected)
+    );
+
+    // Signed by all the validators, but for another block than the finalized one.
+    let conflicting_proof = FinalizationProof {
+        round: 0,
+        signatures: keys
+            .iter()
+            .map(|key| {
+                TypedSignature::sign(
+                    &FinalizationSignTarget {
+                        block_hash: another_block_hash,
+                        round: 0,
+                    },
+                    key,
+                )
+                .unwrap()
+            })
+            .collect(),
+    };
+    for i in [0, 3] {
+        let error = nodes[i]
+            .apply_external_finalization(another_block_hash, conflicting_proof.clone(), 20_000)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ConsensusError::ConflictingFinalization {
+                local: block_hash,
+                external: another_block_hash,
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn request_missing_votes_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let validators = fi
+        .header
+        .validator_set
+        .iter()
+        .map(|(validator, _)| validator.clone())
+        .collect::<Vec<_>>();
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // The validator 3 is offline, so the quorum needs the precommit of the validator 2,
+    // which the gossip loses.
+    let precommit = ConsensusMessage::NonNilPreCommitted(fi.header.height + 1, 0, block_hash);
+    network.set_gossip_drop_filter(Some(PacketFilter {
+        message_prefixes: vec![precommit.to_compact()],
+        committers: vec![validators[2].clone()],
+    }));
+    for _ in 0..5 {
+        step(&mut nodes[..3], &network, 0).await;
+    }
+    assert!(nodes[2].check_finalized().await.unwrap().is_some());
+    for node in &nodes[..2] {
+        assert!(node.check_finalized().await.unwrap().is_none());
+    }
+
+    let requested = nodes[0]
+        .request_missing_votes(&network, 0, 0)
+        .await
+        .unwrap();
+    assert_eq!(requested, validators[2..]);
+    nodes[0].update().await.unwrap();
+    let results = nodes[0].progress(0).await.unwrap();
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, ProgressResult::Finalized(_))));
+    assert_eq!(
+        nodes[0]
+            .check_finalized()
+            .await
+            .unwrap()
+            .unwrap()
+            .block_hash,
+        block_hash
+    );
+}
+
+#[tokio::test]
+async fn progress_dry_run_1() {
+    setup_test();
+    let (mut nodes, fi) = NodesBuilder::new(4).build().await;
+    let (node, _) = &mut nodes[1];
+    let block_hash = Hash256::hash("block");
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.progress(0).await.unwrap();
+    let status = node.status().await.unwrap();
+
+    // The proposal times out.
+    let expected = vec![ProgressResult::NilPreVoted(0, 6000)];
+    for _ in 0..2 {
+        assert_eq!(
+            node.progress_dry_run(6000).await.unwrap(),
+            (
+                expected.clone(),
+                vec![ConsensusMessage::NilPreVoted(fi.header.height + 1, 0)]
+            )
+        );
+    }
+    assert_eq!(node.status().await.unwrap(), status);
+    assert!(node
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(node.progress(6000).await.unwrap(), expected);
+}
+
+/// A benchmark of `update()` with 10k messages, the votes of the four validators on 2500 rounds,
+/// where the messages decoded by the first call are reused by the later ones.
+///
+/// Run with `cargo test --release -- --ignored --nocapture update_with_many_messages_1`.
+#[ignore]
+#[tokio::test]
+async fn update_with_many_messages_1() {
+    setup_test();
+    let (mut nodes, fi) = NodesBuilder::new(4).build().await;
+    let keys = nodes
+        .iter()
+        .map(|(_, key)| key.clone().unwrap())
+        .collect::<Vec<_>>();
+    let (node, _) = &mut nodes[0];
+    node.set_max_round_lookahead(2500).await.unwrap();
+    let dms = node.get_dms();
+    let dms_key = dms.read().await.get_config().dms_key;
+    for round in 0..2500 {
+        let message = ConsensusMessage::NilPreVoted(fi.header.height + 1, round);
+        for key in &keys {
+            let commitment = message.commit(&dms_key, key).unwrap();
+            dms.write()
+                .await
+                .receive_message(&message, commitment)
+                .await
+                .unwrap();
+        }
+    }
+
+    let timer = std::time::Instant::now();
+    node.update().await.unwrap();
+    let first = timer.elapsed();
+    let timer = std::time::Instant::now();
+    node.update().await.unwrap();
+    let second = timer.elapsed();
+    println!("update() with 10k messages: {first:?} (first), {second:?} (cached)");
+}
+
+/// The leader rotates from the round 1, and the proposer of the round 0 crashes,
+/// so the block is proposed by the validator 1 in the round 1.
+#[tokio::test]
+async fn proposer_1() {
+    setup_test();
+    let params = ConsensusParams {
+        repeat_round_for_first_leader: 1,
+        ..default_params()
+    };
+    let (mut nodes, network, fi) = NodesBuilder::new(4).params(params).build_gossiping().await;
+    let validator_set = &fi.header.validator_set;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        node.set_proposal_candidate(block_hash, 0).await.unwrap();
+    }
+
+    let schedule = nodes[0].proposer_schedule(6).await.unwrap();
+    let expected = [0, 1, 2, 3, 0, 1]
+        .into_iter()
+        .enumerate()
+        .map(|(round, i)| (round as ConsensusRound, validator_set[i].0.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(schedule, expected);
+    for (i, node) in nodes.iter().enumerate() {
+        assert_eq!(node.proposer(1).await.unwrap(), validator_set[1].0);
+        assert_eq!(node.is_this_node_proposer(1).await.unwrap(), i == 1);
+    }
+
+    // The validator 0 stays in the network but never runs.
+    step(&mut nodes[1..], &network, 0).await;
+    for _ in 0..8 {
+        step(&mut nodes[1..], &network, 6000).await;
+    }
+    let finalization = nodes[1].check_finalized().await.unwrap().unwrap();
+    assert_eq!(finalization.block_hash, block_hash);
+    assert_eq!(finalization.proof.round, 1);
+
+    // Every proposal is made by the reported proposer of its round.
+    let messages = nodes[1]
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap();
+    let proposals = messages
+        .iter()
+        .filter_map(|message| match message.message {
+            ConsensusMessage::Proposal { round, .. } => Some((round, &message.committers)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(proposals.len(), 1);
+    for (round, committers) in proposals {
+        let proposer = nodes[1].proposer(round).await.unwrap();
+        assert!(committers
+            .iter()
+            .all(|commitment| commitment.committer == proposer));
+    }
+}
+
+#[tokio::test]
+async fn veto_round_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[1].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[1].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let mut node = new_node(storage.clone()).await.unwrap();
+    assert!(node.progress(0).await.unwrap().is_empty());
+    // Vetoing twice is the same as once.
+    node.veto_round(0, 0).await.unwrap();
+    node.veto_round(0, 0).await.unwrap();
+    assert_eq!(node.status().await.unwrap().vetoed_rounds, vec![0]);
+    assert_eq!(
+        node.progress(0).await.unwrap(),
+        vec![ProgressResult::NilPreVoted(0, 0)]
+    );
+    drop(node);
+
+    // The veto survives the restart.
+    let mut node = new_node(storage.clone()).await.unwrap();
+    assert_eq!(node.status().await.unwrap().vetoed_rounds, vec![0]);
+    node.veto_round(0, 0).await.unwrap();
+    assert!(node.progress(0).await.unwrap().is_empty());
+}
+
+/// Three of the validators veto the first round, so everyone moves on to the next round.
+#[tokio::test]
+async fn veto_round_2() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // The round 0 is vetoed once started.
+    for node in nodes.iter_mut().skip(1) {
+        node.progress(0).await.unwrap();
+        node.veto_round(0, 0).await.unwrap();
+    }
+
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        assert_eq!(node.status().await.unwrap().round, 1);
+    }
+    // The round 0 is already completed.
+    assert!(nodes[0].veto_round(0, 0).await.is_err());
+    assert!(nodes[0].status().await.unwrap().vetoed_rounds.is_empty());
+    nodes[0].veto_round(1, 0).await.unwrap();
+    assert_eq!(nodes[0].status().await.unwrap().vetoed_rounds, vec![1]);
+}
+
+/// The validator 1 withdraws its candidate, so it proposes nothing in its round.
+#[tokio::test]
+async fn clear_proposal_candidate_1() {
+    setup_test();
+    let params = ConsensusParams {
+        repeat_round_for_first_leader: 1,
+        ..default_params()
+    };
+    let (mut nodes, network, _) = NodesBuilder::new(4).params(params).build_gossiping().await;
+    let withdrawn = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(withdrawn).await.unwrap();
+    }
+    nodes[1].set_proposal_candidate(withdrawn, 0).await.unwrap();
+    nodes[1].clear_proposal_candidate(0).await.unwrap();
+    assert!(nodes[1].is_this_node_proposer(1).await.unwrap());
+
+    // The validator 0 never runs, so the round 0 times out.
+    let mut results = Vec::new();
+    for timestamp in [0, 6000, 6000, 6000, 6000] {
+        for node in nodes.iter_mut().skip(1) {
+            results.push(node.progress(timestamp).await.unwrap());
+        }
+        exchange(&mut nodes[1..], &network).await;
+    }
+    assert_eq!(nodes[1].status().await.unwrap().round, 1);
+    assert!(results
+        .iter()
+        .flatten()
+        .all(|result| !matches!(result, ProgressResult::Proposed(..))));
+    let messages = nodes[1]
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap();
+    assert!(messages
+        .iter()
+        .all(|message| !matches!(message.message, ConsensusMessage::Proposal { .. })));
+}
+
+#[tokio::test]
+async fn proposal_candidate_queue_1() {
+    setup_test();
+    let (nodes, _) = NodesBuilder::new(4).build().await;
+    let mut node = nodes.into_iter().next().unwrap().0;
+    let this_node = node.get_dms().read().await.public_key();
+    let low = Hash256::hash("low");
+    let high = Hash256::hash("high");
+    for block_hash in [low, high] {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    node.push_proposal_candidate(low, 1, 0).await.unwrap();
+    node.push_proposal_candidate(high, 2, 0).await.unwrap();
+    assert_eq!(
+        node.status().await.unwrap().proposal_candidates,
+        vec![(high, 2), (low, 1)]
+    );
+    assert!(node
+        .push_proposal_candidate(Hash256::hash("unknown"), 3, 0)
+        .await
+        .is_err());
+
+    // The vetoed candidate is passed over.
+    node.veto_block(high).await.unwrap();
+    let proposed = node
+        .progress(0)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|result| match result {
+            ProgressResult::Proposed(round, block_hash, valid_round, proposer, _) => {
+                Some((round, block_hash, valid_round, proposer))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(proposed, vec![(0, low, None, this_node)]);
+}
+
+#[tokio::test]
+async fn observed_votes_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    // The proposer stays in the network but never runs.
+    let mut nodes = nodes.split_off(1);
+    nodes[0].set_report_observed_votes(true);
+    let mut others = Vec::new();
+    for node in nodes[1..].iter() {
+        others.push(node.get_dms().read().await.public_key());
+    }
+
+    for node in nodes.iter_mut() {
+        node.progress(0).await.unwrap();
+        node.progress(6000).await.unwrap();
+    }
+    exchange(&mut nodes, &network).await;
+    let mut observed = Vec::new();
+    for result in nodes[0].progress(6000).await.unwrap() {
+        match result {
+            ProgressResult::VoteObserved {
+                signer,
+                round: 0,
+                block_hash: None,
+                kind: VoteKind::Prevote,
+                ..
+            } => observed.push(signer),
+            ProgressResult::NilPreCommitted(0, _) => (),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+    observed.sort();
+    others.sort();
+    assert_eq!(observed, others);
+    // The others don't report them.
+    for node in nodes[1..].iter_mut() {
+        let results = node.progress(6000).await.unwrap();
+        assert!(matches!(
+            results[..],
+            [ProgressResult::NilPreCommitted(0, _)]
+        ));
+    }
+}
+
+/// The observer reports the prevotes of the validators that lead to the finalization.
+#[tokio::test]
+async fn vote_observed_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).observers(1).build_gossiping().await;
+    // The validator 3 stays in the network but never runs.
+    nodes.remove(3);
+    nodes[3].set_report_observed_votes(true);
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE, PREVOTE, PRECOMMIT and FINALIZE
+    let mut results = Vec::new();
+    for _ in 0..4 {
+        for (i, node) in nodes.iter_mut().enumerate() {
+            if node.check_finalized().await.unwrap().is_some() {
+                continue;
+            }
+            let node_results = node.progress(0).await.unwrap();
+            if i == 3 {
+                results.extend(node_results);
+            } else {
+                assert!(node_results
+                    .iter()
+                    .all(|result| !matches!(result, ProgressResult::VoteObserved { .. })));
+            }
+        }
+        exchange(&mut nodes, &network).await;
+    }
+    let finalized = results
+        .iter()
+        .position(|result| matches!(result, ProgressResult::Finalized(_)))
+        .unwrap();
+    let mut prevoters = results[..finalized]
+        .iter()
+        .filter_map(|result| match result {
+            ProgressResult::VoteObserved {
+                signer,
+                round: 0,
+                block_hash: Some(x),
+                kind: VoteKind::Prevote,
+                ..
+            } if *x == block_hash => Some(signer.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(prevoters.len(), 3);
+    prevoters.sort();
+    prevoters.dedup();
+    assert_eq!(prevoters.len(), 3);
+}
+
+/// A single validator finalizes by its own votes, read back from its own DMS,
+/// even if nothing is delivered over the network.
+#[tokio::test]
+async fn loopback_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(1).build_gossiping().await;
+    network.set_drop_probability(1.0);
+    let block_hash = Hash256::hash("block");
+    nodes[0]
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    for _ in 0..10 {
+        step(&mut nodes, &network, 0).await;
+        nodes[0].broadcast(&network, 0).await.unwrap();
+    }
+    let finalization = nodes[0].check_finalized().await.unwrap().unwrap();
+    assert_eq!(finalization.block_hash, block_hash);
+    let status = nodes[0].status().await.unwrap();
+    assert!(status.deliveries.iter().all(|delivery| !delivery.delivered));
+}
+
+/// The proposer consumes the echoes of its own messages, re-delivered by the peers
+/// after it has lost them, without reporting anything twice.
+#[tokio::test]
+async fn own_echo_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let mut results = Vec::new();
+    for _ in 0..10 {
+        if nodes[0].check_finalized().await.unwrap().is_some() {
+            break;
+        }
+        results.extend(nodes[0].progress(0).await.unwrap());
+        for node in nodes[1..].iter_mut() {
+            if node.check_finalized().await.unwrap().is_none() {
+                node.progress(0).await.unwrap();
+            }
+        }
+        exchange(&mut nodes, &network).await;
+        // Losing the messages makes the peers deliver them again as new ones.
+        let dms = nodes[0].get_dms();
+        let messages = dms.read().await.read_messages().await.unwrap();
+        for message in messages {
+            dms.write()
+                .await
+                .remove_message(message.message.to_hash256(), None)
+                .await
+                .unwrap();
+        }
+        network.gossip().await.unwrap();
+    }
+    let finalization = nodes[0].check_finalized().await.unwrap().unwrap();
+    assert_eq!(finalization.block_hash, block_hash);
+    for (i, result) in results.iter().enumerate() {
+        assert!(
+            !results[..i].contains(result),
+            "{result:?} is reported twice"
+        );
+        assert!(!matches!(result, ProgressResult::ViolationReported(..)));
+    }
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, ProgressResult::Proposed(..))));
+}
+
+/// Two observers catch up with the backlog of a finalized height,
+/// one at once and the other in small batches, reaching the same finalization.
+#[tokio::test]
+async fn fetch_and_progress_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).observers(2).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // The observers receive the messages without processing them.
+    for _ in 0..4 {
+        step(&mut nodes[..4], &network, 0).await;
+    }
+    let (results, more) = nodes[4].fetch_and_progress(0, usize::MAX).await.unwrap();
+    assert!(!more);
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, ProgressResult::Finalized(_))));
+
+    let mut calls = 0;
+    loop {
+        let (results, more) = nodes[5].fetch_and_progress(0, 2).await.unwrap();
+        calls += 1;
+        if results
+            .iter()
+            .any(|result| matches!(result, ProgressResult::Finalized(_)))
+        {
+            break;
+        }
+        assert!(more);
+    }
+    assert!(calls > 1);
+    let (at_once, in_batches) = (
+        nodes[4].check_finalized().await.unwrap().unwrap(),
+        nodes[5].check_finalized().await.unwrap().unwrap(),
+    );
+    assert_eq!(at_once.block_hash, block_hash);
+    assert_eq!(in_batches.block_hash, block_hash);
+    assert_eq!(
+        nodes[4].status().await.unwrap().round,
+        nodes[5].status().await.unwrap().round
+    );
+}
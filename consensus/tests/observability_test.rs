@@ -0,0 +1,474 @@
+use simperby_consensus::simulation::{seed_from_env, Simulation, StepOrder};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn status_1() {
+    setup_test();
+    let (mut nodes, fi) = NodesBuilder::new(4).observers(1).build().await;
+    let block_hashes = (0..3)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+
+    for (i, (node, _)) in nodes.iter_mut().enumerate() {
+        let status = node.status().await.unwrap();
+        assert_eq!(status.height, fi.header.height + 1);
+        assert_eq!(status.round, 0);
+        assert_eq!(status.step, ConsensusStep::Initial);
+        assert_eq!(status.this_node_index, if i < 4 { Some(i) } else { None });
+        assert!(!status.finalized);
+        let total = node.total_voting_power().await.unwrap();
+        assert_eq!(total, total_voting_power(&fi.header.validator_set));
+        let threshold = node.quorum_threshold().await.unwrap();
+        let quorum = Quorum::default();
+        assert!(quorum.is_reached(threshold, total) && !quorum.is_reached(threshold - 1, total));
+        assert_eq!(
+            node.this_node_voting_power().await.unwrap(),
+            status
+                .this_node_index
+                .map(|index| fi.header.validator_set[index].1)
+        );
+
+        for block_hash in block_hashes.iter().rev() {
+            node.register_verified_block_hash(*block_hash)
+                .await
+                .unwrap();
+        }
+        node.veto_block(block_hashes[1]).await.unwrap();
+        node.progress(0).await.unwrap();
+
+        let status = node.status().await.unwrap();
+        assert_eq!(status.round, 0);
+        // The proposer of the first round proposes its initial candidate right away.
+        if i == 0 {
+            assert_eq!(status.step, ConsensusStep::Prevote);
+        } else {
+            assert_eq!(status.step, ConsensusStep::Propose);
+        }
+        assert_eq!(status.locked, None);
+        assert_eq!(status.valid, None);
+        assert_eq!(
+            status.verified_block_hashes,
+            block_hashes.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(status.vetoed_block_hashes, vec![block_hashes[1]]);
+    }
+}
+
+/// A dashboard inspects the storage of a node while it progresses in a simulation,
+/// without taking the lock of the node.
+#[tokio::test]
+async fn inspector_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let block_hash = Hash256::hash("block");
+    let directory = create_temp_dir();
+    let mut nodes = Vec::new();
+    for (i, (_, key)) in keys.iter().enumerate() {
+        let dir = if i == 0 {
+            directory.clone()
+        } else {
+            create_temp_dir()
+        };
+        StorageImpl::create(&dir).await.unwrap();
+        let mut node = Consensus::new(
+            create_dms(members.clone(), key.clone()).await,
+            StorageImpl::open(&dir).await.unwrap(),
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(key.clone())),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        node.set_event_log(true);
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        nodes.push(node);
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let inspector =
+        Consensus::open_read_only(StorageImpl::open_read_only(&directory).await.unwrap(), None)
+            .await
+            .unwrap();
+    assert!(!inspector.status().await.unwrap().finalized);
+
+    let mut simulation = Simulation::new(
+        nodes,
+        fi.header.validator_set.clone(),
+        0,
+        StepOrder::RoundRobin,
+        seed_from_env(),
+    )
+    .await
+    .unwrap();
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let watcher = tokio::spawn(async move {
+        loop {
+            let status = inspector.status().await.unwrap();
+            let tally = inspector.vote_tally(status.round).await.unwrap();
+            assert_eq!(tally.round, status.round);
+            inspector.read_event_log(0).await.unwrap();
+            if *stopped.borrow() {
+                return inspector;
+            }
+            tokio::task::yield_now().await;
+        }
+    });
+    simulation.run_until_finalized(20).await.unwrap();
+    stop.send(true).unwrap();
+    let inspector = watcher.await.unwrap();
+
+    assert!(inspector.status().await.unwrap().finalized);
+    let proof = inspector.get_finalization_proof().await.unwrap().unwrap();
+    verify_finalization_proof(&block_hash, &proof, &fi.header.validator_set).unwrap();
+    let tally = inspector.vote_tally(proof.round).await.unwrap();
+    assert!(tally.precommits.contains_key(&Some(block_hash)));
+    assert!(inspector
+        .read_event_log(0)
+        .await
+        .unwrap()
+        .iter()
+        .any(|entry| matches!(entry.result, ProgressResult::Finalized(_))));
+    assert!(inspector.list_evidence().await.unwrap().is_empty());
+    assert_eq!(
+        simulation.nodes()[0]
+            .get_finalization_proof()
+            .await
+            .unwrap(),
+        Some(proof)
+    );
+}
+
+/// Only two of the four validators are running, so the round stalls with their prevotes.
+#[tokio::test]
+async fn vote_tally_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE and PREVOTE
+    for _ in 0..3 {
+        step(&mut nodes[..2], &network, 0).await;
+    }
+    for node in nodes.iter().take(2) {
+        let tally = node.vote_tally(0).await.unwrap();
+        assert_eq!(tally.round, 0);
+        assert_eq!(
+            tally.prevotes,
+            vec![(Some(block_hash), 2)].into_iter().collect()
+        );
+        assert!(tally.precommits.is_empty());
+        assert_eq!(
+            tally.missing_validators,
+            vec![
+                fi.header.validator_set[2].0.clone(),
+                fi.header.validator_set[3].0.clone()
+            ]
+        );
+        assert_eq!(tally.total_voting_power, 4);
+    }
+    // Nothing in the next round
+    let tally = nodes[0].vote_tally(1).await.unwrap();
+    assert!(tally.prevotes.is_empty());
+    assert_eq!(tally.missing_validators.len(), 4);
+}
+
+/// An observer reports the validators that don't run as silent.
+#[tokio::test]
+async fn liveness_report_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).observers(1).build_gossiping().await;
+    let mut observer = nodes.pop().unwrap();
+    nodes.truncate(2);
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    // Otherwise the messages on the block are held back from its DMS.
+    observer
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE and PREVOTE
+    for _ in 0..3 {
+        step(&mut nodes, &network, 0).await;
+    }
+    network.gossip().await.unwrap();
+    let report = observer.liveness_report(10).await.unwrap();
+    assert_eq!((report.first_round, report.last_round), (0, 0));
+    for (i, liveness) in report.validators.iter().enumerate() {
+        assert_eq!(liveness.validator, fi.header.validator_set[i].0);
+        assert_eq!(liveness.voting_power, 1);
+        let expected = if i < 2 { (1, 0) } else { (0, 1) };
+        assert_eq!((liveness.active_rounds, liveness.silent_rounds), expected);
+    }
+    assert!(observer.liveness_report(0).await.is_err());
+}
+
+#[tokio::test]
+async fn metrics_1() {
+    setup_test();
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let metrics = Arc::new(AtomicMetrics::default());
+    nodes[0]
+        .set_metrics(Arc::clone(&metrics) as Arc<dyn ConsensusMetrics>)
+        .await
+        .unwrap();
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE, PREVOTE, PRECOMMIT and FINALIZE
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    assert!(nodes[0].check_finalized().await.unwrap().is_some());
+    let load =
+        |counter: &std::sync::atomic::AtomicU64| counter.load(std::sync::atomic::Ordering::Relaxed);
+    // The proposal, the prevote and the precommit of this node
+    assert_eq!(load(&metrics.messages_broadcast), 3);
+    assert_eq!(load(&metrics.broadcast_failures), 0);
+    assert!(load(&metrics.messages_processed) > 0);
+    assert!(load(&metrics.messages_accepted) > 0);
+    assert_eq!(metrics.rounds_per_height.count(), 1);
+    assert_eq!(metrics.rounds_per_height.sum(), 1);
+    assert_eq!(metrics.time_to_finalization_ms.count(), 1);
+    assert!(metrics.state_commit_latency_us.count() > 0);
+}
+
+/// Captures the output of a tracing subscriber.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[tokio::test]
+async fn tracing_1() {
+    setup_test();
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(logs.clone())
+        .finish();
+    // The test runs on a single thread, so the subscriber sees everything.
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (mut nodes, network, _) = NodesBuilder::new(4).build_gossiping().await;
+    let block_hash = Hash256::hash("block");
+    nodes[0]
+        .register_verified_block_hash(block_hash)
+        .await
+        .unwrap();
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // The others reject the proposal of the block unknown to them, and prevote nil.
+    step(&mut nodes, &network, 0).await;
+    step(&mut nodes, &network, 6000).await;
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    for expected in [
+        "progress{",
+        "update{",
+        "commit_messages{",
+        "consumed a consensus message",
+        "kind=Prevote",
+        "rejected a consensus message",
+        "reason=UnverifiedBlock",
+    ] {
+        assert!(
+            logs.contains(expected),
+            "{expected} is not in the logs:\n{logs}"
+        );
+    }
+}
+
+/// The event log of a node matches what it has done: the messages it has signed
+/// and the finalization in its state.
+#[tokio::test]
+async fn event_log_1() {
+    setup_test();
+    let (mut nodes, network, fi) = NodesBuilder::new(4).build_gossiping().await;
+    nodes[0].set_event_log(true);
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // PROPOSE, PREVOTE, PRECOMMIT and FINALIZE
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    let entries = nodes[0].read_event_log(0).await.unwrap();
+    assert_eq!(entries.len(), 4);
+    for (i, entry) in entries.iter().enumerate() {
+        assert_eq!(entry.seq, i as u64);
+        assert_eq!(entry.height, fi.header.height + 1);
+        assert_eq!(entry.round, 0);
+    }
+    assert_eq!(nodes[0].read_event_log(3).await.unwrap(), entries[3..]);
+
+    // Replay the log against the DMS and the state.
+    let this_node = nodes[0].get_dms().read().await.public_key();
+    let mut signed = nodes[0]
+        .get_dms()
+        .read()
+        .await
+        .read_messages()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|message| {
+            message
+                .committers
+                .iter()
+                .any(|commitment| commitment.committer == this_node)
+        })
+        .map(|message| message.message)
+        .collect::<Vec<_>>();
+    let height = fi.header.height + 1;
+    let mut replayed = Vec::new();
+    for entry in entries.iter() {
+        match &entry.result {
+            ProgressResult::Proposed(round, block_hash, valid_round, proposer, _) => {
+                assert_eq!(*proposer, this_node);
+                replayed.push(ConsensusMessage::Proposal {
+                    height,
+                    round: *round,
+                    valid_round: *valid_round,
+                    block_hash: *block_hash,
+                });
+            }
+            ProgressResult::NonNilPreVoted(round, block_hash, _) => replayed.push(
+                ConsensusMessage::NonNilPreVoted(height, *round, *block_hash),
+            ),
+            ProgressResult::NonNilPreCommitted(round, block_hash, _) => replayed.push(
+                ConsensusMessage::NonNilPreCommitted(height, *round, *block_hash),
+            ),
+            ProgressResult::Finalized(finalization) => assert_eq!(
+                Some(finalization),
+                nodes[0].check_finalized().await.unwrap().as_ref()
+            ),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+    replayed.sort_by_key(|message| message.to_hash256());
+    signed.sort_by_key(|message| message.to_hash256());
+    assert_eq!(replayed, signed);
+}
+
+/// The statistics of the DMS tell the arrived messages and the rejected ones.
+#[tokio::test]
+async fn dms_stats_1() {
+    setup_test();
+    let (garbage_public_key, garbage_private_key) = generate_keypair("garbage");
+    let (mut nodes, mut network, fi) = NodesBuilder::new(4)
+        .other_members(vec![garbage_public_key.clone()])
+        .build_gossiping()
+        .await;
+    let (_, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .chain(std::iter::once(garbage_public_key))
+        .collect::<Vec<_>>();
+    let garbage = Arc::new(RwLock::new(
+        create_test_dms::<ConsensusMessage>("consensus".to_owned(), members, garbage_private_key)
+            .await,
+    ));
+    for round in 0..3 {
+        garbage
+            .write()
+            .await
+            .commit_message(&ConsensusMessage::NilPreVoted(fi.header.height + 1, round))
+            .await
+            .unwrap();
+    }
+    network.add_node(garbage);
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    let stats = nodes[1].dms_stats().await.unwrap();
+    assert_eq!(stats.height, fi.header.height + 1);
+    assert_eq!(stats.total_messages, 0);
+    assert_eq!(stats.last_admitted, None);
+    assert!(stats.rejections.values().all(|count| *count == 0));
+
+    // PROPOSE and PREVOTE
+    for _ in 0..2 {
+        step(&mut nodes, &network, 0).await;
+    }
+    let stats = nodes[1].dms_stats().await.unwrap();
+    assert_eq!(stats.total_messages, 5);
+    assert_eq!(stats.messages_by_kind.get(&VoteKind::Proposal), Some(&1));
+    assert_eq!(stats.messages_by_kind.get(&VoteKind::Prevote), Some(&4));
+    assert_eq!(stats.messages_by_kind.get(&VoteKind::Precommit), None);
+    assert_eq!(stats.messages_by_round, vec![(0, 5)].into_iter().collect());
+    assert!(stats.last_admitted.is_some());
+    assert!(stats.rejections[&FilterRejection::NotAValidator] >= 3);
+    assert_eq!(stats.rejections[&FilterRejection::InvalidSignature], 0);
+    assert!(stats.storage_size > 0);
+}
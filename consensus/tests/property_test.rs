@@ -0,0 +1,354 @@
+//! The safety invariants of `ConsensusCore`, checked over arbitrary interleavings of the messages.
+//!
+//! The number of the cases is bounded for `cargo test`;
+//! set `SIMPERBY_PROPTEST_CASES` for a longer fuzzing run.
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The environment variable to give the number of the cases.
+const CASES_ENV_VAR: &str = "SIMPERBY_PROPTEST_CASES";
+const DEFAULT_CASES: u32 = 64;
+
+fn config() -> ProptestConfig {
+    let cases = match std::env::var(CASES_ENV_VAR) {
+        Ok(cases) => cases
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid {CASES_ENV_VAR}: {cases}")),
+        Err(_) => DEFAULT_CASES,
+    };
+    ProptestConfig::with_cases(cases)
+}
+
+/// A step of the schedule, whose indices are taken modulo the number of the candidates.
+#[derive(Debug, Clone)]
+enum Step {
+    /// Delivers one of the messages that the honest node hasn't received yet.
+    Deliver { to: usize, pick: usize },
+    /// Lets the time pass for every honest node.
+    Tick(Timestamp),
+    /// Makes a byzantine validator sign an arbitrary message and send it to an honest node,
+    /// after which it is gossiped like the others.
+    Equivocate {
+        signer: usize,
+        to: usize,
+        round: ConsensusRound,
+        kind: u8,
+        block: usize,
+    },
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        6 => (any::<usize>(), any::<usize>()).prop_map(|(to, pick)| Step::Deliver { to, pick }),
+        1 => (1..250 as Timestamp).prop_map(Step::Tick),
+        2 => (
+            any::<usize>(),
+            any::<usize>(),
+            0..3 as ConsensusRound,
+            0..5u8,
+            any::<usize>()
+        )
+            .prop_map(|(signer, to, round, kind, block)| Step::Equivocate {
+                signer,
+                to,
+                round,
+                kind,
+                block,
+            }),
+    ]
+}
+
+/// The voting powers of the validators, with whether each one wants to be byzantine.
+fn validators() -> impl Strategy<Value = Vec<(VotingPower, bool)>> {
+    prop::collection::vec((1..=10 as VotingPower, any::<bool>()), 4..=7)
+}
+
+struct HonestNode {
+    core: ConsensusCore,
+    private_key: PrivateKey,
+    /// The indices of the messages in the pool that this node has received.
+    received: BTreeSet<usize>,
+    /// The messages that this node has consumed, including its own ones.
+    consumed: Vec<(ConsensusMessage, PublicKey)>,
+    signed: BTreeMap<(ConsensusRound, VoteKind), ConsensusMessage>,
+}
+
+struct Harness {
+    validator_set: Vec<(PublicKey, VotingPower)>,
+    height: BlockHeight,
+    dms_key: DmsKey,
+    blocks: Vec<Hash256>,
+    honest_nodes: Vec<HonestNode>,
+    byzantine_keys: Vec<PrivateKey>,
+    /// Every message that has been sent, which can be delivered to any honest node.
+    pool: Vec<(ConsensusMessage, MessageCommitmentProof)>,
+    finalized: BTreeMap<PublicKey, Hash256>,
+    now: Timestamp,
+}
+
+fn fail(e: impl std::fmt::Display) -> TestCaseError {
+    TestCaseError::fail(e.to_string())
+}
+
+fn vote_key(message: &ConsensusMessage) -> (ConsensusRound, VoteKind) {
+    match message {
+        ConsensusMessage::Proposal { round, .. } => (*round, VoteKind::Proposal),
+        ConsensusMessage::NonNilPreVoted(_, round, _) | ConsensusMessage::NilPreVoted(_, round) => {
+            (*round, VoteKind::Prevote)
+        }
+        ConsensusMessage::NonNilPreCommitted(_, round, _)
+        | ConsensusMessage::NilPreCommitted(_, round) => (*round, VoteKind::Precommit),
+    }
+}
+
+impl Harness {
+    fn new(validators: &[(VotingPower, bool)]) -> Result<Self, TestCaseError> {
+        let (mut fi, keys) = test_utils::generate_fi(validators.len());
+        for (i, (power, _)) in validators.iter().enumerate() {
+            fi.header.validator_set[i].1 = *power;
+        }
+        let total = total_voting_power(&fi.header.validator_set);
+        // The byzantine ones must have less than 1/3 of the voting power.
+        let mut byzantine_power = 0;
+        let mut byzantine = vec![false; validators.len()];
+        for (i, (power, wants_byzantine)) in validators.iter().enumerate() {
+            if *wants_byzantine && (byzantine_power + *power as u128) * 3 < total {
+                byzantine_power += *power as u128;
+                byzantine[i] = true;
+            }
+        }
+        let blocks = (0..validators.len())
+            .map(|i| Hash256::hash(format!("block-{i}")))
+            .collect::<Vec<_>>();
+        let params = ConsensusParams {
+            timeout_ms: 100,
+            timeout_increment_ms: 50,
+            repeat_round_for_first_leader: 1,
+            quorum: None,
+        };
+        let mut honest_nodes = Vec::new();
+        let mut byzantine_keys = Vec::new();
+        for (i, (public_key, private_key)) in keys.into_iter().enumerate() {
+            if byzantine[i] {
+                byzantine_keys.push(private_key);
+                continue;
+            }
+            let mut core = ConsensusCore::new(&fi.header, params.clone(), 0, Some(public_key))
+                .map_err(fail)?;
+            for block in &blocks {
+                core.register_verified_block_hash(*block).map_err(fail)?;
+            }
+            core.set_proposal_candidate(blocks[i], 0).map_err(fail)?;
+            honest_nodes.push(HonestNode {
+                core,
+                private_key,
+                received: BTreeSet::new(),
+                consumed: Vec::new(),
+                signed: BTreeMap::new(),
+            });
+        }
+        let mut harness = Self {
+            validator_set: fi.header.validator_set.clone(),
+            height: fi.header.height + 1,
+            dms_key: "consensus".to_owned(),
+            blocks,
+            honest_nodes,
+            byzantine_keys,
+            pool: Vec::new(),
+            finalized: BTreeMap::new(),
+            now: 0,
+        };
+        for i in 0..harness.honest_nodes.len() {
+            harness.progress(i)?;
+        }
+        Ok(harness)
+    }
+
+    fn run(&mut self, step: &Step) -> Result<(), TestCaseError> {
+        match *step {
+            Step::Deliver { to, pick } => {
+                let to = to % self.honest_nodes.len();
+                let pending = (0..self.pool.len())
+                    .filter(|i| !self.honest_nodes[to].received.contains(i))
+                    .collect::<Vec<_>>();
+                if pending.is_empty() {
+                    return Ok(());
+                }
+                self.deliver(to, pending[pick % pending.len()])?;
+                self.progress(to)
+            }
+            Step::Tick(duration) => {
+                self.now += duration;
+                for i in 0..self.honest_nodes.len() {
+                    self.progress(i)?;
+                }
+                Ok(())
+            }
+            Step::Equivocate {
+                signer,
+                to,
+                round,
+                kind,
+                block,
+            } => {
+                if self.byzantine_keys.is_empty() {
+                    return Ok(());
+                }
+                let private_key = &self.byzantine_keys[signer % self.byzantine_keys.len()];
+                let block_hash = self.blocks[block % self.blocks.len()];
+                let message = match kind {
+                    0 => ConsensusMessage::Proposal {
+                        height: self.height,
+                        round,
+                        valid_round: None,
+                        block_hash,
+                    },
+                    1 => ConsensusMessage::NonNilPreVoted(self.height, round, block_hash),
+                    2 => ConsensusMessage::NonNilPreCommitted(self.height, round, block_hash),
+                    3 => ConsensusMessage::NilPreVoted(self.height, round),
+                    _ => ConsensusMessage::NilPreCommitted(self.height, round),
+                };
+                let commitment = message.commit(&self.dms_key, private_key).map_err(fail)?;
+                self.pool.push((message, commitment));
+                let to = to % self.honest_nodes.len();
+                self.deliver(to, self.pool.len() - 1)?;
+                self.progress(to)
+            }
+        }
+    }
+
+    fn deliver(&mut self, to: usize, index: usize) -> Result<(), TestCaseError> {
+        let node = &mut self.honest_nodes[to];
+        node.received.insert(index);
+        if node.core.check_finalized().is_some() {
+            return Ok(());
+        }
+        let (message, commitment) = self.pool[index].clone();
+        node.consumed
+            .push((message.clone(), commitment.committer.clone()));
+        node.core
+            .receive(message, commitment, &self.dms_key, self.now)
+            .map_err(fail)?;
+        Ok(())
+    }
+
+    /// Makes a progress of the node, broadcasting its messages and consuming them by itself,
+    /// until there is nothing more to broadcast.
+    fn progress(&mut self, i: usize) -> Result<(), TestCaseError> {
+        loop {
+            if self.honest_nodes[i].core.check_finalized().is_some() {
+                return Ok(());
+            }
+            let effects = self.honest_nodes[i].core.progress(self.now).map_err(fail)?;
+            let mut broadcasted = false;
+            for effect in effects {
+                match effect {
+                    ConsensusEffect::PersistState => (),
+                    ConsensusEffect::Broadcast(message) => {
+                        broadcasted = true;
+                        self.broadcast(i, message)?;
+                    }
+                    ConsensusEffect::EmitResult(ProgressResult::Finalized(finalization)) => {
+                        self.check_finalization(i, &finalization)?;
+                    }
+                    ConsensusEffect::EmitResult(_) => (),
+                }
+            }
+            if !broadcasted {
+                return Ok(());
+            }
+        }
+    }
+
+    fn broadcast(&mut self, i: usize, message: ConsensusMessage) -> Result<(), TestCaseError> {
+        let node = &mut self.honest_nodes[i];
+        let public_key = node.private_key.public_key();
+        if let Some(signed) = node.signed.get(&vote_key(&message)) {
+            prop_assert_eq!(
+                signed,
+                &message,
+                "{} signed two conflicting messages",
+                public_key
+            );
+        }
+        node.signed.insert(vote_key(&message), message.clone());
+        let commitment = message
+            .commit(&self.dms_key, &node.private_key)
+            .map_err(fail)?;
+        node.core.mark_broadcast(&message);
+        self.pool.push((message.clone(), commitment));
+        let index = self.pool.len() - 1;
+        if node.core.check_finalized().is_some() {
+            // The own precommit that has finalized the block, which vetomint has counted.
+            node.received.insert(index);
+            node.consumed.push((message, public_key));
+            return Ok(());
+        }
+        self.deliver(i, index)
+    }
+
+    fn check_finalization(
+        &mut self,
+        i: usize,
+        finalization: &Finalization,
+    ) -> Result<(), TestCaseError> {
+        let node = &self.honest_nodes[i];
+        let public_key = node.private_key.public_key();
+        let precommitters = node
+            .consumed
+            .iter()
+            .filter(|(message, _)| {
+                *message
+                    == ConsensusMessage::NonNilPreCommitted(
+                        self.height,
+                        finalization.proof.round,
+                        finalization.block_hash,
+                    )
+            })
+            .map(|(_, author)| author)
+            .collect::<BTreeSet<_>>();
+        let voting_power = self
+            .validator_set
+            .iter()
+            .filter(|(validator, _)| precommitters.contains(&validator))
+            .map(|(_, power)| *power as u128)
+            .sum();
+        prop_assert!(
+            Quorum::default().is_reached(voting_power, total_voting_power(&self.validator_set)),
+            "{} finalized {} without the quorum of the precommits",
+            public_key,
+            finalization.block_hash
+        );
+        for (other, block_hash) in &self.finalized {
+            prop_assert_eq!(
+                block_hash,
+                &finalization.block_hash,
+                "{} and {} finalized different blocks",
+                other,
+                public_key
+            );
+        }
+        self.finalized.insert(public_key, finalization.block_hash);
+        Ok(())
+    }
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    fn safety(
+        validators in validators(),
+        steps in prop::collection::vec(step(), 1..300),
+    ) {
+        let mut harness = Harness::new(&validators)?;
+        for step in &steps {
+            harness.run(step)?;
+        }
+    }
+}
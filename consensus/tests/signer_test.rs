@@ -0,0 +1,442 @@
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn no_double_sign_after_restart_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+    let proposals = |messages: Vec<dms::Message<ConsensusMessage>>| {
+        messages
+            .into_iter()
+            .filter(|message| matches!(message.message, ConsensusMessage::Proposal { .. }))
+            .map(|message| message.message)
+            .collect::<Vec<_>>()
+    };
+
+    let block_hashes = (0..2)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.register_verified_block_hash(block_hashes[0])
+        .await
+        .unwrap();
+    node.set_proposal_candidate(block_hashes[0], 0)
+        .await
+        .unwrap();
+    node.progress(0).await.unwrap();
+    node.flush().await.unwrap();
+    let proposal = proposals(dms.read().await.read_messages().await.unwrap());
+    assert_eq!(proposal.len(), 1);
+    drop(node);
+
+    // Simulate a restart that loses the consensus state after the proposal is signed.
+    let mut lost = storage.clone();
+    lost.remove_file("state.json").await.unwrap();
+    lost.remove_file("state.backup.json").await.unwrap();
+
+    let mut node = new_node(storage.clone()).await.unwrap();
+    assert!(dms.read().await.read_messages().await.unwrap().is_empty());
+    // The operator changes the candidate in between.
+    for block_hash in &block_hashes {
+        node.register_verified_block_hash(*block_hash)
+            .await
+            .unwrap();
+    }
+    node.set_proposal_candidate(block_hashes[1], 0)
+        .await
+        .unwrap();
+    node.progress(0).await.unwrap();
+    assert!(node.flush().await.is_err());
+    // The identical proposal is committed again instead of the new one.
+    assert_eq!(
+        proposals(dms.read().await.read_messages().await.unwrap()),
+        proposal
+    );
+}
+
+#[tokio::test]
+async fn key_errors_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let mut dmses = Vec::new();
+    for (_, private_key) in keys.iter().take(2) {
+        dmses.push(create_dms(members.clone(), private_key.clone()).await);
+    }
+    let storage = MemoryStorage::new().await;
+    let new_node = |dms_index: usize, this_node_key: Option<PrivateKey>| {
+        Consensus::new(
+            Arc::clone(&dmses[dms_index]),
+            storage.clone(),
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(this_node_key),
+                ..Default::default()
+            },
+        )
+    };
+
+    // The DMS signs with the key of another validator.
+    let error = new_node(1, Some(keys[0].1.clone())).await.err().unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::KeyMismatch {
+            expected: Box::new(keys[0].0.clone()),
+            actual: Box::new(keys[1].0.clone()),
+            index: 0,
+        }
+    );
+
+    let node = new_node(0, Some(keys[0].1.clone())).await.unwrap();
+    drop(node);
+    // The stored state is of the validator 0.
+    let error = new_node(1, Some(keys[1].1.clone())).await.err().unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::KeyMismatch {
+            expected: Box::new(keys[0].0.clone()),
+            actual: Box::new(keys[1].0.clone()),
+            index: 0,
+        }
+    );
+    let error = new_node(0, None).await.err().unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::MissingKey {
+            expected: keys[0].0.clone(),
+            index: 0,
+        }
+    );
+
+    let node = new_node(0, Some(keys[0].1.clone())).await.unwrap();
+    let mut removed = storage.clone();
+    for name in ["state.json", "state.backup.json"] {
+        removed.remove_file(name).await.unwrap();
+    }
+    let error = node.status().await.unwrap_err();
+    assert_eq!(error, ConsensusError::StateNotInitialized);
+}
+
+/// A signer standing for an HSM, which takes a while and fails while `failing` is set.
+struct MockSigner {
+    key: PrivateKey,
+    latency: std::time::Duration,
+    failing: std::sync::atomic::AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl ConsensusSigner for MockSigner {
+    async fn public_key(&self) -> PublicKey {
+        self.key.public_key()
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Signature, Error> {
+        tokio::time::sleep(self.latency).await;
+        if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(eyre::eyre!("the signer is unavailable"));
+        }
+        ConsensusSigner::sign(&self.key, payload).await
+    }
+}
+
+/// A validator signs with a remote signer, which is unavailable or slow for a while,
+/// and connects to the network with a separate key.
+#[tokio::test]
+async fn remote_signer_1() {
+    setup_test();
+    let params = default_params();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let (network_public_key, network_private_key) = generate_keypair("network key of node 0");
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .chain(std::iter::once(network_public_key))
+        .collect::<Vec<_>>();
+    let remote_signer = Arc::new(MockSigner {
+        key: keys[0].1.clone(),
+        latency: std::time::Duration::from_millis(50),
+        failing: std::sync::atomic::AtomicBool::new(true),
+    });
+    let mut network = MockNetwork::new();
+    let mut nodes = Vec::new();
+    for (i, (_, private_key)) in keys.iter().enumerate() {
+        let (dms_key, this_node_signer) = if i == 0 {
+            (
+                network_private_key.clone(),
+                Some(Arc::clone(&remote_signer) as Arc<dyn ConsensusSigner>),
+            )
+        } else {
+            (private_key.clone(), signer(Some(private_key.clone())))
+        };
+        let dms = create_dms(members.clone(), dms_key).await;
+        network.add_node(Arc::clone(&dms));
+        nodes.push(
+            Consensus::new(
+                dms,
+                MemoryStorage::new().await,
+                fi.header.clone(),
+                params.clone(),
+                0,
+                ConsensusConfig {
+                    this_node_signer,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+        );
+    }
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+
+    // The proposal is kept to be signed while the signer is unavailable.
+    let dms = nodes[0].get_dms();
+    nodes[0].progress(0).await.unwrap();
+    assert!(matches!(
+        nodes[0].flush().await.unwrap_err(),
+        ConsensusError::Signer(_)
+    ));
+    assert!(matches!(
+        nodes[0].progress(0).await.unwrap_err(),
+        ConsensusError::Signer(_)
+    ));
+    assert!(dms.read().await.read_messages().await.unwrap().is_empty());
+
+    // Or while it doesn't respond in time.
+    remote_signer
+        .failing
+        .store(false, std::sync::atomic::Ordering::SeqCst);
+    nodes[0].set_signer_timeout(std::time::Duration::from_millis(10));
+    assert!(matches!(
+        nodes[0].flush().await.unwrap_err(),
+        ConsensusError::Signer(_)
+    ));
+    assert!(dms.read().await.read_messages().await.unwrap().is_empty());
+
+    nodes[0].set_signer_timeout(DEFAULT_SIGNER_TIMEOUT);
+    nodes[0].flush().await.unwrap();
+    // The proposal and the prevote on it
+    assert_eq!(dms.read().await.read_messages().await.unwrap().len(), 2);
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        verify_finalization_proof(&block_hash, &finalization.proof, &fi.header.validator_set)
+            .unwrap();
+        assert!(finalization
+            .proof
+            .signatures
+            .iter()
+            .any(|signature| *signature.signer() == keys[0].0));
+    }
+}
+
+/// Signs with a consensus key delegated by a validator.
+struct DelegatedSigner {
+    key: PrivateKey,
+    delegation: SignedDelegation,
+}
+
+#[async_trait::async_trait]
+impl ConsensusSigner for DelegatedSigner {
+    async fn public_key(&self) -> PublicKey {
+        self.key.public_key()
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Signature, Error> {
+        ConsensusSigner::sign(&self.key, payload).await
+    }
+
+    async fn delegation(&self) -> Option<SignedDelegation> {
+        Some(self.delegation.clone())
+    }
+}
+
+/// A validator keeps its identity key cold and signs with the consensus keys delegated by it,
+/// rotating them at the boundary of the heights, until the last one is revoked.
+#[tokio::test]
+async fn delegation_1() {
+    setup_test();
+    let params = default_params();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let height = fi.header.height + 1;
+    let consensus_keys = (0..2)
+        .map(|i| generate_keypair(format!("consensus key {i}")))
+        .collect::<Vec<_>>();
+    let delegate = |i: usize, heights: std::ops::RangeInclusive<BlockHeight>, sequence| {
+        SignedDelegation::sign(
+            Delegation {
+                identity: keys[0].0.clone(),
+                consensus_key: consensus_keys[i].0.clone(),
+                from_height: *heights.start(),
+                to_height: *heights.end(),
+                sequence,
+            },
+            &keys[0].1,
+        )
+        .unwrap()
+    };
+    // The first key for this height, rotated to the second one from the next height.
+    let delegations = [
+        delegate(0, height..=height, 0),
+        delegate(1, (height + 1)..=(height + 10), 1),
+    ];
+    let delegated_signer = |i: usize| {
+        Some(Arc::new(DelegatedSigner {
+            key: consensus_keys[i].1.clone(),
+            delegation: delegations[i].clone(),
+        }) as Arc<dyn ConsensusSigner>)
+    };
+    // The consensus keys must be the members of the DMS to have their messages received.
+    let members = keys
+        .iter()
+        .chain(consensus_keys.iter())
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let mut network = MockNetwork::new();
+    let mut nodes = Vec::new();
+    for (i, (_, private_key)) in keys.iter().enumerate() {
+        let (dms_key, this_node_signer) = if i == 0 {
+            (consensus_keys[0].1.clone(), delegated_signer(0))
+        } else {
+            (private_key.clone(), signer(Some(private_key.clone())))
+        };
+        let dms = create_dms(members.clone(), dms_key).await;
+        network.add_node(Arc::clone(&dms));
+        let mut node = Consensus::new(
+            dms,
+            MemoryStorage::new().await,
+            fi.header.clone(),
+            params.clone(),
+            0,
+            ConsensusConfig {
+                this_node_signer,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        for delegation in delegations.iter() {
+            node.add_delegation(delegation.clone()).await.unwrap();
+        }
+        nodes.push(node);
+    }
+    assert_eq!(nodes[0].status().await.unwrap().this_node_index, Some(0));
+
+    let mut header = fi.header.clone();
+    for consensus_key in consensus_keys.iter() {
+        let next_header = BlockHeader {
+            author: header.validator_set[0].0.clone(),
+            previous_hash: header.to_hash256(),
+            height: header.height + 1,
+            ..header.clone()
+        };
+        let block_hash = next_header.to_hash256();
+        for node in nodes.iter_mut() {
+            node.register_verified_block_hash(block_hash).await.unwrap();
+        }
+        nodes[0]
+            .set_proposal_candidate(block_hash, 0)
+            .await
+            .unwrap();
+        for _ in 0..4 {
+            step(&mut nodes, &network, 0).await;
+        }
+        for node in nodes.iter() {
+            let finalization = node.check_finalized().await.unwrap().unwrap();
+            verify_delegated_finalization_proof(
+                &block_hash,
+                &finalization.proof,
+                &header.validator_set,
+                Quorum::default(),
+                &node.get_delegations().await.unwrap(),
+                next_header.height,
+            )
+            .unwrap();
+            assert!(finalization
+                .proof
+                .signatures
+                .iter()
+                .any(|signature| *signature.signer() == consensus_key.0));
+        }
+        let mut next_nodes = Vec::new();
+        for (i, node) in nodes.into_iter().enumerate() {
+            let this_node_signer = if i == 0 {
+                delegated_signer(1)
+            } else {
+                signer(Some(keys[i].1.clone()))
+            };
+            next_nodes.push(
+                node.finalize_and_advance(next_header.clone(), params.clone(), 0, this_node_signer)
+                    .await
+                    .unwrap(),
+            );
+        }
+        nodes = next_nodes;
+        header = next_header;
+    }
+
+    // The second key is revoked from this height, so the identity key signs again.
+    let revocation = delegate(1, (height + 1)..=(height + 1), 2);
+    for node in nodes.iter_mut() {
+        node.add_delegation(revocation.clone()).await.unwrap();
+    }
+    let dms = nodes[1].get_dms();
+    let dms_key = dms.read().await.get_config().dms_key;
+    let message = ConsensusMessage::NilPreVoted(height + 2, 0);
+    let commitment = message.commit(&dms_key, &consensus_keys[1].1).unwrap();
+    assert!(dms
+        .write()
+        .await
+        .receive_message(&message, commitment)
+        .await
+        .is_err());
+    let commitment = message.commit(&dms_key, &keys[0].1).unwrap();
+    dms.write()
+        .await
+        .receive_message(&message, commitment)
+        .await
+        .unwrap();
+    // An older delegation can't undo the revocation.
+    assert!(nodes[1]
+        .add_delegation(delegations[1].clone())
+        .await
+        .is_err());
+}
@@ -0,0 +1,185 @@
+use simperby_consensus::simulation::{StepOrder, DEFAULT_TICK_MS};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_test_suite::*;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn simulation_1() {
+    setup_test();
+    for order in [StepOrder::RoundRobin, StepOrder::Randomized] {
+        let (mut simulation, _, block_hash) = NodesBuilder::new(4).build_simulation(order).await;
+        simulation.run_until_finalized(20).await.unwrap();
+        assert_eq!(simulation.finalized().len(), 4);
+        assert!(simulation.finalized().values().all(|x| *x == block_hash));
+    }
+}
+
+/// A validator set mixing the secp256k1 and the Ed25519 keys.
+#[tokio::test]
+async fn simulation_mixed_schemes_1() {
+    setup_test();
+    let keys = (0..4)
+        .map(|i| {
+            if i % 2 == 0 {
+                generate_keypair(format!("{i}"))
+            } else {
+                generate_keypair_ed25519(format!("{i}"))
+            }
+        })
+        .collect();
+    let (mut simulation, fi, block_hash) = NodesBuilder::with_keys(keys)
+        .build_simulation(StepOrder::Randomized)
+        .await;
+    simulation.run_until_finalized(20).await.unwrap();
+    assert_eq!(simulation.finalized().len(), 4);
+    assert!(simulation.finalized().values().all(|x| *x == block_hash));
+
+    let proof = simulation.nodes()[0]
+        .get_finalization_proof()
+        .await
+        .unwrap()
+        .unwrap();
+    let schemes = proof
+        .signatures
+        .iter()
+        .map(|signature| signature.signer().scheme())
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(schemes.len(), 2);
+    verify_finalization_proof(&block_hash, &proof, &fi.header.validator_set).unwrap();
+}
+
+#[tokio::test]
+async fn simulation_crash_1() {
+    setup_test();
+    // More than 2/3 are still running.
+    let (mut simulation, _, block_hash) = NodesBuilder::new(4)
+        .build_simulation(StepOrder::Randomized)
+        .await;
+    simulation.crash(3);
+    simulation.run_until_finalized(20).await.unwrap();
+    assert_eq!(simulation.finalized().len(), 3);
+    assert!(simulation.finalized().values().all(|x| *x == block_hash));
+
+    // Not any more, so it never finalizes but it's not a violation.
+    let (mut simulation, _, _) = NodesBuilder::new(4)
+        .build_simulation(StepOrder::Randomized)
+        .await;
+    simulation.crash(2);
+    simulation.crash(3);
+    simulation.run_until_finalized(20).await.unwrap();
+    assert!(simulation.finalized().is_empty());
+    assert_eq!(simulation.now(), 20 * DEFAULT_TICK_MS);
+}
+
+#[tokio::test]
+async fn simulation_quorum_1() {
+    setup_test();
+    let params = ConsensusParams {
+        quorum: Some(Quorum {
+            numerator: 4,
+            denominator: 5,
+        }),
+        ..default_params()
+    };
+    for crashed in [None, Some(3)] {
+        let (mut simulation, fi, block_hash) = NodesBuilder::new(4)
+            .params(params.clone())
+            .build_simulation(StepOrder::Randomized)
+            .await;
+        for node in simulation.nodes() {
+            assert_eq!(node.quorum_threshold().await.unwrap(), 4);
+        }
+        if let Some(i) = crashed {
+            simulation.crash(i);
+        }
+        simulation.run_until_finalized(20).await.unwrap();
+        if crashed.is_some() {
+            // 3 of the 4 are not more than 4/5.
+            assert!(simulation.finalized().is_empty());
+        } else {
+            assert_eq!(simulation.finalized().len(), 4);
+            let proof = simulation.nodes()[0]
+                .check_finalized()
+                .await
+                .unwrap()
+                .unwrap()
+                .proof;
+            verify_delegated_finalization_proof(
+                &block_hash,
+                &proof,
+                &fi.header.validator_set,
+                params.quorum(),
+                &Delegations::default(),
+                fi.header.height + 1,
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Returns `rounds_without_progress`, `last_message_timestamp` and `missing_power`
+/// of the stalls in the event log.
+async fn stalls(node: &Consensus<MemoryStorage>) -> Vec<(u64, Option<Timestamp>, VotingPower)> {
+    node.read_event_log(0)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|entry| match entry.result {
+            ProgressResult::Stalled {
+                rounds_without_progress,
+                last_message_timestamp,
+                missing_power,
+                ..
+            } => Some((
+                rounds_without_progress,
+                last_message_timestamp,
+                missing_power,
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn simulation_stall_1() {
+    setup_test();
+    let (mut simulation, _, block_hash) = NodesBuilder::new(4)
+        .build_simulation(StepOrder::Randomized)
+        .await;
+    simulation.crash(2);
+    simulation.crash(3);
+    for node in simulation.nodes_mut() {
+        node.set_event_log(true);
+        node.set_stall_policy(StallPolicy {
+            max_rounds_without_progress: 3,
+            max_silence_ms: 10_000,
+        });
+    }
+    for _ in 0..40 {
+        simulation.step().await.unwrap();
+    }
+    assert!(simulation.finalized().is_empty());
+    for node in &simulation.nodes()[..2] {
+        // Stuck in the first round, without the quorum of the prevotes to time out.
+        let stalls = stalls(node).await;
+        assert_eq!(stalls.len(), 1);
+        let (rounds_without_progress, last_message_timestamp, missing_power) = stalls[0];
+        assert_eq!(rounds_without_progress, 0);
+        assert!(last_message_timestamp.unwrap() < simulation.now() - 10_000);
+        assert_eq!(missing_power, 2);
+    }
+
+    simulation.recover(2);
+    simulation.recover(3);
+    simulation.run_until_finalized(20).await.unwrap();
+    for node in simulation.nodes() {
+        assert_eq!(
+            node.check_finalized().await.unwrap().unwrap().block_hash,
+            block_hash
+        );
+        assert!(stalls(node).await.len() <= 1);
+    }
+}
@@ -0,0 +1,736 @@
+use simperby_consensus::simulation::{seed_from_env, Simulation, StepOrder};
+use simperby_consensus::*;
+use simperby_core::*;
+use simperby_network::*;
+use simperby_test_suite::*;
+use std::sync::Arc;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn recover_corrupted_state_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let block_hashes = (0..2)
+        .map(|i| Hash256::hash(format!("block{i}")))
+        .collect::<Vec<_>>();
+    let mut node = new_node(storage.clone()).await.unwrap();
+    for block_hash in block_hashes.iter() {
+        node.register_verified_block_hash(*block_hash)
+            .await
+            .unwrap();
+    }
+    node.veto_block(block_hashes[1]).await.unwrap();
+    drop(node);
+
+    // Simulate a crash in the middle of writing the state.
+    let raw_state = storage.read_file("state.json").await.unwrap();
+    storage
+        .clone()
+        .add_or_overwrite_file("state.json", raw_state[0..raw_state.len() / 2].to_owned())
+        .await
+        .unwrap();
+
+    let node = new_node(storage.clone()).await.unwrap();
+    let status = node.status().await.unwrap();
+    assert_eq!(status.verified_block_hashes.len(), 2);
+    assert_eq!(status.vetoed_block_hashes, vec![block_hashes[1]]);
+    // The primary file is restored.
+    assert_eq!(storage.read_file("state.json").await.unwrap(), raw_state);
+}
+
+/// The state written by the versions before the versioning, which is migrated on load.
+#[tokio::test]
+async fn baseline_state_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[1].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    storage
+        .clone()
+        .add_or_overwrite_file(
+            "state.json",
+            include_str!("fixtures/state_v0.bincode.txt").to_owned(),
+        )
+        .await
+        .unwrap();
+
+    let mut node = Consensus::new(
+        dms,
+        storage.clone(),
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[1].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let status = node.status().await.unwrap();
+    assert_eq!(status.this_node_index, Some(1));
+    assert_eq!(
+        status.verified_block_hashes,
+        vec![Hash256::hash("block0"), Hash256::hash("block1")]
+    );
+    assert_eq!(status.vetoed_block_hashes, vec![Hash256::hash("block1")]);
+
+    // It is written back in the current version once it changes.
+    node.veto_block(Hash256::hash("block0")).await.unwrap();
+    assert!(storage
+        .read_file("state.json")
+        .await
+        .unwrap()
+        .starts_with(&format!("bincode:{STATE_VERSION}:")));
+}
+
+#[tokio::test]
+async fn state_codec_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let block_hash = Hash256::hash("block");
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.set_state_codec(StateCodec::Cbor);
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    assert!(storage
+        .read_file("state.json")
+        .await
+        .unwrap()
+        .starts_with("cbor:"));
+    drop(node);
+
+    // It is read by a node with the default codec, which writes it back in its own.
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.veto_block(block_hash).await.unwrap();
+    assert!(storage
+        .read_file("state.json")
+        .await
+        .unwrap()
+        .starts_with("bincode:"));
+    let status = node.status().await.unwrap();
+    assert_eq!(status.verified_block_hashes, vec![block_hash]);
+    assert_eq!(status.vetoed_block_hashes, vec![block_hash]);
+}
+
+/// Nothing is written while nothing happens.
+#[tokio::test]
+async fn no_write_without_change_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let storage = MemoryStorage::new().await;
+    let mut node = Consensus::new(
+        create_dms(members, keys[1].1.clone()).await,
+        storage.clone(),
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[1].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    node.register_verified_block_hash(Hash256::hash("block"))
+        .await
+        .unwrap();
+    node.progress(0).await.unwrap();
+
+    let write_count = storage.write_count();
+    for timestamp in 1..5 {
+        node.update().await.unwrap();
+        assert!(node.progress(timestamp).await.unwrap().is_empty());
+        node.flush().await.unwrap();
+    }
+    assert_eq!(storage.write_count(), write_count);
+
+    // The timeout changes the state.
+    node.progress(6000).await.unwrap();
+    assert!(storage.write_count() > write_count);
+}
+
+#[tokio::test]
+async fn storage_failure_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let block_hash = Hash256::hash("block");
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.register_verified_block_hash(block_hash).await.unwrap();
+
+    // The disk is full while writing the state.
+    storage.fail_next_writes(1);
+    let error = node.veto_block(block_hash).await.unwrap_err();
+    assert!(matches!(error, ConsensusError::Storage(_)));
+    // Nothing has been changed.
+    let status = node.status().await.unwrap();
+    assert!(status.vetoed_block_hashes.is_empty());
+
+    // The retry succeeds and survives a restart.
+    node.veto_block(block_hash).await.unwrap();
+    drop(node);
+    let node = new_node(storage.clone()).await.unwrap();
+    let status = node.status().await.unwrap();
+    assert_eq!(status.vetoed_block_hashes, vec![block_hash]);
+}
+
+/// A crash in the middle of committing the state with the event log is recovered by the replay.
+#[tokio::test]
+async fn storage_batch_crash_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+
+    let block_hash = Hash256::hash("block");
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.set_event_log(true);
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.set_proposal_candidate(block_hash, 0).await.unwrap();
+
+    // It crashes after writing the state, but before the backup and the event log.
+    storage.crash_next_batch(1);
+    let error = node.progress(0).await.unwrap_err();
+    assert!(matches!(error, ConsensusError::Storage(_)));
+    drop(node);
+    assert!(storage.read_file("events.log").await.is_err());
+
+    // The restart completes the batch.
+    let storage = MemoryStorage::open(storage.directory()).await.unwrap();
+    assert_eq!(
+        storage.read_file("state.json").await.unwrap(),
+        storage.read_file("state.backup.json").await.unwrap()
+    );
+    let node = new_node(storage.clone()).await.unwrap();
+    let events = node.read_event_log(0).await.unwrap();
+    assert!(events
+        .iter()
+        .any(|entry| matches!(entry.result, ProgressResult::Proposed(..))));
+    assert_eq!(node.status().await.unwrap().step, ConsensusStep::Prevote);
+}
+
+/// Flips the last byte of the file in the storage.
+async fn flip_last_byte(storage: &MemoryStorage, name: &str) {
+    let mut raw = storage.read_file(name).await.unwrap();
+    let last = raw.pop().unwrap();
+    raw.push(if last == 'a' { 'b' } else { 'a' });
+    storage
+        .clone()
+        .add_or_overwrite_file(name, raw)
+        .await
+        .unwrap();
+}
+
+/// A flipped byte in each kind of the files is detected with the name of the file,
+/// and repaired where possible.
+#[tokio::test]
+async fn corrupt_files_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(1);
+    let dms = create_dms(vec![keys[0].0.clone()], keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let params = default_params();
+    let new_node = |storage| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            params.clone(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                ..Default::default()
+            },
+        )
+    };
+    let corrupt = |file: &str| ConsensusError::CorruptState {
+        file: file.to_owned(),
+        reason: "checksum mismatch".to_owned(),
+    };
+
+    let next_header = BlockHeader {
+        author: fi.header.validator_set[0].0.clone(),
+        previous_hash: fi.header.to_hash256(),
+        height: fi.header.height + 1,
+        ..fi.header.clone()
+    };
+    let block_hash = next_header.to_hash256();
+    let mut node = new_node(storage.clone()).await.unwrap();
+    node.set_event_log(true);
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.set_proposal_candidate(block_hash, 0).await.unwrap();
+    for _ in 0..10 {
+        if node.check_finalized().await.unwrap().is_some() {
+            break;
+        }
+        node.update().await.unwrap();
+        node.progress(0).await.unwrap();
+        node.flush().await.unwrap();
+    }
+    let proof = node.get_finalization_proof().await.unwrap().unwrap();
+    let status = node.status().await.unwrap();
+    assert!(!node.read_event_log(0).await.unwrap().is_empty());
+
+    flip_last_byte(&storage, "finalization.json").await;
+    let error = node.get_finalization_proof().await.unwrap_err();
+    assert_eq!(error, corrupt("finalization.json"));
+
+    flip_last_byte(&storage, "events.log").await;
+    let error = node.read_event_log(0).await.unwrap_err();
+    assert_eq!(error, corrupt("events.log"));
+
+    flip_last_byte(&storage, "peer_scores.json").await;
+    let error = new_node(storage.clone()).await.err().unwrap();
+    assert_eq!(error, corrupt("peer_scores.json"));
+
+    // The backup is read instead.
+    flip_last_byte(&storage, "state.json").await;
+    assert_eq!(node.status().await.unwrap(), status);
+
+    let mut repaired = node.repair().await.unwrap();
+    repaired.sort();
+    assert_eq!(
+        repaired,
+        vec![
+            "events.log",
+            "finalization.json",
+            "peer_scores.json",
+            "state.json"
+        ]
+    );
+    assert_eq!(
+        storage.read_file("state.json").await.unwrap(),
+        storage.read_file("state.backup.json").await.unwrap()
+    );
+    assert_eq!(node.get_finalization_proof().await.unwrap(), Some(proof));
+    // There is no archive to rebuild the event log from.
+    assert!(node.read_event_log(0).await.unwrap().is_empty());
+    assert!(node.repair().await.unwrap().is_empty());
+    drop(node);
+    let mut node = new_node(storage.clone()).await.unwrap();
+    assert!(node.status().await.unwrap().finalized);
+
+    // The record of the signed messages is never repaired.
+    flip_last_byte(&storage, "own_votes.json").await;
+    let error = node.repair().await.unwrap_err();
+    assert_eq!(error, corrupt("own_votes.json"));
+    let error = node
+        .finalize_and_advance(
+            next_header,
+            params.clone(),
+            0,
+            signer(Some(keys[0].1.clone())),
+        )
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error, corrupt("own_votes.json"));
+}
+
+/// The files of the storage are encrypted at rest with the key given to `Consensus::new()`.
+#[tokio::test]
+async fn encryption_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let new_node = |storage, encryption_key| {
+        Consensus::new(
+            Arc::clone(&dms),
+            storage,
+            fi.header.clone(),
+            default_params(),
+            0,
+            ConsensusConfig {
+                this_node_signer: signer(Some(keys[0].1.clone())),
+                encryption_key,
+                ..Default::default()
+            },
+        )
+    };
+    let block_hash = Hash256::hash("block");
+    let run = |mut node: Consensus<MemoryStorage>| async move {
+        node.set_event_log(true);
+        node.register_verified_block_hash(block_hash).await.unwrap();
+        node.set_proposal_candidate(block_hash, 0).await.unwrap();
+        node.progress(0).await.unwrap();
+        node.flush().await.unwrap();
+        node.status().await.unwrap()
+    };
+
+    // Round trip
+    let key = StorageEncryptionKey::generate();
+    let storage = MemoryStorage::new().await;
+    let node = new_node(storage.clone(), Some(key.clone())).await.unwrap();
+    let status = run(node).await;
+    let files = storage.list_files().await.unwrap();
+    for name in ["state.json", "events.log", "own_votes.json"] {
+        assert!(files.iter().any(|file| file == name), "{name} is missing");
+    }
+    for name in files.iter() {
+        let raw = storage.read_file(name).await.unwrap();
+        assert!(raw.starts_with("encrypted-1:"), "{name} is not encrypted");
+    }
+    let node = new_node(storage.clone(), Some(key.clone())).await.unwrap();
+    assert_eq!(node.status().await.unwrap().step, status.step);
+    assert!(!node.read_event_log(0).await.unwrap().is_empty());
+    drop(node);
+
+    // Wrong key, where the backup is tried after the primary.
+    let error = new_node(storage.clone(), Some(StorageEncryptionKey::generate()))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::WrongEncryptionKey {
+            file: "state.backup.json".to_owned()
+        }
+    );
+    // Missing key
+    let error = new_node(storage.clone(), None).await.err().unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::MissingEncryptionKey {
+            file: "state.backup.json".to_owned()
+        }
+    );
+
+    // Migration of a plaintext storage
+    let mut storage = MemoryStorage::new().await;
+    let node = new_node(storage.clone(), None).await.unwrap();
+    let status = run(node).await;
+    let error = new_node(storage.clone(), Some(key.clone()))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::UnencryptedStorage {
+            file: "state.backup.json".to_owned()
+        }
+    );
+    let mut encrypted = encrypt_storage(&mut storage, &key).await.unwrap();
+    encrypted.sort();
+    let mut files = storage.list_files().await.unwrap();
+    files.sort();
+    assert_eq!(encrypted, files);
+    assert!(encrypt_storage(&mut storage, &key)
+        .await
+        .unwrap()
+        .is_empty());
+    let node = new_node(storage.clone(), Some(key)).await.unwrap();
+    assert_eq!(node.status().await.unwrap().step, status.step);
+    assert!(!node.read_event_log(0).await.unwrap().is_empty());
+}
+
+/// The first validator moves to a new node in the middle of the height,
+/// which finalizes the block with the others while the old node is stopped.
+#[tokio::test]
+async fn backup_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let mut network = MockNetwork::new();
+    let mut keys = Vec::new();
+    let mut nodes = nodes
+        .into_iter()
+        .map(|(node, key)| {
+            network.add_node(node.get_dms());
+            keys.push(key.unwrap());
+            node
+        })
+        .collect::<Vec<_>>();
+    let members = keys.iter().map(|key| key.public_key()).collect::<Vec<_>>();
+    let block_hash = Hash256::hash("block");
+    for node in nodes.iter_mut() {
+        node.register_verified_block_hash(block_hash).await.unwrap();
+    }
+    nodes[0]
+        .set_proposal_candidate(block_hash, 0)
+        .await
+        .unwrap();
+    // PROPOSE
+    step(&mut nodes, &network, 0).await;
+
+    let bundle = nodes[0].export_backup().await.unwrap();
+    assert_eq!((bundle.height, bundle.round), (fi.header.height + 1, 0));
+    let storage = Consensus::import_backup(&bundle, MemoryStorage::new().await, None)
+        .await
+        .unwrap();
+    let new_node = Consensus::new(
+        create_dms(members, keys[0].clone()).await,
+        storage,
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(new_node.status().await.unwrap().round, 0);
+    network.add_node(new_node.get_dms());
+    network.partition(&[vec![0], vec![1, 2, 3, 4]]);
+    let old_node = std::mem::replace(&mut nodes[0], new_node);
+
+    // PREVOTE, PRECOMMIT and FINALIZE
+    for _ in 0..4 {
+        step(&mut nodes, &network, 0).await;
+    }
+    for node in nodes.iter() {
+        let finalization = node.check_finalized().await.unwrap().unwrap();
+        assert_eq!(finalization.block_hash, block_hash);
+        verify_finalization_proof(&block_hash, &finalization.proof, &fi.header.validator_set)
+            .unwrap();
+    }
+    assert!(old_node.check_finalized().await.unwrap().is_none());
+}
+
+/// The old node keeps running after the export, so the backup is refused
+/// by the storage that has its newer votes.
+#[tokio::test]
+async fn stale_backup_1() {
+    setup_test();
+    let (fi, keys) = test_utils::generate_fi(4);
+    let members = keys
+        .iter()
+        .map(|(public_key, _)| public_key.clone())
+        .collect::<Vec<_>>();
+    let dms = create_dms(members, keys[0].1.clone()).await;
+    let storage = MemoryStorage::new().await;
+    let mut node = Consensus::new(
+        Arc::clone(&dms),
+        storage.clone(),
+        fi.header.clone(),
+        default_params(),
+        0,
+        ConsensusConfig {
+            this_node_signer: signer(Some(keys[0].1.clone())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let bundle = node.export_backup().await.unwrap();
+
+    // The same backup can be imported again while nothing has been signed since.
+    let storage = Consensus::import_backup(&bundle, storage, None)
+        .await
+        .unwrap();
+    let block_hash = Hash256::hash("block");
+    node.register_verified_block_hash(block_hash).await.unwrap();
+    node.set_proposal_candidate(block_hash, 0).await.unwrap();
+    node.progress(0).await.unwrap();
+    node.flush().await.unwrap();
+    let error = Consensus::import_backup(&bundle, storage, None)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::StaleBackup {
+            height: fi.header.height + 1
+        }
+    );
+
+    // A damaged copy
+    let mut json = serde_json::to_value(&bundle).unwrap();
+    json["round"] = 1.into();
+    let damaged: BackupBundle = serde_json::from_value(json).unwrap();
+    let error = Consensus::import_backup(&damaged, MemoryStorage::new().await, None)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        error,
+        ConsensusError::InvalidBackup("checksum mismatch".to_owned())
+    );
+}
+
+/// Archives three heights with their events and messages, and prunes them down to one.
+#[tokio::test]
+async fn archive_1() {
+    setup_test();
+    let (nodes, fi) = NodesBuilder::new(4).build().await;
+    let (mut nodes, keys): (Vec<_>, Vec<_>) = nodes.into_iter().unzip();
+    for node in nodes.iter_mut() {
+        node.set_event_log(true);
+        node.set_archive_messages(true);
+    }
+    let params = default_params();
+    let mut header = fi.header.clone();
+    let mut round_zero_timestamp = 0;
+    let mut block_hashes = Vec::new();
+    for _ in 0..3 {
+        let next_header = BlockHeader {
+            author: header.validator_set[0].0.clone(),
+            previous_hash: header.to_hash256(),
+            height: header.height + 1,
+            timestamp: round_zero_timestamp,
+            ..header.clone()
+        };
+        let block_hash = next_header.to_hash256();
+        for node in nodes.iter_mut() {
+            node.register_verified_block_hash(block_hash).await.unwrap();
+        }
+        nodes[0]
+            .set_proposal_candidate(block_hash, round_zero_timestamp)
+            .await
+            .unwrap();
+        let mut simulation = Simulation::new(
+            nodes,
+            header.validator_set.clone(),
+            round_zero_timestamp,
+            StepOrder::RoundRobin,
+            seed_from_env(),
+        )
+        .await
+        .unwrap();
+        simulation.run_until_finalized(20).await.unwrap();
+        assert_eq!(simulation.finalized().len(), 4);
+
+        round_zero_timestamp = simulation.now();
+        nodes = Vec::new();
+        for (node, key) in simulation.into_nodes().into_iter().zip(keys.iter()) {
+            let node = node
+                .finalize_and_advance(
+                    next_header.clone(),
+                    params.clone(),
+                    round_zero_timestamp,
+                    signer(key.clone()),
+                )
+                .await
+                .unwrap();
+            nodes.push(node);
+        }
+        header = next_header;
+        block_hashes.push(block_hash);
+    }
+
+    let node = &mut nodes[1];
+    let heights = (1..=3).map(|i| fi.header.height + i).collect::<Vec<_>>();
+    for (height, block_hash) in heights.iter().zip(block_hashes.iter()) {
+        let archived = node.read_archived_height(*height).await.unwrap().unwrap();
+        assert_eq!(archived.status.height, *height);
+        assert!(archived.status.finalized);
+        assert_eq!(archived.finalization.block_hash, *block_hash);
+        assert!(!archived.events.is_empty());
+        assert!(archived.events.iter().all(|entry| entry.height == *height));
+        assert!(archived
+            .events
+            .iter()
+            .any(|entry| matches!(entry.result, ProgressResult::Finalized(_))));
+        assert!(!archived.messages.is_empty());
+        assert!(archived
+            .messages
+            .iter()
+            .all(|(message, committers)| message.height() == *height && !committers.is_empty()));
+    }
+
+    // Nothing is older than the default retention yet.
+    assert!(node.prune_archives().await.unwrap().is_empty());
+    node.set_archive_retention(1);
+    assert_eq!(node.prune_archives().await.unwrap(), heights[..2].to_vec());
+    for height in &heights[..2] {
+        assert!(node.read_archived_height(*height).await.unwrap().is_none());
+    }
+    assert!(node
+        .read_archived_height(heights[2])
+        .await
+        .unwrap()
+        .is_some());
+    // The event log itself is kept across the heights.
+    let event_log = node.read_event_log(0).await.unwrap();
+    assert!(event_log.iter().any(|entry| entry.height == heights[0]));
+}
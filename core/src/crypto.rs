@@ -1,4 +1,8 @@
 //! A set of types and functions related to cryptography, that are widely used in the entire Simperby project.
+//!
+//! The keys and the signatures are of secp256k1 (ECDSA, recoverable as in EVM) or of Ed25519,
+//! which is tagged in their encodings (see `SignatureScheme`), so a validator set may mix them.
+use ed25519_dalek::Signer;
 use secp256k1::{
     ecdsa::{RecoverableSignature, RecoveryId},
     Message, Secp256k1, SecretKey,
@@ -9,6 +13,23 @@ use std::fmt;
 use thiserror::Error;
 
 const EVM_EC_RECOVERY_OFFSET: u8 = 27;
+/// The tag of the Ed25519 keys and signatures, which is the first byte of a public key
+/// (`0x04` for secp256k1) and the last byte of a signature (the recovery id for secp256k1).
+const ED25519_TAG: u8 = 0xed;
+/// The prefix of the Ed25519 private keys in the human-readable encodings.
+const ED25519_PRIVATE_KEY_PREFIX: &str = "ed25519:";
+
+/// The signature scheme of a key or a signature.
+///
+/// The human-readable encodings of secp256k1 are the same as before the schemes were tagged.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub enum SignatureScheme {
+    #[default]
+    Secp256k1,
+    Ed25519,
+}
 
 #[derive(Error, Debug, Clone)]
 pub enum CryptoError {
@@ -244,6 +265,14 @@ impl Signature {
 
     /// Creates a new signature from the given data and keys.
     pub fn sign(data: Hash256, private_key: &PrivateKey) -> Result<Self, Error> {
+        if private_key.scheme == SignatureScheme::Ed25519 {
+            let signing_key = ed25519_dalek::SigningKey::from_bytes(&private_key.key.data);
+            let mut bytes = [ED25519_TAG; 65];
+            bytes[0..64].copy_from_slice(&signing_key.sign(data.as_ref()).to_bytes());
+            return Ok(Signature {
+                signature: HexSerializedBytes { data: bytes },
+            });
+        }
         let private_key = secp256k1::SecretKey::from_slice(&private_key.key.data)
             .map_err(|_| Error::InvalidFormat("private key: [omitted]".to_owned()))?;
         let message = Message::from_slice(data.as_ref()).unwrap();
@@ -263,8 +292,32 @@ impl Signature {
         })
     }
 
+    /// Returns the scheme of the signature.
+    pub fn scheme(&self) -> SignatureScheme {
+        if self.signature.data[64] == ED25519_TAG {
+            SignatureScheme::Ed25519
+        } else {
+            SignatureScheme::Secp256k1
+        }
+    }
+
     /// Verifies the signature against the given data and public key.
     pub fn verify(&self, data: Hash256, public_key: &PublicKey) -> Result<(), Error> {
+        if self.scheme() != public_key.scheme() {
+            return Err(Error::VerificationFailed);
+        }
+        if public_key.scheme() == SignatureScheme::Ed25519 {
+            let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(
+                &public_key.key.data[1..33].try_into().unwrap(),
+            )
+            .map_err(|_| Error::InvalidFormat(format!("public_key: {public_key}")))?;
+            let signature = ed25519_dalek::Signature::from_bytes(
+                &self.signature.data[0..64].try_into().unwrap(),
+            );
+            return verifying_key
+                .verify_strict(data.as_ref(), &signature)
+                .map_err(|_| Error::VerificationFailed);
+        }
         let signature = secp256k1::ecdsa::Signature::from_compact(&self.signature.data[0..64])
             .map_err(|_| Error::InvalidFormat(format!("signature: {self}")))?;
         let public_key = secp256k1::PublicKey::from_slice(&public_key.key.data)
@@ -275,8 +328,46 @@ impl Signature {
             .map_err(|_| Error::VerificationFailed)
     }
 
-    /// Recover a public key from the given signature.
+    /// Verifies the Ed25519 signatures against the given data and public keys all at once,
+    /// which is much faster than verifying them one by one.
+    ///
+    /// It fails as a whole without telling which one is invalid, so verify them with `verify()`
+    /// to find it. It also fails if any of them is not Ed25519, or has a weak key that
+    /// `verify()` rejects.
+    pub fn verify_batch(items: &[(Hash256, &Signature, &PublicKey)]) -> Result<(), Error> {
+        let mut messages = Vec::with_capacity(items.len());
+        let mut signatures = Vec::with_capacity(items.len());
+        let mut verifying_keys = Vec::with_capacity(items.len());
+        for (data, signature, public_key) in items {
+            if signature.scheme() != SignatureScheme::Ed25519
+                || public_key.scheme() != SignatureScheme::Ed25519
+            {
+                return Err(Error::VerificationFailed);
+            }
+            let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(
+                &public_key.key.data[1..33].try_into().unwrap(),
+            )
+            .map_err(|_| Error::InvalidFormat(format!("public_key: {public_key}")))?;
+            if verifying_key.is_weak() {
+                return Err(Error::VerificationFailed);
+            }
+            messages.push(data.as_ref());
+            signatures.push(ed25519_dalek::Signature::from_bytes(
+                &signature.signature.data[0..64].try_into().unwrap(),
+            ));
+            verifying_keys.push(verifying_key);
+        }
+        ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys)
+            .map_err(|_| Error::VerificationFailed)
+    }
+
+    /// Recover a public key from the given signature, which is only possible for secp256k1.
     pub fn recover(&self, data: Hash256) -> Result<PublicKey, Error> {
+        if self.scheme() == SignatureScheme::Ed25519 {
+            return Err(Error::InvalidFormat(
+                "an ed25519 signature can't be recovered".to_owned(),
+            ));
+        }
         let message = Message::from_slice(data.as_ref()).unwrap();
         let recovery_id = RecoveryId::from_i32(
             self.signature.data[64..65][0] as i32 - EVM_EC_RECOVERY_OFFSET as i32,
@@ -387,6 +478,15 @@ impl PublicKey {
         }
     }
 
+    /// Returns the scheme of the key.
+    pub fn scheme(&self) -> SignatureScheme {
+        if self.key.data[0] == ED25519_TAG {
+            SignatureScheme::Ed25519
+        } else {
+            SignatureScheme::Secp256k1
+        }
+    }
+
     pub fn from_array_uncompressed(array: [u8; 65]) -> Result<Self, Error> {
         let key = secp256k1::PublicKey::from_slice(array.as_ref())
             .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?
@@ -404,13 +504,71 @@ impl PublicKey {
             key: HexSerializedBytes { data: key },
         })
     }
+
+    /// Constructs an Ed25519 public key, which is encoded after its tag and padded with zeros.
+    pub fn from_array_ed25519(array: [u8; 32]) -> Result<Self, Error> {
+        ed25519_dalek::VerifyingKey::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?;
+        let mut key = [0; 65];
+        key[0] = ED25519_TAG;
+        key[1..33].copy_from_slice(&array);
+        Ok(PublicKey {
+            key: HexSerializedBytes { data: key },
+        })
+    }
 }
 
 /// A private key.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
-#[serde(transparent)]
+///
+/// An Ed25519 one is prefixed with `ed25519:` in the human-readable encodings;
+/// the binary encodings always carry the scheme next to the key.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct PrivateKey {
     pub key: HexSerializedBytes<32>,
+    scheme: SignatureScheme,
+}
+
+impl Serialize for PrivateKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        if !serializer.is_human_readable() {
+            return (self.scheme, &self.key).serialize(serializer);
+        }
+        match self.scheme {
+            SignatureScheme::Secp256k1 => self.key.serialize(serializer),
+            SignatureScheme::Ed25519 => {
+                serializer.serialize_str(&format!("{ED25519_PRIVATE_KEY_PREFIX}{}", self.key))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrivateKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        if !deserializer.is_human_readable() {
+            let (scheme, key) =
+                <(SignatureScheme, HexSerializedBytes<32>)>::deserialize(deserializer)?;
+            return Ok(PrivateKey { key, scheme });
+        }
+        let s: String = Deserialize::deserialize(deserializer)?;
+        let (s, scheme) = match s.strip_prefix(ED25519_PRIVATE_KEY_PREFIX) {
+            Some(s) => (s, SignatureScheme::Ed25519),
+            None => (s.as_str(), SignatureScheme::Secp256k1),
+        };
+        let bytes = hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))?;
+        let data = bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("invalid length"))?;
+        Ok(PrivateKey {
+            key: HexSerializedBytes { data },
+            scheme,
+        })
+    }
 }
 
 impl std::convert::AsRef<[u8]> for PrivateKey {
@@ -423,6 +581,7 @@ impl PrivateKey {
     pub fn zero() -> Self {
         Self {
             key: HexSerializedBytes::zero(),
+            scheme: SignatureScheme::Secp256k1,
         }
     }
 
@@ -432,10 +591,29 @@ impl PrivateKey {
             .secret_bytes();
         Ok(PrivateKey {
             key: HexSerializedBytes { data: key },
+            scheme: SignatureScheme::Secp256k1,
         })
     }
 
+    /// Constructs an Ed25519 private key from its seed.
+    pub fn from_array_ed25519(array: [u8; 32]) -> Result<Self, Error> {
+        Ok(PrivateKey {
+            key: HexSerializedBytes { data: array },
+            scheme: SignatureScheme::Ed25519,
+        })
+    }
+
+    /// Returns the scheme of the key.
+    pub fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
     pub fn public_key(&self) -> PublicKey {
+        if self.scheme == SignatureScheme::Ed25519 {
+            let signing_key = ed25519_dalek::SigningKey::from_bytes(&self.key.data);
+            return PublicKey::from_array_ed25519(signing_key.verifying_key().to_bytes())
+                .expect("invalid public key");
+        }
         let private_key = SecretKey::from_slice(&self.key.data).expect("invalid private key");
         let secp = Secp256k1::new();
         let public_key = private_key.public_key(&secp);
@@ -466,6 +644,13 @@ pub fn generate_keypair(seed: impl AsRef<[u8]>) -> (PublicKey, PrivateKey) {
     )
 }
 
+/// Generates a new Ed25519 keypair using the seed.
+pub fn generate_keypair_ed25519(seed: impl AsRef<[u8]>) -> (PublicKey, PrivateKey) {
+    let private_key =
+        PrivateKey::from_array_ed25519(Hash256::hash(seed).hash.data).expect("invalid private key");
+    (private_key.public_key(), private_key)
+}
+
 /// Generates a new keypair randomly
 pub fn generate_keypair_random() -> (PublicKey, PrivateKey) {
     use secp256k1::rand::SeedableRng;
@@ -585,6 +770,157 @@ mod tests {
         check_keypair_match(&public_key, &private_key).unwrap();
     }
 
+    /// A key from the existing infrastructure (the well-known example key of Ethereum),
+    /// which is imported as it is and identifies the same account.
+    #[test]
+    fn existing_secp256k1_key() {
+        let private_key = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        let private_key =
+            PrivateKey::from_array(hex::decode(private_key).unwrap().try_into().unwrap()).unwrap();
+        let public_key = private_key.public_key();
+        assert_eq!(public_key.scheme(), SignatureScheme::Secp256k1);
+        assert_eq!(
+            public_key.to_string(),
+            "044e3b81af9c2234cad09d679ce6035ed1392347ce64ce405f5dcd36228a25de6e\
+            47fd35c4215d1edf53e6f83de344615ce719bdb0fd878f6ed76f06dd277956de"
+        );
+        let compressed = "024e3b81af9c2234cad09d679ce6035ed1392347ce64ce405f5dcd36228a25de6e";
+        assert_eq!(
+            PublicKey::from_array(hex::decode(compressed).unwrap().try_into().unwrap()).unwrap(),
+            public_key
+        );
+        // The Ethereum address
+        assert_eq!(
+            hex::encode(&Hash256::hash(&public_key.as_ref()[1..]).as_ref()[12..]),
+            "2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+        );
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        signature
+            .verify(Hash256::hash("hello world"), &public_key)
+            .unwrap();
+        assert_eq!(
+            signature.recover(Hash256::hash("hello world")).unwrap(),
+            public_key
+        );
+    }
+
+    /// The key of the test 1 of RFC 8032, and a signature of it which is deterministic.
+    #[test]
+    fn ed25519_vector() {
+        let private_key = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+        let private_key =
+            PrivateKey::from_array_ed25519(hex::decode(private_key).unwrap().try_into().unwrap())
+                .unwrap();
+        let public_key = private_key.public_key();
+        assert_eq!(public_key.scheme(), SignatureScheme::Ed25519);
+        assert_eq!(
+            public_key.to_string(),
+            format!(
+                "ed{}{}",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "00".repeat(32)
+            )
+        );
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        assert_eq!(signature.scheme(), SignatureScheme::Ed25519);
+        assert_eq!(
+            signature.to_string(),
+            "c3fca9e288ae36457347403c1bb093078be5a0dfdf079d0791392ee6052c4641\
+            412325b430fcdf093d5e9e81fba1d4ad4f6c5e7523bce0097601da35fb30a509ed"
+        );
+        signature
+            .verify(Hash256::hash("hello world"), &public_key)
+            .unwrap();
+        signature
+            .verify(Hash256::hash("hello world2"), &public_key)
+            .unwrap_err();
+        signature.recover(Hash256::hash("hello world")).unwrap_err();
+        assert_eq!(
+            serde_spb::to_string(&private_key).unwrap(),
+            "\"ed25519:9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60\""
+        );
+    }
+
+    #[test]
+    fn ed25519_encode_decode() {
+        let (public_key, private_key) = generate_keypair_ed25519("hello world");
+        let encoded = serde_spb::to_string(&public_key).unwrap();
+        assert_eq!(
+            serde_spb::from_str::<PublicKey>(&encoded).unwrap(),
+            public_key
+        );
+        let encoded = serde_spb::to_string(&private_key).unwrap();
+        assert_eq!(
+            serde_spb::from_str::<PrivateKey>(&encoded).unwrap(),
+            private_key
+        );
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        let encoded = serde_spb::to_string(&signature).unwrap();
+        let decoded: Signature = serde_spb::from_str(&encoded).unwrap();
+        decoded
+            .verify(Hash256::hash("hello world"), &public_key)
+            .unwrap();
+        check_keypair_match(&public_key, &private_key).unwrap();
+    }
+
+    #[test]
+    fn private_key_bincode() {
+        let (_, private_key) = generate_keypair("hello world");
+        let (_, ed25519_private_key) = generate_keypair_ed25519("hello world");
+        for private_key in [private_key, ed25519_private_key] {
+            let encoded = bincode::serialize(&private_key).unwrap();
+            let decoded: PrivateKey = bincode::deserialize(&encoded).unwrap();
+            assert_eq!(decoded, private_key);
+            assert_eq!(decoded.scheme(), private_key.scheme());
+            assert_eq!(decoded.public_key(), private_key.public_key());
+        }
+    }
+
+    #[test]
+    fn ed25519_batch() {
+        let keys = (0..10)
+            .map(|i| generate_keypair_ed25519(format!("{i}")))
+            .collect::<Vec<_>>();
+        let data = (0..10)
+            .map(|i| Hash256::hash(format!("message {i}")))
+            .collect::<Vec<_>>();
+        let signatures = keys
+            .iter()
+            .zip(&data)
+            .map(|((_, private_key), data)| Signature::sign(*data, private_key).unwrap())
+            .collect::<Vec<_>>();
+        let mut items = data
+            .iter()
+            .zip(signatures.iter().zip(&keys))
+            .map(|(data, (signature, (public_key, _)))| (*data, signature, public_key))
+            .collect::<Vec<_>>();
+        Signature::verify_batch(&items).unwrap();
+        Signature::verify_batch(&[]).unwrap();
+
+        items[3].1 = &signatures[4];
+        Signature::verify_batch(&items).unwrap_err();
+        items[3].1 = &signatures[3];
+
+        // A secp256k1 one fails the batch even if it is valid.
+        let (public_key, private_key) = generate_keypair("secp256k1");
+        let signature = Signature::sign(data[0], &private_key).unwrap();
+        items.push((data[0], &signature, &public_key));
+        Signature::verify_batch(&items).unwrap_err();
+    }
+
+    /// A signature of one scheme never verifies against a key of the other.
+    #[test]
+    fn mixed_schemes() {
+        let (ed25519_public_key, ed25519_private_key) = generate_keypair_ed25519("hello world");
+        let (public_key, private_key) = generate_keypair("hello world");
+        let data = Hash256::hash("hello world");
+        let signature = Signature::sign(data, &ed25519_private_key).unwrap();
+        signature.verify(data, &public_key).unwrap_err();
+        let signature = Signature::sign(data, &private_key).unwrap();
+        signature.verify(data, &ed25519_public_key).unwrap_err();
+        check_keypair_match(&public_key, &ed25519_private_key).unwrap_err();
+    }
+
     #[test]
     fn recover_public_key() {
         let (public_key, private_key) = generate_keypair("hello world");
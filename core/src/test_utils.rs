@@ -1,7 +1,18 @@
 use crate::*;
 
 pub fn generate_fi(member_number: usize) -> (FinalizationInfo, Vec<(PublicKey, PrivateKey)>) {
-    let (rs, keys) = generate_standard_genesis(member_number);
+    generate_fi_with_keys(
+        (0..member_number)
+            .map(|i| generate_keypair(format!("{i}")))
+            .collect(),
+    )
+}
+
+/// Does `generate_fi()` with the given key pairs of the members, which may mix the schemes.
+pub fn generate_fi_with_keys(
+    keys: Vec<(PublicKey, PrivateKey)>,
+) -> (FinalizationInfo, Vec<(PublicKey, PrivateKey)>) {
+    let (rs, keys) = generate_genesis_with_keys(keys);
     let fi = FinalizationInfo {
         header: rs.genesis_info.header.clone(),
         commit_hash: CommitHash::zero(),
@@ -16,9 +27,18 @@ pub fn generate_fi(member_number: usize) -> (FinalizationInfo, Vec<(PublicKey, P
 pub fn generate_standard_genesis(
     member_number: usize,
 ) -> (ReservedState, Vec<(PublicKey, PrivateKey)>) {
-    let keys = (0..member_number)
-        .map(|i| generate_keypair(format!("{i}")))
-        .collect::<Vec<_>>();
+    generate_genesis_with_keys(
+        (0..member_number)
+            .map(|i| generate_keypair(format!("{i}")))
+            .collect(),
+    )
+}
+
+/// Does `generate_standard_genesis()` with the given key pairs of the members.
+pub fn generate_genesis_with_keys(
+    keys: Vec<(PublicKey, PrivateKey)>,
+) -> (ReservedState, Vec<(PublicKey, PrivateKey)>) {
+    let member_number = keys.len();
     let members = keys
         .iter()
         .enumerate()
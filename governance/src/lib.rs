@@ -34,26 +34,8 @@ impl DmsMessage for Vote {
     }
 
     /// Agenda hash cryptographically contains the information of height. It's safe to ignore `dms_key`.
-    fn commit(
-        &self,
-        _dms_key: &DmsKey,
-        private_key: &PrivateKey,
-    ) -> Result<MessageCommitmentProof, CryptoError>
-    where
-        Self: Sized,
-    {
-        Signature::sign(self.to_hash256(), private_key).map(|signature| MessageCommitmentProof {
-            committer: private_key.public_key(),
-            signature,
-        })
-    }
-
-    fn verify_commitment(
-        &self,
-        proof: &MessageCommitmentProof,
-        _dms_key: &DmsKey,
-    ) -> Result<(), CryptoError> {
-        proof.signature.verify(self.to_hash256(), &proof.committer)
+    fn commitment_target(&self, _dms_key: &DmsKey) -> Hash256 {
+        self.to_hash256()
     }
 }
 
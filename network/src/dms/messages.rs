@@ -16,17 +16,35 @@ pub trait DmsMessage:
     /// Checks if the message is valid.
     fn check(&self) -> Result<(), Error>;
 
-    /// Defines how to commit a message, by cryptographically signing it.
+    /// Encodes the message to be sent over the network (`serde_spb` by default).
+    fn encode_wire(&self) -> Vec<u8> {
+        serde_spb::to_vec(self).expect("failed to serialize a DMS message")
+    }
+
+    /// Decodes the message received from the network, which must accept what `encode_wire()` does.
+    fn decode_wire(data: &[u8]) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        serde_spb::from_slice(data).map_err(|e| eyre!("can't decode the message: {e}"))
+    }
+
+    /// Returns the hash that the committer of the message signs.
     ///
     /// In case that the message can't be guaranteed to be unique among other protocols,
     /// this method provides `dms_key` to be used as a unique identifier.
     ///
-    /// One potential use case other than the default implementation which ignores `dms_key`
+    /// One potential use case other than the default implementation which aggregates `dms_key`
     /// is when the messages of which the signature is presented to another protocol,
     /// so not aggregating `dms_key`, (which is a very specific implementation detail of of DMS)
     /// makes sense.
     /// Of course, the message must be guaranteed to be unique so that the signature can't be replayed
     /// on another height or another chain.
+    fn commitment_target(&self, dms_key: &DmsKey) -> Hash256 {
+        self.to_hash256().aggregate(&dms_key.to_hash256())
+    }
+
+    /// Commits a message, by cryptographically signing its `commitment_target()`.
     fn commit(
         &self,
         dms_key: &DmsKey,
@@ -35,8 +53,7 @@ pub trait DmsMessage:
     where
         Self: Sized,
     {
-        let hash = self.to_hash256();
-        Signature::sign(hash.aggregate(&dms_key.to_hash256()), private_key).map(|signature| {
+        Signature::sign(self.commitment_target(dms_key), private_key).map(|signature| {
             MessageCommitmentProof {
                 committer: private_key.public_key(),
                 signature,
@@ -44,16 +61,32 @@ pub trait DmsMessage:
         })
     }
 
-    /// It must match the `commit()` method if you implemented it.
+    /// Verifies the commitment of the message, which is a signature on its `commitment_target()`.
+    ///
+    /// The DMS may verify the commitments in batches over `commitment_target()` instead.
     fn verify_commitment(
         &self,
         proof: &MessageCommitmentProof,
         dms_key: &DmsKey,
     ) -> Result<(), CryptoError> {
-        proof.signature.verify(
-            self.to_hash256().aggregate(&dms_key.to_hash256()),
-            &proof.committer,
-        )
+        proof
+            .signature
+            .verify(self.commitment_target(dms_key), &proof.committer)
+    }
+}
+
+/// A filter that decides whether a message received from a peer can be admitted to the DMS.
+///
+/// `filter_raw()` is applied before the message is decoded,
+/// and `filter()` after the commitment of the message is verified.
+pub trait MessageFilter<M: DmsMessage>: Send + Sync + 'static {
+    /// Returns `Err` with the reason if the message must be rejected.
+    fn filter(&self, message: &M, commitment: &MessageCommitmentProof) -> Result<(), String>;
+
+    /// Returns `Err` with the reason if the encoded message must be rejected before decoding it,
+    /// e.g., for its size. It admits every message by default.
+    fn filter_raw(&self, _message: &[u8]) -> Result<(), String> {
+        Ok(())
     }
 }
 
@@ -73,7 +106,7 @@ pub struct MessageCommitmentProof {
 /// The physical packet that is sent over the network.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Packet {
-    /// The original message data encoded in `serde_spb`.
+    /// The original message data encoded by `DmsMessage::encode_wire()`.
     pub message: Vec<u8>,
     /// Commitment to the message with the proof.
     pub commitment: MessageCommitmentProof,
@@ -85,6 +118,13 @@ impl ToHash256 for Packet {
     }
 }
 
+/// An entry of the log of the commitments, in the order of storing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitmentLogEntry {
+    pub message_hash: Hash256,
+    pub commitment: MessageCommitmentProof,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageMetadata {
     pub message_hash: Hash256,
@@ -0,0 +1,259 @@
+use super::*;
+
+/// The delivery conditions of a `MockGossipNetwork`.
+struct Conditions {
+    latency: Duration,
+    drop_probability: f64,
+    /// The partition of each node, if partitioned.
+    partitions: Option<Vec<usize>>,
+    /// The packets that `gossip()` never delivers.
+    gossip_drop_filter: Option<PacketFilter>,
+}
+
+/// An in-process network that gossips the packets among the DMS instances,
+/// for the tests of the modules on top of the DMS (e.g., multi-node consensus tests).
+///
+/// Unlike the real one, the delivery is controllable:
+/// the latency, the probability to drop each packet and the partitions can be changed mid-test.
+pub struct MockGossipNetwork<S, M> {
+    nodes: Vec<Arc<RwLock<DistributedMessageSet<S, M>>>>,
+    conditions: parking_lot::RwLock<Conditions>,
+}
+
+impl<S: Storage, M: DmsMessage> Default for MockGossipNetwork<S, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Storage, M: DmsMessage> MockGossipNetwork<S, M> {
+    /// Creates an empty network that delivers every packet without any delay.
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            conditions: parking_lot::RwLock::new(Conditions {
+                latency: Duration::ZERO,
+                drop_probability: 0.0,
+                partitions: None,
+                gossip_drop_filter: None,
+            }),
+        }
+    }
+
+    /// Connects the DMS to the network, returning the index of the node.
+    pub fn add_node(&mut self, dms: Arc<RwLock<DistributedMessageSet<S, M>>>) -> usize {
+        self.nodes.push(dms);
+        self.nodes.len() - 1
+    }
+
+    /// Sets the delay of every delivery.
+    pub fn set_latency(&self, latency: Duration) {
+        self.conditions.write().latency = latency;
+    }
+
+    /// Sets the probability to drop each packet for each receiver, which is in `[0, 1]`.
+    pub fn set_drop_probability(&self, drop_probability: f64) {
+        assert!((0.0..=1.0).contains(&drop_probability));
+        self.conditions.write().drop_probability = drop_probability;
+    }
+
+    /// Makes `gossip()` drop every packet matching the filter (or none if `None`),
+    /// which can still be fetched, e.g., to lose a specific vote in the gossip.
+    pub fn set_gossip_drop_filter(&self, filter: Option<PacketFilter>) {
+        self.conditions.write().gossip_drop_filter = filter;
+    }
+
+    /// Splits the network so that only the nodes in the same group can communicate.
+    ///
+    /// The nodes that are not in any of the groups are isolated.
+    pub fn partition(&self, groups: &[Vec<usize>]) {
+        // The isolated nodes are in the partitions of their own.
+        let mut partitions = (0..self.nodes.len())
+            .map(|i| groups.len() + i)
+            .collect::<Vec<_>>();
+        for (group_index, group) in groups.iter().enumerate() {
+            for &node in group {
+                partitions[node] = group_index;
+            }
+        }
+        self.conditions.write().partitions = Some(partitions);
+    }
+
+    /// Heals the partitions so that every node can communicate again.
+    pub fn heal(&self) {
+        self.conditions.write().partitions = None;
+    }
+
+    fn index_of(&self, dms: &Arc<RwLock<DistributedMessageSet<S, M>>>) -> Result<usize, Error> {
+        self.nodes
+            .iter()
+            .position(|node| Arc::ptr_eq(node, dms))
+            .ok_or_else(|| eyre!("the DMS is not connected to the network"))
+    }
+
+    fn is_connected(&self, from: usize, to: usize) -> bool {
+        match &self.conditions.read().partitions {
+            Some(partitions) => partitions[from] == partitions[to],
+            None => true,
+        }
+    }
+
+    /// Sends the packets of every node to the connected ones, once.
+    ///
+    /// The nodes are visited in order, so the packets are relayed
+    /// within a partition even if some of them are not directly delivered.
+    pub async fn gossip(&self) -> Result<(), Error> {
+        let latency = self.conditions.read().latency;
+        tokio::time::sleep(latency).await;
+        for (from, sender) in self.nodes.iter().enumerate() {
+            let packets = sender.read().await.retrieve_packets().await?;
+            for (to, receiver) in self.nodes.iter().enumerate() {
+                if from == to || !self.is_connected(from, to) {
+                    continue;
+                }
+                let packets = {
+                    let conditions = self.conditions.read();
+                    packets
+                        .iter()
+                        .filter(|packet| {
+                            !conditions
+                                .gossip_drop_filter
+                                .as_ref()
+                                .is_some_and(|filter| {
+                                    filter.matches(&packet.message, &packet.commitment.committer)
+                                })
+                        })
+                        .filter(|_| rand::random::<f64>() >= conditions.drop_probability)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                };
+                receiver.write().await.receive_packets(packets).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps gossiping with the given interval. This function will block the current thread.
+    pub async fn run(&self, interval: Duration) -> Result<(), Error> {
+        loop {
+            self.gossip().await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Delivers the messages from the node of the DMS to every connected node,
+/// each of which acknowledges them if not dropped.
+///
+/// Unlike `gossip()`, the messages to a receiver are dropped all together,
+/// since the acknowledgement is for the whole delivery.
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageBroadcaster<S, M> for MockGossipNetwork<S, M> {
+    async fn broadcast_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        message_hashes: &[Hash256],
+    ) -> Result<Vec<PublicKey>, Error> {
+        let from = self.index_of(dms)?;
+        let latency = self.conditions.read().latency;
+        tokio::time::sleep(latency).await;
+        let packets = dms.read().await.retrieve_packets_of(message_hashes).await?;
+        let mut acknowledged = Vec::new();
+        for (to, receiver) in self.nodes.iter().enumerate() {
+            if from == to || !self.is_connected(from, to) {
+                continue;
+            }
+            let drop_probability = self.conditions.read().drop_probability;
+            if rand::random::<f64>() < drop_probability {
+                continue;
+            }
+            let mut receiver = receiver.write().await;
+            receiver.receive_packets(packets.clone()).await?;
+            acknowledged.push(receiver.public_key());
+        }
+        Ok(acknowledged)
+    }
+}
+
+/// Fetches the packets of the nodes of the given public keys that are connected,
+/// dropping each packet as `gossip()` does.
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageFetcher<S, M> for MockGossipNetwork<S, M> {
+    async fn peers(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+    ) -> Result<Vec<PublicKey>, Error> {
+        let this = self.index_of(dms)?;
+        let mut peers = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let public_key = node.read().await.public_key();
+            if index != this && !peers.contains(&public_key) {
+                peers.push(public_key);
+            }
+        }
+        Ok(peers)
+    }
+
+    async fn fetch_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.fetch_packets(dms, peers, None).await
+    }
+
+    async fn fetch_matching_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: &PacketFilter,
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.fetch_packets(dms, peers, Some(filter)).await
+    }
+}
+
+impl<S: Storage, M: DmsMessage> MockGossipNetwork<S, M> {
+    /// Fetches the packets of the peers, only the ones matching the filter if given.
+    async fn fetch_packets(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: Option<&PacketFilter>,
+    ) -> Result<Vec<FetchReport>, Error> {
+        let this = self.index_of(dms)?;
+        let latency = self.conditions.read().latency;
+        tokio::time::sleep(latency).await;
+        let mut reports = Vec::new();
+        for peer in peers {
+            let mut report = FetchReport {
+                peer: peer.clone(),
+                admitted: 0,
+                rejections: Vec::new(),
+            };
+            for (index, node) in self.nodes.iter().enumerate() {
+                if index == this || !self.is_connected(index, this) {
+                    continue;
+                }
+                let node = node.read().await;
+                if node.public_key() != *peer {
+                    continue;
+                }
+                let packets = match filter {
+                    Some(filter) => node.retrieve_packets_matching(filter).await?,
+                    None => node.retrieve_packets().await?,
+                };
+                drop(node);
+                let drop_probability = self.conditions.read().drop_probability;
+                let packets = packets
+                    .into_iter()
+                    .filter(|_| rand::random::<f64>() >= drop_probability)
+                    .collect::<Vec<_>>();
+                let (admitted, rejections) = dms.write().await.receive_packets(packets).await?;
+                report.admitted += admitted;
+                report.rejections.extend(rejections);
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+}
@@ -1,4 +1,6 @@
 mod messages;
+#[cfg(feature = "test-util")]
+mod mock;
 mod rpc;
 pub mod server;
 #[cfg(test)]
@@ -20,10 +22,16 @@ use std::time::Duration;
 use tokio::sync::RwLock;
 
 const STATE_FILE_PATH: &str = "state.json";
+const COMMITMENT_LOG_FILE_PREFIX: &str = "log-";
+
+/// The number of the received messages whose commitments are verified together by default.
+pub const DEFAULT_VERIFICATION_BATCH_SIZE: usize = 256;
 
 pub type Error = eyre::Error;
 
-pub use messages::{DmsKey, DmsMessage, Message, MessageCommitmentProof};
+pub use messages::{DmsKey, DmsMessage, Message, MessageCommitmentProof, MessageFilter};
+#[cfg(feature = "test-util")]
+pub use mock::MockGossipNetwork;
 pub use rpc::PeerStatus;
 pub use server::*;
 
@@ -39,6 +47,101 @@ impl IntegrityError {
     }
 }
 
+/// An error for a message from a peer that is not admitted to the DMS.
+///
+/// Unlike other errors, it doesn't stop receiving the remaining messages.
+#[derive(thiserror::Error, Debug)]
+#[error("message rejected: {msg}")]
+pub struct RejectionError {
+    pub msg: String,
+}
+
+impl RejectionError {
+    pub fn new(msg: String) -> Self {
+        Self { msg }
+    }
+}
+
+/// A way to send the messages of a DMS to the peers directly,
+/// reporting which of them have received the messages.
+#[async_trait]
+pub trait MessageBroadcaster<S: Storage, M: DmsMessage>: Send + Sync {
+    /// Sends the messages of the given hashes in the DMS to every peer,
+    /// returning the public keys of the peers that have acknowledged them.
+    ///
+    /// An unreachable peer is not an error, but just left out of the result.
+    async fn broadcast_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        message_hashes: &[Hash256],
+    ) -> Result<Vec<PublicKey>, Error>;
+}
+
+/// What a DMS has received from a peer by `MessageFetcher::fetch_messages()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchReport {
+    pub peer: PublicKey,
+    /// The number of the admitted packets, including the ones already in the DMS.
+    pub admitted: usize,
+    /// The reasons of the rejected packets.
+    pub rejections: Vec<String>,
+}
+
+/// Selects the packets to fetch by `MessageFetcher::fetch_matching_messages()`,
+/// so that the peers send only the missing ones rather than all of them.
+///
+/// A packet must meet both of the conditions, each of which is ignored if empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketFilter {
+    /// The prefixes of the messages encoded by `DmsMessage::encode_wire()`,
+    /// one of which the message must start with.
+    pub message_prefixes: Vec<Vec<u8>>,
+    /// The keys, one of which the message must be committed by.
+    pub committers: Vec<PublicKey>,
+}
+
+impl PacketFilter {
+    /// Returns whether the encoded message committed by the key meets the filter.
+    pub fn matches(&self, message: &[u8], committer: &PublicKey) -> bool {
+        (self.message_prefixes.is_empty()
+            || self
+                .message_prefixes
+                .iter()
+                .any(|prefix| message.starts_with(prefix)))
+            && (self.committers.is_empty() || self.committers.contains(committer))
+    }
+}
+
+/// A way to fetch the messages of the peers into a DMS,
+/// reporting what has been received from each of them.
+#[async_trait]
+pub trait MessageFetcher<S: Storage, M: DmsMessage>: Send + Sync {
+    /// Returns the public keys of the peers of the DMS.
+    async fn peers(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+    ) -> Result<Vec<PublicKey>, Error>;
+
+    /// Fetches the messages from the given peers into the DMS,
+    /// receiving them in the order of the peers.
+    ///
+    /// An unreachable peer is not an error, but just left out of the result.
+    async fn fetch_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+    ) -> Result<Vec<FetchReport>, Error>;
+
+    /// Fetches only the messages matching the filter from the given peers
+    /// as `fetch_messages()` does, e.g., to recover the few votes lost in the gossip.
+    async fn fetch_matching_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: &PacketFilter,
+    ) -> Result<Vec<FetchReport>, Error>;
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Config {
     pub dms_key: String,
@@ -51,6 +154,10 @@ pub struct DistributedMessageSet<S, M> {
     storage: Arc<RwLock<S>>,
     config: Config,
     private_key: PrivateKey,
+    filter: Option<Arc<dyn MessageFilter<M>>>,
+    verification_batch_size: usize,
+    /// The sequence number for the next entry of the commitment log.
+    next_sequence: u64,
     _marker: std::marker::PhantomData<M>,
 }
 
@@ -117,20 +224,42 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
             }
         }
 
+        let next_sequence = storage
+            .list_files()
+            .await?
+            .iter()
+            .filter_map(|x| parse_commitment_log_file_name(x))
+            .max()
+            .map_or(0, |x| x + 1);
         Ok(Self {
             storage: Arc::new(RwLock::new(storage)),
             config,
             private_key,
+            filter: None,
+            verification_batch_size: DEFAULT_VERIFICATION_BATCH_SIZE,
+            next_sequence,
             _marker: std::marker::PhantomData,
         })
     }
 
+    /// Sets the filter which is applied to every message received from the peers.
+    pub fn set_filter(&mut self, filter: Arc<dyn MessageFilter<M>>) {
+        self.filter = Some(filter);
+    }
+
+    /// Sets the number of the received messages whose commitments are verified together
+    /// (`DEFAULT_VERIFICATION_BATCH_SIZE` by default); `1` verifies them one by one.
+    pub fn set_verification_batch_size(&mut self, verification_batch_size: usize) {
+        self.verification_batch_size = verification_batch_size;
+    }
+
     /// Returns the underlying storage.
     ///
     /// This is useful for when you want to store some additional data
     /// under the same file lock that this DMS uses.
     ///
-    /// Note that you MUST NOT create or access files that start with `message-`.
+    /// Note that you MUST NOT create or access files
+    /// that start with `message-`, `metadata-` or `log-`.
     pub fn get_storage(&self) -> Arc<RwLock<S>> {
         Arc::clone(&self.storage)
     }
@@ -139,8 +268,14 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         self.config.clone()
     }
 
+    /// Returns the public key that this instance signs the messages with.
+    pub fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
     pub async fn clear(&mut self) -> Result<(), Error> {
         self.storage.write().await.remove_all_files().await?;
+        self.next_sequence = 0;
         self.storage
             .write()
             .await
@@ -162,6 +297,82 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         Ok(messages)
     }
 
+    /// Reads the commitments of the stored messages by the message hashes,
+    /// without decoding the messages themselves.
+    ///
+    /// This is for the user who keeps the decoded messages by itself;
+    /// the unknown ones can be read by `query_message()`.
+    pub async fn read_commitments(
+        &self,
+    ) -> Result<Vec<(Hash256, Vec<MessageCommitmentProof>)>, Error> {
+        let files = self.storage.read().await.list_files().await?;
+        let tasks = files
+            .iter()
+            .filter(|x| x.starts_with("metadata-"))
+            .map(|f| async move { self.storage.read().await.read_file(f).await });
+        let mut result = Vec::new();
+        for metadata in future::join_all(tasks).await {
+            let metadata = serde_spb::from_str::<MessageMetadata>(&metadata?)
+                .map_err(|e| IntegrityError::new(format!("can't decode stored data: {e}")))?;
+            result.push((metadata.message_hash, metadata.committers));
+        }
+        Ok(result)
+    }
+
+    /// Reads the commitments stored since the given cursor, in the order of storing,
+    /// without decoding the messages themselves.
+    ///
+    /// Returns the commitments with their message hashes, and the cursor for the next call.
+    /// Pass `0` to read all of them.
+    pub async fn read_commitments_since(
+        &self,
+        cursor: u64,
+    ) -> Result<(Vec<(Hash256, MessageCommitmentProof)>, u64), Error> {
+        let (result, cursor, _) = self
+            .read_commitments_since_limited(cursor, usize::MAX)
+            .await?;
+        Ok((result, cursor))
+    }
+
+    /// Reads at most `limit` of the commitments stored since the given cursor,
+    /// like `read_commitments_since()`.
+    ///
+    /// Returns the commitments with their message hashes, the cursor for the next call,
+    /// and the number of the commitments left after them.
+    pub async fn read_commitments_since_limited(
+        &self,
+        cursor: u64,
+        limit: usize,
+    ) -> Result<(Vec<(Hash256, MessageCommitmentProof)>, u64, usize), Error> {
+        let mut sequences = self
+            .storage
+            .read()
+            .await
+            .list_files()
+            .await?
+            .iter()
+            .filter_map(|x| parse_commitment_log_file_name(x))
+            .filter(|x| *x >= cursor)
+            .collect::<Vec<_>>();
+        sequences.sort();
+        let left = sequences.len().saturating_sub(limit);
+        sequences.truncate(limit);
+        let mut result = Vec::new();
+        for sequence in &sequences {
+            let data = self
+                .storage
+                .read()
+                .await
+                .read_file(&commitment_log_file_name(*sequence))
+                .await?;
+            let entry = serde_spb::from_str::<CommitmentLogEntry>(&data)
+                .map_err(|e| IntegrityError::new(format!("can't decode stored data: {e}")))?;
+            result.push((entry.message_hash, entry.commitment));
+        }
+        let cursor = sequences.last().map_or(cursor, |x| x + 1);
+        Ok((result, cursor, left))
+    }
+
     pub async fn query_message(&self, message_hash: Hash256) -> Result<Option<Message<M>>, Error> {
         Ok(self
             .read_raw_message(message_hash)
@@ -180,6 +391,16 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         Ok(())
     }
 
+    /// Signs the given message and adds it to the storage with the same checks
+    /// as the one received from the peers, so that the filter sees it as well.
+    ///
+    /// Fails with `RejectionError` if the message is rejected.
+    pub async fn commit_message_filtered(&mut self, message: &M) -> Result<(), Error> {
+        message.check()?;
+        let commitment = message.commit(&self.config.dms_key, &self.private_key)?;
+        self.receive_message(message, commitment).await
+    }
+
     /// Removes the message from the storage.
     /// If `permanent` is `Some` with the reason, it permanently rejects the message.
     pub async fn remove_message(
@@ -274,16 +495,101 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         self.config.members.contains(member)
     }
 
-    async fn receive_packet(&mut self, packet: Packet) -> Result<(), Error> {
-        let message = serde_spb::from_slice::<M>(&packet.message)?;
-        message.verify_commitment(&packet.commitment, &self.config.dms_key)?;
-        if !self.test_membership(&packet.commitment.committer) {
-            return Err(eyre!("commitment committer is not a member"));
+    /// Applies the raw filter to the packet and decodes its message.
+    fn decode_packet(&self, packet: &Packet) -> Result<M, RejectionError> {
+        if let Some(filter) = &self.filter {
+            filter
+                .filter_raw(&packet.message)
+                .map_err(RejectionError::new)?;
+        }
+        M::decode_wire(&packet.message).map_err(|e| RejectionError::new(e.to_string()))
+    }
+
+    /// Adds the message committed by another member to the storage, with the same checks
+    /// as the one received from the peers, e.g., to admit a message that the filter held back.
+    ///
+    /// Fails with `RejectionError` if the message is rejected.
+    pub async fn receive_message(
+        &mut self,
+        message: &M,
+        commitment: MessageCommitmentProof,
+    ) -> Result<(), Error> {
+        message
+            .verify_commitment(&commitment, &self.config.dms_key)
+            .map_err(invalid_commitment)?;
+        self.receive_verified_message(message, commitment).await
+    }
+
+    /// Does `receive_message()` for the message whose commitment has been verified.
+    async fn receive_verified_message(
+        &mut self,
+        message: &M,
+        commitment: MessageCommitmentProof,
+    ) -> Result<(), Error> {
+        if !self.test_membership(&commitment.committer) {
+            return Err(
+                RejectionError::new("commitment committer is not a member".to_owned()).into(),
+            );
+        }
+        if let Some(filter) = &self.filter {
+            filter
+                .filter(message, &commitment)
+                .map_err(RejectionError::new)?;
         }
-        self.store_message(&message, packet.commitment).await?;
+        self.store_message(message, commitment).await?;
         Ok(())
     }
 
+    /// Receives the given packets, skipping the rejected ones.
+    ///
+    /// The commitments of the decoded messages are verified in batches
+    /// (see `set_verification_batch_size()`) before any of them is admitted.
+    ///
+    /// Returns the number of the admitted packets and the reasons of the rejected ones.
+    async fn receive_packets(
+        &mut self,
+        packets: Vec<Packet>,
+    ) -> Result<(usize, Vec<String>), Error> {
+        let mut admitted = 0;
+        let mut rejections = Vec::new();
+        let mut messages = Vec::with_capacity(packets.len());
+        for packet in packets {
+            match self.decode_packet(&packet) {
+                Ok(message) => messages.push((message, packet.commitment)),
+                Err(e) => {
+                    log::warn!("{}", e);
+                    rejections.push(e.msg);
+                }
+            }
+        }
+        // The verification is CPU-bound, so it must not block the runtime.
+        let dms_key = self.config.dms_key.clone();
+        let batch_size = self.verification_batch_size;
+        let (messages, verifications) = tokio::task::spawn_blocking(move || {
+            let verifications = verify_commitments(&messages, &dms_key, batch_size);
+            (messages, verifications)
+        })
+        .await
+        .map_err(|e| eyre!("the verification task failed: {e}"))?;
+        for ((message, commitment), verification) in messages.into_iter().zip(verifications) {
+            let result = match verification {
+                Ok(()) => self.receive_verified_message(&message, commitment).await,
+                Err(e) => Err(invalid_commitment(e).into()),
+            };
+            match result {
+                Ok(()) => admitted += 1,
+                Err(e) => match e.downcast::<RejectionError>() {
+                    Ok(e) => {
+                        log::warn!("{}", e);
+                        rejections.push(e.msg);
+                    }
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+        Ok((admitted, rejections))
+    }
+
     async fn store_message(
         &mut self,
         message: &M,
@@ -294,7 +600,7 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
             if metadata.committers.contains(&commitment) {
                 return Ok(());
             } else {
-                metadata.committers.push(commitment);
+                metadata.committers.push(commitment.clone());
                 self.storage
                     .write()
                     .await
@@ -311,7 +617,7 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
                     &format!("metadata-{message_hash}.json"),
                     serde_spb::to_string(&MessageMetadata {
                         message_hash,
-                        committers: vec![commitment],
+                        committers: vec![commitment.clone()],
                     })
                     .unwrap(),
                 )
@@ -323,6 +629,20 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
                 )
                 .await?;
         };
+        // The log is written last so that its readers can always find the message.
+        self.storage
+            .write()
+            .await
+            .add_or_overwrite_file(
+                &commitment_log_file_name(self.next_sequence),
+                serde_spb::to_string(&CommitmentLogEntry {
+                    message_hash,
+                    commitment,
+                })
+                .unwrap(),
+            )
+            .await?;
+        self.next_sequence += 1;
         Ok(())
     }
 
@@ -333,10 +653,116 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
             for commitment in metadata.committers {
                 result.push(Packet {
                     commitment,
-                    message: serde_spb::to_vec(&message).unwrap(),
+                    message: message.encode_wire(),
                 });
             }
         }
         Ok(result)
     }
+
+    /// Retrieves the packets matching the filter.
+    async fn retrieve_packets_matching(&self, filter: &PacketFilter) -> Result<Vec<Packet>, Error> {
+        let mut packets = self.retrieve_packets().await?;
+        packets.retain(|packet| filter.matches(&packet.message, &packet.commitment.committer));
+        Ok(packets)
+    }
+
+    /// Retrieves the packets of the given messages, skipping the ones not in the storage.
+    async fn retrieve_packets_of(&self, message_hashes: &[Hash256]) -> Result<Vec<Packet>, Error> {
+        let mut result = Vec::new();
+        for message_hash in message_hashes {
+            if let Some((message, metadata)) = self.read_raw_message(*message_hash).await? {
+                for commitment in metadata.committers {
+                    result.push(Packet {
+                        commitment,
+                        message: message.encode_wire(),
+                    });
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn commitment_log_file_name(sequence: u64) -> String {
+    format!("{COMMITMENT_LOG_FILE_PREFIX}{sequence:020}.json")
+}
+
+fn parse_commitment_log_file_name(name: &str) -> Option<u64> {
+    name.strip_prefix(COMMITMENT_LOG_FILE_PREFIX)?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+fn invalid_commitment(e: CryptoError) -> RejectionError {
+    RejectionError::new(format!("invalid commitment: {e}"))
+}
+
+/// Verifies the commitments of the messages in batches of `batch_size`,
+/// returning the result of each in the same order.
+///
+/// The Ed25519 commitments of a batch are verified at once with `Signature::verify_batch()`,
+/// falling back to one by one to find the invalid ones only if it fails.
+/// ECDSA has no batch verification of its own, so the rest of a batch is split across
+/// the available cores instead.
+pub(crate) fn verify_commitments<M: DmsMessage>(
+    messages: &[(M, MessageCommitmentProof)],
+    dms_key: &DmsKey,
+    batch_size: usize,
+) -> Vec<Result<(), CryptoError>> {
+    let verify = |(message, commitment): &(M, MessageCommitmentProof)| {
+        message.verify_commitment(commitment, dms_key)
+    };
+    if batch_size <= 1 {
+        return messages.iter().map(verify).collect();
+    }
+    let (ed25519, others): (Vec<_>, Vec<_>) =
+        messages
+            .iter()
+            .enumerate()
+            .partition(|(_, (_, commitment))| {
+                commitment.committer.scheme() == SignatureScheme::Ed25519
+            });
+    let mut results = vec![Ok(()); messages.len()];
+    for batch in ed25519.chunks(batch_size) {
+        let items = batch
+            .iter()
+            .map(|(_, (message, commitment))| {
+                (
+                    message.commitment_target(dms_key),
+                    &commitment.signature,
+                    &commitment.committer,
+                )
+            })
+            .collect::<Vec<_>>();
+        if Signature::verify_batch(&items).is_err() {
+            for (index, message) in batch {
+                results[*index] = verify(message);
+            }
+        }
+    }
+    let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+    for batch in others.chunks(batch_size) {
+        let chunk_size = (batch.len() + threads - 1) / threads;
+        std::thread::scope(|scope| {
+            let handles = batch
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(index, message)| (*index, verify(message)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                for (index, result) in handle.join().expect("a verification thread panicked") {
+                    results[index] = result;
+                }
+            }
+        });
+    }
+    results
 }
@@ -25,6 +25,9 @@ pub(super) trait DistributedMessageSetRpcInterface: Send + Sync + 'static {
     /// Requests to response some packets.
     async fn request_packets(&self) -> Result<Vec<Packet>, String>;
 
+    /// Requests to response the packets matching the filter.
+    async fn request_packets_matching(&self, filter: PacketFilter) -> Result<Vec<Packet>, String>;
+
     /// Sends packets to the peer.
     async fn send_packets(&self, packets: Vec<Packet>) -> Result<(), String>;
 
@@ -57,6 +60,22 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSetRpcInterface for DmsWrapper
         Ok(packets)
     }
 
+    async fn request_packets_matching(&self, filter: PacketFilter) -> Result<Vec<Packet>, String> {
+        let dms = Arc::clone(
+            self.dms
+                .read()
+                .as_ref()
+                .ok_or_else(|| "server terminated".to_owned())?,
+        );
+        let packets = dms
+            .read()
+            .await
+            .retrieve_packets_matching(&filter)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(packets)
+    }
+
     async fn send_packets(&self, packets: Vec<Packet>) -> Result<(), String> {
         let dms = Arc::clone(
             self.dms
@@ -64,13 +83,11 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSetRpcInterface for DmsWrapper
                 .as_ref()
                 .ok_or_else(|| "server terminated".to_owned())?,
         );
-        for packet in packets {
-            dms.write()
-                .await
-                .receive_packet(packet)
-                .await
-                .map_err(|e| e.to_string())?;
-        }
+        dms.write()
+            .await
+            .receive_packets(packets)
+            .await
+            .map_err(|e| e.to_string())?;
         Ok(())
     }
 
@@ -97,42 +114,28 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         this: Arc<RwLock<Self>>,
         network_config: &ClientNetworkConfig,
     ) -> Result<(), Error> {
-        let mut tasks = Vec::new();
-        for peer in &network_config.peers {
-            let this_ = Arc::clone(&this);
-            let task = async move {
-                let this_read = this_.read().await;
-                let port_key = keys::port_key_dms::<M>();
-                let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
-                    format!(
-                        "{}:{}/dms",
-                        peer.address.ip(),
-                        peer.ports
-                            .get(&port_key)
-                            .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
-                    ),
-                    reqwest::Client::new(),
-                )));
-                let packets = stub
-                    .request_packets()
-                    .await
-                    .map_err(|e| eyre!("{}", e))?
-                    .map_err(|e| eyre!(e))?;
-                // Important: drop the lock before `write()`
-                drop(this_read);
-                for packet in packets {
-                    this_.write().await.receive_packet(packet).await?;
-                }
-                Result::<(), Error>::Ok(())
-            };
-            tasks.push(task);
-        }
-        let results = future::join_all(tasks).await;
-        for (result, peer) in results.into_iter().zip(network_config.peers.iter()) {
-            if let Err(e) = result {
-                log::warn!("failed to fetch from client {:?}: {}", peer, e);
-            }
-        }
+        let peers = network_config
+            .peers
+            .iter()
+            .map(|peer| peer.public_key.clone())
+            .collect::<Vec<_>>();
+        MessageFetcher::<S, M>::fetch_messages(network_config, &this, &peers).await?;
+        Ok(())
+    }
+
+    /// Fetches only the messages matching the filter from the peers, as `fetch()` does.
+    pub async fn fetch_matching(
+        this: Arc<RwLock<Self>>,
+        network_config: &ClientNetworkConfig,
+        filter: &PacketFilter,
+    ) -> Result<(), Error> {
+        let peers = network_config
+            .peers
+            .iter()
+            .map(|peer| peer.public_key.clone())
+            .collect::<Vec<_>>();
+        MessageFetcher::<S, M>::fetch_matching_messages(network_config, &this, &peers, filter)
+            .await?;
         Ok(())
     }
 
@@ -145,44 +148,8 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         this: Arc<RwLock<Self>>,
         network_config: &ClientNetworkConfig,
     ) -> Result<(), Error> {
-        let mut tasks_and_messages = Vec::new();
-
         let packets = this.read().await.retrieve_packets().await?;
-        if packets.is_empty() {
-            return Ok(());
-        }
-        for peer in &network_config.peers {
-            let port_key = keys::port_key_dms::<M>();
-            let packets_ = packets.clone();
-            let task = async move {
-                let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
-                    format!(
-                        "{}:{}/dms",
-                        peer.address.ip(),
-                        peer.ports
-                            .get(&port_key)
-                            .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
-                    ),
-                    reqwest::Client::new(),
-                )));
-                stub.send_packets(packets_.clone())
-                    .await
-                    .map_err(|e| eyre!(e))?
-                    .map_err(|e| eyre!(e))?;
-                Result::<(), Error>::Ok(())
-            };
-            tasks_and_messages.push((task, format!("RPC message add to {}", peer.public_key)));
-        }
-        let (tasks, messages) = tasks_and_messages
-            .into_iter()
-            .unzip::<_, _, Vec<_>, Vec<_>>();
-
-        let results = future::join_all(tasks).await;
-        for (result, msg) in results.into_iter().zip(messages.iter()) {
-            if let Err(e) = result {
-                log::warn!("failure in {}: {}", msg, e);
-            }
-        }
+        send_packets_to_peers::<M>(packets, network_config).await;
         Ok(())
     }
 
@@ -256,3 +223,203 @@ impl<S: Storage, M: DmsMessage> DistributedMessageSet<S, M> {
         Ok(final_results)
     }
 }
+
+/// Sends the packets to every peer, returning the public keys of the ones that have received.
+async fn send_packets_to_peers<M: DmsMessage>(
+    packets: Vec<Packet>,
+    network_config: &ClientNetworkConfig,
+) -> Vec<PublicKey> {
+    if packets.is_empty() {
+        return Vec::new();
+    }
+    let mut tasks_and_messages = Vec::new();
+    for peer in &network_config.peers {
+        let port_key = keys::port_key_dms::<M>();
+        let packets_ = packets.clone();
+        let task = async move {
+            let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
+                format!(
+                    "{}:{}/dms",
+                    peer.address.ip(),
+                    peer.ports
+                        .get(&port_key)
+                        .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
+                ),
+                reqwest::Client::new(),
+            )));
+            stub.send_packets(packets_.clone())
+                .await
+                .map_err(|e| eyre!(e))?
+                .map_err(|e| eyre!(e))?;
+            Result::<(), Error>::Ok(())
+        };
+        tasks_and_messages.push((task, format!("RPC message add to {}", peer.public_key)));
+    }
+    let (tasks, messages) = tasks_and_messages
+        .into_iter()
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+
+    let results = future::join_all(tasks).await;
+    let mut acknowledged = Vec::new();
+    for ((result, msg), peer) in results
+        .into_iter()
+        .zip(messages.iter())
+        .zip(network_config.peers.iter())
+    {
+        match result {
+            Ok(()) => acknowledged.push(peer.public_key.clone()),
+            Err(e) => log::warn!("failure in {}: {}", msg, e),
+        }
+    }
+    acknowledged
+}
+
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageBroadcaster<S, M> for ClientNetworkConfig {
+    async fn broadcast_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        message_hashes: &[Hash256],
+    ) -> Result<Vec<PublicKey>, Error> {
+        let packets = dms.read().await.retrieve_packets_of(message_hashes).await?;
+        Ok(send_packets_to_peers::<M>(packets, self).await)
+    }
+}
+
+/// Requests the packets of the peer, only the ones matching the filter if given.
+async fn request_packets_from<M: DmsMessage>(
+    peer: &Peer,
+    filter: Option<&PacketFilter>,
+) -> Result<Vec<Packet>, Error> {
+    let port_key = keys::port_key_dms::<M>();
+    let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
+        format!(
+            "{}:{}/dms",
+            peer.address.ip(),
+            peer.ports
+                .get(&port_key)
+                .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
+        ),
+        reqwest::Client::new(),
+    )));
+    let packets = match filter {
+        Some(filter) => stub.request_packets_matching(filter.clone()).await,
+        None => stub.request_packets().await,
+    };
+    packets.map_err(|e| eyre!("{}", e))?.map_err(|e| eyre!(e))
+}
+
+impl ClientNetworkConfig {
+    /// Fetches the packets of the peers, only the ones matching the filter if given.
+    async fn fetch_packets<S: Storage, M: DmsMessage>(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: Option<&PacketFilter>,
+    ) -> Result<Vec<FetchReport>, Error> {
+        let peers = peers
+            .iter()
+            .filter_map(|public_key| {
+                self.peers
+                    .iter()
+                    .find(|peer| peer.public_key == *public_key)
+            })
+            .collect::<Vec<_>>();
+        let results = future::join_all(
+            peers
+                .iter()
+                .map(|peer| request_packets_from::<M>(peer, filter)),
+        )
+        .await;
+        let mut reports = Vec::new();
+        for (result, peer) in results.into_iter().zip(peers) {
+            match result {
+                Ok(packets) => {
+                    let (admitted, rejections) = dms.write().await.receive_packets(packets).await?;
+                    reports.push(FetchReport {
+                        peer: peer.public_key.clone(),
+                        admitted,
+                        rejections,
+                    });
+                }
+                Err(e) => log::warn!("failed to fetch from client {:?}: {}", peer, e),
+            }
+        }
+        Ok(reports)
+    }
+}
+
+/// Requests the packets of the peers all at once,
+/// and then receives them one peer after another in the given order.
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageFetcher<S, M> for ClientNetworkConfig {
+    async fn peers(
+        &self,
+        _dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+    ) -> Result<Vec<PublicKey>, Error> {
+        Ok(self
+            .peers
+            .iter()
+            .map(|peer| peer.public_key.clone())
+            .collect())
+    }
+
+    async fn fetch_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.fetch_packets(dms, peers, None).await
+    }
+
+    async fn fetch_matching_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: &PacketFilter,
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.fetch_packets(dms, peers, Some(filter)).await
+    }
+}
+
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageBroadcaster<S, M> for SharedKnownPeers {
+    async fn broadcast_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        message_hashes: &[Hash256],
+    ) -> Result<Vec<PublicKey>, Error> {
+        self.network_config()
+            .broadcast_messages(dms, message_hashes)
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: Storage, M: DmsMessage> MessageFetcher<S, M> for SharedKnownPeers {
+    async fn peers(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+    ) -> Result<Vec<PublicKey>, Error> {
+        MessageFetcher::<S, M>::peers(&self.network_config(), dms).await
+    }
+
+    async fn fetch_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.network_config().fetch_messages(dms, peers).await
+    }
+
+    async fn fetch_matching_messages(
+        &self,
+        dms: &Arc<RwLock<DistributedMessageSet<S, M>>>,
+        peers: &[PublicKey],
+        filter: &PacketFilter,
+    ) -> Result<Vec<FetchReport>, Error> {
+        self.network_config()
+            .fetch_matching_messages(dms, peers, filter)
+            .await
+    }
+}
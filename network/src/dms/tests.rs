@@ -63,6 +63,88 @@ async fn single_1() {
     );
 }
 
+#[tokio::test]
+async fn read_commitments() {
+    let key = generate_random_string();
+    let ((_, private_key), _, _) = setup_server_client_nodes(1).await;
+    let mut dms = create_dms(
+        Config {
+            dms_key: key,
+            members: vec![private_key.public_key()],
+        },
+        private_key.clone(),
+    )
+    .await;
+
+    for i in 0..10 {
+        let msg = format!("{i}");
+        dms.commit_message(&msg).await.unwrap();
+    }
+
+    let commitments = dms.read_commitments().await.unwrap();
+    assert_eq!(
+        (0..10)
+            .map(|x| format!("{x}").to_hash256())
+            .collect::<std::collections::BTreeSet<_>>(),
+        commitments
+            .iter()
+            .map(|(hash, _)| *hash)
+            .collect::<std::collections::BTreeSet<_>>()
+    );
+    for (_, committers) in commitments {
+        assert_eq!(committers.len(), 1);
+        assert_eq!(committers[0].committer, private_key.public_key());
+    }
+}
+
+#[tokio::test]
+async fn read_commitments_since() {
+    let key = generate_random_string();
+    let ((_, private_key), _, _) = setup_server_client_nodes(1).await;
+    let mut dms = create_dms(
+        Config {
+            dms_key: key,
+            members: vec![private_key.public_key()],
+        },
+        private_key,
+    )
+    .await;
+
+    for i in 0..10 {
+        dms.commit_message(&format!("{i}")).await.unwrap();
+    }
+    let (commitments, cursor) = dms.read_commitments_since(0).await.unwrap();
+    assert_eq!(
+        commitments
+            .iter()
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>(),
+        (0..10)
+            .map(|x| format!("{x}").to_hash256())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(cursor, 10);
+    let (commitments, cursor) = dms.read_commitments_since(cursor).await.unwrap();
+    assert!(commitments.is_empty());
+    assert_eq!(cursor, 10);
+
+    // Committing the same message again is not logged.
+    dms.commit_message(&"3".to_owned()).await.unwrap();
+    dms.commit_message(&"10".to_owned()).await.unwrap();
+    let (commitments, cursor) = dms.read_commitments_since(cursor).await.unwrap();
+    assert_eq!(commitments.len(), 1);
+    assert_eq!(commitments[0].0, "10".to_owned().to_hash256());
+    assert_eq!(cursor, 11);
+
+    // In batches
+    let (commitments, cursor, left) = dms.read_commitments_since_limited(0, 4).await.unwrap();
+    assert_eq!(commitments.len(), 4);
+    assert_eq!((cursor, left), (4, 7));
+    let (commitments, cursor, left) = dms.read_commitments_since_limited(cursor, 7).await.unwrap();
+    assert_eq!(commitments[6].0, "10".to_owned().to_hash256());
+    assert_eq!((cursor, left), (11, 0));
+}
+
 pub async fn setup_server_client_nodes(
     client_n: usize,
 ) -> (
@@ -350,3 +432,273 @@ async fn multi_3() {
     // TODO: test with the server turing off and on repeatedly.
     // clients must be able to sync with each other even if the server is not available 100% of the time.
 }
+
+fn committed_messages(
+    count: usize,
+    dms_key: &DmsKey,
+    private_key: &PrivateKey,
+) -> Vec<(String, MessageCommitmentProof)> {
+    (0..count)
+        .map(|i| {
+            let message = format!("{i}");
+            let commitment = message.commit(dms_key, private_key).unwrap();
+            (message, commitment)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn batch_verification() {
+    let key = generate_random_string();
+    let ((_, private_key), _, _) = setup_server_client_nodes(1).await;
+    let mut messages = committed_messages(100, &key, &private_key);
+    // Swap the commitments of a few, which invalidates them.
+    for i in [3, 50] {
+        let commitment = messages[i].1.clone();
+        messages[i].1 = messages[i + 1].1.clone();
+        messages[i + 1].1 = commitment;
+    }
+    let expected = (0..100)
+        .map(|i| [3, 4, 50, 51].contains(&i))
+        .collect::<Vec<_>>();
+    for batch_size in [1, 7, 100, DEFAULT_VERIFICATION_BATCH_SIZE] {
+        let invalid = verify_commitments(&messages, &key, batch_size)
+            .iter()
+            .map(|result| result.is_err())
+            .collect::<Vec<_>>();
+        assert_eq!(invalid, expected, "batch size {batch_size}");
+    }
+
+    let mut dms = create_dms(
+        Config {
+            dms_key: key,
+            members: vec![private_key.public_key()],
+        },
+        private_key,
+    )
+    .await;
+    dms.set_verification_batch_size(16);
+    let packets = messages
+        .into_iter()
+        .map(|(message, commitment)| Packet {
+            message: message.encode_wire(),
+            commitment,
+        })
+        .collect();
+    let (admitted, rejections) = dms.receive_packets(packets).await.unwrap();
+    assert_eq!(admitted, 96);
+    assert_eq!(rejections.len(), 4);
+    assert!(rejections.iter().all(|e| e.contains("invalid commitment")));
+    assert_eq!(dms.read_messages().await.unwrap().len(), 96);
+}
+
+/// The Ed25519 commitments are verified in batches of their own, among the secp256k1 ones.
+#[test]
+fn batch_verification_mixed_schemes() {
+    let key = generate_random_string();
+    let (_, private_key) = generate_keypair_random();
+    let (_, ed25519_private_key) = generate_keypair_ed25519(generate_random_string());
+    let mut messages = committed_messages(50, &key, &private_key)
+        .into_iter()
+        .zip(committed_messages(50, &key, &ed25519_private_key))
+        .flat_map(|(x, y)| [x, y])
+        .collect::<Vec<_>>();
+    // Swap the commitments of the Ed25519 pairs (9, 11) and (51, 53), and the secp256k1 pair (20, 22).
+    for i in [9, 20, 51] {
+        let commitment = messages[i].1.clone();
+        messages[i].1 = messages[i + 2].1.clone();
+        messages[i + 2].1 = commitment;
+    }
+    let expected = (0..100)
+        .map(|i| [9, 11, 20, 22, 51, 53].contains(&i))
+        .collect::<Vec<_>>();
+    for batch_size in [1, 7, 100, DEFAULT_VERIFICATION_BATCH_SIZE] {
+        let invalid = verify_commitments(&messages, &key, batch_size)
+            .iter()
+            .map(|result| result.is_err())
+            .collect::<Vec<_>>();
+        assert_eq!(invalid, expected, "batch size {batch_size}");
+    }
+}
+
+/// The verification of the messages one by one and the batched one agree.
+#[test]
+fn verification_batch_sizes() {
+    let key = generate_random_string();
+    let (_, private_key) = generate_keypair_random();
+    let messages = committed_messages(1000, &key, &private_key);
+    for batch_size in [1, DEFAULT_VERIFICATION_BATCH_SIZE] {
+        let results = verify_commitments(&messages, &key, batch_size);
+        assert_eq!(results.len(), messages.len());
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+}
+
+/// Compares the verification of the messages one by one with the batched one, for each scheme.
+///
+/// Run with `cargo test --release -- --ignored --nocapture verification_benchmark`.
+#[ignore]
+#[test]
+fn verification_benchmark() {
+    let key = generate_random_string();
+    let (_, private_key) = generate_keypair_random();
+    let (_, ed25519_private_key) = generate_keypair_ed25519(generate_random_string());
+    for private_key in [private_key, ed25519_private_key] {
+        let messages = committed_messages(10_000, &key, &private_key);
+        for batch_size in [1, DEFAULT_VERIFICATION_BATCH_SIZE] {
+            let start = std::time::Instant::now();
+            let results = verify_commitments(&messages, &key, batch_size);
+            assert!(results.iter().all(|result| result.is_ok()));
+            println!(
+                "verified {} {:?} messages in batches of {batch_size}: {:?}",
+                messages.len(),
+                private_key.scheme(),
+                start.elapsed()
+            );
+        }
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn mock_gossip_network() {
+    let key = generate_random_string();
+    let ((_, server_private_key), clients, members) = setup_server_client_nodes(3).await;
+    let config = Config {
+        dms_key: key,
+        members,
+    };
+    let mut network = MockGossipNetwork::new();
+    let mut dmses = Vec::new();
+    for private_key in clients
+        .into_iter()
+        .map(|(_, private_key)| private_key)
+        .chain(std::iter::once(server_private_key))
+    {
+        let dms = Arc::new(RwLock::new(create_dms(config.clone(), private_key).await));
+        network.add_node(Arc::clone(&dms));
+        dmses.push(dms);
+    }
+    async fn messages(dms: &Arc<RwLock<Dms>>) -> std::collections::BTreeSet<String> {
+        dms.read()
+            .await
+            .read_messages()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.message)
+            .collect()
+    }
+
+    // Nothing is delivered over a fully lossy network.
+    network.set_drop_probability(1.0);
+    dmses[0]
+        .write()
+        .await
+        .commit_message(&"0".to_owned())
+        .await
+        .unwrap();
+    network.gossip().await.unwrap();
+    for dms in &dmses[1..] {
+        assert!(messages(dms).await.is_empty());
+    }
+    network.set_drop_probability(0.0);
+
+    // Only the nodes in the same partition receive the messages.
+    network.partition(&[vec![0, 1], vec![2]]);
+    network.set_latency(Duration::from_millis(10));
+    network.gossip().await.unwrap();
+    assert_eq!(messages(&dmses[1]).await.len(), 1);
+    assert!(messages(&dmses[2]).await.is_empty());
+    assert!(messages(&dmses[3]).await.is_empty());
+
+    network.heal();
+    network.gossip().await.unwrap();
+    for dms in &dmses {
+        assert_eq!(
+            messages(dms).await,
+            std::iter::once("0".to_owned()).collect()
+        );
+    }
+
+    // Only the receivers of a direct broadcast acknowledge it.
+    let message = "1".to_owned();
+    dmses[0]
+        .write()
+        .await
+        .commit_message(&message)
+        .await
+        .unwrap();
+    network.partition(&[vec![0, 1], vec![2, 3]]);
+    let acknowledged = network
+        .broadcast_messages(&dmses[0], &[message.to_hash256()])
+        .await
+        .unwrap();
+    assert_eq!(acknowledged, vec![dmses[1].read().await.public_key()]);
+    assert!(messages(&dmses[1]).await.contains(&message));
+    assert!(!messages(&dmses[2]).await.contains(&message));
+    network.heal();
+    network.set_drop_probability(1.0);
+    let acknowledged = network
+        .broadcast_messages(&dmses[0], &[message.to_hash256()])
+        .await
+        .unwrap();
+    assert!(acknowledged.is_empty());
+    network.set_drop_probability(0.0);
+
+    // The packets dropped by the gossip are recovered by fetching only them.
+    let public_keys = {
+        let mut public_keys = Vec::new();
+        for dms in &dmses {
+            public_keys.push(dms.read().await.public_key());
+        }
+        public_keys
+    };
+    let filter = PacketFilter {
+        message_prefixes: Vec::new(),
+        committers: vec![public_keys[2].clone()],
+    };
+    network.set_gossip_drop_filter(Some(filter.clone()));
+    dmses[1]
+        .write()
+        .await
+        .commit_message(&"2".to_owned())
+        .await
+        .unwrap();
+    dmses[2]
+        .write()
+        .await
+        .commit_message(&"3".to_owned())
+        .await
+        .unwrap();
+    network.gossip().await.unwrap();
+    assert!(messages(&dmses[3]).await.contains("2"));
+    assert!(!messages(&dmses[3]).await.contains("3"));
+    let reports = network
+        .fetch_matching_messages(&dmses[3], &public_keys[..3], &filter)
+        .await
+        .unwrap();
+    assert_eq!(
+        reports
+            .iter()
+            .map(|report| report.admitted)
+            .collect::<Vec<_>>(),
+        vec![0, 0, 1]
+    );
+    assert!(messages(&dmses[3]).await.contains("3"));
+}
+
+#[test]
+fn packet_filter() {
+    let (public_key, _) = generate_keypair("committer");
+    let (another_public_key, _) = generate_keypair("another committer");
+    assert!(PacketFilter::default().matches(b"message", &public_key));
+    let filter = PacketFilter {
+        message_prefixes: vec![b"ab".to_vec(), b"x".to_vec()],
+        committers: vec![public_key.clone()],
+    };
+    assert!(filter.matches(b"abc", &public_key));
+    assert!(filter.matches(b"x", &public_key));
+    assert!(!filter.matches(b"a", &public_key));
+    assert!(!filter.matches(b"abc", &another_public_key));
+}
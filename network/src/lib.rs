@@ -14,8 +14,11 @@ use std::net::SocketAddrV4;
 pub type Error = eyre::Error;
 pub type Dms<T> = dms::DistributedMessageSet<storage::StorageImpl, T>;
 
-pub use dms::{Config, DmsKey, DmsMessage, MessageCommitmentProof};
-pub use storage::{Storage, StorageError, StorageImpl};
+pub use dms::{
+    Config, DmsKey, DmsMessage, FetchReport, MessageBroadcaster, MessageCommitmentProof,
+    MessageFetcher, MessageFilter, PacketFilter, DEFAULT_VERIFICATION_BATCH_SIZE,
+};
+pub use storage::{Storage, StorageError, StorageImpl, StorageOp};
 
 /// The information of a network peer that is discovered by the discovery protocol.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -37,11 +40,62 @@ pub struct ClientNetworkConfig {
     pub peers: Vec<Peer>,
 }
 
+/// The peers known to this node, shared by the network operations
+/// so that they can be changed while the operations are running (e.g., while serving).
+///
+/// Every operation reads the peers at the time it is called.
+#[derive(Debug, Clone, Default)]
+pub struct SharedKnownPeers {
+    peers: std::sync::Arc<parking_lot::RwLock<Vec<Peer>>>,
+}
+
+impl SharedKnownPeers {
+    pub fn new(peers: Vec<Peer>) -> Self {
+        Self {
+            peers: std::sync::Arc::new(parking_lot::RwLock::new(peers)),
+        }
+    }
+
+    /// Returns the current peers.
+    pub fn read(&self) -> Vec<Peer> {
+        self.peers.read().clone()
+    }
+
+    /// Adds the peer, replacing the one of the same public key (e.g., whose address has changed).
+    pub fn add_peer(&self, peer: Peer) {
+        let mut peers = self.peers.write();
+        peers.retain(|x| x.public_key != peer.public_key);
+        peers.push(peer);
+    }
+
+    /// Removes the peer, returning whether it has been known.
+    pub fn remove_peer(&self, public_key: &PublicKey) -> bool {
+        let mut peers = self.peers.write();
+        let len = peers.len();
+        peers.retain(|x| x.public_key != *public_key);
+        peers.len() != len
+    }
+
+    /// Returns the current peers as a `ClientNetworkConfig`.
+    pub fn network_config(&self) -> ClientNetworkConfig {
+        ClientNetworkConfig { peers: self.read() }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerNetworkConfig {
     pub port: u16, // TODO: add various configurations for NAT traversal
 }
 
+/// The identity and the listening configuration of this node,
+/// which a long-running node may change while serving (e.g., with a `watch` channel).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// The key that this node is identified and signs the messages with.
+    pub private_key: PrivateKey,
+    pub server: ServerNetworkConfig,
+}
+
 pub mod keys {
     use simperby_core::*;
 
@@ -1,10 +1,24 @@
 use async_trait::async_trait;
 use fs2::FileExt;
 use futures::stream::*;
+use serde::{Deserialize, Serialize};
 use tokio::{fs, io::AsyncWriteExt, task::spawn_blocking};
 
 pub type StorageError = std::io::Error;
 
+/// An operation in a batch applied by `Storage::apply_batch()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageOp {
+    AddOrOverwrite {
+        name: String,
+        content: String,
+    },
+    /// Removes the file, which is not an error if there is no such file.
+    Remove {
+        name: String,
+    },
+}
+
 /// An abstraction of the synchronized storage backed by the host file system.
 #[async_trait]
 pub trait Storage: Send + Sync + 'static {
@@ -21,6 +35,9 @@ pub trait Storage: Send + Sync + 'static {
     async fn list_files(&self) -> Result<Vec<String>, StorageError>;
 
     /// Adds the given file to the storage.
+    ///
+    /// The file is either fully written or left untouched even if the process crashes.
+    /// It fails if the name is reserved for the internal files of the implementation.
     async fn add_or_overwrite_file(
         &mut self,
         name: &str,
@@ -35,9 +52,37 @@ pub trait Storage: Send + Sync + 'static {
 
     /// Removes all files.
     async fn remove_all_files(&mut self) -> Result<(), StorageError>;
+
+    /// Applies the operations in order, atomically with respect to crashes:
+    /// after a restart (i.e., `open()`), either all of them or none are observed.
+    async fn apply_batch(&mut self, ops: Vec<StorageOp>) -> Result<(), StorageError>;
+}
+
+/// The file locked by the instance that has opened the storage.
+const LOCK_FILE_NAME: &str = "lock";
+/// The suffix of the temporary files used for the atomic writes.
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+/// The batch being applied by `apply_batch()`, replayed by `open()` if it has been interrupted.
+const JOURNAL_FILE_NAME: &str = "journal";
+
+/// Whether the name is of an internal file, which is hidden from `list_files()`.
+fn is_reserved_name(name: &str) -> bool {
+    name == LOCK_FILE_NAME || name == JOURNAL_FILE_NAME || name.ends_with(TEMP_FILE_SUFFIX)
+}
+
+/// Fails if the name is of an internal file, which must not be written by the users.
+fn check_name(name: &str) -> Result<(), StorageError> {
+    if is_reserved_name(name) {
+        return Err(StorageError::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("the file name {name} is reserved for the storage"),
+        ));
+    }
+    Ok(())
 }
 
 pub struct StorageImpl {
+    /// `None` if opened by `open_read_only()`.
     lock_file: Option<std::fs::File>,
     path: String,
 }
@@ -47,7 +92,7 @@ impl Storage for StorageImpl {
     async fn create(storage_directory: &str) -> Result<(), StorageError> {
         let _ = fs::remove_dir_all(storage_directory).await;
         fs::create_dir_all(storage_directory).await?;
-        fs::File::create(format!("{storage_directory}/lock")).await?;
+        fs::File::create(format!("{storage_directory}/{LOCK_FILE_NAME}")).await?;
         Ok(())
     }
 
@@ -56,18 +101,21 @@ impl Storage for StorageImpl {
         Self: Sized,
     {
         let storage_directory_ = storage_directory.to_owned();
-        let file =
-            spawn_blocking(move || std::fs::File::open(format!("{storage_directory_}/lock")))
-                .await??;
+        let file = spawn_blocking(move || {
+            std::fs::File::open(format!("{storage_directory_}/{LOCK_FILE_NAME}"))
+        })
+        .await??;
         let file = spawn_blocking(move || {
             let result = file.lock_exclusive();
             result.map(|_| file)
         })
         .await??;
-        Ok(Self {
+        let mut storage = Self {
             lock_file: Some(file),
             path: storage_directory.to_owned(),
-        })
+        };
+        storage.replay_journal().await?;
+        Ok(storage)
     }
 
     async fn list_files(&self) -> Result<Vec<String>, StorageError> {
@@ -80,7 +128,7 @@ impl Storage for StorageImpl {
         Ok(files
             .into_iter()
             .map(|file| file.file_name().into_string().unwrap())
-            .filter(|file| file != "lock")
+            .filter(|file| !is_reserved_name(file))
             .collect())
     }
 
@@ -89,11 +137,9 @@ impl Storage for StorageImpl {
         name: &str,
         content: String,
     ) -> Result<(), StorageError> {
-        let mut file = fs::File::create(format!("{}/{}", self.path, name)).await?;
-        file.write_all(content.as_bytes()).await?;
-        // IMPORTANT!
-        file.flush().await?;
-        Ok(())
+        self.check_writable()?;
+        check_name(name)?;
+        self.write_atomically(name, &content).await
     }
 
     async fn read_file(&self, name: &str) -> Result<String, StorageError> {
@@ -101,21 +147,137 @@ impl Storage for StorageImpl {
     }
 
     async fn remove_file(&mut self, name: &str) -> Result<(), StorageError> {
-        fs::remove_file(format!("{}/{}", self.path, name)).await
+        self.check_writable()?;
+        check_name(name)?;
+        fs::remove_file(format!("{}/{}", self.path, name)).await?;
+        self.sync_directory().await
     }
 
     async fn remove_all_files(&mut self) -> Result<(), StorageError> {
+        self.check_writable()?;
         let files = self.list_files().await?;
         for file in files {
             self.remove_file(&file).await?;
+            // Clean up the leftover of an interrupted write, if any.
+            let _ = fs::remove_file(format!("{}/{}{}", self.path, file, TEMP_FILE_SUFFIX)).await;
+        }
+        Ok(())
+    }
+
+    async fn apply_batch(&mut self, ops: Vec<StorageOp>) -> Result<(), StorageError> {
+        self.check_writable()?;
+        for op in &ops {
+            match op {
+                StorageOp::AddOrOverwrite { name, .. } | StorageOp::Remove { name } => {
+                    check_name(name)?
+                }
+            }
+        }
+        // Once the journal is written, the batch is replayed to the end even if it crashes.
+        self.write_atomically(JOURNAL_FILE_NAME, &serde_json::to_string(&ops).unwrap())
+            .await?;
+        self.apply_ops(ops).await?;
+        fs::remove_file(format!("{}/{}", self.path, JOURNAL_FILE_NAME)).await?;
+        self.sync_directory().await
+    }
+}
+
+impl StorageImpl {
+    /// Opens an existing directory to read only, without locking it,
+    /// so that it can be inspected while another instance keeps writing to it.
+    ///
+    /// Every write fails, and an interrupted batch is left to the writer to replay.
+    pub async fn open_read_only(storage_directory: &str) -> Result<Self, StorageError> {
+        if !fs::metadata(storage_directory).await?.is_dir() {
+            return Err(StorageError::new(
+                std::io::ErrorKind::NotFound,
+                format!("{storage_directory} is not a directory"),
+            ));
+        }
+        Ok(Self {
+            lock_file: None,
+            path: storage_directory.to_owned(),
+        })
+    }
+
+    fn check_writable(&self) -> Result<(), StorageError> {
+        if self.lock_file.is_none() {
+            return Err(StorageError::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("the storage {} is opened to read only", self.path),
+            ));
         }
         Ok(())
     }
+
+    /// Writes to a temporary file first and then renames it,
+    /// so that a crash never leaves a partially written file.
+    async fn write_atomically(&self, name: &str, content: &str) -> Result<(), StorageError> {
+        let path = format!("{}/{}", self.path, name);
+        let temp_path = format!("{path}{TEMP_FILE_SUFFIX}");
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(content.as_bytes()).await?;
+        // IMPORTANT!
+        file.flush().await?;
+        file.sync_all().await?;
+        fs::rename(&temp_path, &path).await?;
+        // The rename itself is durable only once the directory is synced.
+        self.sync_directory().await
+    }
+
+    /// Syncs the directory, so that the files created, renamed or removed in it
+    /// survive a crash.
+    async fn sync_directory(&self) -> Result<(), StorageError> {
+        #[cfg(unix)]
+        {
+            let path = self.path.clone();
+            spawn_blocking(move || std::fs::File::open(path)?.sync_all()).await??;
+        }
+        Ok(())
+    }
+
+    /// Applies the operations, which is idempotent so that they can be replayed.
+    async fn apply_ops(&mut self, ops: Vec<StorageOp>) -> Result<(), StorageError> {
+        for op in ops {
+            match op {
+                StorageOp::AddOrOverwrite { name, content } => {
+                    self.write_atomically(&name, &content).await?
+                }
+                StorageOp::Remove { name } => match self.remove_file(&name).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => (),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the batch interrupted by a crash, if any.
+    async fn replay_journal(&mut self) -> Result<(), StorageError> {
+        let journal_path = format!("{}/{}", self.path, JOURNAL_FILE_NAME);
+        let journal = match fs::read_to_string(&journal_path).await {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let ops = serde_json::from_str(&journal)
+            .map_err(|e| StorageError::new(std::io::ErrorKind::InvalidData, e))?;
+        log::warn!(
+            "replaying the interrupted batch of the storage {}",
+            self.path
+        );
+        self.apply_ops(ops).await?;
+        fs::remove_file(&journal_path).await?;
+        self.sync_directory().await
+    }
 }
 
 impl Drop for StorageImpl {
     fn drop(&mut self) {
-        let lock_file = self.lock_file.take().unwrap();
+        let lock_file = match self.lock_file.take() {
+            Some(lock_file) => lock_file,
+            None => return,
+        };
         spawn_blocking(move || {
             if let Err(e) = lock_file.unlock() {
                 log::error!("failed to unlock storage: {}", e);
@@ -211,6 +373,158 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn read_only() {
+        let dir = gerenate_random_storage_directory();
+        StorageImpl::create(&dir).await.unwrap();
+        let mut storage = StorageImpl::open(&dir).await.unwrap();
+        let name = generate_random_string();
+        let content = generate_random_string();
+        storage
+            .add_or_overwrite_file(&name, content.clone())
+            .await
+            .unwrap();
+
+        // It is not blocked by the lock of the writer.
+        let mut read_only = StorageImpl::open_read_only(&dir).await.unwrap();
+        assert_eq!(read_only.read_file(&name).await.unwrap(), content);
+        assert_eq!(read_only.list_files().await.unwrap(), vec![name.clone()]);
+        let error = read_only
+            .add_or_overwrite_file(&name, generate_random_string())
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(read_only.remove_file(&name).await.is_err());
+        assert!(read_only.apply_batch(Vec::new()).await.is_err());
+        drop(read_only);
+
+        // The writer keeps the lock and the file.
+        storage.remove_file(&name).await.unwrap();
+        assert!(StorageImpl::open_read_only(&format!("{dir}/missing"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reserved_names() {
+        let dir = gerenate_random_storage_directory();
+        StorageImpl::create(&dir).await.unwrap();
+        let mut storage = StorageImpl::open(&dir).await.unwrap();
+        let name = generate_random_string();
+        storage
+            .add_or_overwrite_file(&name, "content".to_owned())
+            .await
+            .unwrap();
+        for reserved in [
+            LOCK_FILE_NAME.to_owned(),
+            JOURNAL_FILE_NAME.to_owned(),
+            format!("{name}{TEMP_FILE_SUFFIX}"),
+        ] {
+            let error = storage
+                .add_or_overwrite_file(&reserved, "content".to_owned())
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(storage.remove_file(&reserved).await.is_err());
+            let ops = vec![
+                StorageOp::AddOrOverwrite {
+                    name: name.clone(),
+                    content: "new".to_owned(),
+                },
+                StorageOp::Remove { name: reserved },
+            ];
+            assert!(storage.apply_batch(ops).await.is_err());
+        }
+        // Nothing has been touched.
+        assert_eq!(storage.read_file(&name).await.unwrap(), "content");
+        assert_eq!(storage.list_files().await.unwrap(), vec![name]);
+        drop(storage);
+        StorageImpl::open(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn interrupted_write() {
+        let dir = gerenate_random_storage_directory();
+        StorageImpl::create(&dir).await.unwrap();
+        let mut storage = StorageImpl::open(&dir).await.unwrap();
+
+        let name = generate_random_string();
+        let content = generate_random_string();
+        storage
+            .add_or_overwrite_file(&name, content.clone())
+            .await
+            .unwrap();
+        // Simulate a crash in the middle of overwriting the file.
+        std::fs::write(format!("{dir}/{name}{TEMP_FILE_SUFFIX}"), &content[0..4]).unwrap();
+
+        assert_eq!(storage.read_file(&name).await.unwrap(), content);
+        assert_eq!(storage.list_files().await.unwrap(), vec![name.clone()]);
+
+        let content = generate_random_string();
+        storage
+            .add_or_overwrite_file(&name, content.clone())
+            .await
+            .unwrap();
+        assert_eq!(storage.read_file(&name).await.unwrap(), content);
+        assert_eq!(storage.list_files().await.unwrap(), vec![name]);
+    }
+
+    #[tokio::test]
+    async fn interrupted_batch() {
+        let dir = gerenate_random_storage_directory();
+        StorageImpl::create(&dir).await.unwrap();
+        let mut storage = StorageImpl::open(&dir).await.unwrap();
+        let names = (0..3).map(|_| generate_random_string()).collect::<Vec<_>>();
+        storage
+            .apply_batch(
+                names
+                    .iter()
+                    .map(|name| StorageOp::AddOrOverwrite {
+                        name: name.clone(),
+                        content: "old".to_owned(),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+        assert!(!std::path::Path::new(&dir).join(JOURNAL_FILE_NAME).exists());
+
+        // Simulate a crash after the first operation of a batch.
+        let ops = vec![
+            StorageOp::AddOrOverwrite {
+                name: names[0].clone(),
+                content: "new".to_owned(),
+            },
+            StorageOp::AddOrOverwrite {
+                name: names[1].clone(),
+                content: "new".to_owned(),
+            },
+            StorageOp::Remove {
+                name: names[2].clone(),
+            },
+        ];
+        std::fs::write(
+            format!("{dir}/{JOURNAL_FILE_NAME}"),
+            serde_json::to_string(&ops).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(format!("{dir}/{}", names[0]), "new").unwrap();
+        assert_eq!(storage.read_file(&names[1]).await.unwrap(), "old");
+        drop(storage);
+
+        // The batch is completed by the restart.
+        let storage = StorageImpl::open(&dir).await.unwrap();
+        for name in &names[0..2] {
+            assert_eq!(storage.read_file(name).await.unwrap(), "new");
+        }
+        assert!(storage.read_file(&names[2]).await.is_err());
+        let mut files = storage.list_files().await.unwrap();
+        files.sort();
+        let mut expected = names[0..2].to_vec();
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+
     #[tokio::test]
     async fn never_interrupted() {
         let dir = gerenate_random_storage_directory();
@@ -85,10 +85,19 @@ impl Client {
                     lfi.header,
                     ConsensusParams {
                         timeout_ms: 10000000,
+                        timeout_increment_ms: 0,
                         repeat_round_for_first_leader: 100,
+                        quorum: None,
                     },
                     get_timestamp(),
-                    Some(auth.private_key),
+                    ConsensusConfig {
+                        this_node_signer: Some(
+                            Arc::new(auth.private_key) as Arc<dyn ConsensusSigner>
+                        ),
+                        // Only the blocks that have passed the verification by the repository
+                        // are registered to the consensus, which the default takes as valid.
+                        ..Default::default()
+                    },
                 )
                 .await?,
                 peers,
@@ -121,9 +130,25 @@ impl Client {
     /// TODO: it has to consume the object if finalized.
     pub async fn progress_for_consensus(&mut self) -> Result<String> {
         let mut this = self.inner.take().unwrap();
-        let result = this.consensus.progress(get_timestamp()).await?;
+        let result = match this.consensus.progress(get_timestamp()).await {
+            Ok(result) => result,
+            Err(e) => {
+                self.inner = Some(this);
+                return Err(e.into());
+            }
+        };
         let report = format!("{result:?}");
         for result in result {
+            if let ProgressResult::ViolationReported(violation, _) = &result {
+                log::warn!(
+                    "{:?} by {} in round {} ({:?}): {}",
+                    violation.kind,
+                    violation.violator,
+                    violation.round,
+                    violation.evidence_hashes,
+                    violation.description
+                );
+            }
             if let ProgressResult::Finalized(Finalization {
                 block_hash, proof, ..
             }) = result
@@ -283,12 +308,20 @@ impl Client {
                 .await?;
         }
 
-        // Update consensus
-        this.consensus.update().await?;
+        // Update consensus; once finalized, it's left to `progress_for_consensus()`.
+        match this.consensus.update().await {
+            Ok(()) | Err(ConsensusError::Finalized) => {}
+            Err(e) => return Err(e.into()),
+        }
         for (_, block_hash) in this.repository.read_blocks().await? {
-            this.consensus
+            match this
+                .consensus
                 .register_verified_block_hash(block_hash)
-                .await?;
+                .await
+            {
+                Ok(()) | Err(ConsensusError::Finalized) => {}
+                Err(e) => return Err(e.into()),
+            }
         }
         Ok(())
     }
@@ -1,3 +1,7 @@
+mod memory_storage;
+
+pub use memory_storage::MemoryStorage;
+
 use path_slash::PathExt as _;
 use simperby_core::*;
 use simperby_network::*;
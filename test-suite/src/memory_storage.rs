@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use simperby_network::{Storage, StorageError, StorageOp};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Directory {
+    files: HashMap<String, String>,
+    /// The number of the next writes to fail.
+    failing_writes: usize,
+    /// The number of the successful writes so far.
+    writes: usize,
+    /// The batch being applied, replayed by `open()` if it has been interrupted.
+    journal: Option<Vec<StorageOp>>,
+    /// The number of the operations after which the next batch is interrupted.
+    crash_after_ops: Option<usize>,
+}
+
+impl Directory {
+    fn apply(&mut self, op: StorageOp) {
+        match op {
+            StorageOp::AddOrOverwrite { name, content } => {
+                self.files.insert(name, content);
+                self.writes += 1;
+            }
+            StorageOp::Remove { name } => {
+                self.files.remove(&name);
+            }
+        }
+    }
+}
+
+/// All the directories created in this process, by their names.
+static DIRECTORIES: Lazy<Mutex<HashMap<String, Arc<Mutex<Directory>>>>> =
+    Lazy::new(Default::default);
+
+/// A `Storage` that keeps the files in the memory, which is fast and leaves nothing behind.
+///
+/// The storages opened with the same directory share the files,
+/// so a restart can be simulated by opening it again.
+/// Unlike `StorageImpl`, it doesn't lock the directory.
+#[derive(Clone)]
+pub struct MemoryStorage {
+    directory: String,
+    inner: Arc<Mutex<Directory>>,
+}
+
+impl MemoryStorage {
+    /// Creates a new directory and opens it.
+    pub async fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let directory = format!("memory-storage-{}", COUNT.fetch_add(1, Ordering::SeqCst));
+        Self::create(&directory).await.unwrap();
+        Self::open(&directory).await.unwrap()
+    }
+
+    pub fn directory(&self) -> &str {
+        &self.directory
+    }
+
+    /// Returns the number of the files written so far (including the overwrites).
+    pub fn write_count(&self) -> usize {
+        self.inner.lock().writes
+    }
+
+    /// Makes the next `count` writes fail without touching the files,
+    /// as if the disk were full.
+    /// A batch counts as a single write.
+    pub fn fail_next_writes(&self, count: usize) {
+        self.inner.lock().failing_writes = count;
+    }
+
+    /// Makes the next batch crash after applying `count` of its operations,
+    /// leaving the rest to be replayed when the directory is opened again.
+    pub fn crash_next_batch(&self, count: usize) {
+        self.inner.lock().crash_after_ops = Some(count);
+    }
+}
+
+fn not_found(name: &str) -> StorageError {
+    StorageError::new(ErrorKind::NotFound, format!("{name} not found"))
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn create(storage_directory: &str) -> Result<(), StorageError> {
+        DIRECTORIES
+            .lock()
+            .insert(storage_directory.to_owned(), Default::default());
+        Ok(())
+    }
+
+    async fn open(storage_directory: &str) -> Result<Self, StorageError>
+    where
+        Self: Sized,
+    {
+        let inner = DIRECTORIES
+            .lock()
+            .get(storage_directory)
+            .cloned()
+            .ok_or_else(|| not_found(storage_directory))?;
+        {
+            let mut directory = inner.lock();
+            if let Some(ops) = directory.journal.take() {
+                for op in ops {
+                    directory.apply(op);
+                }
+            }
+        }
+        Ok(Self {
+            directory: storage_directory.to_owned(),
+            inner,
+        })
+    }
+
+    async fn list_files(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.inner.lock().files.keys().cloned().collect())
+    }
+
+    async fn add_or_overwrite_file(
+        &mut self,
+        name: &str,
+        content: String,
+    ) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock();
+        if inner.failing_writes > 0 {
+            inner.failing_writes -= 1;
+            return Err(StorageError::new(
+                ErrorKind::Other,
+                format!("injected failure on writing {name}"),
+            ));
+        }
+        inner.files.insert(name.to_owned(), content);
+        inner.writes += 1;
+        Ok(())
+    }
+
+    async fn read_file(&self, name: &str) -> Result<String, StorageError> {
+        self.inner
+            .lock()
+            .files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| not_found(name))
+    }
+
+    async fn remove_file(&mut self, name: &str) -> Result<(), StorageError> {
+        self.inner
+            .lock()
+            .files
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    async fn remove_all_files(&mut self) -> Result<(), StorageError> {
+        self.inner.lock().files.clear();
+        Ok(())
+    }
+
+    async fn apply_batch(&mut self, ops: Vec<StorageOp>) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock();
+        if inner.failing_writes > 0 {
+            inner.failing_writes -= 1;
+            return Err(StorageError::new(
+                ErrorKind::Other,
+                "injected failure on writing a batch",
+            ));
+        }
+        let total = ops.len();
+        let applied = inner.crash_after_ops.take().unwrap_or(total).min(total);
+        inner.journal = Some(ops.clone());
+        for op in ops.into_iter().take(applied) {
+            inner.apply(op);
+        }
+        if applied < total {
+            return Err(StorageError::new(
+                ErrorKind::Interrupted,
+                format!("injected crash after {applied} operations of a batch"),
+            ));
+        }
+        inner.journal = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! The formats of `Vetomint` in the previous versions, to read the ones stored by them.
+use super::*;
+use crate::state::{ConsensusState, Proposal, Vote};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// `ConsensusParams` before `timeout_increment_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct ConsensusParamsV0 {
+    timeout_ms: u64,
+    repeat_round_for_first_leader: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct HeightInfoV0 {
+    validators: Vec<VotingPower>,
+    this_node_index: Option<ValidatorIndex>,
+    timestamp: Timestamp,
+    consensus_params: ConsensusParamsV0,
+    initial_block_candidate: BlockIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct ConsensusStateV0 {
+    height_info: HeightInfoV0,
+    round: Round,
+    step: ConsensusStep,
+    locked_value: Option<BlockIdentifier>,
+    locked_round: Option<Round>,
+    valid_value: Option<BlockIdentifier>,
+    valid_round: Option<Round>,
+    block_candidate: BlockIdentifier,
+    proposals: BTreeMap<BlockIdentifier, Proposal>,
+    prevotes: BTreeSet<Vote>,
+    precommits: BTreeSet<Vote>,
+    propose_timeout_schedules: BTreeSet<(Round, Timestamp)>,
+    precommit_timeout_schedules: BTreeSet<(Round, Timestamp)>,
+    for_the_first_time_1: BTreeSet<Round>,
+    for_the_first_time_2: BTreeSet<Round>,
+    finalized: Option<(BlockIdentifier, Vec<ValidatorIndex>, Round)>,
+}
+
+/// `Vetomint` before `ConsensusParams::timeout_increment_ms`, which is read with no increment.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VetomintV0 {
+    state: ConsensusStateV0,
+}
+
+impl VetomintV0 {
+    /// Returns the rounds in which this node has prevoted.
+    pub fn prevoted_rounds(&self) -> BTreeSet<Round> {
+        self.state
+            .prevotes
+            .iter()
+            .filter(|vote| Some(vote.signer) == self.state.height_info.this_node_index)
+            .map(|vote| vote.round)
+            .collect()
+    }
+
+    /// Returns the block candidate, which was `0` until a candidate was given.
+    pub fn block_candidate(&self) -> BlockIdentifier {
+        self.state.block_candidate
+    }
+
+    /// Replaces the block candidate, including the initial one.
+    pub fn set_block_candidate(&mut self, block_candidate: BlockIdentifier) {
+        self.state.height_info.initial_block_candidate = block_candidate;
+        self.state.block_candidate = block_candidate;
+    }
+}
+
+impl From<VetomintV0> for VetomintV1 {
+    fn from(vetomint: VetomintV0) -> Self {
+        let state = vetomint.state;
+        let height_info = state.height_info;
+        let params = height_info.consensus_params;
+        VetomintV1 {
+            state: ConsensusStateV1 {
+                height_info: HeightInfoV1 {
+                    validators: height_info.validators,
+                    this_node_index: height_info.this_node_index,
+                    timestamp: height_info.timestamp,
+                    consensus_params: ConsensusParamsV1 {
+                        timeout_ms: params.timeout_ms,
+                        timeout_increment_ms: 0,
+                        repeat_round_for_first_leader: params.repeat_round_for_first_leader,
+                    },
+                    initial_block_candidate: height_info.initial_block_candidate,
+                },
+                round: state.round,
+                step: state.step,
+                locked_value: state.locked_value,
+                locked_round: state.locked_round,
+                valid_value: state.valid_value,
+                valid_round: state.valid_round,
+                block_candidate: state.block_candidate,
+                proposals: state.proposals,
+                prevotes: state.prevotes,
+                precommits: state.precommits,
+                propose_timeout_schedules: state.propose_timeout_schedules,
+                precommit_timeout_schedules: state.precommit_timeout_schedules,
+                for_the_first_time_1: state.for_the_first_time_1,
+                for_the_first_time_2: state.for_the_first_time_2,
+                finalized: state.finalized,
+            },
+        }
+    }
+}
+
+/// Drops the timeout increment, e.g., to write the states of the version 0 in the tests.
+impl From<VetomintV1> for VetomintV0 {
+    fn from(vetomint: VetomintV1) -> Self {
+        let state = vetomint.state;
+        let height_info = state.height_info;
+        let params = height_info.consensus_params;
+        VetomintV0 {
+            state: ConsensusStateV0 {
+                height_info: HeightInfoV0 {
+                    validators: height_info.validators,
+                    this_node_index: height_info.this_node_index,
+                    timestamp: height_info.timestamp,
+                    consensus_params: ConsensusParamsV0 {
+                        timeout_ms: params.timeout_ms,
+                        repeat_round_for_first_leader: params.repeat_round_for_first_leader,
+                    },
+                    initial_block_candidate: height_info.initial_block_candidate,
+                },
+                round: state.round,
+                step: state.step,
+                locked_value: state.locked_value,
+                locked_round: state.locked_round,
+                valid_value: state.valid_value,
+                valid_round: state.valid_round,
+                block_candidate: state.block_candidate,
+                proposals: state.proposals,
+                prevotes: state.prevotes,
+                precommits: state.precommits,
+                propose_timeout_schedules: state.propose_timeout_schedules,
+                precommit_timeout_schedules: state.precommit_timeout_schedules,
+                for_the_first_time_1: state.for_the_first_time_1,
+                for_the_first_time_2: state.for_the_first_time_2,
+                finalized: state.finalized,
+            },
+        }
+    }
+}
+
+/// `ConsensusParams` before `quorum`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct ConsensusParamsV1 {
+    timeout_ms: u64,
+    timeout_increment_ms: u64,
+    repeat_round_for_first_leader: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct HeightInfoV1 {
+    validators: Vec<VotingPower>,
+    this_node_index: Option<ValidatorIndex>,
+    timestamp: Timestamp,
+    consensus_params: ConsensusParamsV1,
+    initial_block_candidate: BlockIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct ConsensusStateV1 {
+    height_info: HeightInfoV1,
+    round: Round,
+    step: ConsensusStep,
+    locked_value: Option<BlockIdentifier>,
+    locked_round: Option<Round>,
+    valid_value: Option<BlockIdentifier>,
+    valid_round: Option<Round>,
+    block_candidate: BlockIdentifier,
+    proposals: BTreeMap<BlockIdentifier, Proposal>,
+    prevotes: BTreeSet<Vote>,
+    precommits: BTreeSet<Vote>,
+    propose_timeout_schedules: BTreeSet<(Round, Timestamp)>,
+    precommit_timeout_schedules: BTreeSet<(Round, Timestamp)>,
+    for_the_first_time_1: BTreeSet<Round>,
+    for_the_first_time_2: BTreeSet<Round>,
+    finalized: Option<(BlockIdentifier, Vec<ValidatorIndex>, Round)>,
+}
+
+/// `Vetomint` before `ConsensusParams::quorum`, which is read with the default quorum.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VetomintV1 {
+    state: ConsensusStateV1,
+}
+
+impl From<VetomintV1> for Vetomint {
+    fn from(vetomint: VetomintV1) -> Self {
+        let state = vetomint.state;
+        let height_info = state.height_info;
+        let params = height_info.consensus_params;
+        Vetomint {
+            state: ConsensusState {
+                height_info: HeightInfo {
+                    validators: height_info.validators,
+                    this_node_index: height_info.this_node_index,
+                    timestamp: height_info.timestamp,
+                    consensus_params: ConsensusParams {
+                        timeout_ms: params.timeout_ms,
+                        timeout_increment_ms: params.timeout_increment_ms,
+                        repeat_round_for_first_leader: params.repeat_round_for_first_leader,
+                        quorum: None,
+                    },
+                    initial_block_candidate: height_info.initial_block_candidate,
+                },
+                round: state.round,
+                step: state.step,
+                locked_value: state.locked_value,
+                locked_round: state.locked_round,
+                valid_value: state.valid_value,
+                valid_round: state.valid_round,
+                block_candidate: state.block_candidate,
+                proposals: state.proposals,
+                prevotes: state.prevotes,
+                precommits: state.precommits,
+                propose_timeout_schedules: state.propose_timeout_schedules,
+                precommit_timeout_schedules: state.precommit_timeout_schedules,
+                for_the_first_time_1: state.for_the_first_time_1,
+                for_the_first_time_2: state.for_the_first_time_2,
+                finalized: state.finalized,
+            },
+        }
+    }
+}
+
+/// Drops the quorum, e.g., to write the states of the previous versions in the tests.
+impl From<Vetomint> for VetomintV1 {
+    fn from(vetomint: Vetomint) -> Self {
+        let state = vetomint.state;
+        let height_info = state.height_info;
+        let params = height_info.consensus_params;
+        VetomintV1 {
+            state: ConsensusStateV1 {
+                height_info: HeightInfoV1 {
+                    validators: height_info.validators,
+                    this_node_index: height_info.this_node_index,
+                    timestamp: height_info.timestamp,
+                    consensus_params: ConsensusParamsV1 {
+                        timeout_ms: params.timeout_ms,
+                        timeout_increment_ms: params.timeout_increment_ms,
+                        repeat_round_for_first_leader: params.repeat_round_for_first_leader,
+                    },
+                    initial_block_candidate: height_info.initial_block_candidate,
+                },
+                round: state.round,
+                step: state.step,
+                locked_value: state.locked_value,
+                locked_round: state.locked_round,
+                valid_value: state.valid_value,
+                valid_round: state.valid_round,
+                block_candidate: state.block_candidate,
+                proposals: state.proposals,
+                prevotes: state.prevotes,
+                precommits: state.precommits,
+                propose_timeout_schedules: state.propose_timeout_schedules,
+                precommit_timeout_schedules: state.precommit_timeout_schedules,
+                for_the_first_time_1: state.for_the_first_time_1,
+                for_the_first_time_2: state.for_the_first_time_2,
+                finalized: state.finalized,
+            },
+        }
+    }
+}
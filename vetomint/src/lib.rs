@@ -1,9 +1,12 @@
+pub mod legacy;
 mod misbehavior;
 mod progress;
 mod state;
 
 use serde::{Deserialize, Serialize};
 
+pub use state::ConsensusStep;
+
 /// An index of the validator, which is for a single height. (Mapping from the actual public key to the index may differ for different heights.)
 pub type ValidatorIndex = usize;
 /// An identifier of the block, which is uniquely mapped to a block. Like `ValidatorIndex`, it is for a single height. (Mapping from the actual block to the index may differ for different heights.)
@@ -17,8 +20,63 @@ pub type Timestamp = i64;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ConsensusParams {
+    /// The timeout of each step in the round 0.
     pub timeout_ms: u64,
+    /// The amount of the timeout increased for every round,
+    /// so that the validators eventually wait long enough for the slow proposer.
+    pub timeout_increment_ms: u64,
     pub repeat_round_for_first_leader: usize,
+    /// The fraction of the total voting power that the votes must exceed to be a quorum,
+    /// which is 2/3 if not given.
+    pub quorum: Option<Quorum>,
+}
+
+impl ConsensusParams {
+    pub fn quorum(&self) -> Quorum {
+        self.quorum.unwrap_or_default()
+    }
+}
+
+/// The fraction of the total voting power that the votes must exceed to be a quorum
+/// (i.e., `voting_power / total > numerator / denominator`).
+///
+/// It must be at least 2/3 for the safety under the byzantine validators;
+/// a larger one tolerates more of them for the safety, but less for the liveness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Quorum {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Default for Quorum {
+    fn default() -> Self {
+        Self::TWO_THIRDS
+    }
+}
+
+impl Quorum {
+    pub const TWO_THIRDS: Self = Self {
+        numerator: 2,
+        denominator: 3,
+    };
+
+    /// Checks that the fraction is at least 2/3 and less than 1 (which nothing could exceed).
+    pub fn is_valid(&self) -> bool {
+        self.numerator < self.denominator
+            && self.numerator as u64 * 3 >= self.denominator as u64 * 2
+    }
+
+    /// Returns whether the voting power exceeds the fraction of the total.
+    ///
+    /// The arithmetic is in `u128`, which can't overflow for fewer than 2^32 validators.
+    pub fn is_reached(&self, voting_power: u128, total_voting_power: u128) -> bool {
+        voting_power * self.denominator as u128 > total_voting_power * self.numerator as u128
+    }
+
+    /// Returns the least voting power that reaches the quorum.
+    pub fn threshold(&self, total_voting_power: u128) -> u128 {
+        total_voting_power * self.numerator as u128 / self.denominator as u128 + 1
+    }
 }
 
 /// An event that (potentially) triggers a state transition of `StateMachine`.
@@ -180,17 +238,81 @@ impl Vetomint {
         &self.state.height_info
     }
 
+    /// Returns the current round.
+    pub fn get_round(&self) -> Round {
+        self.state.round
+    }
+
+    /// Returns the step of the current round.
+    pub fn get_step(&self) -> ConsensusStep {
+        self.state.step
+    }
+
+    /// Returns the locked value and the round in which it was locked, if any.
+    pub fn get_locked_value(&self) -> Option<(BlockIdentifier, Round)> {
+        self.state.locked_value.zip(self.state.locked_round)
+    }
+
+    /// Returns the valid value and the round of its quorum of prevotes, if any.
+    pub fn get_valid_value(&self) -> Option<(BlockIdentifier, Round)> {
+        self.state.valid_value.zip(self.state.valid_round)
+    }
+
+    /// Returns the time when the current step times out, if it is scheduled.
+    pub fn get_timeout(&self) -> Option<Timestamp> {
+        let round = self.state.round;
+        let propose_timeout = if self.state.step == ConsensusStep::Propose {
+            self.state
+                .propose_timeout_schedules
+                .iter()
+                .find(|(r, _)| *r == round)
+        } else {
+            None
+        };
+        propose_timeout
+            .or_else(|| {
+                self.state
+                    .precommit_timeout_schedules
+                    .iter()
+                    .find(|(r, _)| *r == round)
+            })
+            .map(|(_, timeout)| *timeout)
+    }
+
+    /// Returns the finalized proposal and the round in which it was finalized, if any.
+    pub fn get_finalized(&self) -> Option<(BlockIdentifier, Round)> {
+        self.state
+            .finalized
+            .as_ref()
+            .map(|(proposal, _, round)| (*proposal, *round))
+    }
+
     pub fn progress(
         &mut self,
         event: ConsensusEvent,
         timestamp: Timestamp,
     ) -> Vec<ConsensusResponse> {
         let mut responses = progress::progress(&mut self.state, event, timestamp);
+        let this_node_index = if let Some(index) = self.state.height_info.this_node_index {
+            index
+        } else {
+            // A non-validator node follows the state transitions but never broadcasts.
+            return responses
+                .into_iter()
+                .filter(|response| {
+                    !matches!(
+                        response,
+                        ConsensusResponse::BroadcastProposal { .. }
+                            | ConsensusResponse::BroadcastPrevote { .. }
+                            | ConsensusResponse::BroadcastPrecommit { .. }
+                    )
+                })
+                .collect();
+        };
         let mut final_responses = responses.clone();
         // feedback to myself
         loop {
             let mut responses_ = Vec::new();
-            let state = self.state.clone();
             for response in responses.clone() {
                 match response {
                     ConsensusResponse::BroadcastProposal {
@@ -203,7 +325,7 @@ impl Vetomint {
                             proposal,
                             valid: true,
                             valid_round,
-                            proposer: state.height_info.this_node_index.unwrap(),
+                            proposer: this_node_index,
                             round,
                             favor: true,
                         },
@@ -214,7 +336,7 @@ impl Vetomint {
                             &mut self.state,
                             ConsensusEvent::Prevote {
                                 proposal,
-                                signer: state.height_info.this_node_index.unwrap(),
+                                signer: this_node_index,
                                 round,
                             },
                             timestamp,
@@ -225,7 +347,7 @@ impl Vetomint {
                             &mut self.state,
                             ConsensusEvent::Precommit {
                                 proposal,
-                                signer: state.height_info.this_node_index.unwrap(),
+                                signer: this_node_index,
                                 round,
                             },
                             timestamp,
@@ -253,6 +375,10 @@ pub fn decide_proposer(round: usize, height_info: &HeightInfo) -> ValidatorIndex
     }
 }
 
-pub fn decide_timeout(params: &ConsensusParams, _round: usize) -> Timestamp {
-    params.timeout_ms as i64
+pub fn decide_timeout(params: &ConsensusParams, round: usize) -> Timestamp {
+    params
+        .timeout_increment_ms
+        .saturating_mul(round as u64)
+        .saturating_add(params.timeout_ms)
+        .min(i64::MAX as u64) as i64
 }
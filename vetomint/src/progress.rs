@@ -100,7 +100,7 @@ pub(crate) fn progress(
                 round,
             });
             let mut response = Vec::new();
-            response.extend(on_5f_precommit(state, round));
+            response.extend(on_5f_precommit(state, round, timestamp));
             response.extend(on_4f_nil_precommit(state, round, timestamp));
             if let Some(proposal) = proposal {
                 response.extend(on_4f_non_nil_precommit(state, round, proposal));
@@ -229,8 +229,7 @@ fn on_4f_non_nil_prevote_in_propose_step(
     };
 
     if proposal.proposer == valid_proposer
-        && state.get_total_prevotes_on_proposal(vr, target_proposal) * 3
-            > state.get_total_voting_power() * 2
+        && state.is_quorum(state.get_total_prevotes_on_proposal(vr, target_proposal))
         && state.step == ConsensusStep::Propose
         && vr < target_round
     {
@@ -269,8 +268,7 @@ fn on_4f_non_nil_prevote_in_prevote_step(
         return Vec::new();
     };
     if proposal.proposer == valid_proposer
-        && state.get_total_prevotes_on_proposal(target_round, target_proposal) * 3
-            > state.get_total_voting_power() * 2
+        && state.is_quorum(state.get_total_prevotes_on_proposal(target_round, target_proposal))
         && proposal.valid
         && (state.step == ConsensusStep::Prevote || state.step == ConsensusStep::Precommit)
     {
@@ -297,7 +295,7 @@ fn on_4f_nil_prevote(state: &mut ConsensusState, target_round: Round) -> Vec<Con
         return Vec::new();
     }
     if state.step == ConsensusStep::Prevote
-        && state.get_total_prevotes_on_nil(target_round) * 3 > state.get_total_voting_power() * 2
+        && state.is_quorum(state.get_total_prevotes_on_nil(target_round))
     {
         state.step = ConsensusStep::Precommit;
         vec![ConsensusResponse::BroadcastPrecommit {
@@ -322,9 +320,7 @@ fn on_5f_prevote(
     {
         state.step = ConsensusStep::Precommit;
         if let Some(proposal) = target_proposal {
-            if state.get_total_prevotes_on_proposal(target_round, proposal) * 3
-                > state.get_total_voting_power() * 2
-            {
+            if state.is_quorum(state.get_total_prevotes_on_proposal(target_round, proposal)) {
                 vec![ConsensusResponse::BroadcastPrecommit {
                     proposal: target_proposal,
                     round: state.round,
@@ -346,7 +342,11 @@ fn on_5f_prevote(
     }
 }
 
-fn on_5f_precommit(state: &mut ConsensusState, target_round: Round) -> Vec<ConsensusResponse> {
+fn on_5f_precommit(
+    state: &mut ConsensusState,
+    target_round: Round,
+    timestamp: Timestamp,
+) -> Vec<ConsensusResponse> {
     if target_round != state.round {
         return Vec::new();
     }
@@ -354,9 +354,10 @@ fn on_5f_precommit(state: &mut ConsensusState, target_round: Round) -> Vec<Conse
         && state.get_total_precommits(target_round) * 6 > state.get_total_voting_power() * 5
     {
         state.for_the_first_time_2.insert(target_round);
-        state
-            .precommit_timeout_schedules
-            .insert((target_round, 1000));
+        state.precommit_timeout_schedules.insert((
+            target_round,
+            timestamp + decide_timeout(&state.height_info.consensus_params, target_round),
+        ));
     }
     Vec::new()
 }
@@ -369,7 +370,7 @@ fn on_4f_nil_precommit(
     if target_round != state.round {
         return Vec::new();
     }
-    if state.get_total_precommits_on_nil(target_round) * 3 > state.get_total_voting_power() * 2 {
+    if state.is_quorum(state.get_total_precommits_on_nil(target_round)) {
         start_round(state, target_round + 1, timestamp)
     } else {
         Vec::new()
@@ -389,8 +390,7 @@ fn on_4f_non_nil_precommit(
     };
     if proposal.proposer == valid_proposer
         && proposal.valid
-        && state.get_total_precommits_on_proposal(target_round, target_proposal) * 3
-            > state.get_total_voting_power() * 2
+        && state.is_quorum(state.get_total_precommits_on_proposal(target_round, target_proposal))
     {
         let proof: Vec<_> = state
             .precommits
@@ -1,8 +1,9 @@
 use super::*;
 use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-pub(crate) enum ConsensusStep {
+/// The step of a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ConsensusStep {
     Initial,
     Propose,
     Prevote,
@@ -49,6 +50,7 @@ pub(crate) struct ConsensusState {
 impl ConsensusState {
     pub(crate) fn new(height_info: HeightInfo) -> Self {
         ConsensusState {
+            block_candidate: height_info.initial_block_candidate,
             height_info,
             round: 0,
             step: ConsensusStep::Initial,
@@ -56,7 +58,6 @@ impl ConsensusState {
             locked_round: None,
             valid_value: None,
             valid_round: None,
-            block_candidate: BlockIdentifier::default(),
             proposals: Default::default(),
             prevotes: Default::default(),
             precommits: Default::default(),
@@ -68,23 +69,36 @@ impl ConsensusState {
         }
     }
 
-    pub(crate) fn get_total_voting_power(&self) -> VotingPower {
-        self.height_info.validators.iter().sum()
+    /// The sums of the voting power are in `u128`, so that the quorum comparisons
+    /// (multiplying them by up to 6) never overflow even with the powers close to `u64::MAX`.
+    pub(crate) fn get_total_voting_power(&self) -> u128 {
+        self.height_info
+            .validators
+            .iter()
+            .map(|power| *power as u128)
+            .sum()
+    }
+
+    pub(crate) fn is_quorum(&self, voting_power: u128) -> bool {
+        self.height_info
+            .consensus_params
+            .quorum()
+            .is_reached(voting_power, self.get_total_voting_power())
     }
 
-    pub(crate) fn get_total_prevotes(&self, round: Round) -> VotingPower {
+    pub(crate) fn get_total_prevotes(&self, round: Round) -> u128 {
         self.prevotes
             .iter()
             .filter(|vote| vote.round == round)
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 
-    pub(crate) fn get_total_precommits(&self, round: Round) -> VotingPower {
+    pub(crate) fn get_total_precommits(&self, round: Round) -> u128 {
         self.precommits
             .iter()
             .filter(|vote| vote.round == round)
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 
@@ -92,11 +106,11 @@ impl ConsensusState {
         &self,
         round: Round,
         proposal: BlockIdentifier,
-    ) -> VotingPower {
+    ) -> u128 {
         self.prevotes
             .iter()
             .filter(|vote| vote.round == round && vote.proposal == Some(proposal))
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 
@@ -104,27 +118,27 @@ impl ConsensusState {
         &self,
         round: Round,
         proposal: BlockIdentifier,
-    ) -> VotingPower {
+    ) -> u128 {
         self.precommits
             .iter()
             .filter(|vote| vote.round == round && vote.proposal == Some(proposal))
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 
-    pub(crate) fn get_total_prevotes_on_nil(&self, round: Round) -> VotingPower {
+    pub(crate) fn get_total_prevotes_on_nil(&self, round: Round) -> u128 {
         self.prevotes
             .iter()
             .filter(|vote| vote.round == round && vote.proposal.is_none())
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 
-    pub(crate) fn get_total_precommits_on_nil(&self, round: Round) -> VotingPower {
+    pub(crate) fn get_total_precommits_on_nil(&self, round: Round) -> u128 {
         self.precommits
             .iter()
             .filter(|vote| vote.round == round && vote.proposal.is_none())
-            .map(|vote| self.height_info.validators[vote.signer])
+            .map(|vote| self.height_info.validators[vote.signer] as u128)
             .sum()
     }
 }
@@ -140,7 +154,9 @@ mod tests {
             timestamp: 0,
             consensus_params: ConsensusParams {
                 timeout_ms: 100,
+                timeout_increment_ms: 0,
                 repeat_round_for_first_leader: 1,
+                quorum: None,
             },
             initial_block_candidate: 0,
         };
@@ -153,6 +169,23 @@ mod tests {
         assert_eq!(consensus_state.get_total_voting_power(), 4);
     }
 
+    #[test]
+    fn get_total_voting_power_without_overflow() {
+        let mut consensus_state = create_default_consensus_state();
+        consensus_state.height_info.validators = vec![VotingPower::MAX; 4];
+        for signer in 0..4 {
+            consensus_state.precommits.insert(Vote {
+                proposal: None,
+                signer,
+                round: 0,
+            });
+        }
+        let total = consensus_state.get_total_voting_power();
+        assert_eq!(total, VotingPower::MAX as u128 * 4);
+        assert_eq!(consensus_state.get_total_precommits_on_nil(0), total);
+        assert!(consensus_state.get_total_precommits(0) * 6 > total * 5);
+    }
+
     #[test]
     fn get_total_prevotes() {
         // TODO
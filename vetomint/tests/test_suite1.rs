@@ -9,7 +9,9 @@ fn normal_1() {
         timestamp: 0,
         consensus_params: ConsensusParams {
             timeout_ms: 100,
+            timeout_increment_ms: 0,
             repeat_round_for_first_leader: 1,
+            quorum: None,
         },
         initial_block_candidate: 0,
     };